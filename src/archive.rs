@@ -0,0 +1,391 @@
+//! Looking inside downloaded archives, for `verify --deep`, to check that the
+//! paths a binary names within its archive exist, and decompressing them for
+//! binaries whose sha256 is over their decompressed content.
+//!
+//! Entries are streamed and only the one asked for is read; nothing is written
+//! to disk. lzma-rs only decodes xz into a writer, so xz is decoded on a thread
+//! of its own and piped to the reader a chunk at a time.
+
+use std::{
+    io::{self, BufReader, BufWriter, Cursor, Read, Seek, Write},
+    sync::mpsc::{self, Receiver, SyncSender},
+    thread,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+}
+
+/// The archive formats that can be looked inside.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Zip,
+    Tar(Compression),
+}
+
+impl Format {
+    /// The format of an archive with the `"type"` `type_`, or else named `name`
+    /// (a URL or path, whose query or fragment is ignored). `None` for formats
+    /// that can't be looked inside, such as `.deb`.
+    pub fn of(type_: Option<&str>, name: &str) -> Option<Format> {
+        let name = name.split(['?', '#']).next().unwrap_or(name);
+        let format = |extension: &str| match extension {
+            "zip" | "jar" | "war" | "aar" => Some(Format::Zip),
+            "tar" => Some(Format::Tar(Compression::None)),
+            "tar.gz" | "tgz" => Some(Format::Tar(Compression::Gzip)),
+            "tar.xz" | "txz" => Some(Format::Tar(Compression::Xz)),
+            "tar.zst" | "tzst" => Some(Format::Tar(Compression::Zstd)),
+            "tar.bz2" | "tbz" => Some(Format::Tar(Compression::Bzip2)),
+            _ => None,
+        };
+        match type_ {
+            Some(type_) => format(type_),
+            None => [
+                "tar.gz", "tar.xz", "tar.zst", "tar.bz2", "tgz", "txz", "tzst", "tbz", "tar",
+                "zip", "jar", "war", "aar",
+            ]
+            .into_iter()
+            .find(|extension| {
+                name.strip_suffix(extension)
+                    .is_some_and(|rest| rest.ends_with('.'))
+            })
+            .and_then(format),
+        }
+    }
+}
+
+/// An entry's name without a leading `./` or trailing `/`, as paths are
+/// written in the lockfile.
+fn normalize(name: &str) -> &str {
+    let name = name.trim_start_matches("./").trim_start_matches('/');
+    name.trim_end_matches('/')
+}
+
+/// Calls `read` with a reader of `source` with `compression` undone.
+fn decompressed<T>(
+    mut source: impl Read + Send,
+    compression: Compression,
+    read: impl FnOnce(&mut dyn Read) -> Result<T, String>,
+) -> Result<T, String> {
+    match compression {
+        Compression::None => read(&mut source),
+        Compression::Gzip => read(&mut flate2::read::MultiGzDecoder::new(source)),
+        Compression::Xz => thread::scope(|scope| {
+            let (chunks, receiver) = mpsc::sync_channel(PIPE_CHUNKS);
+            let decoder = scope.spawn(move || {
+                let mut pipe = BufWriter::with_capacity(PIPE_CHUNK_SIZE, Pipe(chunks));
+                lzma_rs::xz_decompress(&mut BufReader::new(source), &mut pipe)?;
+                pipe.flush().map_err(lzma_rs::error::Error::IoError)
+            });
+            let read = read(&mut PipeReader {
+                chunks: receiver,
+                chunk: Cursor::new(Vec::new()),
+            });
+            // the reader is gone by now, so a decoder whose output is no longer
+            // wanted stops at its next write
+            match decoder.join().expect("xz decoder panicked") {
+                Err(lzma_rs::error::Error::IoError(e)) if e.kind() == io::ErrorKind::BrokenPipe => {
+                    read
+                }
+                Err(e) => Err(format!("not a readable xz stream: {e}")),
+                Ok(()) => read,
+            }
+        }),
+        Compression::Zstd => read(
+            &mut ruzstd::streaming_decoder::StreamingDecoder::new(source)
+                .map_err(|e| format!("not a readable zstd stream: {e}"))?,
+        ),
+        Compression::Bzip2 => read(&mut bzip2::read::MultiBzDecoder::new(source)),
+    }
+}
+
+/// How many chunks of decoded xz may wait to be read, and how large each is.
+const PIPE_CHUNKS: usize = 4;
+const PIPE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// The writing end of the pipe from an xz decoder thread.
+struct Pipe(SyncSender<Vec<u8>>);
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The reading end of a [`Pipe`], at its end once the decoder is done.
+struct PipeReader {
+    chunks: Receiver<Vec<u8>>,
+    chunk: Cursor<Vec<u8>>,
+}
+
+impl Read for PipeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.chunk.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            match self.chunks.recv() {
+                Ok(chunk) => self.chunk = Cursor::new(chunk),
+                Err(_) => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Writes `source` with `compression` undone, such as the tar stream of a
+/// `.tar.gz`, to `sink` as it's decompressed rather than collecting it, so a
+/// stream that inflates to far more than its download isn't held in memory.
+pub fn decompress_into(
+    source: impl Read + Send,
+    compression: Compression,
+    sink: &mut impl Write,
+) -> Result<(), String> {
+    decompressed(source, compression, |reader| {
+        io::copy(reader, sink)
+            .map(|_| ())
+            .map_err(|e| format!("not a readable compressed stream: {e}"))
+    })
+}
+
+/// Calls `visit` with the name and contents of every entry of the archive read
+/// from `source` until it returns true.
+fn scan(
+    source: impl Read + Seek + Send,
+    format: Format,
+    mut visit: impl FnMut(&str, &mut dyn Read) -> io::Result<bool>,
+) -> Result<(), String> {
+    let compression = match format {
+        Format::Zip => {
+            let mut archive = zip::ZipArchive::new(source)
+                .map_err(|e| format!("not a readable zip archive: {e}"))?;
+            for i in 0..archive.len() {
+                let mut entry = archive
+                    .by_index(i)
+                    .map_err(|e| format!("unreadable zip entry: {e}"))?;
+                let name = entry.name().to_owned();
+                if visit(normalize(&name), &mut entry).map_err(|e| e.to_string())? {
+                    break;
+                }
+            }
+            return Ok(());
+        }
+        Format::Tar(compression) => compression,
+    };
+
+    decompressed(source, compression, |reader| {
+        let mut archive = tar::Archive::new(reader);
+        let unreadable = |e: io::Error| format!("not a readable tar archive: {e}");
+        for entry in archive.entries().map_err(unreadable)? {
+            let mut entry = entry.map_err(unreadable)?;
+            let name = entry
+                .path()
+                .map_err(unreadable)?
+                .to_string_lossy()
+                .into_owned();
+            if visit(normalize(&name), &mut entry).map_err(unreadable)? {
+                break;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// The contents of the file at `path` in the archive read from `source`, or
+/// `None` if the archive has no such entry.
+pub fn read_file(
+    source: impl Read + Seek + Send,
+    format: Format,
+    path: &str,
+) -> Result<Option<Vec<u8>>, String> {
+    let path = normalize(path);
+    let mut found = None;
+    scan(source, format, |name, contents| {
+        if name != path {
+            return Ok(false);
+        }
+        let mut buffer = Vec::new();
+        contents.read_to_end(&mut buffer)?;
+        found = Some(buffer);
+        Ok(true)
+    })?;
+    Ok(found)
+}
+
+/// Whether the archive read from `source` has a directory at `path`, either as
+/// its own entry or as the parent of one.
+pub fn has_dir(
+    source: impl Read + Seek + Send,
+    format: Format,
+    path: &str,
+) -> Result<bool, String> {
+    let path = normalize(path);
+    let mut found = false;
+    scan(source, format, |name, _| {
+        found = name == path
+            || name
+                .strip_prefix(path)
+                .is_some_and(|rest| rest.starts_with('/'));
+        Ok(found)
+    })?;
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::io::Write;
+
+    fn zip(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (path, contents) in files {
+            writer
+                .start_file(*path, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn format_from_type_or_name() {
+        assert_eq!(
+            Format::of(None, "https://example.com/tool-1.2.3.tar.zst?x=1"),
+            Some(Format::Tar(Compression::Zstd))
+        );
+        assert_eq!(
+            Format::of(None, "tool.tgz"),
+            Some(Format::Tar(Compression::Gzip))
+        );
+        assert_eq!(Format::of(None, "tool.JAR"), None);
+        assert_eq!(Format::of(None, "tool.jar"), Some(Format::Zip));
+        assert_eq!(Format::of(None, "tool.deb"), None);
+        assert_eq!(Format::of(None, "tool"), None);
+        assert_eq!(
+            Format::of(Some("tar.bz2"), "https://example.com/download?id=1"),
+            Some(Format::Tar(Compression::Bzip2))
+        );
+        assert_eq!(Format::of(Some("deb"), "tool.tar.gz"), None);
+    }
+
+    fn xz(bytes: &[u8]) -> Vec<u8> {
+        let mut xz = Vec::new();
+        lzma_rs::xz_compress(&mut Cursor::new(bytes), &mut xz).unwrap();
+        xz
+    }
+
+    fn bzip2(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A zstd frame holding `bytes` in a single raw block, there being no zstd
+    /// encoder among the dependencies.
+    fn zstd(bytes: &[u8]) -> Vec<u8> {
+        let size = u32::try_from(bytes.len()).unwrap();
+        assert!((256..128 * 1024).contains(&size));
+        let mut frame = vec![0x28, 0xb5, 0x2f, 0xfd];
+        // a single segment with a two-byte content size, which is stored less 256
+        frame.push(0x60);
+        frame.extend_from_slice(&u16::try_from(size - 256).unwrap().to_le_bytes());
+        // the last block, raw
+        frame.extend_from_slice(&(size << 3 | 1).to_le_bytes()[..3]);
+        frame.extend_from_slice(bytes);
+        frame
+    }
+
+    #[test]
+    fn finds_files_and_directories() {
+        let files = [
+            ("./tool-1.2.3/bin/tool", "hello"),
+            ("tool-1.2.3/README", ""),
+        ];
+        let tar = testing::tar(&files);
+        for (bytes, format) in [
+            (tar.clone(), Format::Tar(Compression::None)),
+            (testing::tar_gz(&files), Format::Tar(Compression::Gzip)),
+            (xz(&tar), Format::Tar(Compression::Xz)),
+            (zstd(&tar), Format::Tar(Compression::Zstd)),
+            (bzip2(&tar), Format::Tar(Compression::Bzip2)),
+            (zip(&files), Format::Zip),
+        ] {
+            let bytes = || Cursor::new(&bytes);
+            assert_eq!(
+                read_file(bytes(), format, "tool-1.2.3/bin/tool").unwrap(),
+                Some(b"hello".to_vec()),
+                "{format:?}"
+            );
+            assert_eq!(
+                read_file(bytes(), format, "tool-1.2.3/bin/tool2").unwrap(),
+                None
+            );
+            assert!(has_dir(bytes(), format, "tool-1.2.3").unwrap());
+            assert!(has_dir(bytes(), format, "tool-1.2.3/bin/").unwrap());
+            assert!(!has_dir(bytes(), format, "tool-1.2").unwrap());
+        }
+        for compression in [
+            Compression::Gzip,
+            Compression::Xz,
+            Compression::Zstd,
+            Compression::Bzip2,
+        ] {
+            let read = read_file(Cursor::new(b"hello"), Format::Tar(compression), "tool");
+            assert!(read.is_err(), "{compression:?}");
+        }
+        assert!(read_file(Cursor::new(b"hello"), Format::Zip, "tool").is_err());
+    }
+
+    #[test]
+    fn xz_is_streamed_and_can_stop_early() {
+        // far more decoded output than the pipe holds, with the entry first
+        let padding = "0".repeat(4 * PIPE_CHUNKS * PIPE_CHUNK_SIZE);
+        let tar = testing::tar(&[("tool", "hello"), ("padding", &padding)]);
+        let xz = xz(&tar);
+        assert_eq!(
+            read_file(Cursor::new(&xz), Format::Tar(Compression::Xz), "tool").unwrap(),
+            Some(b"hello".to_vec())
+        );
+        assert_eq!(
+            read_file(Cursor::new(&xz), Format::Tar(Compression::Xz), "padding").unwrap(),
+            Some(padding.into_bytes())
+        );
+
+        // a stream cut short is reported as such, not as a missing entry
+        let truncated = &xz[..xz.len() / 2];
+        let error = read_file(Cursor::new(truncated), Format::Tar(Compression::Xz), "none")
+            .err()
+            .unwrap();
+        assert!(error.starts_with("not a readable xz stream"), "{error}");
+    }
+
+    #[test]
+    fn decompresses_into_a_writer() {
+        let tar = testing::tar(&[("tool", "hello")]);
+        for (bytes, compression) in [
+            (testing::tar_gz(&[("tool", "hello")]), Compression::Gzip),
+            (xz(&tar), Compression::Xz),
+            (zstd(&tar), Compression::Zstd),
+            (bzip2(&tar), Compression::Bzip2),
+            (tar.clone(), Compression::None),
+        ] {
+            let mut decompressed = Vec::new();
+            decompress_into(&bytes[..], compression, &mut decompressed).unwrap();
+            assert_eq!(decompressed, tar, "{compression:?}");
+        }
+        assert!(decompress_into(&b"hello"[..], Compression::Xz, &mut Vec::new()).is_err());
+    }
+}
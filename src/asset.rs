@@ -0,0 +1,419 @@
+//! Heuristic matching of release assets to platforms, for when an upstream
+//! renames its assets between releases.
+
+use crate::lockfile::{Libc, SupportedCpu, SupportedOs};
+use clap::ValueEnum;
+
+/// How to pick a replacement when the rewritten asset URL isn't in the release.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AssetSelection {
+    /// Score assets by the OS and CPU tokens in their names (`amd64`, `darwin`, …).
+    ArchTriple,
+}
+
+const OS_ALIASES: [(SupportedOs, &[&str]); 3] = [
+    (SupportedOs::Linux, &["linux"]),
+    (
+        SupportedOs::MacOS,
+        &["apple", "darwin", "mac", "macos", "osx"],
+    ),
+    (
+        SupportedOs::Windows,
+        &["mingw", "msvc", "win", "win32", "win64", "windows"],
+    ),
+];
+
+const CPU_ALIASES: [(SupportedCpu, &[&str]); 2] = [
+    (SupportedCpu::Arm64, &["aarch64", "arm64", "armv8"]),
+    (SupportedCpu::X86_64, &["amd64", "x64", "x86_64"]),
+];
+
+const LIBC_ALIASES: [(Libc, &[&str]); 2] =
+    [(Libc::Gnu, &["glibc", "gnu"]), (Libc::Musl, &["musl"])];
+
+/// Assets that describe other assets rather than being installable themselves.
+const METADATA_SUFFIXES: [&str; 10] = [
+    ".asc",
+    ".intoto.jsonl",
+    ".json",
+    ".pem",
+    ".sbom",
+    ".sha256",
+    ".sha256sum",
+    ".sha512",
+    ".sig",
+    ".txt",
+];
+
+/// Lowercased name tokens split on `-`, `_` and `.`, with `x86`/`64` rejoined.
+fn tokens(name: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    for token in name.to_ascii_lowercase().split(['-', '_', '.']) {
+        if token == "64" && tokens.last().is_some_and(|last| last == "x86") {
+            tokens.last_mut().unwrap().push_str("_64");
+        } else if !token.is_empty() {
+            tokens.push(token.to_owned());
+        }
+    }
+    tokens
+}
+
+fn mentioned<T: Clone + PartialEq>(tokens: &[String], aliases: &[(T, &[&str])]) -> Vec<T> {
+    aliases
+        .iter()
+        .filter(|(_, names)| tokens.iter().any(|token| names.contains(&token.as_str())))
+        .map(|(value, _)| value.clone())
+        .collect()
+}
+
+fn is_metadata(name: &str) -> bool {
+    METADATA_SUFFIXES
+        .iter()
+        .any(|suffix| name.to_ascii_lowercase().ends_with(suffix))
+}
+
+/// An asset passing a heuristic's platform checks.
+struct Candidate<'a> {
+    url: &'a str,
+    name: &'a str,
+    /// How many of its name tokens are among the expected name's.
+    shared: usize,
+    libcs: Vec<Libc>,
+}
+
+/// Candidates found for `expected` among `assets` (download URLs) passing
+/// `keep`, which sees each asset's name tokens. Assets naming a libc other than
+/// `libc` never pass, and `libc` itself counts as an expected token.
+fn candidates<'a>(
+    assets: &'a [String],
+    expected: &str,
+    libc: Option<&Libc>,
+    keep: impl Fn(&[String]) -> bool,
+) -> Vec<Candidate<'a>> {
+    let mut expected = tokens(expected);
+    expected.extend(libc.map(|libc| libc.to_string()));
+    assets
+        .iter()
+        .filter_map(|url| {
+            let name = url.rsplit('/').next().unwrap_or(url);
+            if is_metadata(name) {
+                return None;
+            }
+            let tokens = tokens(name);
+            let libcs = mentioned(&tokens, &LIBC_ALIASES);
+            if !keep(&tokens) || libc.is_some_and(|libc| libcs.iter().any(|named| named != libc)) {
+                return None;
+            }
+            let shared = tokens
+                .iter()
+                .filter(|token| expected.contains(token))
+                .count();
+            Some(Candidate {
+                url: url.as_str(),
+                name,
+                shared,
+                libcs,
+            })
+        })
+        .collect()
+}
+
+/// The Levenshtein distance between `a` and `b`, ignoring ASCII case.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let (a, b) = (a.to_ascii_lowercase(), b.to_ascii_lowercase());
+    let b = b.as_bytes();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, x) in a.bytes().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &y) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(x != y);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(diagonal + 1).min(row[j] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// The best of `candidates`: the most tokens shared with `expected`, then the
+/// smallest edit distance to it, then the lexicographically smallest name, so
+/// the same asset list always yields the same pick. Assets tied on shared
+/// tokens that name different libcs are where the heuristics stop guessing, as
+/// nothing in the names says which build is wanted; that's an error.
+fn pick<'a>(candidates: Vec<Candidate<'a>>, expected: &str) -> Result<Option<&'a str>, String> {
+    let Some(most) = candidates.iter().map(|candidate| candidate.shared).max() else {
+        return Ok(None);
+    };
+    let mut tied: Vec<Candidate> = candidates
+        .into_iter()
+        .filter(|candidate| candidate.shared == most)
+        .collect();
+    if tied
+        .iter()
+        .any(|candidate| candidate.libcs != tied[0].libcs)
+    {
+        let mut names: Vec<&str> = tied.iter().map(|candidate| candidate.name).collect();
+        names.sort_unstable();
+        return Err(format!(
+            "assets {} match equally well but are built against different libcs; set \"libc\" on the binary to choose one",
+            names.join(", ")
+        ));
+    }
+    tied.sort_by_cached_key(|candidate| (edit_distance(candidate.name, expected), candidate.name));
+    Ok(tied.first().map(|candidate| candidate.url))
+}
+
+/// Picks the asset for `os`/`cpu`/`libc` from `assets` (download URLs), or
+/// `None` when no asset names the platform.
+///
+/// A candidate must name exactly this OS and CPU (by any alias) and no others.
+/// Among candidates the one sharing the most name tokens with `expected`, the
+/// asset name the mechanical substitution produced, wins, with ties broken as
+/// in [`pick`].
+pub fn select<'a>(
+    assets: &'a [String],
+    expected: &str,
+    os: &SupportedOs,
+    cpu: &SupportedCpu,
+    libc: Option<&Libc>,
+) -> Result<Option<&'a str>, String> {
+    let candidates = candidates(assets, expected, libc, |tokens| {
+        mentioned(tokens, &OS_ALIASES) == [os.clone()]
+            && mentioned(tokens, &CPU_ALIASES) == [cpu.clone()]
+    });
+    pick(candidates, expected)
+}
+
+/// Picks the asset most like `previous`, the binary's current asset name, for
+/// `--prefer-api-assets`, or `None` when no asset is a plausible match.
+///
+/// Unlike [`select`], a candidate needn't name a platform at all: it must name
+/// the same OS and CPU as `previous` does (by any alias), so platform-neutral
+/// assets stay platform-neutral. Candidates are scored by the name tokens they
+/// share with `expected`, `previous` with the new version substituted in, with
+/// ties broken as in [`pick`].
+pub fn closest<'a>(
+    assets: &'a [String],
+    previous: &str,
+    expected: &str,
+    os: &SupportedOs,
+    cpu: &SupportedCpu,
+    libc: Option<&Libc>,
+) -> Result<Option<&'a str>, String> {
+    let previous = tokens(previous);
+    let oses = mentioned(&previous, &OS_ALIASES);
+    let cpus = mentioned(&previous, &CPU_ALIASES);
+    if oses.iter().any(|named| named != os) || cpus.iter().any(|named| named != cpu) {
+        return Ok(None);
+    }
+    let mut candidates = candidates(assets, expected, libc, |tokens| {
+        mentioned(tokens, &OS_ALIASES) == oses && mentioned(tokens, &CPU_ALIASES) == cpus
+    });
+    candidates.retain(|candidate| candidate.shared > 0);
+    pick(candidates, expected)
+}
+
+/// Whether `chosen` differs from `previous` by more than version numbers, such
+/// as a renamed platform token or a different archive format.
+pub fn renamed(previous: &str, chosen: &str) -> bool {
+    let shape = |name: &str| -> Vec<String> {
+        tokens(name)
+            .into_iter()
+            .filter(|token| {
+                let digits = token.strip_prefix('v').unwrap_or(token);
+                !digits.bytes().all(|byte| byte.is_ascii_digit())
+            })
+            .collect()
+    };
+    shape(previous) != shape(chosen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assets(names: &[&str]) -> Vec<String> {
+        names
+            .iter()
+            .map(|name| format!("https://github.com/org/tool/releases/download/v2.0.0/{name}"))
+            .collect()
+    }
+
+    fn name(url: Result<Option<&str>, String>) -> Option<&str> {
+        url.unwrap().map(|url| url.rsplit('/').next().unwrap())
+    }
+
+    #[test]
+    fn tokens_keep_x86_64_together() {
+        assert_eq!(
+            tokens("Tool-2.0.0-x86_64-unknown-linux-gnu.tar.gz"),
+            ["tool", "2", "0", "0", "x86_64", "unknown", "linux", "gnu", "tar", "gz"]
+        );
+    }
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("Tool.zip", "tool.zip"), 0);
+    }
+
+    #[test]
+    fn select_renamed_arch_tokens() {
+        let assets = assets(&[
+            "tool-2.0.0-linux-amd64.tar.gz",
+            "tool-2.0.0-linux-arm64.tar.gz",
+            "tool-2.0.0-darwin-amd64.tar.gz",
+            "tool-2.0.0-darwin-arm64.tar.gz",
+            "tool-2.0.0-windows-amd64.zip",
+            "checksums.txt",
+        ]);
+        let expected = "tool-2.0.0-x86_64-unknown-linux-gnu.tar.gz";
+        let select = |os, cpu| name(select(&assets, expected, &os, &cpu, None));
+        assert_eq!(
+            select(SupportedOs::Linux, SupportedCpu::X86_64),
+            Some("tool-2.0.0-linux-amd64.tar.gz")
+        );
+        assert_eq!(
+            select(SupportedOs::MacOS, SupportedCpu::Arm64),
+            Some("tool-2.0.0-darwin-arm64.tar.gz")
+        );
+        assert_eq!(
+            select(SupportedOs::Windows, SupportedCpu::X86_64),
+            Some("tool-2.0.0-windows-amd64.zip")
+        );
+        assert_eq!(select(SupportedOs::Windows, SupportedCpu::Arm64), None);
+    }
+
+    #[test]
+    fn select_prefers_names_closest_to_expected() {
+        let assets = assets(&[
+            "tool-x86_64-unknown-linux-gnu.tar.gz",
+            "tool-x86_64-unknown-linux-musl.tar.gz",
+            "tool-x86_64-unknown-linux-gnu.tar.gz.sha256",
+        ]);
+        let select = |expected, libc| {
+            select(
+                &assets,
+                expected,
+                &SupportedOs::Linux,
+                &SupportedCpu::X86_64,
+                libc,
+            )
+        };
+        assert_eq!(
+            name(select("tool-v2-x86_64-linux-musl.tar.gz", None)),
+            Some("tool-x86_64-unknown-linux-musl.tar.gz")
+        );
+        // nothing distinguishes gnu from musl but the binary's libc
+        assert_eq!(
+            select("tool-v2-linux-amd64.tar.gz", None).unwrap_err(),
+            "assets tool-x86_64-unknown-linux-gnu.tar.gz, tool-x86_64-unknown-linux-musl.tar.gz match equally well but are built against different libcs; set \"libc\" on the binary to choose one"
+        );
+        assert_eq!(
+            name(select("tool-v2-linux-amd64.tar.gz", Some(&Libc::Gnu))),
+            Some("tool-x86_64-unknown-linux-gnu.tar.gz")
+        );
+        // a libc excludes assets built against the other one
+        assert_eq!(
+            name(select("tool-v2-x86_64-linux-musl.tar.gz", Some(&Libc::Gnu))),
+            Some("tool-x86_64-unknown-linux-gnu.tar.gz")
+        );
+    }
+
+    #[test]
+    fn select_breaks_ties_deterministically() {
+        let expected = "tool-2.0.0-linux-x86_64.tgz";
+        let select = |names: &[&str]| {
+            let assets = assets(names);
+            name(select(
+                &assets,
+                expected,
+                &SupportedOs::Linux,
+                &SupportedCpu::X86_64,
+                None,
+            ))
+            .map(str::to_owned)
+        };
+        // tied on shared tokens, the smaller edit distance wins in any order
+        let archives = [
+            "tool-2.0.0-linux-amd64.tar.gz",
+            "tool-2.0.0-linux-amd64.tgz.zip",
+            "tool-2.0.0-linux-amd64.tgz",
+        ];
+        for rotation in 0..archives.len() {
+            let mut names = archives;
+            names.rotate_left(rotation);
+            assert_eq!(
+                select(&names).as_deref(),
+                Some("tool-2.0.0-linux-amd64.tgz")
+            );
+        }
+        // equally distant names fall back to the lexicographically smallest
+        let mut names = [
+            "tool-2.0.0-linux-amd64-b.tgz",
+            "tool-2.0.0-linux-amd64-a.tgz",
+        ];
+        assert_eq!(
+            select(&names).as_deref(),
+            Some("tool-2.0.0-linux-amd64-a.tgz")
+        );
+        names.reverse();
+        assert_eq!(
+            select(&names).as_deref(),
+            Some("tool-2.0.0-linux-amd64-a.tgz")
+        );
+    }
+
+    #[test]
+    fn closest_follows_naming_drift() {
+        let assets = assets(&[
+            "tool-2.0.0-x86_64-unknown-linux-gnu.tar.gz",
+            "tool-2.0.0-x86_64-unknown-linux-musl.tar.gz",
+            "tool-2.0.0-aarch64-unknown-linux-gnu.tar.gz",
+            "tool-2.0.0.jar",
+            "checksums.txt",
+        ]);
+        let closest = |previous: &str, expected: &str| {
+            closest(
+                &assets,
+                previous,
+                expected,
+                &SupportedOs::Linux,
+                &SupportedCpu::X86_64,
+                None,
+            )
+        };
+        assert_eq!(
+            name(closest(
+                "tool-1.9.0-linux-amd64-musl.tar.gz",
+                "tool-2.0.0-linux-amd64-musl.tar.gz"
+            )),
+            Some("tool-2.0.0-x86_64-unknown-linux-musl.tar.gz")
+        );
+        // platform-neutral assets only match platform-neutral assets
+        assert_eq!(
+            name(closest("tool-1.9.0.jar", "tool-2.0.0.jar")),
+            Some("tool-2.0.0.jar")
+        );
+        assert!(closest("tool-1.9.0-linux-amd64", "tool-2.0.0-linux-amd64").is_err());
+        // a current asset naming another platform is never matched
+        assert_eq!(
+            name(closest("tool-1.9.0-darwin.jar", "tool-2.0.0-darwin.jar")),
+            None
+        );
+    }
+
+    #[test]
+    fn renamed_ignores_versions() {
+        assert!(!renamed(
+            "tool-v1.9.0-linux.tar.gz",
+            "tool-v2.0.0-linux.tar.gz"
+        ));
+        assert!(renamed(
+            "tool-1.9.0-linux-amd64.tar.gz",
+            "tool-2.0.0-x86_64-unknown-linux-gnu.tar.gz"
+        ));
+        assert!(renamed("tool-1.9.0-linux.tar.gz", "tool-2.0.0-linux.zip"));
+    }
+}
@@ -0,0 +1,96 @@
+//! Writing files so an interrupted write never leaves a partial one: contents
+//! go to a temporary file beside the target, which is then renamed over it.
+//! Renames within a directory are atomic, so readers, and a killed run, see
+//! either the old file or the new one. The temporary file is synced before
+//! the rename, so a crash soon after can't leave the new name on empty data.
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// The temporary file `path` is written to before being renamed into place.
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+/// Replaces the file at `path` with `contents`, keeping its permissions if it
+/// already exists. If `path` is a symlink, the file it points to is replaced
+/// and the link is kept.
+pub fn write(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    write_with(path, contents.as_ref(), |from, to| fs::rename(from, to))
+}
+
+fn write_with(
+    path: &Path,
+    contents: &[u8],
+    rename: impl FnOnce(&Path, &Path) -> io::Result<()>,
+) -> io::Result<()> {
+    let target = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => fs::canonicalize(path)?,
+        _ => path.to_owned(),
+    };
+    let partial = partial_path(&target);
+    let written = fs::File::create(&partial)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            if let Ok(metadata) = fs::metadata(&target) {
+                file.set_permissions(metadata.permissions())?;
+            }
+            file.sync_all()
+        })
+        .and_then(|()| rename(&partial, &target));
+    if written.is_err() {
+        let _ = fs::remove_file(&partial);
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failed_writes_leave_the_original() {
+        let dir = std::env::temp_dir().join(format!("multitool-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("multitool.lock.json");
+        fs::write(&path, "original").unwrap();
+
+        let interrupted = write_with(&path, b"updated", |_, _| {
+            Err(io::Error::new(io::ErrorKind::Interrupted, "killed"))
+        });
+        assert!(interrupted.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+        assert!(!partial_path(&path).exists());
+
+        write(&path, "updated").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "updated");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_written_through() {
+        let dir = std::env::temp_dir().join(format!("multitool-symlink-{}", std::process::id()));
+        fs::create_dir_all(dir.join("shared")).unwrap();
+        let target = dir.join("shared").join("multitool.lock.json");
+        fs::write(&target, "original").unwrap();
+        let link = dir.join("multitool.lock.json");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        write(&link, "updated").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "updated");
+        assert_eq!(fs::read_dir(dir.join("shared")).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
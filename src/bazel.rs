@@ -0,0 +1,291 @@
+//! `update --emit-bazel-lock` and `validate`: checks a lockfile as written
+//! against what rules_multitool accepts, so a lockfile multitool itself can read
+//! but Bazel would reject fails here, with the field at fault, rather than as a
+//! confusing error in a downstream build.
+
+use crate::lockfile::PlatformNames;
+use serde_json::Value;
+use std::{collections::BTreeSet, fmt};
+
+/// The fields rules_multitool requires of a binary of `kind`, or `None` for a
+/// kind it doesn't know.
+fn required_fields(kind: &str) -> Option<&'static [&'static str]> {
+    match kind {
+        "file" | "dir" => Some(&["url", "sha256", "os", "cpu"]),
+        "archive" | "pkg" => Some(&["url", "file", "sha256", "os", "cpu"]),
+        _ => None,
+    }
+}
+
+const OSES: [&str; 3] = ["linux", "macos", "windows"];
+
+fn cpus(names: PlatformNames) -> [&'static str; 2] {
+    match names {
+        PlatformNames::Canonical => ["x86_64", "arm64"],
+        PlatformNames::Legacy => ["amd64", "aarch64"],
+    }
+}
+
+/// Something rules_multitool would reject.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Violation {
+    pub tool: String,
+    /// The index of the binary at fault in the tool's `binaries`, unless it's the
+    /// tool itself.
+    pub binary: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.binary {
+            Some(i) => write!(f, "{}: binaries[{i}]: {}", self.tool, self.message),
+            None => write!(f, "{}: {}", self.tool, self.message),
+        }
+    }
+}
+
+/// The violations in `rendered`, a lockfile as multitool writes it with `names`.
+/// A lockfile that isn't a JSON object is reported as a single violation.
+pub fn check(rendered: &str, names: PlatformNames) -> Vec<Violation> {
+    let lockfile: Value = match serde_json::from_str(rendered) {
+        Ok(lockfile) => lockfile,
+        Err(e) => {
+            return vec![Violation {
+                tool: "lockfile".to_owned(),
+                binary: None,
+                message: format!("is not valid JSON: {e}"),
+            }]
+        }
+    };
+    let Some(tools) = lockfile.as_object() else {
+        return vec![Violation {
+            tool: "lockfile".to_owned(),
+            binary: None,
+            message: "must be a JSON object".to_owned(),
+        }];
+    };
+
+    let mut violations = Vec::new();
+    for (tool, definition) in tools {
+        if tool == "$schema" {
+            continue;
+        }
+        let mut violation = |binary, message: String| {
+            violations.push(Violation {
+                tool: tool.clone(),
+                binary,
+                message,
+            })
+        };
+        if tool == "metadata" {
+            violation(
+                None,
+                "is read as a tool by rules_multitool, which treats every key but \"$schema\" as one"
+                    .to_owned(),
+            );
+            continue;
+        }
+        let Some(binaries) = definition["binaries"].as_array() else {
+            violation(None, "\"binaries\" must be a list".to_owned());
+            continue;
+        };
+
+        let mut platforms = BTreeSet::new();
+        for (i, binary) in binaries.iter().enumerate() {
+            let field = |name: &str| binary[name].as_str().filter(|value| !value.is_empty());
+            let Some(kind) = binary["kind"].as_str() else {
+                violation(Some(i), "missing required field \"kind\"".to_owned());
+                continue;
+            };
+            let Some(required) = required_fields(kind) else {
+                violation(Some(i), format!("unknown kind \"{kind}\""));
+                continue;
+            };
+            for name in required {
+                if field(name).is_none() {
+                    violation(
+                        Some(i),
+                        format!("binary of kind \"{kind}\" is missing required field \"{name}\""),
+                    );
+                }
+            }
+
+            if let Some(os) = field("os").filter(|os| !OSES.contains(os)) {
+                violation(
+                    Some(i),
+                    format!("os \"{os}\" is not one of {}", OSES.join(", ")),
+                );
+            }
+            if let Some(cpu) = field("cpu").filter(|cpu| !cpus(names).contains(cpu)) {
+                violation(
+                    Some(i),
+                    format!("cpu \"{cpu}\" is not one of {}", cpus(names).join(", ")),
+                );
+            }
+            if let Some(sha256) = field("sha256") {
+                let hex = |c: char| c.is_ascii_digit() || ('a'..='f').contains(&c);
+                if sha256.len() != 64 || !sha256.chars().all(hex) {
+                    violation(
+                        Some(i),
+                        "sha256 must be 64 lowercase hex characters".to_owned(),
+                    );
+                }
+            }
+
+            if field("sha256_of") == Some("decompressed") {
+                violation(
+                    Some(i),
+                    "sha256_of \"decompressed\" can't be checked by rules_multitool, which hashes the download".to_owned(),
+                );
+            }
+
+            if !binary["parts"].is_null() {
+                violation(
+                    Some(i),
+                    "parts can't be fetched by rules_multitool, which downloads only the url"
+                        .to_owned(),
+                );
+            }
+
+            // rules_multitool has no notion of libc, so binaries that differ only
+            // in it are for the same platform there
+            if let (Some(os), Some(cpu)) = (field("os"), field("cpu")) {
+                if !platforms.insert((os, cpu)) {
+                    violation(Some(i), format!("a second binary for {os}/{cpu}"));
+                }
+            }
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(binaries: &str, names: PlatformNames) -> Vec<String> {
+        check(
+            &format!(r#"{{"tool": {{"binaries": [{binaries}]}}}}"#),
+            names,
+        )
+        .iter()
+        .map(Violation::to_string)
+        .collect()
+    }
+
+    const SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn required_fields_per_kind() {
+        for (kind, file) in [
+            ("file", false),
+            ("archive", true),
+            ("pkg", true),
+            ("dir", false),
+        ] {
+            let complete = format!(
+                r#"{{"kind": "{kind}", "url": "https://example.com/tool.tar.gz", "file": "tool", "sha256": "{SHA256}", "os": "linux", "cpu": "x86_64"}}"#
+            );
+            assert!(
+                messages(&complete, PlatformNames::Canonical).is_empty(),
+                "{kind}"
+            );
+
+            let bare = format!(r#"{{"kind": "{kind}", "file": ""}}"#);
+            let mut expected: Vec<String> = ["url", "sha256", "os", "cpu"]
+                .iter()
+                .map(|name| {
+                    format!("tool: binaries[0]: binary of kind \"{kind}\" is missing required field \"{name}\"")
+                })
+                .collect();
+            if file {
+                expected.insert(
+                    1,
+                    format!("tool: binaries[0]: binary of kind \"{kind}\" is missing required field \"file\""),
+                );
+            }
+            assert_eq!(messages(&bare, PlatformNames::Canonical), expected);
+        }
+
+        assert_eq!(
+            messages(r#"{"kind": "exe"}, {}"#, PlatformNames::Canonical),
+            [
+                "tool: binaries[0]: unknown kind \"exe\"",
+                "tool: binaries[1]: missing required field \"kind\"",
+            ]
+        );
+        assert_eq!(
+            check(
+                r#"{"$schema": "x", "metadata": {}, "tool": {}}"#,
+                PlatformNames::Canonical
+            )
+            .iter()
+            .map(Violation::to_string)
+            .collect::<Vec<_>>(),
+            [
+                "metadata: is read as a tool by rules_multitool, which treats every key but \"$schema\" as one",
+                "tool: \"binaries\" must be a list",
+            ]
+        );
+    }
+
+    #[test]
+    fn platforms_and_digests() {
+        let binary = |os: &str, cpu: &str, sha256: &str| {
+            format!(
+                r#"{{"kind": "file", "url": "https://example.com/tool", "sha256": "{sha256}", "os": "{os}", "cpu": "{cpu}"}}"#
+            )
+        };
+        let binaries = [
+            binary("linux", "amd64", SHA256),
+            binary("freebsd", "x86_64", &SHA256.to_uppercase()),
+            binary("macos", "arm64", "00"),
+            binary("macos", "arm64", SHA256),
+        ]
+        .join(", ");
+        assert_eq!(
+            messages(&binaries, PlatformNames::Canonical),
+            [
+                "tool: binaries[0]: cpu \"amd64\" is not one of x86_64, arm64",
+                "tool: binaries[1]: os \"freebsd\" is not one of linux, macos, windows",
+                "tool: binaries[1]: sha256 must be 64 lowercase hex characters",
+                "tool: binaries[2]: sha256 must be 64 lowercase hex characters",
+                "tool: binaries[3]: a second binary for macos/arm64",
+            ]
+        );
+        assert_eq!(
+            messages(&binary("linux", "x86_64", SHA256), PlatformNames::Legacy),
+            ["tool: binaries[0]: cpu \"x86_64\" is not one of amd64, aarch64"]
+        );
+
+        let decompressed = format!(
+            r#"{{"kind": "archive", "url": "https://example.com/tool.tar.gz", "file": "tool", "sha256": "{SHA256}", "os": "linux", "cpu": "x86_64", "sha256_of": "decompressed"}}"#
+        );
+        assert_eq!(
+            messages(&decompressed, PlatformNames::Canonical),
+            ["tool: binaries[0]: sha256_of \"decompressed\" can't be checked by rules_multitool, which hashes the download"]
+        );
+
+        let split = format!(
+            r#"{{"kind": "file", "url": "https://example.com/tool.part1", "sha256": "{SHA256}", "os": "linux", "cpu": "x86_64", "parts": ["https://example.com/tool.part2"]}}"#
+        );
+        assert_eq!(
+            messages(&split, PlatformNames::Canonical),
+            ["tool: binaries[0]: parts can't be fetched by rules_multitool, which downloads only the url"]
+        );
+
+        let libc = |libc: &str| {
+            format!(
+                r#"{{"kind": "file", "url": "https://example.com/tool-{libc}", "sha256": "{SHA256}", "os": "linux", "cpu": "x86_64", "libc": "{libc}"}}"#
+            )
+        };
+        assert_eq!(
+            messages(
+                &[libc("gnu"), libc("musl")].join(", "),
+                PlatformNames::Canonical
+            ),
+            ["tool: binaries[1]: a second binary for linux/x86_64"]
+        );
+    }
+}
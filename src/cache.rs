@@ -0,0 +1,233 @@
+//! On-disk caches, keyed by URL or source: artifact digests validated with
+//! ETags, and recently resolved latest tags.
+
+use crate::error::Error;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const DIGESTS_FILE: &str = "digests.json";
+pub const TAGS_FILE: &str = "tags.json";
+
+/// Every file multitool writes to a cache directory. `clean` removes only
+/// these, so pointing `--cache-dir` at a shared directory is safe.
+const CACHE_FILES: [&str; 2] = [DIGESTS_FILE, TAGS_FILE];
+
+/// How long a resolved tag is reused, in seconds. Long enough for a `resolve`
+/// to warm the cache for the `update` after it, short enough that new releases
+/// are soon seen.
+pub const TAG_TTL_SECS: u64 = 3600;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedDigest {
+    pub etag: String,
+    pub sha256: String,
+    /// Absent for entries cached before sizes were recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedTag {
+    pub tag: String,
+    /// Seconds since the epoch.
+    pub resolved_at: u64,
+}
+
+/// One cache file's entries. Without a cache directory nothing is remembered
+/// and [`Cache::save`] is a no-op.
+pub struct Cache<T> {
+    path: Option<PathBuf>,
+    entries: BTreeMap<String, T>,
+    dirty: bool,
+}
+
+/// Digests of previously downloaded artifacts, by URL.
+pub type DigestCache = Cache<CachedDigest>;
+
+/// Latest tags, by a key describing the release source and how it's searched.
+pub type TagCache = Cache<CachedTag>;
+
+impl<T> Default for Cache<T> {
+    fn default() -> Cache<T> {
+        Cache {
+            path: None,
+            entries: BTreeMap::new(),
+            dirty: false,
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + PartialEq> Cache<T> {
+    /// Loads the cache file `name` from `dir`. A missing or unreadable file
+    /// starts an empty cache, since it can always be rebuilt.
+    fn load_file(dir: Option<&Path>, name: &str) -> Cache<T> {
+        let Some(dir) = dir else {
+            return Cache::default();
+        };
+        let path = dir.join(name);
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+        Cache {
+            path: Some(path),
+            entries,
+            dirty: false,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&T> {
+        self.entries.get(key)
+    }
+
+    pub fn insert(&mut self, key: &str, entry: T) {
+        if self.path.is_some() && self.entries.get(key) != Some(&entry) {
+            self.entries.insert(key.to_owned(), entry);
+            self.dirty = true;
+        }
+    }
+
+    /// Writes the cache back if anything changed.
+    pub fn save(&self) -> Result<(), Error> {
+        let Some(path) = self.path.as_ref().filter(|_| self.dirty) else {
+            return Ok(());
+        };
+        let write = || -> std::io::Result<()> {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            crate::atomic::write(path, serde_json::to_string_pretty(&self.entries)?)
+        };
+        write().map_err(|e| format!("Unable to write '{}': {e}", path.display()).into())
+    }
+}
+
+impl DigestCache {
+    pub fn load(dir: Option<&Path>) -> DigestCache {
+        Cache::load_file(dir, DIGESTS_FILE)
+    }
+}
+
+impl TagCache {
+    pub fn load(dir: Option<&Path>) -> TagCache {
+        Cache::load_file(dir, TAGS_FILE)
+    }
+
+    /// The tag cached under `key`, if it was resolved within [`TAG_TTL_SECS`]
+    /// of `now`.
+    pub fn fresh(&self, key: &str, now: u64) -> Option<&str> {
+        self.get(key)
+            .filter(|cached| now.saturating_sub(cached.resolved_at) < TAG_TTL_SECS)
+            .map(|cached| cached.tag.as_str())
+    }
+}
+
+/// Removes the cache files in `dir`, and `dir` itself if that leaves it empty,
+/// returning how many bytes were reclaimed. A missing directory is already clean.
+pub fn clean(dir: &Path) -> Result<u64, Error> {
+    let mut reclaimed = 0;
+    for name in CACHE_FILES {
+        let path = dir.join(name);
+        let remove = || -> std::io::Result<u64> {
+            let size = fs::metadata(&path)?.len();
+            fs::remove_file(&path)?;
+            Ok(size)
+        };
+        match remove() {
+            Ok(size) => reclaimed += size,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(format!("Unable to remove '{}': {e}", path.display()).into()),
+        }
+    }
+    // fails unless empty, which leaves anything else in the directory alone
+    let _ = fs::remove_dir(dir);
+    Ok(reclaimed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(etag: &str) -> CachedDigest {
+        CachedDigest {
+            etag: etag.to_owned(),
+            sha256: "00".to_owned(),
+            size: Some(5),
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = std::env::temp_dir().join(format!("multitool-cache-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+
+        let mut cache = DigestCache::load(Some(&dir));
+        assert_eq!(cache.get("https://example.com/a"), None);
+        cache.insert("https://example.com/a", digest("\"abc\""));
+        cache.save().unwrap();
+
+        let cache = DigestCache::load(Some(&dir));
+        assert_eq!(cache.get("https://example.com/a"), Some(&digest("\"abc\"")));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn clean_removes_entries() {
+        let dir = std::env::temp_dir().join(format!("multitool-clean-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        assert_eq!(clean(&dir).unwrap(), 0);
+
+        let mut cache = DigestCache::load(Some(&dir));
+        cache.insert("https://example.com/a", digest("\"abc\""));
+        cache.save().unwrap();
+        let size = fs::metadata(dir.join(DIGESTS_FILE)).unwrap().len();
+        assert_eq!(clean(&dir).unwrap(), size);
+        assert!(!dir.exists());
+        assert_eq!(
+            DigestCache::load(Some(&dir)).get("https://example.com/a"),
+            None
+        );
+
+        // files multitool didn't write are kept, with their directory
+        cache.insert("https://example.com/b", digest("\"def\""));
+        cache.save().unwrap();
+        fs::write(dir.join("other"), "keep").unwrap();
+        assert!(clean(&dir).unwrap() > 0);
+        assert!(!dir.join(DIGESTS_FILE).exists());
+        assert_eq!(fs::read_to_string(dir.join("other")).unwrap(), "keep");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tags_expire() {
+        let dir = std::env::temp_dir().join(format!("multitool-tags-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut cache = TagCache::load(Some(&dir));
+        let tag = CachedTag {
+            tag: "v1.2.3".to_owned(),
+            resolved_at: 1000,
+        };
+        cache.insert("github org/tool", tag);
+        cache.save().unwrap();
+
+        let cache = TagCache::load(Some(&dir));
+        assert_eq!(cache.fresh("github org/tool", 1000), Some("v1.2.3"));
+        assert_eq!(cache.fresh("github org/tool", 1000 + TAG_TTL_SECS), None);
+        assert_eq!(cache.fresh("github org/other", 1000), None);
+        assert!(clean(&dir).unwrap() > 0);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn without_directory_nothing_is_cached() {
+        let mut cache = DigestCache::load(None);
+        cache.insert("https://example.com/a", digest("\"abc\""));
+        assert_eq!(cache.get("https://example.com/a"), None);
+        cache.save().unwrap();
+    }
+}
@@ -0,0 +1,271 @@
+use crate::{
+    color::Palette,
+    config::Settings,
+    exit::ExitCode,
+    explain::{self, Decision, Trace},
+    filter::ToolFilter,
+    lockfile::{Lockfile, SupportedCpu, SupportedOs},
+    resolve::{ReleaseSource, Resolver},
+    Common,
+};
+use serde::Serialize;
+use std::fmt::Write;
+
+/// A binary whose pinned version is behind the latest upstream release.
+#[derive(Debug, Serialize)]
+pub struct Outdated {
+    pub tool: String,
+    pub os: SupportedOs,
+    pub cpu: SupportedCpu,
+    pub version: String,
+    pub latest: String,
+}
+
+pub struct CheckResult {
+    pub outdated: Vec<Outdated>,
+    pub errors: usize,
+}
+
+impl CheckResult {
+    pub fn is_ok(&self) -> bool {
+        self.outdated.is_empty() && self.errors == 0
+    }
+
+    /// Failed lookups take precedence over outdated binaries, since they leave
+    /// the result incomplete.
+    pub fn exit_code(&self) -> ExitCode {
+        if self.errors > 0 {
+            ExitCode::NetworkError
+        } else if !self.outdated.is_empty() {
+            ExitCode::UpdatesAvailable
+        } else {
+            ExitCode::Success
+        }
+    }
+}
+
+pub fn check(lockfile: &Lockfile, settings: &Settings, filter: &ToolFilter) -> CheckResult {
+    check_with(&mut Resolver::new(settings), lockfile, filter)
+}
+
+/// Like [`check`], looking releases up through an existing `resolver`.
+pub fn check_with(
+    resolver: &mut Resolver,
+    lockfile: &Lockfile,
+    filter: &ToolFilter,
+) -> CheckResult {
+    let settings = resolver.settings;
+    resolver.prefetch(lockfile, filter);
+    let mut result = CheckResult {
+        outdated: Vec::new(),
+        errors: 0,
+    };
+
+    for (tool, definition) in &lockfile.tools {
+        if !filter.selects(tool, definition) {
+            if filter.matches(tool) {
+                explain::pinned(settings, tool, definition);
+            }
+            continue;
+        }
+
+        for binary in &definition.binaries {
+            if !filter.selects_binary(tool, binary) {
+                continue;
+            }
+            let trace = |selection, versions, decision| Trace {
+                tool,
+                binary,
+                selection,
+                versions,
+                decision,
+            };
+            let Some(source) = ReleaseSource::of(&definition.source, binary) else {
+                trace(None, None, Decision::SkippedNoSource).emit(settings);
+                continue;
+            };
+
+            let versions = source.current_version(binary).and_then(|version| {
+                let latest = resolver.resolve_latest(&source)?;
+                let names = resolver.release_names(&source, &version, &latest)?;
+                let (pinned, newest) = names.unwrap_or_else(|| (version.clone(), latest.clone()));
+                // as in an update, a latest release published before the pinned
+                // one isn't taken with --only-if-newer-published
+                let backdated = pinned != newest
+                    && matches!(
+                        resolver.published_order(&source, &version, &latest)?,
+                        Some((false, ..))
+                    );
+                Ok((pinned, newest, backdated))
+            });
+            let selection = || Some(explain::selection(&source));
+            match versions {
+                Ok((version, latest, true)) => {
+                    trace(selection(), Some((&version, &latest)), Decision::NotNewer).emit(settings)
+                }
+                Ok((version, latest, false)) if version != latest => {
+                    trace(selection(), Some((&version, &latest)), Decision::Outdated)
+                        .emit(settings);
+                    result.outdated.push(Outdated {
+                        tool: tool.clone(),
+                        os: binary.os().clone(),
+                        cpu: binary.cpu().clone(),
+                        version: version.strip_prefix('v').unwrap_or(&version).to_owned(),
+                        latest: latest.strip_prefix('v').unwrap_or(&latest).to_owned(),
+                    })
+                }
+                Ok((version, latest, false)) => {
+                    trace(selection(), Some((&version, &latest)), Decision::UpToDate).emit(settings)
+                }
+                Err(e) => {
+                    trace(selection(), None, Decision::Failed(e.to_string())).emit(settings);
+                    eprintln!(
+                        "{} while attempting to check {tool} ({}/{}): {e}",
+                        settings.stderr_palette.red("Encountered error"),
+                        binary.os(),
+                        binary.cpu()
+                    );
+                    result.errors += 1;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Renders outdated binaries grouped by tool; empty when everything is current.
+pub fn render_text(outdated: &[Outdated], palette: Palette) -> String {
+    let mut out = String::new();
+    let mut current_tool = None;
+    for entry in outdated {
+        if current_tool != Some(&entry.tool) {
+            writeln!(out, "{}", palette.bold(&entry.tool)).unwrap();
+            current_tool = Some(&entry.tool);
+        }
+        writeln!(
+            out,
+            "  {}/{}: {} -> {}",
+            entry.os,
+            entry.cpu,
+            palette.yellow(&entry.version),
+            palette.green(&entry.latest)
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{settings, Response, Server};
+
+    fn lockfile() -> Lockfile {
+        serde_json::from_str(
+            r#"{
+              "current": {
+                "binaries": [
+                  {
+                    "kind": "file",
+                    "url": "https://github.com/org/current/releases/download/v1.0.0/current-linux",
+                    "sha256": "0000000000000000000000000000000000000000000000000000000000000000",
+                    "os": "linux",
+                    "cpu": "x86_64"
+                  }
+                ]
+              },
+              "stale": {
+                "binaries": [
+                  {
+                    "kind": "file",
+                    "url": "https://github.com/org/stale/releases/download/v1.0.0/stale-linux",
+                    "sha256": "0000000000000000000000000000000000000000000000000000000000000000",
+                    "os": "linux",
+                    "cpu": "x86_64"
+                  },
+                  {
+                    "kind": "file",
+                    "url": "https://github.com/org/stale/releases/download/v1.0.0/stale-macos",
+                    "sha256": "0000000000000000000000000000000000000000000000000000000000000000",
+                    "os": "macos",
+                    "cpu": "arm64"
+                  }
+                ]
+              }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn github() -> Server {
+        Server::start(|request| match request.path.as_str() {
+            "/repos/org/current/releases/latest" => Response::ok(r#"{"tag_name": "v1.0.0"}"#),
+            "/repos/org/stale/releases/latest" => Response::ok(r#"{"tag_name": "v1.1.0"}"#),
+            _ => Response::status(404, "{}"),
+        })
+    }
+
+    #[test]
+    fn check_reports_only_outdated_binaries() {
+        let server = github();
+        let result = check(&lockfile(), &settings(&server.url), &ToolFilter::default());
+        assert!(!result.is_ok());
+        assert_eq!(result.errors, 0);
+        assert_eq!(result.exit_code(), ExitCode::UpdatesAvailable);
+        assert_eq!(
+            render_text(&result.outdated, Palette::default()),
+            "stale\n  linux/x86_64: 1.0.0 -> 1.1.0\n  macos/arm64: 1.0.0 -> 1.1.0\n"
+        );
+        // one lookup per repo
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn check_skips_releases_published_before_the_pinned_one() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/current/releases/latest" => Response::ok(r#"{"tag_name": "v1.0.0"}"#),
+            "/repos/org/stale/releases/latest" => Response::ok(r#"{"tag_name": "v1.1.0"}"#),
+            "/repos/org/stale/releases/tags/v1.0.0" => {
+                Response::ok(r#"{"published_at": "2024-03-04T00:00:00Z"}"#)
+            }
+            "/repos/org/stale/releases/tags/v1.1.0" => {
+                Response::ok(r#"{"published_at": "2024-01-02T00:00:00Z"}"#)
+            }
+            _ => Response::status(404, "{}"),
+        });
+        let guarded = Settings {
+            only_if_newer_published: true,
+            ..settings(&server.url)
+        };
+        let result = check(&lockfile(), &guarded, &ToolFilter::default());
+        assert!(result.is_ok());
+
+        let result = check(&lockfile(), &settings(&server.url), &ToolFilter::default());
+        assert_eq!(result.outdated.len(), 2);
+    }
+
+    #[test]
+    fn check_up_to_date_tool_produces_no_output() {
+        let server = github();
+        let result = check(
+            &lockfile(),
+            &settings(&server.url),
+            &ToolFilter {
+                tool: Some("current".to_owned()),
+                ..Default::default()
+            },
+        );
+        assert!(result.is_ok());
+        assert_eq!(result.exit_code(), ExitCode::Success);
+        assert_eq!(render_text(&result.outdated, Palette::default()), "");
+    }
+
+    #[test]
+    fn check_lookup_failures_exit_with_network_error() {
+        let server = Server::start(|_| Response::status(500, "{}"));
+        let result = check(&lockfile(), &settings(&server.url), &ToolFilter::default());
+        assert!(result.errors > 0);
+        assert_eq!(result.exit_code(), ExitCode::NetworkError);
+    }
+}
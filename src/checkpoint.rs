@@ -0,0 +1,155 @@
+//! `update --checkpoint`: the tools a long update has already finished, saved
+//! as it goes so an interrupted run can pick up where it stopped.
+//!
+//! The checkpoint sits next to the lockfile as `<lockfile>.checkpoint`. It's a
+//! JSON object holding the sha256 of the lockfile it was started from and the
+//! updated definition of every tool finished so far:
+//!
+//! ```json
+//! {"lockfile_sha256": "…", "tools": {"tool-name": {"binaries": […]}}}
+//! ```
+//!
+//! It only applies while the lockfile is unchanged, and is removed once an
+//! update completes.
+
+use crate::{atomic, hash, lockfile::ToolDefinition, warn::warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Serialize, Deserialize)]
+struct Contents {
+    lockfile_sha256: String,
+    tools: BTreeMap<String, Value>,
+}
+
+pub struct Checkpoint {
+    path: PathBuf,
+    contents: Contents,
+}
+
+/// Where the checkpoint of an update of `lockfile` is kept.
+pub fn path_for(lockfile: &Path) -> PathBuf {
+    let mut name = lockfile.file_name().unwrap_or_default().to_owned();
+    name.push(".checkpoint");
+    lockfile.with_file_name(name)
+}
+
+impl Checkpoint {
+    /// The checkpoint of an update of the lockfile at `lockfile`, whose
+    /// contents are `original`. A checkpoint left from another version of the
+    /// lockfile is ignored with a warning, and one that can't be read is
+    /// reported and ignored.
+    pub fn open(lockfile: &Path, original: &[u8]) -> Checkpoint {
+        let path = path_for(lockfile);
+        let lockfile_sha256 = hash::sha256_hex(original);
+        let fresh = Contents {
+            lockfile_sha256: lockfile_sha256.clone(),
+            tools: BTreeMap::new(),
+        };
+        let contents = match fs::read_to_string(&path) {
+            Err(_) => fresh,
+            Ok(text) => match serde_json::from_str::<Contents>(&text) {
+                Ok(contents) if contents.lockfile_sha256 == lockfile_sha256 => contents,
+                Ok(_) => {
+                    warn(format!(
+                        "ignoring checkpoint {}: the lockfile changed since it was written",
+                        path.display()
+                    ));
+                    fresh
+                }
+                Err(e) => {
+                    warn(format!(
+                        "ignoring unreadable checkpoint {}: {e}",
+                        path.display()
+                    ));
+                    fresh
+                }
+            },
+        };
+        Checkpoint { path, contents }
+    }
+
+    /// The tools finished by an earlier, interrupted run, as they were updated.
+    pub fn finished(&self) -> Result<BTreeMap<String, ToolDefinition>, String> {
+        self.contents
+            .tools
+            .iter()
+            .map(|(tool, definition)| {
+                let definition = serde_json::from_value(definition.clone())
+                    .map_err(|e| format!("checkpoint {}: {tool}: {e}", self.path.display()))?;
+                Ok((tool.clone(), definition))
+            })
+            .collect()
+    }
+
+    /// Records `tool` as finished with `definition`, rewriting the checkpoint.
+    /// An interruption never leaves a partial file.
+    pub fn record(&mut self, tool: &str, definition: &ToolDefinition) {
+        let definition = serde_json::to_value(definition).unwrap();
+        self.contents.tools.insert(tool.to_owned(), definition);
+        let written = atomic::write(&self.path, serde_json::to_string(&self.contents).unwrap());
+        if let Err(e) = written {
+            warn(format!(
+                "unable to write checkpoint {}: {e}",
+                self.path.display()
+            ));
+        }
+    }
+
+    /// Removes the checkpoint of a completed update.
+    pub fn finish(self) {
+        if self.path.exists() {
+            if let Err(e) = fs::remove_file(&self.path) {
+                warn(format!(
+                    "unable to remove checkpoint {}: {e}",
+                    self.path.display()
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Common;
+
+    #[test]
+    fn checkpoints_apply_to_their_lockfile_only() {
+        let dir = std::env::temp_dir().join(format!("multitool-checkpoint-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let lockfile = dir.join("multitool.lock.json");
+        assert_eq!(
+            path_for(&lockfile),
+            dir.join("multitool.lock.json.checkpoint")
+        );
+
+        let definition: ToolDefinition = serde_json::from_str(
+            r#"{"binaries": [{"kind": "file", "url": "https://example.com/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}]}"#,
+        )
+        .unwrap();
+        let mut checkpoint = Checkpoint::open(&lockfile, b"original");
+        assert!(checkpoint.finished().unwrap().is_empty());
+        checkpoint.record("tool", &definition);
+
+        let resumed = Checkpoint::open(&lockfile, b"original").finished().unwrap();
+        assert_eq!(
+            resumed["tool"].binaries[0].url(),
+            "https://example.com/tool"
+        );
+        // a checkpoint of another version of the lockfile isn't applied
+        assert!(Checkpoint::open(&lockfile, b"edited")
+            .finished()
+            .unwrap()
+            .is_empty());
+
+        Checkpoint::open(&lockfile, b"original").finish();
+        assert!(!path_for(&lockfile).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+use clap::ValueEnum;
+use std::fmt::Display;
+
+/// When to color human-readable output.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// Color only a stream that is a terminal, and only while `NO_COLOR` is unset.
+    #[default]
+    Auto,
+    /// Always color, even when piped.
+    Always,
+    /// Never color.
+    Never,
+}
+
+impl ColorChoice {
+    /// Resolves the choice for a stream against the environment. Per
+    /// <https://no-color.org> an empty `NO_COLOR` doesn't count as set.
+    pub fn palette(self, no_color: Option<&str>, is_terminal: bool) -> Palette {
+        let enabled = match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                is_terminal && !matches!(no_color, Some(value) if !value.is_empty())
+            }
+        };
+        Palette { enabled }
+    }
+}
+
+/// Wraps text in ANSI styles, or passes it through unchanged when color is off.
+/// The default palette is uncolored.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Palette {
+    enabled: bool,
+}
+
+impl Palette {
+    fn paint(self, code: &str, text: impl Display) -> String {
+        if self.enabled {
+            format!("\x1b[{code}m{text}\x1b[0m")
+        } else {
+            text.to_string()
+        }
+    }
+
+    pub fn bold(self, text: impl Display) -> String {
+        self.paint("1", text)
+    }
+
+    pub fn green(self, text: impl Display) -> String {
+        self.paint("32", text)
+    }
+
+    pub fn yellow(self, text: impl Display) -> String {
+        self.paint("33", text)
+    }
+
+    pub fn red(self, text: impl Display) -> String {
+        self.paint("31", text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_respects_terminal_and_no_color() {
+        let red = |palette: Palette| palette.red("x") != "x";
+        assert!(red(ColorChoice::Auto.palette(None, true)));
+        assert!(red(ColorChoice::Auto.palette(Some(""), true)));
+        assert!(!red(ColorChoice::Auto.palette(Some("1"), true)));
+        assert!(!red(ColorChoice::Auto.palette(None, false)));
+
+        assert!(red(ColorChoice::Always.palette(Some("1"), false)));
+        assert!(!red(ColorChoice::Never.palette(None, true)));
+    }
+
+    #[test]
+    fn paint_wraps_in_ansi_codes() {
+        let palette = ColorChoice::Always.palette(None, false);
+        assert_eq!(palette.green("1.2.3"), "\x1b[32m1.2.3\x1b[0m");
+        assert_eq!(Palette::default().green("1.2.3"), "1.2.3");
+    }
+}
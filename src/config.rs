@@ -0,0 +1,460 @@
+use crate::asset::AssetSelection;
+use crate::color::Palette;
+use crate::error::Error;
+use crate::github_app::GitHubApp;
+use crate::lockfile::{url_host, PlatformNames, ToolOrder};
+use crate::warn::warn;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+pub const CONFIG_FILE_NAME: &str = "multitool.toml";
+
+pub const DEFAULT_GITHUB_API_URL: &str = "https://api.github.com";
+
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// The longest `--max-retry-wait-secs` waits before retrying a throttled request.
+pub const DEFAULT_MAX_RETRY_WAIT: Duration = Duration::from_secs(60);
+
+/// GitHub's maximum `per_page` for list endpoints.
+pub const DEFAULT_API_PAGE_SIZE: u32 = 100;
+
+/// Stop scanning a repo's releases after this many pages rather than walking
+/// its entire history.
+pub const MAX_API_PAGES: usize = 10;
+
+/// Defaults for command-line options, read from `multitool.toml`.
+///
+/// Values here only apply when the corresponding flag (or its environment
+/// variable) is unset. Tokens are deliberately not configurable here so they
+/// are never committed alongside the lockfile; `tokens` only names the
+/// environment variable holding each host's token.
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    pub github_api_url: Option<String>,
+    pub timeout_secs: Option<u64>,
+    pub max_redirects: Option<usize>,
+    pub max_retry_wait_secs: Option<u64>,
+    /// Host to environment variable, like repeated `--token host=ENV_VAR`.
+    #[serde(default)]
+    pub tokens: BTreeMap<String, String>,
+}
+
+impl Config {
+    pub fn parse(contents: &str) -> Result<Config, Error> {
+        toml::from_str(contents).map_err(|e| Error::Message(e.to_string()))
+    }
+
+    /// Loads the config at `path` if given, otherwise the nearest `multitool.toml`
+    /// in the lockfile's directory or any of its ancestors.
+    pub fn load(path: Option<&Path>, lockfile: &Path) -> Result<Config, Error> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match discover(lockfile) {
+                Some(path) => path,
+                None => return Ok(Config::default()),
+            },
+        };
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| format!("Unable to read config '{}': {e}", path.display()))?;
+        Config::parse(&contents)
+            .map_err(|e| format!("Invalid config '{}': {e}", path.display()).into())
+    }
+}
+
+/// A token for one host, read from an environment variable.
+pub struct HostToken {
+    /// The environment variable it was read from, for diagnostics.
+    pub source: String,
+    pub token: String,
+}
+
+/// Reads the token for each `host = ENV_VAR` mapping, keyed by lowercased host.
+/// A mapping whose variable is unset or empty is skipped with a warning.
+pub fn host_tokens(
+    mappings: impl IntoIterator<Item = (String, String)>,
+    env: impl Fn(&str) -> Option<String>,
+) -> BTreeMap<String, HostToken> {
+    let mut tokens = BTreeMap::new();
+    for (host, source) in mappings {
+        match env(&source).filter(|token| !token.is_empty()) {
+            Some(token) => {
+                tokens.insert(host.to_ascii_lowercase(), HostToken { source, token });
+            }
+            None => warn(format!("no token for {host}: {source} is not set")),
+        }
+    }
+    tokens
+}
+
+/// Effective settings after layering flags, environment variables and the config file.
+pub struct Settings {
+    pub github_api_url: String,
+    pub github_token: Option<String>,
+    /// Where the token came from, for diagnostics; never the token itself.
+    pub github_token_source: Option<&'static str>,
+    /// Per-host tokens from `--token` and the config's `tokens`, sent to matching
+    /// hosts only.
+    pub host_tokens: BTreeMap<String, HostToken>,
+    pub github_app: Option<GitHubApp>,
+    pub timeout: Option<Duration>,
+    pub timeout_per_tool: Option<Duration>,
+    pub max_redirects: usize,
+    /// The longest wait before retrying a 429 or 503, whatever its `Retry-After`.
+    pub max_retry_wait: Duration,
+    /// Extra PEM root certificates to trust alongside the system's.
+    pub ca_bundle: Option<PathBuf>,
+    pub danger_accept_invalid_certs: bool,
+    pub from_dir: Option<PathBuf>,
+    pub max_download_size: Option<u64>,
+    pub cache_dir: Option<PathBuf>,
+    pub require_https: bool,
+    pub allow_insecure_http: bool,
+    pub retry_on_checksum_mismatch: bool,
+    pub api_page_size: u32,
+    pub github_graphql: bool,
+    pub select_asset_by: Option<AssetSelection>,
+    pub prefer_api_assets: bool,
+    pub verify_assets: bool,
+    pub verify_signatures: bool,
+    pub only_if_newer_published: bool,
+    pub explain: bool,
+    pub sort_tools: ToolOrder,
+    pub platform_names: PlatformNames,
+    /// Colors for stdout.
+    pub palette: Palette,
+    /// Colors for stderr, chosen separately since either stream may be piped
+    /// while the other is a terminal.
+    pub stderr_palette: Palette,
+    pub verbose: bool,
+}
+
+impl Settings {
+    /// The `--token` for `url`'s host, if one was given.
+    pub fn token_for(&self, url: &str) -> Option<&str> {
+        let host = url_host(url)?.to_ascii_lowercase();
+        self.host_tokens
+            .get(&host)
+            .map(|entry| entry.token.as_str())
+    }
+
+    /// Builds the HTTP client used for all requests.
+    ///
+    /// Redirects are followed up to `max_redirects` hops. When a redirect crosses
+    /// to a different origin (scheme, host or port) the `Authorization`, `Cookie`
+    /// and `Proxy-Authorization` headers are dropped so credentials for GitHub
+    /// are never sent to e.g. a signed S3 URL; other headers are forwarded.
+    ///
+    /// Panics if the CA bundle can't be used; `main` checks it up front with
+    /// [`Settings::try_client`].
+    pub fn client(&self) -> reqwest::blocking::Client {
+        self.try_client().unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Builds the client, failing if `ca_bundle` can't be read or holds no usable
+    /// certificates.
+    pub fn try_client(&self) -> Result<reqwest::blocking::Client, Error> {
+        let max_redirects = self.max_redirects;
+        let redirect = reqwest::redirect::Policy::custom(move |attempt| {
+            // `previous` includes the original request, so it holds one entry per hop taken
+            if attempt.previous().len() > max_redirects {
+                attempt.error(format!("exceeded {max_redirects} redirects"))
+            } else {
+                attempt.follow()
+            }
+        });
+        let mut builder = reqwest::blocking::Client::builder()
+            .user_agent("multitool")
+            .redirect(redirect);
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(path) = &self.ca_bundle {
+            let invalid = |e: &dyn std::fmt::Display| {
+                Error::Message(format!("Invalid CA bundle '{}': {e}", path.display()))
+            };
+            let pem = fs::read(path).map_err(|e| invalid(&e))?;
+            let certificates =
+                reqwest::Certificate::from_pem_bundle(&pem).map_err(|e| invalid(&e))?;
+            if certificates.is_empty() {
+                return Err(invalid(&"no PEM certificates found"));
+            }
+            for certificate in certificates {
+                builder = builder.add_root_certificate(certificate);
+            }
+        }
+        if self.danger_accept_invalid_certs {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        builder.build().map_err(|e| Error::Message(e.to_string()))
+    }
+}
+
+/// The nearest `multitool.toml` in the lockfile's directory or any of its ancestors.
+pub fn discover(lockfile: &Path) -> Option<PathBuf> {
+    let lockfile = lockfile.canonicalize().ok()?;
+    lockfile
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|candidate| candidate.is_file())
+}
+
+impl Settings {
+    /// The effective settings as `name: value` lines, for `print-config`. The
+    /// token is only identified by where it came from, never shown.
+    pub fn render(&self, config: Option<&Path>, proxy: &str) -> String {
+        let or_none = |value: Option<String>| value.unwrap_or_else(|| "none".to_owned());
+        let secs = |duration: Option<Duration>| duration.map(|d| format!("{}s", d.as_secs()));
+        let path = |path: Option<&Path>| path.map(|path| path.display().to_string());
+        let token = match (&self.github_token, self.github_token_source) {
+            (Some(_), Some(source)) => format!("set via {source} (value redacted)"),
+            (Some(_), None) => "set (value redacted)".to_owned(),
+            (None, _) => "none".to_owned(),
+        };
+        let host_tokens = if self.host_tokens.is_empty() {
+            "none".to_owned()
+        } else {
+            let hosts: Vec<String> = self
+                .host_tokens
+                .iter()
+                .map(|(host, entry)| format!("{host} via {} (value redacted)", entry.source))
+                .collect();
+            hosts.join(", ")
+        };
+        let lines = [
+            ("config", or_none(path(config))),
+            ("github-api-url", self.github_api_url.clone()),
+            ("github-token", token),
+            ("tokens", host_tokens),
+            (
+                "github-app",
+                or_none(
+                    self.github_app
+                        .as_ref()
+                        .map(|app| match app.installation_id {
+                            Some(id) => format!("{} (installation {id})", app.app_id),
+                            None => format!("{} (its only installation)", app.app_id),
+                        }),
+                ),
+            ),
+            ("timeout", or_none(secs(self.timeout))),
+            ("timeout-per-tool", or_none(secs(self.timeout_per_tool))),
+            ("max-redirects", self.max_redirects.to_string()),
+            (
+                "max-retry-wait",
+                format!("{}s", self.max_retry_wait.as_secs()),
+            ),
+            ("proxy", proxy.to_owned()),
+            ("ca-bundle", or_none(path(self.ca_bundle.as_deref()))),
+            (
+                "danger-accept-invalid-certs",
+                self.danger_accept_invalid_certs.to_string(),
+            ),
+            ("cache-dir", or_none(path(self.cache_dir.as_deref()))),
+            (
+                "retry-on-checksum-mismatch",
+                self.retry_on_checksum_mismatch.to_string(),
+            ),
+            ("from-dir", or_none(path(self.from_dir.as_deref()))),
+            (
+                "max-download-size",
+                self.max_download_size
+                    .map_or_else(|| "unlimited".to_owned(), |size| format!("{size} bytes")),
+            ),
+            ("api-page-size", self.api_page_size.to_string()),
+            ("github-graphql", self.github_graphql.to_string()),
+        ];
+        lines
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}\n"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{settings, Response, Server};
+
+    #[test]
+    fn parse_empty_config() {
+        assert_eq!(Config::parse("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn parse_config() {
+        let config = Config::parse(
+            r#"
+            github-api-url = "https://github.example.com/api/v3"
+            timeout-secs = 60
+            max-redirects = 3
+            max-retry-wait-secs = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.github_api_url.as_deref(),
+            Some("https://github.example.com/api/v3")
+        );
+        assert_eq!(config.timeout_secs, Some(60));
+        assert_eq!(config.max_redirects, Some(3));
+        assert_eq!(config.max_retry_wait_secs, Some(5));
+    }
+
+    #[test]
+    fn host_tokens_by_host() {
+        let config = Config::parse(
+            r#"
+            [tokens]
+            "GHE.example.com" = "GHES_TOKEN"
+            "gitlab.com" = "GITLAB_TOKEN"
+            "#,
+        )
+        .unwrap();
+        let settings = Settings {
+            host_tokens: host_tokens(config.tokens, |name| {
+                (name == "GHES_TOKEN").then(|| "s3cret".to_owned())
+            }),
+            ..settings(DEFAULT_GITHUB_API_URL)
+        };
+        assert_eq!(
+            settings.token_for("https://ghe.example.com/api/v3/repos"),
+            Some("s3cret")
+        );
+        assert_eq!(settings.token_for("https://gitlab.com/api/v4"), None);
+        assert_eq!(settings.token_for("https://api.github.com"), None);
+
+        let rendered = settings.render(None, "none");
+        assert!(rendered.contains("tokens: ghe.example.com via GHES_TOKEN (value redacted)\n"));
+        assert!(!rendered.contains("s3cret"));
+    }
+
+    #[test]
+    fn parse_config_rejects_unknown_keys() {
+        assert!(Config::parse("github-token = \"secret\"").is_err());
+    }
+
+    #[test]
+    fn render_redacts_token() {
+        let settings = Settings {
+            github_token: Some("s3cret".to_owned()),
+            github_token_source: Some("GITHUB_TOKEN"),
+            cache_dir: Some(PathBuf::from("/tmp/cache")),
+            ..settings(DEFAULT_GITHUB_API_URL)
+        };
+        let rendered = settings.render(Some(Path::new("multitool.toml")), "none");
+        assert!(!rendered.contains("s3cret"));
+        assert!(rendered.starts_with(
+            "config: multitool.toml\n\
+             github-api-url: https://api.github.com\n\
+             github-token: set via GITHUB_TOKEN (value redacted)\n\
+             tokens: none\n\
+             github-app: none\n\
+             timeout: 5s\n\
+             timeout-per-tool: none\n"
+        ));
+        assert!(rendered.contains("cache-dir: /tmp/cache\n"));
+        assert!(rendered.contains("max-download-size: unlimited\n"));
+    }
+
+    #[test]
+    fn discover_config_in_ancestor() {
+        let root = std::env::temp_dir().join(format!("multitool-config-{}", std::process::id()));
+        let nested = root.join("a").join("b");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(root.join(CONFIG_FILE_NAME), "timeout-secs = 5\n").unwrap();
+        let lockfile = nested.join("multitool.lock.json");
+        fs::write(&lockfile, "{}").unwrap();
+
+        let config = Config::load(None, &lockfile).unwrap();
+        assert_eq!(config.timeout_secs, Some(5));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn client_follows_redirect_chain_without_leaking_authorization() {
+        let cdn = Server::start(|_| Response::ok("asset"));
+        let cdn_url = format!("{}/signed/asset", cdn.url);
+        let origin = Server::start(move |request| match request.path.as_str() {
+            "/first" => Response::redirect("/second"),
+            "/second" => Response::redirect(&cdn_url),
+            _ => Response::status(404, ""),
+        });
+
+        let body = settings(DEFAULT_GITHUB_API_URL)
+            .client()
+            .get(format!("{}/first", origin.url))
+            .bearer_auth("secret")
+            .header("X-Custom", "kept")
+            .send()
+            .unwrap()
+            .error_for_status()
+            .unwrap()
+            .text()
+            .unwrap();
+        assert_eq!(body, "asset");
+
+        // same-origin hops keep the credentials
+        let origin_requests = origin.requests();
+        assert_eq!(origin_requests.len(), 2);
+        assert!(origin_requests
+            .iter()
+            .all(|r| r.headers.get("authorization").map(String::as_str) == Some("Bearer secret")));
+
+        // the cross-origin hop drops them but keeps other headers
+        let cdn_requests = cdn.requests();
+        assert_eq!(cdn_requests.len(), 1);
+        assert!(!cdn_requests[0].headers.contains_key("authorization"));
+        assert_eq!(cdn_requests[0].headers["x-custom"], "kept");
+    }
+
+    #[test]
+    fn client_stops_after_max_redirects() {
+        let server = Server::start(|request| {
+            let hop: usize = request.path.trim_start_matches('/').parse().unwrap();
+            Response::redirect(&format!("/{}", hop + 1))
+        });
+
+        let error = Settings {
+            max_redirects: 2,
+            ..settings(DEFAULT_GITHUB_API_URL)
+        }
+        .client()
+        .get(format!("{}/0", server.url))
+        .send()
+        .unwrap_err();
+        assert!(error.is_redirect());
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn try_client_rejects_unusable_ca_bundle() {
+        let path = std::env::temp_dir().join(format!("multitool-ca-{}.pem", std::process::id()));
+        let with_bundle = Settings {
+            ca_bundle: Some(path.clone()),
+            ..settings(DEFAULT_GITHUB_API_URL)
+        };
+        let error = with_bundle.try_client().err().unwrap().to_string();
+        assert!(error.starts_with(&format!("Invalid CA bundle '{}': ", path.display())));
+
+        fs::write(&path, "not a certificate").unwrap();
+        let error = with_bundle.try_client().err().unwrap().to_string();
+        assert!(error.ends_with("no PEM certificates found"));
+        fs::remove_file(&path).unwrap();
+
+        assert!(Settings {
+            danger_accept_invalid_certs: true,
+            ..settings(DEFAULT_GITHUB_API_URL)
+        }
+        .try_client()
+        .is_ok());
+    }
+}
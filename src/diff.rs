@@ -0,0 +1,229 @@
+//! Line-based unified diffs, for previewing lockfile changes.
+
+use crate::error::Error;
+use std::{fmt::Write, path::Path, process::Command};
+
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Keep,
+    Delete,
+    Insert,
+}
+
+/// Renders a unified diff from `old` to `new`, labelled with `path`; empty when
+/// they are identical.
+pub fn unified(path: &str, old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let ops = edit_script(&old, &new);
+    if ops.iter().all(|op| *op == Op::Keep) {
+        return String::new();
+    }
+
+    let mut out = format!("--- {path}\n+++ {path}\n");
+    // (old line, new line) at the start of each op
+    let mut positions = Vec::with_capacity(ops.len());
+    let (mut o, mut n) = (0, 0);
+    for op in &ops {
+        positions.push((o, n));
+        match op {
+            Op::Keep => (o, n) = (o + 1, n + 1),
+            Op::Delete => o += 1,
+            Op::Insert => n += 1,
+        }
+    }
+
+    let mut i = 0;
+    while i < ops.len() {
+        if ops[i] == Op::Keep {
+            i += 1;
+            continue;
+        }
+        // extend the hunk while changes are within 2 * CONTEXT kept lines of each other
+        let start = i.saturating_sub(CONTEXT);
+        let mut end = i;
+        let mut kept = 0;
+        while end < ops.len() && kept <= 2 * CONTEXT {
+            kept = if ops[end] == Op::Keep { kept + 1 } else { 0 };
+            end += 1;
+        }
+        let end = (end - kept + CONTEXT.min(kept)).min(ops.len());
+
+        let (old_start, new_start) = positions[start];
+        let old_len = ops[start..end]
+            .iter()
+            .filter(|op| **op != Op::Insert)
+            .count();
+        let new_len = ops[start..end]
+            .iter()
+            .filter(|op| **op != Op::Delete)
+            .count();
+        writeln!(
+            out,
+            "@@ -{},{old_len} +{},{new_len} @@",
+            old_start + (old_len > 0) as usize,
+            new_start + (new_len > 0) as usize
+        )
+        .unwrap();
+        for (op, (o, n)) in ops[start..end].iter().zip(&positions[start..end]) {
+            match op {
+                Op::Keep => writeln!(out, " {}", old[*o]),
+                Op::Delete => writeln!(out, "-{}", old[*o]),
+                Op::Insert => writeln!(out, "+{}", new[*n]),
+            }
+            .unwrap();
+        }
+        i = end;
+    }
+    out
+}
+
+/// Reads `path` as it was at the git revision `reference`, via `git show`.
+pub fn at_revision(path: &Path, reference: &str) -> Result<String, Error> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .arg("show")
+        .arg(format!("{reference}:./{name}"))
+        .output()
+        .map_err(|e| format!("Unable to run git: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Unable to read {} at {reference}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| format!("{} at {reference} is not UTF-8", path.display()).into())
+}
+
+/// The shortest edit script turning `old` into `new`. Common leading and trailing
+/// lines are matched up front so the quadratic LCS only covers the changed region.
+fn edit_script(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    // lcs[i][j] is the LCS length of a[i..] and b[j..]
+    let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = vec![Op::Keep; prefix];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            ops.push(Op::Keep);
+            (i, j) = (i + 1, j + 1);
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op::Delete);
+            i += 1;
+        } else {
+            ops.push(Op::Insert);
+            j += 1;
+        }
+    }
+    ops.extend(std::iter::repeat(Op::Keep).take(suffix));
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical_inputs_have_no_diff() {
+        assert_eq!(unified("x", "a\nb\n", "a\nb\n"), "");
+    }
+
+    #[test]
+    fn single_hunk_with_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n";
+        assert_eq!(
+            unified("lock.json", old, new),
+            "--- lock.json\n+++ lock.json\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+    }
+
+    #[test]
+    fn distant_changes_get_separate_hunks() {
+        let line = |i: usize| format!("{i}\n");
+        let old: String = (1..=20).map(line).collect();
+        let new: String = (1..=20)
+            .map(|i| match i {
+                2 => "two\n".to_owned(),
+                15 => "15\n15.5\n".to_owned(),
+                19 => String::new(),
+                _ => line(i),
+            })
+            .collect();
+        let diff = unified("f", &old, &new);
+        let headers: Vec<&str> = diff.lines().filter(|l| l.starts_with("@@")).collect();
+        assert_eq!(headers, ["@@ -1,5 +1,5 @@", "@@ -13,8 +13,8 @@"]);
+    }
+
+    #[test]
+    fn at_revision_reads_committed_contents() {
+        let dir = std::env::temp_dir().join(format!("multitool-git-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let git = |args: &[&str]| {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {args:?} failed");
+        };
+        let path = dir.join("multitool.lock.json");
+        git(&["init", "-q"]);
+        std::fs::write(&path, "old\n").unwrap();
+        git(&["add", "multitool.lock.json"]);
+        git(&["commit", "-q", "-m", "lockfile"]);
+        std::fs::write(&path, "new\n").unwrap();
+
+        assert_eq!(at_revision(&path, "HEAD").unwrap(), "old\n");
+        let error = at_revision(&dir.join("missing.json"), "HEAD").unwrap_err();
+        assert!(error.to_string().starts_with(&format!(
+            "Unable to read {}",
+            dir.join("missing.json").display()
+        )));
+        assert!(at_revision(&path, "no-such-ref").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn insertion_into_empty_file() {
+        assert_eq!(
+            unified("f", "", "a\n"),
+            "--- f\n+++ f\n@@ -0,0 +1,1 @@\n+a\n"
+        );
+    }
+}
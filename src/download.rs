@@ -0,0 +1,1109 @@
+use crate::archive::{self, Compression, Format};
+use crate::cache::{CachedDigest, DigestCache};
+use crate::config::Settings;
+use crate::error::Error;
+use crate::github_app;
+use crate::hash;
+use crate::lockfile::{Binary, DigestOf, ToolSignature};
+use crate::retry;
+use crate::signature;
+use crate::warn::warn;
+use crate::Common;
+use regex::Regex;
+use reqwest::{
+    header::{ACCEPT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, ETAG, IF_NONE_MATCH, RANGE},
+    Method, StatusCode,
+};
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read, Seek, Write},
+    path::PathBuf,
+    sync::LazyLock,
+    time::Instant,
+};
+
+static ENV_VAR_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap());
+
+/// Expands `${NAME}` references in a header value using `lookup`, failing on the
+/// first variable that isn't set.
+fn expand_env(value: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<String, Error> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut last = 0;
+    for capture in ENV_VAR_PATTERN.captures_iter(value) {
+        let whole = capture.get(0).unwrap();
+        let name = &capture[1];
+        let resolved = lookup(name)
+            .ok_or_else(|| format!("header references unset environment variable '{name}'"))?;
+        expanded.push_str(&value[last..whole.start()]);
+        expanded.push_str(&resolved);
+        last = whole.end();
+    }
+    expanded.push_str(&value[last..]);
+    Ok(expanded)
+}
+
+/// The file in `--from-dir` that stands in for `url`, matched by the URL's basename.
+fn local_path(settings: &Settings, url: &str) -> Option<PathBuf> {
+    let dir = settings.from_dir.as_ref()?;
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let basename = path.rsplit('/').next().filter(|name| !name.is_empty())?;
+    Some(dir.join(basename)).filter(|candidate| candidate.is_file())
+}
+
+fn download_error(url: &str, source: Box<dyn std::error::Error + Send + Sync>) -> Error {
+    Error::Download {
+        url: url.to_owned(),
+        source,
+    }
+}
+
+/// A request of `method` for `url` carrying `headers`, with any `${ENV_VAR}`
+/// references expanded. The `--token` for the URL's host, or else a GitHub App's
+/// installation token for github.com, is sent unless the headers already set
+/// `Authorization`.
+///
+/// Artifacts are requested with `Accept-Encoding: identity`, unless the headers
+/// say otherwise, so digests are over the bytes a plain download saves. The
+/// client never decodes a `Content-Encoding` either (reqwest's `gzip`, `brotli`,
+/// `zstd` and `deflate` features stay off), so a server that compresses anyway
+/// still yields its wire bytes rather than a silently different digest.
+fn request(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    method: Method,
+    url: &str,
+    headers: Option<&BTreeMap<String, String>>,
+) -> Result<reqwest::blocking::RequestBuilder, Error> {
+    let mut request = client.request(method, url);
+    let has_header = |header: &str| {
+        headers
+            .into_iter()
+            .flatten()
+            .any(|(name, _)| name.eq_ignore_ascii_case(header))
+    };
+    if !has_header("accept-encoding") {
+        request = request.header(ACCEPT_ENCODING, "identity");
+    }
+    let has_authorization = has_header("authorization");
+    if !has_authorization {
+        let token = settings
+            .token_for(url)
+            .map(str::to_owned)
+            .or_else(|| github_app::token_for(settings, url));
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+    }
+    for (name, value) in headers.into_iter().flatten() {
+        let value = expand_env(value, |name| std::env::var(name).ok())
+            .map_err(|e| format!("{e} (header '{name}' for {url})"))?;
+        request = request.header(name, value);
+    }
+    Ok(request)
+}
+
+/// Copies the body of `response` into `sink`, failing once more than
+/// `--max-download-size` would have been copied. `read` is how much of the
+/// limit earlier reads used. Returns the number of bytes copied.
+fn read_body(
+    settings: &Settings,
+    url: &str,
+    response: reqwest::blocking::Response,
+    read: u64,
+    sink: &mut impl Write,
+) -> Result<u64, Error> {
+    let too_large = || Error::TooLarge {
+        url: url.to_owned(),
+        limit: settings.max_download_size.unwrap_or_default(),
+    };
+    let limit = settings
+        .max_download_size
+        .map_or(u64::MAX, |limit| limit.saturating_sub(read));
+    if response
+        .content_length()
+        .is_some_and(|length| length > limit)
+    {
+        return Err(too_large());
+    }
+
+    // read at most one byte past the limit so servers that omit or understate
+    // Content-Length are still caught
+    let copied = io::copy(&mut response.take(limit.saturating_add(1)), sink)
+        .map_err(|e| download_error(url, e.into()))?;
+    if copied > limit {
+        return Err(too_large());
+    }
+    Ok(copied)
+}
+
+/// What an artifact's response headers reveal about it without downloading it.
+pub struct Metadata {
+    pub size: Option<u64>,
+    pub etag: Option<String>,
+}
+
+/// Looks up the size and ETag of the artifact at `url` with a HEAD request,
+/// without downloading it. Servers that refuse HEAD get a GET of its first byte
+/// instead, whose `Content-Range` carries the full size. A `--from-dir` copy is
+/// sized from disk and has no ETag.
+pub fn fetch_metadata(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    url: &str,
+    headers: Option<&BTreeMap<String, String>>,
+) -> Result<Metadata, Error> {
+    if let Some(path) = local_path(settings, url) {
+        let size = fs::metadata(&path)
+            .map_err(|e| format!("Unable to read '{}': {e}", path.display()))?
+            .len();
+        return Ok(Metadata {
+            size: Some(size),
+            etag: None,
+        });
+    }
+
+    let send = |request: reqwest::blocking::RequestBuilder| {
+        retry::send(request, settings, None).map_err(|e| download_error(url, e.into()))
+    };
+    let mut response = send(request(client, settings, Method::HEAD, url, headers)?)?;
+    // 403 covers presigned URLs, whose signature is only valid for GET
+    if matches!(
+        response.status(),
+        StatusCode::FORBIDDEN | StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED
+    ) {
+        response =
+            send(request(client, settings, Method::GET, url, headers)?.header(RANGE, "bytes=0-0"))?;
+    }
+    if !response.status().is_success() {
+        return Err(Error::DownloadStatus {
+            url: url.to_owned(),
+            status: response.status(),
+        });
+    }
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    // a 206 reports the full size after the `/` of `bytes 0-0/1234`; content_length()
+    // isn't used because it reflects the (empty) body of a HEAD response
+    let size = match response.status() {
+        StatusCode::PARTIAL_CONTENT => header(CONTENT_RANGE)
+            .and_then(|range| range.rsplit('/').next())
+            .and_then(|total| total.parse().ok()),
+        _ => header(CONTENT_LENGTH).and_then(|length| length.parse().ok()),
+    };
+    Ok(Metadata {
+        size,
+        etag: header(ETAG).map(str::to_owned),
+    })
+}
+
+/// An artifact's sha256 and, when known, its size in bytes.
+pub struct Digest {
+    pub sha256: String,
+    pub size: Option<u64>,
+}
+
+/// Warns when `binary` of `tool` hashed as `digest` at a size other than its
+/// recorded one: the upstream likely replaced the artifact, so its sha256
+/// deserves a second look.
+pub fn warn_if_resized(tool: &str, binary: &Binary, digest: &Digest) {
+    if let (Some(expected), Some(actual)) = (binary.size(), digest.size) {
+        if expected != actual {
+            warn(format!(
+                "{tool} ({}/{}): recorded {expected} bytes, got {actual}; recheck its sha256",
+                binary.os(),
+                binary.cpu()
+            ));
+        }
+    }
+}
+
+/// Computes the digest of an artifact published in `parts`, hashing their
+/// contents concatenated in order as they stream in, so the artifact is never
+/// held in memory. Each part comes from `--from-dir` when it has a copy there
+/// and is downloaded otherwise; `--max-download-size` bounds the combined size.
+///
+/// Parts aren't revalidated against the digest cache, since no single ETag
+/// describes the whole artifact.
+pub fn compute_digest_of_parts(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    parts: &[String],
+    headers: Option<&BTreeMap<String, String>>,
+    expected_size: Option<u64>,
+    deadline: Option<Instant>,
+) -> Result<Digest, Error> {
+    let mut hasher = hash::Sha256Writer::default();
+    let size = copy_parts(
+        client,
+        settings,
+        parts,
+        headers,
+        expected_size,
+        deadline,
+        &mut hasher,
+    )?;
+    Ok(Digest {
+        size: Some(size),
+        sha256: hasher.finish(),
+    })
+}
+
+/// The contents of an artifact published in `parts`, concatenated in order,
+/// fetched as [`compute_digest_of_parts`] does. A single-part artifact is read
+/// whole this way too, bypassing the digest cache, when its contents are needed.
+pub fn fetch_parts(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    parts: &[String],
+    headers: Option<&BTreeMap<String, String>>,
+    expected_size: Option<u64>,
+    deadline: Option<Instant>,
+) -> Result<Vec<u8>, Error> {
+    let mut bytes = Vec::new();
+    copy_parts(
+        client,
+        settings,
+        parts,
+        headers,
+        expected_size,
+        deadline,
+        &mut bytes,
+    )?;
+    Ok(bytes)
+}
+
+/// Copies the contents of `parts` into `sink` in order, returning their
+/// combined size.
+fn copy_parts(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    parts: &[String],
+    headers: Option<&BTreeMap<String, String>>,
+    expected_size: Option<u64>,
+    deadline: Option<Instant>,
+    sink: &mut impl Write,
+) -> Result<u64, Error> {
+    let mut size = 0;
+    for part in parts {
+        if let Some(path) = local_path(settings, part) {
+            let read_error = |e: io::Error| format!("Unable to read '{}': {e}", path.display());
+            size +=
+                io::copy(&mut File::open(&path).map_err(read_error)?, sink).map_err(read_error)?;
+            continue;
+        }
+        let response = retry::send(
+            request(client, settings, Method::GET, part, headers)?,
+            settings,
+            deadline,
+        )
+        .map_err(|e| download_error(part, e.into()))?;
+        if !response.status().is_success() {
+            return Err(Error::DownloadStatus {
+                url: part.to_owned(),
+                status: response.status(),
+            });
+        }
+        size += read_body(settings, part, response, size, sink)?;
+    }
+    if let Some(limit) = settings.max_download_size {
+        if size > limit {
+            return Err(Error::TooLarge {
+                url: parts[0].clone(),
+                limit,
+            });
+        }
+    }
+    match expected_size {
+        Some(expected) if expected != size => Err(Error::SizeMismatch {
+            url: parts[0].clone(),
+            expected,
+            actual: size,
+        }),
+        _ => Ok(size),
+    }
+}
+
+/// Computes the sha256 of the artifact at `url`, preferring a local copy in
+/// `--from-dir` over downloading it. `headers` are sent with the download after
+/// expanding any `${ENV_VAR}` references.
+///
+/// When `digests` has an entry for `url` the download is made conditional on its
+/// ETag, and an unchanged (304) artifact reuses the cached digest without
+/// transferring or hashing anything.
+///
+/// With an `expected_size`, an artifact of any other size is rejected before it
+/// is hashed.
+pub fn compute_sha256(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    digests: &mut DigestCache,
+    url: &str,
+    headers: Option<&BTreeMap<String, String>>,
+    expected_size: Option<u64>,
+) -> Result<String, Error> {
+    compute_digest(client, settings, digests, url, headers, expected_size, None)
+        .map(|digest| digest.sha256)
+}
+
+/// Like [`compute_sha256`], but also returns the size of the hashed artifact.
+/// The size is unknown only for an unchanged artifact whose cached digest
+/// predates recorded sizes.
+pub fn compute_digest(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    digests: &mut DigestCache,
+    url: &str,
+    headers: Option<&BTreeMap<String, String>>,
+    expected_size: Option<u64>,
+    deadline: Option<Instant>,
+) -> Result<Digest, Error> {
+    let check_size = |actual: u64| match expected_size {
+        Some(expected) if expected != actual => Err(Error::SizeMismatch {
+            url: url.to_owned(),
+            expected,
+            actual,
+        }),
+        _ => Ok(()),
+    };
+
+    if let Some(path) = local_path(settings, url) {
+        let read_error = |e: std::io::Error| format!("Unable to read '{}': {e}", path.display());
+        let size = fs::metadata(&path).map_err(read_error)?.len();
+        check_size(size)?;
+        return Ok(Digest {
+            sha256: hash::sha256_file_hex(&path).map_err(read_error)?,
+            size: Some(size),
+        });
+    }
+
+    let mut request = request(client, settings, Method::GET, url, headers)?;
+    let cached = digests.get(url).cloned();
+    if let Some(cached) = &cached {
+        request = request.header(IF_NONE_MATCH, &cached.etag);
+    }
+
+    let response =
+        retry::send(request, settings, deadline).map_err(|e| download_error(url, e.into()))?;
+    if let (StatusCode::NOT_MODIFIED, Some(cached)) = (response.status(), &cached) {
+        if let Some(size) = cached.size {
+            check_size(size)?;
+        }
+        return Ok(Digest {
+            sha256: cached.sha256.clone(),
+            size: cached.size,
+        });
+    }
+    let (mut etag, mut sha256, mut size) = hash_response(settings, url, response)?;
+
+    // a digest that differs from the previous run's may just be a corrupted body,
+    // so download once more to tell that apart from a genuine upstream change
+    let previous = cached.filter(|cached| cached.sha256 != sha256);
+    if let Some(previous) = previous.filter(|_| settings.retry_on_checksum_mismatch) {
+        let response = self::request(client, settings, Method::GET, url, headers)?;
+        let response =
+            retry::send(response, settings, deadline).map_err(|e| download_error(url, e.into()))?;
+        let retried = hash_response(settings, url, response)?;
+        if retried.1 == previous.sha256 {
+            eprintln!(
+                "{url}: digest {sha256} was a corrupted download; retrying matched the previous digest"
+            );
+        } else if retried.1 == sha256 {
+            eprintln!(
+                "{url}: changed upstream; retrying confirmed the new digest {sha256} (previously {})",
+                previous.sha256
+            );
+        } else {
+            return Err(format!(
+                "{url}: digest differs on every download ({}, then {sha256}, then {})",
+                previous.sha256, retried.1
+            )
+            .into());
+        }
+        (etag, sha256, size) = retried;
+    }
+
+    check_size(size)?;
+    if let Some(etag) = etag {
+        digests.insert(
+            url,
+            CachedDigest {
+                etag,
+                sha256: sha256.clone(),
+                size: Some(size),
+            },
+        );
+    }
+    Ok(Digest {
+        sha256,
+        size: Some(size),
+    })
+}
+
+/// The compression to undo before hashing `binary`, or `None` when its sha256
+/// is over the download.
+fn decompression(binary: &Binary) -> Result<Option<Compression>, Error> {
+    let Binary::Archive(archive) = binary else {
+        return Ok(None);
+    };
+    if archive.sha256_of != Some(DigestOf::Decompressed) {
+        return Ok(None);
+    }
+    match Format::of(archive.type_.as_deref(), &archive.url) {
+        Some(Format::Tar(compression)) => Ok(Some(compression)),
+        _ => Err(format!(
+            "{}: sha256_of \"decompressed\" needs a tar archive",
+            archive.url
+        )
+        .into()),
+    }
+}
+
+/// The sha256 `binary` declares of its downloaded `bytes`: over the bytes
+/// themselves, or with `"sha256_of": "decompressed"` over them with the
+/// archive's compression undone.
+pub fn declared_sha256(binary: &Binary, bytes: &[u8]) -> Result<String, Error> {
+    match decompression(binary)? {
+        Some(compression) => {
+            let mut hasher = hash::Sha256Writer::default();
+            archive::decompress_into(bytes, compression, &mut hasher)
+                .map_err(|e| format!("{}: {e}", binary.url()))?;
+            Ok(hasher.finish())
+        }
+        None => Ok(hash::sha256_hex(bytes)),
+    }
+}
+
+/// Downloads `binary` into `file` as [`fetch_parts`] does, so an artifact too
+/// large to hold in memory can still be looked inside, and returns the digest
+/// it declares. The download is hashed as it's written; with
+/// `"sha256_of": "decompressed"` the file is read back to hash it decompressed.
+/// Any size is accepted, and `file` is left at its start.
+pub fn fetch_binary_to(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    binary: &Binary,
+    deadline: Option<Instant>,
+    file: &mut File,
+) -> Result<Digest, Error> {
+    let urls = binary
+        .parts()
+        .map_or_else(|| vec![binary.url().to_owned()], <[String]>::to_vec);
+    let mut hasher = hash::Sha256Writer::default();
+    let size = copy_parts(
+        client,
+        settings,
+        &urls,
+        binary.headers(),
+        None,
+        deadline,
+        &mut Tee(&mut hasher, &mut *file),
+    )?;
+    let rewind = |file: &mut File| {
+        file.rewind()
+            .map_err(|e| format!("Unable to read back {}: {e}", binary.url()))
+    };
+    rewind(file)?;
+    let Some(compression) = decompression(binary)? else {
+        return Ok(Digest {
+            sha256: hasher.finish(),
+            size: Some(size),
+        });
+    };
+    let mut hasher = hash::Sha256Writer::default();
+    archive::decompress_into(&mut *file, compression, &mut hasher)
+        .map_err(|e| format!("{}: {e}", binary.url()))?;
+    rewind(file)?;
+    Ok(Digest {
+        sha256: hasher.finish(),
+        size: Some(size),
+    })
+}
+
+/// A writer that writes everything to both of its writers.
+struct Tee<A, B>(A, B);
+
+impl<A: Write, B: Write> Write for Tee<A, B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.1.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()?;
+        self.1.flush()
+    }
+}
+
+/// What [`compute_binary_digest`] downloads and hashes.
+pub struct DigestRequest<'a> {
+    pub binary: &'a Binary,
+    /// How the tool's artifacts are signed, checked with `--verify-signatures`.
+    pub signature: Option<&'a ToolSignature>,
+    /// Where to download from, rather than the binary's own URL, as an update
+    /// rewrites it, and the parts of a split artifact.
+    pub url: &'a str,
+    pub parts: Option<&'a [String]>,
+    /// Rejects an artifact of any other size before it's hashed.
+    pub expected_size: Option<u64>,
+    pub deadline: Option<Instant>,
+}
+
+impl<'a> DigestRequest<'a> {
+    /// A request for `binary` at its own URL, unsigned and of any size.
+    pub fn of(binary: &'a Binary) -> DigestRequest<'a> {
+        DigestRequest {
+            binary,
+            signature: None,
+            url: binary.url(),
+            parts: binary.parts(),
+            expected_size: None,
+            deadline: None,
+        }
+    }
+}
+
+/// Computes the digest `request.binary` declares, downloaded from
+/// `request.url`. The size is always the download's. With
+/// `--verify-signatures`, the download is first checked against the tool's
+/// `signature`.
+///
+/// A digest over decompressed content or of a signed artifact bypasses the
+/// digest cache, since the bytes themselves are needed.
+pub fn compute_binary_digest(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    digests: &mut DigestCache,
+    request: DigestRequest,
+) -> Result<Digest, Error> {
+    let DigestRequest {
+        binary,
+        signature,
+        url,
+        parts,
+        expected_size,
+        deadline,
+    } = request;
+    let headers = binary.headers();
+    let signature = signature.filter(|_| settings.verify_signatures);
+    if signature.is_none() && decompression(binary)?.is_none() {
+        return match parts {
+            Some(parts) => {
+                compute_digest_of_parts(client, settings, parts, headers, expected_size, deadline)
+            }
+            None => compute_digest(
+                client,
+                settings,
+                digests,
+                url,
+                headers,
+                expected_size,
+                deadline,
+            ),
+        };
+    }
+    let urls = parts.map_or_else(|| vec![url.to_owned()], <[String]>::to_vec);
+    let bytes = fetch_parts(client, settings, &urls, headers, expected_size, deadline)?;
+    if let Some(signature) = signature {
+        signature::verify(client, settings, signature, url, headers, &bytes, deadline)?;
+    }
+    Ok(Digest {
+        sha256: declared_sha256(binary, &bytes)?,
+        size: Some(bytes.len() as u64),
+    })
+}
+
+/// The ETag, sha256 and size of a successful response's body.
+fn hash_response(
+    settings: &Settings,
+    url: &str,
+    response: reqwest::blocking::Response,
+) -> Result<(Option<String>, String, u64), Error> {
+    if !response.status().is_success() {
+        return Err(Error::DownloadStatus {
+            url: url.to_owned(),
+            status: response.status(),
+        });
+    }
+
+    let etag = response
+        .headers()
+        .get(ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_owned);
+
+    let mut hasher = hash::Sha256Writer::default();
+    let size = read_body(settings, url, response, 0, &mut hasher)?;
+    Ok((etag, hasher.finish(), size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, settings, Response, Server};
+    use std::fs;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn compute_sha256_prefers_local_copy() {
+        let dir = std::env::temp_dir().join(format!("multitool-from-dir-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool-linux.tar.gz"), "hello").unwrap();
+
+        let server = Server::start(|_| Response::ok("from the network"));
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let client = settings.client();
+
+        let url = format!("{}/releases/tool-linux.tar.gz?signature=abc", server.url);
+        assert_eq!(
+            compute_sha256(
+                &client,
+                &settings,
+                &mut DigestCache::default(),
+                &url,
+                None,
+                None
+            )
+            .unwrap(),
+            HELLO_SHA256
+        );
+        assert!(server.requests().is_empty());
+
+        // files missing from the directory fall back to the network
+        let url = format!("{}/releases/tool-macos.tar.gz", server.url);
+        assert_eq!(
+            compute_sha256(
+                &client,
+                &settings,
+                &mut DigestCache::default(),
+                &url,
+                None,
+                None
+            )
+            .unwrap(),
+            sha256::digest("from the network")
+        );
+        assert_eq!(server.requests().len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compute_sha256_reports_url_and_status() {
+        let server = Server::start(|_| Response::status(404, "Not Found"));
+        let settings = settings(&server.url);
+        let url = format!("{}/releases/download/v1.0.0/missing.tar.gz", server.url);
+
+        let error = compute_sha256(
+            &settings.client(),
+            &settings,
+            &mut DigestCache::default(),
+            &url,
+            None,
+            None,
+        )
+        .unwrap_err();
+        assert!(matches!(error, Error::DownloadStatus { status, .. } if status == 404));
+        assert_eq!(
+            error.to_string(),
+            format!("HTTP 404 Not Found downloading {url}")
+        );
+    }
+
+    #[test]
+    fn expand_env_references() {
+        let lookup = |name: &str| (name == "MY_TOKEN").then(|| "s3cret".to_owned());
+        assert_eq!(
+            expand_env("Bearer ${MY_TOKEN}", lookup).unwrap(),
+            "Bearer s3cret"
+        );
+        assert_eq!(
+            expand_env("${MY_TOKEN}:${MY_TOKEN}", lookup).unwrap(),
+            "s3cret:s3cret"
+        );
+        // values without references, or with `$` not followed by `{NAME}`, are unchanged
+        assert_eq!(
+            expand_env("application/json", lookup).unwrap(),
+            "application/json"
+        );
+        assert_eq!(
+            expand_env("$MY_TOKEN ${}", lookup).unwrap(),
+            "$MY_TOKEN ${}"
+        );
+    }
+
+    #[test]
+    fn expand_env_missing_variable() {
+        let error = expand_env("Bearer ${MISSING_TOKEN}", |_| None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "header references unset environment variable 'MISSING_TOKEN'"
+        );
+    }
+
+    #[test]
+    fn compute_sha256_sends_expanded_headers() {
+        let server = Server::start(|_| Response::ok("hello"));
+        let settings = settings(&server.url);
+        let url = format!("{}/private/asset", server.url);
+        // PATH is set in any environment the tests run in
+        let path = std::env::var("PATH").unwrap();
+        let headers = BTreeMap::from([
+            ("Accept".to_owned(), "application/octet-stream".to_owned()),
+            ("X-Path".to_owned(), "${PATH}".to_owned()),
+        ]);
+
+        let sha256 = compute_sha256(
+            &settings.client(),
+            &settings,
+            &mut DigestCache::default(),
+            &url,
+            Some(&headers),
+            None,
+        );
+        assert_eq!(sha256.unwrap(), HELLO_SHA256);
+        let request = &server.requests()[0];
+        assert_eq!(request.headers["accept"], "application/octet-stream");
+        assert_eq!(request.headers["x-path"], path);
+    }
+
+    #[test]
+    fn compute_sha256_hashes_encoded_bytes() {
+        let gzipped = testing::tar_gz(&[("tool", "hello")]);
+        let body = gzipped.clone();
+        // a server that compresses regardless of what was asked for
+        let server =
+            Server::start(move |_| Response::ok(body.clone()).header("Content-Encoding", "gzip"));
+        let settings = settings(&server.url);
+
+        let sha256 = compute_sha256(
+            &settings.client(),
+            &settings,
+            &mut DigestCache::default(),
+            &format!("{}/tool", server.url),
+            None,
+            None,
+        );
+        assert_eq!(sha256.unwrap(), hash::sha256_hex(&gzipped));
+        assert_eq!(server.requests()[0].headers["accept-encoding"], "identity");
+    }
+
+    #[test]
+    fn binary_digests_over_raw_or_decompressed_bytes() {
+        let files = [("tool-1.2.3/tool", "hello")];
+        let gzipped = testing::tar_gz(&files);
+        let body = gzipped.clone();
+        let server = Server::start(move |_| Response::ok(body.clone()));
+        let settings = settings(&server.url);
+        let url = format!("{}/tool.tar.gz", server.url);
+        let digest = |sha256_of: &str| {
+            let binary: Binary = serde_json::from_str(&format!(
+                r#"{{"kind": "archive", "url": "{url}", "file": "tool-1.2.3/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"{sha256_of}}}"#
+            ))
+            .unwrap();
+            compute_binary_digest(
+                &settings.client(),
+                &settings,
+                &mut DigestCache::default(),
+                DigestRequest::of(&binary),
+            )
+        };
+
+        let raw = digest("").unwrap();
+        assert_eq!(raw.sha256, hash::sha256_hex(&gzipped));
+        assert_eq!(
+            digest(r#", "sha256_of": "raw""#).unwrap().sha256,
+            raw.sha256
+        );
+
+        let decompressed = digest(r#", "sha256_of": "decompressed""#).unwrap();
+        assert_eq!(decompressed.sha256, hash::sha256_hex(testing::tar(&files)));
+        // the size is still the download's
+        assert_eq!(decompressed.size, Some(gzipped.len() as u64));
+
+        let binary: Binary = serde_json::from_str(&format!(
+            r#"{{"kind": "archive", "url": "{url}", "file": "tool", "sha256": "00", "os": "linux", "cpu": "x86_64", "type": "zip", "sha256_of": "decompressed"}}"#
+        ))
+        .unwrap();
+        assert!(declared_sha256(&binary, &gzipped).is_err());
+    }
+
+    #[test]
+    fn compute_sha256_sends_host_token() {
+        let server = Server::start(|_| Response::ok("hello"));
+        let settings = Settings {
+            host_tokens: crate::config::host_tokens(
+                [("127.0.0.1".to_owned(), "TOKEN".to_owned())],
+                |_| Some("s3cret".to_owned()),
+            ),
+            ..settings(&server.url)
+        };
+        let url = format!("{}/asset", server.url);
+        let compute = |headers: Option<&BTreeMap<String, String>>| {
+            let client = settings.client();
+            compute_sha256(
+                &client,
+                &settings,
+                &mut DigestCache::default(),
+                &url,
+                headers,
+                None,
+            )
+            .unwrap()
+        };
+
+        compute(None);
+        let explicit = BTreeMap::from([("Authorization".to_owned(), "Basic abc".to_owned())]);
+        compute(Some(&explicit));
+        let requests = server.requests();
+        assert_eq!(requests[0].headers["authorization"], "Bearer s3cret");
+        assert_eq!(requests[1].headers["authorization"], "Basic abc");
+    }
+
+    #[test]
+    fn compute_sha256_enforces_max_download_size() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/small" => Response::ok("hello"),
+            _ => Response::ok("a body that is well over the limit"),
+        });
+        let settings = Settings {
+            max_download_size: Some(5),
+            ..settings(&server.url)
+        };
+        let client = settings.client();
+
+        let small = format!("{}/small", server.url);
+        assert_eq!(
+            compute_sha256(
+                &client,
+                &settings,
+                &mut DigestCache::default(),
+                &small,
+                None,
+                None,
+            )
+            .unwrap(),
+            HELLO_SHA256
+        );
+
+        let large = format!("{}/large", server.url);
+        let error = compute_sha256(
+            &client,
+            &settings,
+            &mut DigestCache::default(),
+            &large,
+            None,
+            None,
+        )
+        .unwrap_err();
+        assert!(matches!(error, Error::TooLarge { limit: 5, .. }));
+    }
+
+    #[test]
+    fn compute_sha256_checks_expected_size() {
+        let server = Server::start(|_| Response::ok("hello"));
+        let settings = settings(&server.url);
+        let url = format!("{}/asset", server.url);
+        let sha256 = |expected_size| {
+            compute_sha256(
+                &settings.client(),
+                &settings,
+                &mut DigestCache::default(),
+                &url,
+                None,
+                expected_size,
+            )
+        };
+
+        assert_eq!(sha256(Some(5)).unwrap(), HELLO_SHA256);
+        let error = sha256(Some(6)).unwrap_err();
+        assert!(matches!(
+            error,
+            Error::SizeMismatch {
+                expected: 6,
+                actual: 5,
+                ..
+            }
+        ));
+        assert_eq!(error.to_string(), format!("{url} is 5 bytes, expected 6"));
+    }
+
+    #[test]
+    fn fetch_metadata_falls_back_to_ranged_get() {
+        let server =
+            Server::start(
+                |request| match (request.method.as_str(), request.path.as_str()) {
+                    ("HEAD", "/asset") => Response::ok("hello").header("ETag", "\"v1\""),
+                    ("HEAD", "/presigned") => Response::status(403, ""),
+                    ("GET", "/presigned") if request.headers.contains_key("range") => {
+                        Response::status(206, "h").header("Content-Range", "bytes 0-0/5")
+                    }
+                    _ => Response::status(404, ""),
+                },
+            );
+        let settings = settings(&server.url);
+        let metadata = |path: &str| {
+            fetch_metadata(
+                &settings.client(),
+                &settings,
+                &format!("{}{path}", server.url),
+                None,
+            )
+        };
+
+        let asset = metadata("/asset").unwrap();
+        assert_eq!(asset.size, Some(5));
+        assert_eq!(asset.etag.as_deref(), Some("\"v1\""));
+        let presigned = metadata("/presigned").unwrap();
+        assert_eq!(presigned.size, Some(5));
+        assert_eq!(presigned.etag, None);
+        assert!(matches!(
+            metadata("/missing").err().unwrap(),
+            Error::DownloadStatus { status, .. } if status == 404
+        ));
+        assert_eq!(server.requests()[2].headers["range"], "bytes=0-0");
+    }
+
+    #[test]
+    fn compute_sha256_of_parts_hashes_concatenation() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/tool.tar.gz.part1" => Response::ok("hel"),
+            "/tool.tar.gz.part2" => Response::ok("lo"),
+            _ => Response::status(404, "Not Found"),
+        });
+        let part = |n: u32| format!("{}/tool.tar.gz.part{n}", server.url);
+        let sha256 = |settings: &Settings, parts: &[String]| {
+            compute_digest_of_parts(&settings.client(), settings, parts, None, None, None)
+                .map(|digest| digest.sha256)
+        };
+
+        let settings = settings(&server.url);
+        assert_eq!(
+            sha256(&settings, &[part(1), part(2)]).unwrap(),
+            HELLO_SHA256
+        );
+        let digest = compute_digest_of_parts(
+            &settings.client(),
+            &settings,
+            &[part(1), part(2)],
+            None,
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(digest.size, Some(5));
+        let error = sha256(&settings, &[part(1), part(2), part(3)]).unwrap_err();
+        assert!(matches!(error, Error::DownloadStatus { url, .. } if url == part(3)));
+
+        // the limit applies to the parts combined, not each one
+        let limited = Settings {
+            max_download_size: Some(4),
+            ..settings
+        };
+        let error = sha256(&limited, &[part(1), part(2)]).unwrap_err();
+        assert!(matches!(error, Error::TooLarge { limit: 4, .. }));
+    }
+
+    #[test]
+    fn compute_sha256_revalidates_cached_digest_by_etag() {
+        let server = Server::start(|request| {
+            match (request.path.as_str(), request.headers.get("if-none-match")) {
+                ("/unchanged", Some(etag)) if etag == "\"v1\"" => Response::status(304, ""),
+                ("/unchanged", _) => Response::ok("hello").header("ETag", "\"v1\""),
+                // a revalidation of /changed always finds a newer artifact
+                ("/changed", Some(_)) => Response::ok("hello, again").header("ETag", "\"v2\""),
+                ("/changed", None) => Response::ok("hello").header("ETag", "\"v1\""),
+                _ => Response::ok("hello"),
+            }
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-etag-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let settings = settings(&server.url);
+        let client = settings.client();
+
+        let mut digests = DigestCache::load(Some(&dir));
+        let unchanged = format!("{}/unchanged", server.url);
+        let changed = format!("{}/changed", server.url);
+        let no_etag = format!("{}/no-etag", server.url);
+        for url in [&unchanged, &changed, &no_etag] {
+            let sha256 = compute_sha256(&client, &settings, &mut digests, url, None, None);
+            assert_eq!(sha256.unwrap(), HELLO_SHA256);
+        }
+        digests.save().unwrap();
+
+        let mut digests = DigestCache::load(Some(&dir));
+        let sha256 = compute_sha256(&client, &settings, &mut digests, &unchanged, None, None);
+        assert_eq!(sha256.unwrap(), HELLO_SHA256);
+        let sha256 = compute_sha256(&client, &settings, &mut digests, &changed, None, None);
+        assert_eq!(sha256.unwrap(), sha256::digest("hello, again"));
+        assert_eq!(digests.get(&changed).unwrap().etag, "\"v2\"");
+        assert!(digests.get(&no_etag).is_none());
+
+        let revalidations: Vec<String> = server
+            .requests()
+            .iter()
+            .filter_map(|request| request.headers.get("if-none-match").cloned())
+            .collect();
+        assert_eq!(revalidations, ["\"v1\"", "\"v1\""]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compute_sha256_retries_digest_mismatch_once() {
+        // a revalidation of /flaky gets a corrupted body, a fresh download doesn't
+        let server = Server::start(|request| {
+            match (request.path.as_str(), request.headers.get("if-none-match")) {
+                ("/flaky", Some(_)) => Response::ok("hellp").header("ETag", "\"v1\""),
+                ("/flaky", None) => Response::ok("hello").header("ETag", "\"v1\""),
+                ("/inconsistent", Some(_)) => Response::ok("hellp"),
+                ("/inconsistent", None) => Response::ok("hellq"),
+                _ => Response::ok("hello, again").header("ETag", "\"v2\""),
+            }
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-retry-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let settings = Settings {
+            retry_on_checksum_mismatch: true,
+            ..settings(&server.url)
+        };
+        let client = settings.client();
+        let mut digests = DigestCache::load(Some(&dir));
+        let [flaky, changed, inconsistent] =
+            ["flaky", "changed", "inconsistent"].map(|path| format!("{}/{path}", server.url));
+        for url in [&flaky, &changed, &inconsistent] {
+            let cached = CachedDigest {
+                etag: "\"v0\"".to_owned(),
+                sha256: HELLO_SHA256.to_owned(),
+                size: Some(5),
+            };
+            digests.insert(url, cached);
+        }
+
+        let sha256 = compute_sha256(&client, &settings, &mut digests, &flaky, None, None);
+        assert_eq!(sha256.unwrap(), HELLO_SHA256);
+        let sha256 = compute_sha256(&client, &settings, &mut digests, &changed, None, None);
+        assert_eq!(sha256.unwrap(), sha256::digest("hello, again"));
+        assert_eq!(digests.get(&changed).unwrap().etag, "\"v2\"");
+        let error = compute_sha256(&client, &settings, &mut digests, &inconsistent, None, None)
+            .err()
+            .unwrap();
+        assert!(error
+            .to_string()
+            .starts_with(&format!("{inconsistent}: digest differs on every download")));
+        assert_eq!(server.requests().len(), 6);
+
+        fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,472 @@
+//! Lockfile mutations made by `add` and `remove`, and the `--platforms-file`
+//! descriptor `add` reads in bulk.
+
+use crate::{
+    error::Error,
+    lockfile::{
+        ArchiveBinary, Binary, DirBinary, FileBinary, Libc, Lockfile, PkgBinary, SupportedCpu,
+        SupportedOs, ToolDefinition,
+    },
+    Common,
+};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::{collections::BTreeSet, fmt};
+
+/// The kind of binary `add` creates.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BinaryKind {
+    /// The URL is the executable itself.
+    #[default]
+    File,
+    /// The executable is `--file` within the downloaded archive.
+    Archive,
+    /// The executable is `--file` within the downloaded macOS package.
+    Pkg,
+    /// The whole extracted archive.
+    Dir,
+}
+
+/// A platform as `add` and `remove` name it: an OS and CPU, and for binaries
+/// that come in glibc and musl builds, the libc.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Platform {
+    pub os: SupportedOs,
+    pub cpu: SupportedCpu,
+    pub libc: Option<Libc>,
+}
+
+impl Platform {
+    pub fn of(binary: &Binary) -> Platform {
+        Platform {
+            os: binary.os().clone(),
+            cpu: binary.cpu().clone(),
+            libc: binary.libc().cloned(),
+        }
+    }
+
+    /// Whether `binary` is on this platform. Without a libc, every libc variant
+    /// of the OS and CPU is.
+    fn includes(&self, binary: &Binary) -> bool {
+        binary.os() == &self.os
+            && binary.cpu() == &self.cpu
+            && self
+                .libc
+                .as_ref()
+                .map_or(true, |libc| binary.libc() == Some(libc))
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.libc {
+            Some(libc) => write!(f, "{}/{}/{libc}", self.os, self.cpu),
+            None => write!(f, "{}/{}", self.os, self.cpu),
+        }
+    }
+}
+
+/// One binary listed in a `--platforms-file`, with the same meaning as the
+/// matching `add` options.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PlatformEntry {
+    pub os: SupportedOs,
+    pub cpu: SupportedCpu,
+    pub libc: Option<Libc>,
+    #[serde(default)]
+    pub kind: BinaryKind,
+    pub url: String,
+    pub file: Option<String>,
+}
+
+impl PlatformEntry {
+    pub fn platform(&self) -> Platform {
+        Platform {
+            os: self.os.clone(),
+            cpu: self.cpu.clone(),
+            libc: self.libc.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlatformsFile {
+    platform: Vec<PlatformEntry>,
+}
+
+/// Parses a TOML `--platforms-file` of `[[platform]]` tables. The file must list
+/// at least one platform and no platform twice.
+pub fn parse_platforms(contents: &str) -> Result<Vec<PlatformEntry>, Error> {
+    let file: PlatformsFile =
+        toml::from_str(contents).map_err(|e| Error::Message(e.to_string()))?;
+    if file.platform.is_empty() {
+        return Err("no platforms listed".into());
+    }
+    let mut seen = BTreeSet::new();
+    for entry in &file.platform {
+        if !seen.insert(entry.platform().to_string()) {
+            return Err(format!("{} is listed more than once", entry.platform()).into());
+        }
+    }
+    Ok(file.platform)
+}
+
+/// Builds a binary of `kind`; `file` is the path within the artifact and is
+/// required for archives and packages. Packages are macOS-only and never have a
+/// `libc`.
+pub fn new_binary(
+    kind: BinaryKind,
+    url: String,
+    sha256: String,
+    platform: Platform,
+    file: Option<String>,
+) -> Result<Binary, Error> {
+    let Platform { os, cpu, libc } = platform;
+    let file = || {
+        file.clone().ok_or_else(|| {
+            let kind = kind.to_possible_value().unwrap();
+            format!("--file is required for {} binaries", kind.get_name())
+        })
+    };
+    if libc.is_some() && kind == BinaryKind::Pkg {
+        return Err("--libc is not supported for pkg binaries".into());
+    }
+    Ok(match kind {
+        BinaryKind::File => Binary::File(FileBinary {
+            url,
+            sha256,
+            size: None,
+            os,
+            cpu,
+            libc,
+            headers: None,
+            auth_patterns: None,
+        }),
+        BinaryKind::Archive => Binary::Archive(ArchiveBinary {
+            url,
+            parts: None,
+            file: file()?,
+            sha256,
+            size: None,
+            os,
+            cpu,
+            libc: libc.clone(),
+            headers: None,
+            type_: None,
+            inner: None,
+            auth_patterns: None,
+            sha256_of: None,
+        }),
+        BinaryKind::Pkg => Binary::Pkg(PkgBinary {
+            url,
+            file: file()?,
+            sha256,
+            size: None,
+            os,
+            cpu,
+            headers: None,
+            auth_patterns: None,
+            pkg_id: None,
+            install_to: None,
+        }),
+        BinaryKind::Dir => Binary::Dir(DirBinary {
+            url,
+            parts: None,
+            sha256,
+            size: None,
+            os,
+            cpu,
+            libc,
+            root: None,
+            headers: None,
+            type_: None,
+            auth_patterns: None,
+        }),
+    })
+}
+
+/// Adds `binary` to `tool`, creating the tool if needed. A tool has at most one
+/// binary per platform. The lockfile is left unchanged if the binary can't be
+/// added.
+pub fn add_binary(lockfile: &mut Lockfile, tool: &str, binary: Binary) -> Result<(), Error> {
+    let definition = lockfile
+        .tools
+        .entry(tool.to_owned())
+        .or_insert_with(|| ToolDefinition {
+            binaries: Vec::new(),
+            source: None,
+            fallbacks: None,
+            update: None,
+            signature: None,
+        });
+    if definition
+        .binaries
+        .iter()
+        .any(|existing| existing.sort_key() == binary.sort_key())
+    {
+        return Err(format!("{tool} already has a binary for {}", Platform::of(&binary)).into());
+    }
+    let key = binary.sort_key();
+    definition.binaries.push(binary);
+    definition.binaries.sort_by_key(|v| v.sort_key());
+    let Err(e) = definition.validate() else {
+        return Ok(());
+    };
+    definition
+        .binaries
+        .retain(|existing| existing.sort_key() != key);
+    if definition.binaries.is_empty() {
+        lockfile.tools.remove(tool);
+    }
+    Err(format!("Invalid tool '{tool}': {e}").into())
+}
+
+/// Removes `tool` entirely, or only its binaries for `platform`. A `libc` in the
+/// platform removes just that variant; without one every libc variant goes. A
+/// tool left without binaries is removed too.
+pub fn remove(
+    lockfile: &mut Lockfile,
+    tool: &str,
+    platform: Option<Platform>,
+) -> Result<(), Error> {
+    let Some(definition) = lockfile.tools.get_mut(tool) else {
+        return Err(format!("No tool named '{tool}'").into());
+    };
+    let Some(platform) = platform else {
+        lockfile.tools.remove(tool);
+        return Ok(());
+    };
+
+    let before = definition.binaries.len();
+    definition
+        .binaries
+        .retain(|binary| !platform.includes(binary));
+    if definition.binaries.len() == before {
+        return Err(format!("{tool} has no binary for {platform}").into());
+    }
+    if definition.binaries.is_empty() {
+        lockfile.tools.remove(tool);
+        return Ok(());
+    }
+    definition
+        .validate()
+        .map_err(|e| format!("Invalid tool '{tool}': {e}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lockfile() -> Lockfile {
+        serde_json::from_str(
+            r#"{
+              "tool": {
+                "binaries": [
+                  {"kind": "file", "url": "https://example.com/tool-linux", "sha256": "00", "os": "linux", "cpu": "x86_64"},
+                  {"kind": "file", "url": "https://example.com/tool-macos", "sha256": "00", "os": "macos", "cpu": "x86_64"}
+                ],
+                "fallbacks": [
+                  {"os": "macos", "cpu": "arm64", "target": {"os": "macos", "cpu": "x86_64"}}
+                ]
+              }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn linux_x86_64(libc: Option<Libc>) -> Platform {
+        Platform {
+            os: SupportedOs::Linux,
+            cpu: SupportedCpu::X86_64,
+            libc,
+        }
+    }
+
+    fn file(os: SupportedOs, cpu: SupportedCpu) -> Binary {
+        let url = format!("https://example.com/tool-{os}-{cpu}");
+        new_binary(
+            BinaryKind::File,
+            url,
+            "11".to_owned(),
+            Platform {
+                os,
+                cpu,
+                libc: None,
+            },
+            None,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn new_binary_requires_file_for_archives() {
+        let url = || "https://example.com/tool.tar.gz".to_owned();
+        let archive = |file: Option<&str>| {
+            new_binary(
+                BinaryKind::Archive,
+                url(),
+                "11".to_owned(),
+                linux_x86_64(None),
+                file.map(str::to_owned),
+            )
+        };
+        assert!(archive(None).is_err());
+        let Binary::Archive(binary) = archive(Some("bin/tool")).unwrap() else {
+            panic!("expected an archive binary");
+        };
+        assert_eq!(binary.file, "bin/tool");
+    }
+
+    #[test]
+    fn add_binary_sorts_and_rejects_duplicates() {
+        let mut lockfile = lockfile();
+        add_binary(
+            &mut lockfile,
+            "tool",
+            file(SupportedOs::Linux, SupportedCpu::Arm64),
+        )
+        .unwrap();
+        let keys: Vec<String> = lockfile.tools["tool"]
+            .binaries
+            .iter()
+            .map(|binary| binary.sort_key())
+            .collect();
+        assert_eq!(keys, ["linux_arm64", "linux_x86_64", "macos_x86_64"]);
+
+        let duplicate = file(SupportedOs::Linux, SupportedCpu::Arm64);
+        let error = add_binary(&mut lockfile, "tool", duplicate).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "tool already has a binary for linux/arm64"
+        );
+
+        // a platform that already falls back to another binary
+        let shadowing = file(SupportedOs::MacOS, SupportedCpu::Arm64);
+        assert!(add_binary(&mut lockfile, "tool", shadowing).is_err());
+        assert_eq!(lockfile.tools["tool"].binaries.len(), 3);
+
+        add_binary(
+            &mut lockfile,
+            "other",
+            file(SupportedOs::Windows, SupportedCpu::X86_64),
+        )
+        .unwrap();
+        assert_eq!(lockfile.tools["other"].binaries.len(), 1);
+    }
+
+    #[test]
+    fn parse_platforms_file() {
+        let entries = parse_platforms(
+            r#"
+            [[platform]]
+            os = "linux"
+            cpu = "x86_64"
+            libc = "musl"
+            kind = "archive"
+            url = "https://example.com/tool-linux.tar.gz"
+            file = "tool"
+
+            [[platform]]
+            os = "macos"
+            cpu = "arm64"
+            url = "https://example.com/tool-macos"
+            "#,
+        )
+        .unwrap();
+        let platforms: Vec<String> = entries
+            .iter()
+            .map(|entry| entry.platform().to_string())
+            .collect();
+        assert_eq!(platforms, ["linux/x86_64/musl", "macos/arm64"]);
+        assert_eq!(entries[0].kind, BinaryKind::Archive);
+        assert_eq!(entries[1].kind, BinaryKind::File);
+
+        let entry =
+            |os: &str| format!("[[platform]]\nos = \"{os}\"\ncpu = \"arm64\"\nurl = \"u\"\n");
+        assert!(
+            parse_platforms(&format!("{}{}", entry("linux"), entry("linux")))
+                .unwrap_err()
+                .to_string()
+                .contains("linux/arm64 is listed more than once")
+        );
+        assert!(parse_platforms(&entry("beos")).is_err());
+        assert!(parse_platforms(&format!("{}sha256 = \"00\"\n", entry("linux"))).is_err());
+        assert!(parse_platforms("platform = []").is_err());
+    }
+
+    #[test]
+    fn remove_platform_or_tool() {
+        let mut lockfile = lockfile();
+        let error = remove(
+            &mut lockfile,
+            "tool",
+            Some(Platform {
+                os: SupportedOs::Windows,
+                cpu: SupportedCpu::X86_64,
+                libc: None,
+            }),
+        );
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "tool has no binary for windows/x86_64"
+        );
+        // the macos/x86_64 binary is a fallback target
+        let error = remove(
+            &mut lockfile,
+            "tool",
+            Some(Platform {
+                os: SupportedOs::MacOS,
+                cpu: SupportedCpu::X86_64,
+                libc: None,
+            }),
+        );
+        assert!(error
+            .unwrap_err()
+            .to_string()
+            .contains("which has no binary"));
+
+        let mut lockfile = self::lockfile();
+        remove(&mut lockfile, "tool", Some(linux_x86_64(None))).unwrap();
+        assert_eq!(lockfile.tools["tool"].binaries.len(), 1);
+
+        remove(&mut lockfile, "tool", None).unwrap();
+        assert!(lockfile.tools.is_empty());
+        assert!(remove(&mut lockfile, "tool", None).is_err());
+    }
+
+    #[test]
+    fn libc_variants_are_separate_platforms() {
+        let linux = |libc: Option<Libc>| {
+            let url = format!("https://example.com/tool-{libc:?}");
+            new_binary(
+                BinaryKind::File,
+                url,
+                "11".to_owned(),
+                linux_x86_64(libc),
+                None,
+            )
+            .unwrap()
+        };
+        let mut lockfile: Lockfile = serde_json::from_str("{}").unwrap();
+        add_binary(&mut lockfile, "tool", linux(Some(Libc::Musl))).unwrap();
+        add_binary(&mut lockfile, "tool", linux(Some(Libc::Gnu))).unwrap();
+        let error = add_binary(&mut lockfile, "tool", linux(Some(Libc::Gnu))).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "tool already has a binary for linux/x86_64/gnu"
+        );
+
+        remove(&mut lockfile, "tool", Some(linux_x86_64(Some(Libc::Gnu)))).unwrap();
+        let binaries = &lockfile.tools["tool"].binaries;
+        assert_eq!(binaries.len(), 1);
+        assert_eq!(binaries[0].libc(), Some(&Libc::Musl));
+
+        // without --libc every variant for the platform is removed
+        add_binary(&mut lockfile, "tool", linux(Some(Libc::Gnu))).unwrap();
+        remove(&mut lockfile, "tool", Some(linux_x86_64(None))).unwrap();
+        assert!(lockfile.tools.is_empty());
+    }
+}
@@ -0,0 +1,51 @@
+use reqwest::StatusCode;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The server answered a download with a non-success status.
+    #[error("HTTP {status} downloading {url}")]
+    DownloadStatus { url: String, status: StatusCode },
+
+    /// The download failed before a status was received, or while reading the body.
+    #[error("failed to download {url}: {source}")]
+    Download {
+        url: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    /// The download exceeded `--max-download-size`.
+    #[error("{url} exceeds the maximum download size of {limit} bytes")]
+    TooLarge { url: String, limit: u64 },
+
+    /// The download's size differs from the size recorded for the artifact.
+    #[error("{url} is {actual} bytes, expected {expected}")]
+    SizeMismatch {
+        url: String,
+        expected: u64,
+        actual: u64,
+    },
+
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+
+    #[error(transparent)]
+    Regex(#[from] regex::Error),
+
+    #[error("{0}")]
+    Message(String),
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Message(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Message(message.to_owned())
+    }
+}
@@ -0,0 +1,31 @@
+//! Process exit codes. The numbers are part of the CLI's interface, so scripts
+//! can tell "outdated" from "broken"; never renumber an existing variant.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExitCode {
+    Success = 0,
+    /// Any failure without a more specific code, including panics.
+    Error = 1,
+    /// Invalid command-line usage, as reported by clap.
+    Usage = 2,
+    /// `check` found binaries behind their latest release.
+    UpdatesAvailable = 3,
+    /// `verify` found an artifact whose sha256 doesn't match the lockfile.
+    VerificationMismatch = 4,
+    /// The lockfile couldn't be parsed or failed validation.
+    ValidationFailure = 5,
+    /// A release lookup or download failed.
+    NetworkError = 6,
+    /// With `--fail-on-warn`, a run that would otherwise succeed emitted warnings.
+    Warnings = 7,
+    /// `platform-matrix --require` found a tool without the required platform.
+    MissingPlatforms = 8,
+    /// `update --dry-run --diff-exit-code` would have changed the lockfile.
+    WouldChange = 9,
+}
+
+impl ExitCode {
+    pub fn exit(self) -> ! {
+        std::process::exit(self as i32)
+    }
+}
@@ -0,0 +1,173 @@
+//! `--explain` traces of how `update` and `check` decided what to do with each
+//! binary.
+
+use crate::{
+    config::Settings,
+    lockfile::{Binary, ToolDefinition},
+    resolve::ReleaseSource,
+    strip_v, Common,
+};
+use std::fmt;
+
+/// The outcome for one binary.
+pub enum Decision {
+    /// `update` moved the binary to the latest version.
+    Updated,
+    /// `check` found the binary behind the latest version.
+    Outdated,
+    UpToDate,
+    /// With `--only-if-newer-published`, the latest release wasn't published
+    /// after the pinned one.
+    NotNewer,
+    /// The tool is marked `"update": false`.
+    SkippedPinned,
+    /// Nothing says where newer versions come from: the URL isn't a GitHub
+    /// release asset and the tool has no `source`.
+    SkippedNoSource,
+    /// Looking up or applying the latest version failed, including when no
+    /// release passes the tag filters.
+    Failed(String),
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Decision::Updated => f.write_str("updated"),
+            Decision::Outdated => f.write_str("outdated"),
+            Decision::UpToDate => f.write_str("up-to-date"),
+            Decision::NotNewer => f.write_str("held (latest not published after pinned)"),
+            Decision::SkippedPinned => f.write_str("skipped-pinned (update: false)"),
+            Decision::SkippedNoSource => f.write_str("skipped-no-source"),
+            Decision::Failed(e) => write!(f, "failed ({e})"),
+        }
+    }
+}
+
+/// How `source` selects the latest version.
+pub fn selection(source: &ReleaseSource) -> String {
+    match source {
+        ReleaseSource::GitHub(release, source) => {
+            let repo = format!("{}/{}", release.org, release.repo);
+            let tag_regex = source.and_then(|source| source.tag_regex.as_deref());
+            match tag_regex {
+                None => format!("latest release of {repo}"),
+                Some(pattern) => {
+                    format!("newest stable release of {repo} matching tag_regex '{pattern}'")
+                }
+            }
+        }
+        ReleaseSource::Template(source) => format!("version from {}", source.version_url),
+    }
+}
+
+/// One line of `--explain` output.
+pub struct Trace<'a> {
+    pub tool: &'a str,
+    pub binary: &'a Binary,
+    /// How the latest version was selected, once the binary's source is known.
+    pub selection: Option<String>,
+    /// The pinned and latest versions, once both are known.
+    pub versions: Option<(&'a str, &'a str)>,
+    pub decision: Decision,
+}
+
+impl Trace<'_> {
+    /// Prints the trace to stderr when `--explain` is set.
+    pub fn emit(&self, settings: &Settings) {
+        if settings.explain {
+            eprintln!("{self}");
+        }
+    }
+}
+
+impl fmt::Display for Trace<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "explain: {} ({}/{}):",
+            self.tool,
+            self.binary.os(),
+            self.binary.cpu()
+        )?;
+        if let Some((version, latest)) = self.versions {
+            write!(
+                f,
+                " pinned {}, latest {}",
+                strip_v(version),
+                strip_v(latest)
+            )?;
+        }
+        if let Some(selection) = &self.selection {
+            write!(f, " [{selection}]")?;
+        }
+        write!(f, " -> {}", self.decision)
+    }
+}
+
+/// Traces every binary of a tool that's skipped for being marked `"update": false`.
+pub fn pinned(settings: &Settings, tool: &str, definition: &ToolDefinition) {
+    if definition.update != Some(false) {
+        return;
+    }
+    for binary in &definition.binaries {
+        Trace {
+            tool,
+            binary,
+            selection: None,
+            versions: None,
+            decision: Decision::SkippedPinned,
+        }
+        .emit(settings);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lockfile::ToolSource;
+
+    fn binary() -> Binary {
+        serde_json::from_str(
+            r#"{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool-linux", "sha256": "00", "os": "linux", "cpu": "x86_64"}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn selection_describes_tag_filters() {
+        let binary = binary();
+        let source = ReleaseSource::of(&None, &binary).unwrap();
+        assert_eq!(selection(&source), "latest release of org/tool");
+
+        let tool_source: Option<ToolSource> =
+            serde_json::from_str(r#"{"kind": "github", "tag_regex": "^v1\\."}"#).unwrap();
+        let source = ReleaseSource::of(&tool_source, &binary).unwrap();
+        assert_eq!(
+            selection(&source),
+            "newest stable release of org/tool matching tag_regex '^v1\\.'"
+        );
+    }
+
+    #[test]
+    fn trace_lines() {
+        let binary = binary();
+        let trace = |versions: Option<(&str, &str)>, decision| {
+            Trace {
+                tool: "tool",
+                binary: &binary,
+                selection: versions.map(|_| "latest release of org/tool".to_owned()),
+                versions,
+                decision,
+            }
+            .to_string()
+        };
+        assert_eq!(
+            trace(Some(("v1.2.3", "v1.3.0")), Decision::Updated),
+            "explain: tool (linux/x86_64): pinned 1.2.3, latest 1.3.0 [latest release of org/tool] -> updated"
+        );
+        assert_eq!(
+            trace(None, Decision::SkippedPinned),
+            "explain: tool (linux/x86_64): -> skipped-pinned (update: false)"
+        );
+    }
+}
@@ -0,0 +1,365 @@
+//! `--filter` expressions: a small boolean language over each binary's `tool`,
+//! `os`, `cpu` and `kind`, such as
+//! `os == linux and (cpu == arm64 or tool startswith 'llvm')`.
+//!
+//! `not` binds tighter than `and`, which binds tighter than `or`. Comparisons
+//! are case-insensitive, like the other tool filters.
+
+use crate::lockfile::{Binary, SupportedCpu, SupportedOs};
+use crate::Common;
+use clap::ValueEnum;
+
+const KINDS: [&str; 4] = ["file", "archive", "pkg", "dir"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Tool,
+    Os,
+    Cpu,
+    Kind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    StartsWith,
+    EndsWith,
+    Contains,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    Or(Box<Node>, Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    Compare(Field, Op, String),
+}
+
+/// A parsed `--filter` expression, evaluated once per binary.
+#[derive(Clone, Debug)]
+pub struct FilterExpr(Node);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Open,
+    Close,
+    Eq,
+    Ne,
+    Word(&'a str),
+    Quoted(&'a str),
+}
+
+impl Token<'_> {
+    fn describe(&self) -> String {
+        match self {
+            Token::Open => "'('".to_owned(),
+            Token::Close => "')'".to_owned(),
+            Token::Eq => "'=='".to_owned(),
+            Token::Ne => "'!='".to_owned(),
+            Token::Word(word) => format!("'{word}'"),
+            Token::Quoted(text) => format!("'{text}'"),
+        }
+    }
+}
+
+/// Splits `text` into tokens, each with its byte offset.
+fn tokenize(text: &str) -> Result<Vec<(usize, Token<'_>)>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = text.char_indices().peekable();
+    while let Some((start, c)) = rest.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            '=' | '!' if rest.next_if(|&(_, next)| next == '=').is_some() => {
+                if c == '=' {
+                    Token::Eq
+                } else {
+                    Token::Ne
+                }
+            }
+            '\'' | '"' => {
+                let end = text[start + 1..]
+                    .find(c)
+                    .ok_or_else(|| format!("unterminated string starting at offset {start}"))?;
+                let end = start + 1 + end;
+                while rest.next_if(|&(i, _)| i <= end).is_some() {}
+                Token::Quoted(&text[start + 1..end])
+            }
+            c if is_word_char(c) => {
+                let mut end = start + c.len_utf8();
+                while let Some((i, next)) = rest.next_if(|&(_, next)| is_word_char(next)) {
+                    end = i + next.len_utf8();
+                }
+                Token::Word(&text[start..end])
+            }
+            c => return Err(format!("unexpected '{c}' at offset {start}")),
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | '/' | '+')
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, Token<'a>)>,
+    next: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token<'a>> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    fn advance(&mut self) -> Option<(usize, Token<'a>)> {
+        let token = self.tokens.get(self.next).cloned();
+        self.next += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found =
+            matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword));
+        if found {
+            self.next += 1;
+        }
+        found
+    }
+
+    /// An error naming the token where `expected` was wanted.
+    fn unexpected(&self, expected: &str) -> String {
+        match self.tokens.get(self.next) {
+            Some((offset, token)) => format!(
+                "expected {expected} but found {} at offset {offset}",
+                token.describe()
+            ),
+            None => format!("expected {expected} but the expression ended"),
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, String> {
+        let mut node = self.and()?;
+        while self.keyword("or") {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, String> {
+        let mut node = self.not()?;
+        while self.keyword("and") {
+            node = Node::And(Box::new(node), Box::new(self.not()?));
+        }
+        Ok(node)
+    }
+
+    fn not(&mut self) -> Result<Node, String> {
+        if self.keyword("not") {
+            return Ok(Node::Not(Box::new(self.not()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        if self.peek() == Some(&Token::Open) {
+            self.next += 1;
+            let node = self.or()?;
+            if self.peek() != Some(&Token::Close) {
+                return Err(self.unexpected("')'"));
+            }
+            self.next += 1;
+            return Ok(node);
+        }
+
+        let field = match self.peek() {
+            Some(Token::Word(word)) => match word.to_ascii_lowercase().as_str() {
+                "tool" => Field::Tool,
+                "os" => Field::Os,
+                "cpu" => Field::Cpu,
+                "kind" => Field::Kind,
+                _ => return Err(self.unexpected("tool, os, cpu or kind")),
+            },
+            _ => return Err(self.unexpected("tool, os, cpu or kind")),
+        };
+        self.next += 1;
+
+        let op = match self.peek() {
+            Some(Token::Eq) => Op::Eq,
+            Some(Token::Ne) => Op::Ne,
+            Some(Token::Word(word)) => match word.to_ascii_lowercase().as_str() {
+                "startswith" => Op::StartsWith,
+                "endswith" => Op::EndsWith,
+                "contains" => Op::Contains,
+                _ => return Err(self.unexpected("==, !=, startswith, endswith or contains")),
+            },
+            _ => return Err(self.unexpected("==, !=, startswith, endswith or contains")),
+        };
+        self.next += 1;
+
+        let value = match self.peek() {
+            Some(Token::Word(value) | Token::Quoted(value)) => value.to_ascii_lowercase(),
+            _ => return Err(self.unexpected("a value")),
+        };
+        let value = match (field, op) {
+            // exact platform values must be real ones, any spelling
+            (Field::Os, Op::Eq | Op::Ne) => SupportedOs::from_str(&value, true)
+                .map(|os| os.to_string())
+                .map_err(|_| self.unexpected("linux, macos or windows"))?,
+            (Field::Cpu, Op::Eq | Op::Ne) => SupportedCpu::from_str(&value, true)
+                .map(|cpu| cpu.to_string())
+                .map_err(|_| self.unexpected("arm64 or x86_64"))?,
+            (Field::Kind, Op::Eq | Op::Ne) if !KINDS.contains(&value.as_str()) => {
+                return Err(self.unexpected("file, archive, pkg or dir"));
+            }
+            _ => value,
+        };
+        self.advance();
+        Ok(Node::Compare(field, op, value))
+    }
+}
+
+impl FilterExpr {
+    pub fn parse(text: &str) -> Result<FilterExpr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            next: 0,
+        };
+        let node = parser.or()?;
+        if parser.peek().is_some() {
+            return Err(parser.unexpected("'and', 'or' or the end of the expression"));
+        }
+        Ok(FilterExpr(node))
+    }
+
+    /// Whether `binary` of `tool` satisfies the expression.
+    pub fn matches(&self, tool: &str, binary: &Binary) -> bool {
+        let kind = match binary {
+            Binary::File(_) => "file",
+            Binary::Archive(_) => "archive",
+            Binary::Pkg(_) => "pkg",
+            Binary::Dir(_) => "dir",
+        };
+        let (os, cpu) = (binary.os().to_string(), binary.cpu().to_string());
+        let tool = tool.to_ascii_lowercase();
+        evaluate(&self.0, &|field| match field {
+            Field::Tool => &tool,
+            Field::Os => &os,
+            Field::Cpu => &cpu,
+            Field::Kind => kind,
+        })
+    }
+}
+
+fn evaluate<'a>(node: &Node, field: &impl Fn(Field) -> &'a str) -> bool {
+    match node {
+        Node::Or(left, right) => evaluate(left, field) || evaluate(right, field),
+        Node::And(left, right) => evaluate(left, field) && evaluate(right, field),
+        Node::Not(node) => !evaluate(node, field),
+        Node::Compare(name, op, value) => {
+            let actual = field(*name);
+            match op {
+                Op::Eq => actual == value,
+                Op::Ne => actual != value,
+                Op::StartsWith => actual.starts_with(value.as_str()),
+                Op::EndsWith => actual.ends_with(value.as_str()),
+                Op::Contains => actual.contains(value.as_str()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(kind: &str, os: &str, cpu: &str) -> Binary {
+        let extra = match kind {
+            "file" => "",
+            _ => r#", "file": "bin/tool""#,
+        };
+        serde_json::from_str(&format!(
+            r#"{{"kind": "{kind}", "url": "https://example.com/tool.tar.gz", "sha256": "00", "os": "{os}", "cpu": "{cpu}"{extra}}}"#
+        ))
+        .unwrap()
+    }
+
+    fn matches(expr: &str, tool: &str, binary: &Binary) -> bool {
+        FilterExpr::parse(expr).unwrap().matches(tool, binary)
+    }
+
+    #[test]
+    fn expressions_select_binaries() {
+        let linux_arm = binary("archive", "linux", "arm64");
+        let mac = binary("file", "macos", "x86_64");
+        let expr = "os == linux and cpu == arm64 and tool startswith 'llvm'";
+        assert!(matches(expr, "llvm-clang", &linux_arm));
+        assert!(!matches(expr, "clang", &linux_arm));
+        assert!(!matches(expr, "llvm-clang", &mac));
+
+        assert!(matches("kind == file", "rg", &mac));
+        assert!(matches("not kind == file", "rg", &linux_arm));
+        assert!(matches("tool contains LINT", "golangci-lint", &mac));
+        assert!(matches(
+            r#"tool endswith "-lint" or os != macos"#,
+            "rg",
+            &linux_arm
+        ));
+        // platform values take any accepted spelling
+        assert!(matches("cpu == aarch64 and os == Linux", "rg", &linux_arm));
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_than_or() {
+        let linux = binary("file", "linux", "x86_64");
+        // (os == macos and cpu == arm64) or tool == rg
+        assert!(matches(
+            "os == macos and cpu == arm64 or tool == rg",
+            "rg",
+            &linux
+        ));
+        // os == macos and (cpu == arm64 or tool == rg)
+        assert!(!matches(
+            "os == macos and (cpu == arm64 or tool == rg)",
+            "rg",
+            &linux
+        ));
+        // (not os == macos) and tool == rg
+        assert!(matches("not os == macos and tool == rg", "rg", &linux));
+        assert!(!matches("not (os == linux and tool == rg)", "rg", &linux));
+        assert!(matches("not not tool == rg", "rg", &linux));
+    }
+
+    #[test]
+    fn parse_errors_name_the_offending_token() {
+        let error = |expr| FilterExpr::parse(expr).unwrap_err();
+        assert_eq!(
+            error("arch == arm64"),
+            "expected tool, os, cpu or kind but found 'arch' at offset 0"
+        );
+        assert_eq!(error("os = linux"), "unexpected '=' at offset 3");
+        assert_eq!(
+            error("os == solaris"),
+            "expected linux, macos or windows but found 'solaris' at offset 6"
+        );
+        assert_eq!(
+            error("(tool == rg"),
+            "expected ')' but the expression ended"
+        );
+        assert_eq!(
+            error("tool == rg os == linux"),
+            "expected 'and', 'or' or the end of the expression but found 'os' at offset 11"
+        );
+        assert_eq!(
+            error("tool is rg"),
+            "expected ==, !=, startswith, endswith or contains but found 'is' at offset 5"
+        );
+        assert_eq!(
+            error("tool == 'rg"),
+            "unterminated string starting at offset 8"
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use crate::expr::FilterExpr;
+use crate::lockfile::{Binary, Libc, Lockfile, SupportedCpu, SupportedOs, ToolDefinition};
+use crate::warn::warn;
+use crate::Common;
+use clap::Args;
+
+/// Selects which tools a subcommand operates on, and which of their binaries.
+///
+/// Tool filters apply to tool names; `--os`, `--cpu` and `--libc` then narrow the binaries
+/// of each selected tool. Tools marked `"update": false` are never selected.
+#[derive(Args, Clone, Debug, Default)]
+pub struct ToolFilter {
+    /// Only operate on this tool; if unset, all tools are selected. Tools with
+    /// `"update": false` in the lockfile are always skipped: where a tag_regex narrows which
+    /// releases are tracked, `update: false` marks a hand-managed tool with nothing to track.
+    #[clap(long)]
+    pub tool: Option<String>,
+
+    /// Also operate on the tools named in this file, one per line, ignoring blank lines and
+    /// `#` comments; unions with --tool. Names not in the lockfile are warned about.
+    #[clap(long, value_name = "PATH", value_parser = read_tool_list)]
+    pub tool_from_file: Option<ToolList>,
+
+    /// Fail instead of warning when --tool-from-file names a tool that isn't in the lockfile.
+    #[clap(long, requires = "tool_from_file")]
+    pub strict: bool,
+
+    /// Only operate on tools matching this glob (`*` and `?` wildcards); repeatable.
+    #[clap(long, value_name = "GLOB")]
+    pub include: Vec<String>,
+
+    /// Skip tools matching this glob; repeatable. Takes precedence over --include and --tool.
+    #[clap(long, value_name = "GLOB")]
+    pub exclude: Vec<String>,
+
+    /// Only operate on binaries for this OS; repeatable.
+    #[clap(long, value_enum)]
+    pub os: Vec<SupportedOs>,
+
+    /// Only operate on binaries for this CPU; repeatable.
+    #[clap(long, value_enum)]
+    pub cpu: Vec<SupportedCpu>,
+
+    /// Only operate on Linux binaries declaring this libc; repeatable. Binaries
+    /// without a `libc` are skipped.
+    #[clap(long, value_enum)]
+    pub libc: Vec<Libc>,
+
+    /// Only operate on binaries for the OS and CPU of the machine multitool runs on.
+    #[clap(long, conflicts_with_all = ["os", "cpu"])]
+    pub current_platform: bool,
+
+    /// Only operate on binaries matching this expression over `tool`, `os`, `cpu` and `kind`,
+    /// e.g. "os == linux and tool startswith 'llvm'". Combines with the other filters.
+    #[clap(long, value_name = "EXPR", value_parser = FilterExpr::parse)]
+    pub filter: Option<FilterExpr>,
+}
+
+impl ToolFilter {
+    /// Whether `name` is selected. All matching is case-insensitive.
+    pub fn matches(&self, name: &str) -> bool {
+        if self.exclude.iter().any(|pattern| glob_match(pattern, name)) {
+            return false;
+        }
+        if self.tool.is_some() || self.tool_from_file.is_some() {
+            let mut named = self.tool.iter().chain(self.listed());
+            if !named.any(|tool| tool.eq_ignore_ascii_case(name)) {
+                return false;
+            }
+        }
+        self.include.is_empty() || self.include.iter().any(|pattern| glob_match(pattern, name))
+    }
+
+    fn listed(&self) -> impl Iterator<Item = &String> {
+        self.tool_from_file.iter().flat_map(|list| &list.0)
+    }
+
+    /// Checks that every tool named by --tool-from-file is in `lockfile`, warning
+    /// about any that aren't, or with --strict failing on them.
+    pub fn check_tool_list(&self, lockfile: &Lockfile) -> Result<(), String> {
+        let unknown: Vec<&str> = self
+            .listed()
+            .filter(|name| {
+                !lockfile
+                    .tools
+                    .keys()
+                    .any(|tool| tool.eq_ignore_ascii_case(name))
+            })
+            .map(String::as_str)
+            .collect();
+        if unknown.is_empty() {
+            Ok(())
+        } else if self.strict {
+            Err(format!(
+                "--tool-from-file names tools not in the lockfile: {}",
+                unknown.join(", ")
+            ))
+        } else {
+            for name in unknown {
+                warn(format!(
+                    "--tool-from-file names '{name}', which isn't in the lockfile"
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    /// With --current-platform, narrows `--os` and `--cpu` to the host's, given
+    /// as `std::env::consts::OS` and `ARCH`.
+    pub fn apply_current_platform(&mut self, os: &str, arch: &str) -> Result<(), String> {
+        if self.current_platform {
+            let (os, cpu) = host_platform(os, arch)?;
+            self.os = vec![os];
+            self.cpu = vec![cpu];
+        }
+        Ok(())
+    }
+
+    /// Whether the tool `name` is selected and not managed by hand.
+    pub fn selects(&self, name: &str, definition: &ToolDefinition) -> bool {
+        definition.update != Some(false) && self.matches(name)
+    }
+
+    /// Whether `binary` of the selected `tool` is on one of the `--os`, `--cpu`
+    /// and `--libc` values, where an axis without any allows everything, and
+    /// satisfies any `--filter`.
+    pub fn selects_binary(&self, tool: &str, binary: &Binary) -> bool {
+        (self.os.is_empty() || self.os.contains(binary.os()))
+            && (self.cpu.is_empty() || self.cpu.contains(binary.cpu()))
+            && (self.libc.is_empty() || binary.libc().is_some_and(|libc| self.libc.contains(libc)))
+            && self
+                .filter
+                .as_ref()
+                .map_or(true, |filter| filter.matches(tool, binary))
+    }
+}
+
+/// The lockfile's names for a host whose `std::env::consts::OS` and `ARCH` are
+/// `os` and `arch`.
+fn host_platform(os: &str, arch: &str) -> Result<(SupportedOs, SupportedCpu), String> {
+    let os = match os {
+        "linux" => SupportedOs::Linux,
+        "macos" => SupportedOs::MacOS,
+        "windows" => SupportedOs::Windows,
+        _ => return Err(format!("--current-platform: unsupported OS '{os}'")),
+    };
+    let cpu = match arch {
+        "x86_64" => SupportedCpu::X86_64,
+        "aarch64" => SupportedCpu::Arm64,
+        _ => return Err(format!("--current-platform: unsupported CPU '{arch}'")),
+    };
+    Ok((os, cpu))
+}
+
+/// The tool names read from a --tool-from-file list.
+#[derive(Clone, Debug, Default)]
+pub struct ToolList(pub Vec<String>);
+
+/// The tool names in `text`: one per line, with surrounding whitespace, blank
+/// lines and everything from a `#` on ignored.
+fn parse_tool_list(text: &str) -> ToolList {
+    ToolList(
+        text.lines()
+            .map(|line| line.split('#').next().unwrap_or(line).trim())
+            .filter(|name| !name.is_empty())
+            .map(str::to_owned)
+            .collect(),
+    )
+}
+
+fn read_tool_list(path: &str) -> Result<ToolList, String> {
+    std::fs::read_to_string(path)
+        .map(|text| parse_tool_list(&text))
+        .map_err(|e| format!("unable to read '{path}': {e}"))
+}
+
+/// Matches `name` against a glob where `*` matches any run of characters and `?`
+/// matches exactly one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_ascii_lowercase().chars().collect();
+    let name: Vec<char> = name.to_ascii_lowercase().chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` seen, and the name position it is currently matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(tool: Option<&str>, include: &[&str], exclude: &[&str]) -> ToolFilter {
+        ToolFilter {
+            tool: tool.map(str::to_owned),
+            include: include.iter().map(|s| s.to_string()).collect(),
+            exclude: exclude.iter().map(|s| s.to_string()).collect(),
+            ..ToolFilter::default()
+        }
+    }
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob_match("llvm-*", "llvm-clang"));
+        assert!(glob_match("llvm-*", "llvm-"));
+        assert!(!glob_match("llvm-*", "clang"));
+        assert!(glob_match("*-lint", "go-lint"));
+        assert!(glob_match("b?zel*", "bazel-remote"));
+        assert!(glob_match("*a*b*", "xaxxbx"));
+        assert!(!glob_match("*a*b", "xaxxbx"));
+        assert!(glob_match("Tool", "tool"));
+    }
+
+    #[test]
+    fn empty_filter_selects_everything() {
+        assert!(ToolFilter::default().matches("anything"));
+    }
+
+    #[test]
+    fn manually_managed_tools_are_never_selected() {
+        let tool = |update: &str| -> ToolDefinition {
+            serde_json::from_str(&format!(r#"{{"binaries": []{update}}}"#)).unwrap()
+        };
+        let filter = ToolFilter {
+            tool: Some("internal".to_owned()),
+            ..ToolFilter::default()
+        };
+        assert!(filter.selects("internal", &tool("")));
+        assert!(filter.selects("internal", &tool(r#", "update": true"#)));
+        assert!(!filter.selects("internal", &tool(r#", "update": false"#)));
+    }
+
+    #[test]
+    fn exclude_takes_precedence_over_include() {
+        let filter = filter(None, &["llvm-*", "*-format"], &["*-format", "llvm-bolt"]);
+        assert!(filter.matches("llvm-clang"));
+        assert!(!filter.matches("llvm-bolt"));
+        assert!(!filter.matches("clang-format"));
+        assert!(!filter.matches("llvm-format"));
+        assert!(!filter.matches("buildifier"));
+    }
+
+    #[test]
+    fn exclude_takes_precedence_over_tool() {
+        assert!(filter(Some("ruff"), &[], &[]).matches("RUFF"));
+        assert!(!filter(Some("ruff"), &[], &["r*"]).matches("ruff"));
+        assert!(!filter(Some("ruff"), &["x*"], &[]).matches("ruff"));
+    }
+
+    #[test]
+    fn tool_lists_union_with_tool() {
+        let list = parse_tool_list("# tools to bump\nruff\n\n  buildifier  # formatter\n#shfmt\n");
+        assert_eq!(list.0, ["ruff", "buildifier"]);
+
+        let filter = ToolFilter {
+            tool_from_file: Some(list),
+            ..filter(Some("jq"), &[], &[])
+        };
+        assert!(filter.matches("ruff"));
+        assert!(filter.matches("Buildifier"));
+        assert!(filter.matches("jq"));
+        assert!(!filter.matches("shfmt"));
+
+        let lockfile: Lockfile = serde_json::from_str(r#"{"ruff": {"binaries": []}}"#).unwrap();
+        assert!(filter.check_tool_list(&lockfile).is_ok());
+        let strict = ToolFilter {
+            strict: true,
+            ..filter
+        };
+        assert_eq!(
+            strict.check_tool_list(&lockfile).unwrap_err(),
+            "--tool-from-file names tools not in the lockfile: buildifier"
+        );
+    }
+
+    #[test]
+    fn host_platforms() {
+        assert_eq!(
+            host_platform("linux", "x86_64"),
+            Ok((SupportedOs::Linux, SupportedCpu::X86_64))
+        );
+        assert_eq!(
+            host_platform("macos", "aarch64"),
+            Ok((SupportedOs::MacOS, SupportedCpu::Arm64))
+        );
+        assert_eq!(
+            host_platform("windows", "x86_64"),
+            Ok((SupportedOs::Windows, SupportedCpu::X86_64))
+        );
+        assert_eq!(
+            host_platform("freebsd", "x86_64"),
+            Err("--current-platform: unsupported OS 'freebsd'".to_owned())
+        );
+        assert_eq!(
+            host_platform("linux", "riscv64"),
+            Err("--current-platform: unsupported CPU 'riscv64'".to_owned())
+        );
+
+        let mut filter = ToolFilter {
+            current_platform: true,
+            ..ToolFilter::default()
+        };
+        filter.apply_current_platform("linux", "aarch64").unwrap();
+        assert_eq!(filter.os, [SupportedOs::Linux]);
+        assert_eq!(filter.cpu, [SupportedCpu::Arm64]);
+        // without the flag the host doesn't matter
+        assert!(ToolFilter::default()
+            .apply_current_platform("plan9", "mips")
+            .is_ok());
+    }
+
+    #[test]
+    fn os_and_cpu_narrow_binaries() {
+        let binary = |os: &str, cpu: &str| -> Binary {
+            serde_json::from_str(&format!(
+                r#"{{"kind": "file", "url": "https://example.com/tool", "sha256": "00", "os": "{os}", "cpu": "{cpu}"}}"#
+            ))
+            .unwrap()
+        };
+        assert!(ToolFilter::default().selects_binary("tool", &binary("windows", "arm64")));
+
+        let linux = ToolFilter {
+            os: vec![SupportedOs::Linux, SupportedOs::MacOS],
+            ..ToolFilter::default()
+        };
+        assert!(linux.selects_binary("tool", &binary("linux", "x86_64")));
+        assert!(linux.selects_binary("tool", &binary("macos", "arm64")));
+        assert!(!linux.selects_binary("tool", &binary("windows", "x86_64")));
+
+        let linux_arm64 = ToolFilter {
+            cpu: vec![SupportedCpu::Arm64],
+            ..linux
+        };
+        assert!(linux_arm64.selects_binary("tool", &binary("linux", "arm64")));
+        assert!(!linux_arm64.selects_binary("tool", &binary("linux", "x86_64")));
+    }
+
+    #[test]
+    fn libc_narrows_linux_binaries() {
+        let linux = |libc: &str| -> Binary {
+            serde_json::from_str(&format!(
+                r#"{{"kind": "file", "url": "https://example.com/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"{libc}}}"#
+            ))
+            .unwrap()
+        };
+        let musl = ToolFilter {
+            libc: vec![Libc::Musl],
+            ..ToolFilter::default()
+        };
+        assert!(musl.selects_binary("tool", &linux(r#", "libc": "musl""#)));
+        assert!(!musl.selects_binary("tool", &linux(r#", "libc": "gnu""#)));
+        assert!(!musl.selects_binary("tool", &linux("")));
+
+        // --current-platform narrows the OS and CPU, leaving --libc to pick the build
+        let mut host = ToolFilter {
+            current_platform: true,
+            ..musl
+        };
+        host.apply_current_platform("linux", "x86_64").unwrap();
+        assert!(host.selects_binary("tool", &linux(r#", "libc": "musl""#)));
+        assert!(!host.selects_binary("tool", &linux(r#", "libc": "gnu""#)));
+    }
+}
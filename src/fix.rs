@@ -0,0 +1,166 @@
+//! `validate --fix`: mechanical repairs for lockfiles that have drifted from
+//! the form multitool writes, such as hand edits or output of older versions.
+
+use crate::{
+    lockfile::{archive_extension, Binary, Lockfile, PlatformNames},
+    Common,
+};
+use serde_json::Value;
+
+fn sha256_mut(binary: &mut Binary) -> &mut String {
+    match binary {
+        Binary::File(binary) => &mut binary.sha256,
+        Binary::Archive(binary) => &mut binary.sha256,
+        Binary::Pkg(binary) => &mut binary.sha256,
+        Binary::Dir(binary) => &mut binary.sha256,
+    }
+}
+
+/// Applies every fix to `lockfile`, returning a description of each. `raw` is
+/// the lockfile as parsed JSON, for what parsing normalizes away, and `names`
+/// is how it will be written.
+///
+/// The fixes are:
+/// - uppercase hex in a `sha256` is lowercased;
+/// - an archive or dir URL whose extension is hidden behind a query string or
+///   fragment gets the `type` its extension names, as Bazel can't infer it;
+/// - legacy CPU spellings (`amd64`, `aarch64`) are written canonically, unless
+///   `names` asks for them;
+/// - binaries are sorted by platform.
+pub fn fix(lockfile: &mut Lockfile, raw: &Value, names: PlatformNames) -> Vec<String> {
+    let mut fixes = Vec::new();
+    for (tool, definition) in &mut lockfile.tools {
+        let raw_binaries = raw[tool.as_str()]["binaries"].as_array();
+        for (i, binary) in definition.binaries.iter_mut().enumerate() {
+            let platform = format!("tool '{tool}' ({}/{})", binary.os(), binary.cpu());
+
+            let sha256 = sha256_mut(binary);
+            if sha256.chars().any(|c| c.is_ascii_uppercase()) {
+                sha256.make_ascii_lowercase();
+                fixes.push(format!("{platform}: lowercased the sha256"));
+            }
+
+            let (url, type_) = match binary {
+                Binary::Archive(archive) => (&archive.url, &mut archive.type_),
+                Binary::Dir(dir) => (&dir.url, &mut dir.type_),
+                Binary::File(_) | Binary::Pkg(_) => (&String::new(), &mut None),
+            };
+            if type_.is_none() && url.contains(['?', '#']) {
+                if let Some(extension) = archive_extension(url) {
+                    *type_ = Some(extension.to_owned());
+                    fixes.push(format!("{platform}: set \"type\": \"{extension}\""));
+                }
+            }
+
+            let raw_cpu = raw_binaries
+                .and_then(|binaries| binaries.get(i))
+                .and_then(|binary| binary["cpu"].as_str());
+            if let (PlatformNames::Canonical, Some(cpu @ ("amd64" | "aarch64"))) = (names, raw_cpu)
+            {
+                fixes.push(format!(
+                    "{platform}: spelled the cpu \"{cpu}\" as \"{}\"",
+                    binary.cpu()
+                ));
+            }
+        }
+
+        let keys: Vec<String> = definition.binaries.iter().map(|b| b.sort_key()).collect();
+        if keys.windows(2).any(|pair| pair[0] > pair[1]) {
+            definition.binaries.sort_by_key(|binary| binary.sort_key());
+            fixes.push(format!("tool '{tool}': sorted the binaries by platform"));
+        }
+    }
+    fixes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixed(json: &str) -> (Lockfile, Vec<String>) {
+        let mut lockfile: Lockfile = serde_json::from_str(json).unwrap();
+        let raw: Value = serde_json::from_str(json).unwrap();
+        let fixes = fix(&mut lockfile, &raw, PlatformNames::Canonical);
+        (lockfile, fixes)
+    }
+
+    #[test]
+    fn fixes_sha_case() {
+        let (lockfile, fixes) = fixed(
+            r#"{"tool": {"binaries": [{"kind": "file", "url": "https://example.com/tool", "sha256": "2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824", "os": "linux", "cpu": "x86_64"}]}}"#,
+        );
+        assert_eq!(
+            lockfile.tools["tool"].binaries[0].sha256(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(fixes, ["tool 'tool' (linux/x86_64): lowercased the sha256"]);
+    }
+
+    #[test]
+    fn fixes_hidden_archive_types() {
+        let (lockfile, fixes) = fixed(
+            r#"{"tool": {"binaries": [
+              {"kind": "archive", "url": "https://example.com/tool.tar.gz?raw=1", "file": "tool", "sha256": "00", "os": "linux", "cpu": "x86_64"},
+              {"kind": "archive", "url": "https://example.com/tool.zip", "file": "tool", "sha256": "00", "os": "macos", "cpu": "arm64"},
+              {"kind": "dir", "url": "https://example.com/tool.tar.zst#dl", "sha256": "00", "os": "windows", "cpu": "x86_64"}
+            ]}}"#,
+        );
+        let types: Vec<Option<&str>> = lockfile.tools["tool"]
+            .binaries
+            .iter()
+            .map(|binary| match binary {
+                Binary::Archive(archive) => archive.type_.as_deref(),
+                Binary::Dir(dir) => dir.type_.as_deref(),
+                _ => unreachable!(),
+            })
+            .collect();
+        // an extension Bazel can already see is left to it
+        assert_eq!(types, [Some("tar.gz"), None, Some("tar.zst")]);
+        assert_eq!(
+            fixes,
+            [
+                "tool 'tool' (linux/x86_64): set \"type\": \"tar.gz\"",
+                "tool 'tool' (windows/x86_64): set \"type\": \"tar.zst\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn fixes_legacy_cpus_and_order() {
+        let json = r#"{"tool": {"binaries": [
+          {"kind": "file", "url": "https://example.com/tool-mac", "sha256": "00", "os": "macos", "cpu": "aarch64"},
+          {"kind": "file", "url": "https://example.com/tool-linux", "sha256": "00", "os": "linux", "cpu": "amd64"}
+        ]}}"#;
+        let (lockfile, fixes) = fixed(json);
+        let urls: Vec<&str> = lockfile.tools["tool"]
+            .binaries
+            .iter()
+            .map(|binary| binary.url())
+            .collect();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/tool-linux",
+                "https://example.com/tool-mac"
+            ]
+        );
+        assert_eq!(
+            fixes,
+            [
+                "tool 'tool' (macos/arm64): spelled the cpu \"aarch64\" as \"arm64\"",
+                "tool 'tool' (linux/x86_64): spelled the cpu \"amd64\" as \"x86_64\"",
+                "tool 'tool': sorted the binaries by platform",
+            ]
+        );
+
+        // lockfiles written with legacy names keep them
+        let mut lockfile: Lockfile = serde_json::from_str(json).unwrap();
+        let raw: Value = serde_json::from_str(json).unwrap();
+        assert_eq!(fix(&mut lockfile, &raw, PlatformNames::Legacy).len(), 1);
+
+        let (_, fixes) = fixed(
+            r#"{"tool": {"binaries": [{"kind": "file", "url": "https://example.com/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}]}}"#,
+        );
+        assert!(fixes.is_empty());
+    }
+}
@@ -0,0 +1,979 @@
+use crate::config::{Settings, MAX_API_PAGES};
+use crate::error::Error;
+use crate::github_app;
+use crate::report::ReleaseNotes;
+use crate::retry;
+use crate::timestamp;
+use crate::warn::warn;
+use regex::Regex;
+use reqwest::{
+    blocking::RequestBuilder,
+    header::{HeaderMap, ACCEPT, CONTENT_TYPE, LINK},
+    StatusCode,
+};
+use serde_json::Value;
+use std::{collections::HashMap, sync::LazyLock, time::Instant};
+
+static GITHUB_RELEASE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?x)
+        https://github\.com/
+        (?P<org>[A-Za-z0-9_-]+)/
+        (?P<repo>[A-Za-z0-9_-]+)/
+        releases/download/
+        (?P<version>v?[^/]+)/
+        (?P<path>[^?\#]+)
+        (?P<suffix>(?:[?\#].*)?)",
+    )
+    .unwrap()
+});
+
+/// GitHub's generated source archives of a tag, which aren't release assets.
+/// Only the explicit `refs/tags/` form names a tag: a bare `archive/<ref>`
+/// could as well be a branch or a commit pin, which must not be moved to the
+/// latest release.
+static GITHUB_ARCHIVE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(
+        r"(?x)
+        https://github\.com/
+        (?P<org>[A-Za-z0-9_-]+)/
+        (?P<repo>[A-Za-z0-9_-]+)/
+        archive/
+        (?P<refs>refs/tags/)
+        (?P<version>[^/?\#]+?)
+        (?P<extension>\.tar\.gz|\.zip)
+        (?P<suffix>(?:[?\#].*)?)$",
+    )
+    .unwrap()
+});
+
+/// The REST API version every request asks for. Bump it deliberately, after
+/// checking GitHub's list of breaking changes for the new version.
+pub const API_VERSION: &str = "2022-11-28";
+
+/// Adds what every GitHub API request carries: GitHub's own media type, the
+/// pinned [`API_VERSION`] and the token, if there is one. A `--token` for the
+/// API's host takes precedence over a GitHub App's installation token, which
+/// takes precedence over `--github-token`.
+pub fn api_request(request: RequestBuilder, settings: &Settings) -> RequestBuilder {
+    let request = request
+        .header(ACCEPT, "application/vnd.github+json")
+        .header("X-GitHub-Api-Version", API_VERSION);
+    let token = settings
+        .token_for(&settings.github_api_url)
+        .map(str::to_owned)
+        .or_else(|| github_app::token_for(settings, &settings.github_api_url))
+        .or_else(|| settings.github_token.clone());
+    match token {
+        Some(token) => request.bearer_auth(token),
+        None => request,
+    }
+}
+
+pub struct GitHubRelease<'a> {
+    pub org: &'a str,
+    pub repo: &'a str,
+    pub version: &'a str,
+    pub path: &'a str,
+    /// Any query string and/or fragment following the asset path, including
+    /// the leading `?` or `#`; empty if there is none.
+    pub suffix: &'a str,
+    /// For a source archive, what comes between `archive/` and the tag, always
+    /// `refs/tags/`. `path` is then the archive's extension.
+    pub source_archive: Option<&'a str>,
+}
+
+impl GitHubRelease<'_> {
+    /// The release asset or source archive `url` downloads.
+    pub fn from(url: &str) -> Option<GitHubRelease<'_>> {
+        if let Some(capture) = GITHUB_RELEASE_PATTERN.captures(url) {
+            let (_, [org, repo, version, path, suffix]) = capture.extract();
+            return Some(GitHubRelease {
+                org,
+                repo,
+                version,
+                path,
+                suffix,
+                source_archive: None,
+            });
+        }
+        GITHUB_ARCHIVE_PATTERN.captures(url).map(|capture| {
+            let (_, [org, repo, refs, version, extension, suffix]) = capture.extract();
+            GitHubRelease {
+                org,
+                repo,
+                version,
+                path: extension,
+                suffix,
+                source_archive: Some(refs),
+            }
+        })
+    }
+
+    /// The download URL of this release's asset under a different tag. Only the
+    /// asset path is rewritten; any query string or fragment is kept verbatim.
+    pub fn url_for_tag(&self, tag: &str) -> String {
+        self.url_for_release(tag, self.version, tag)
+    }
+
+    /// Like [`GitHubRelease::url_for_tag`], replacing `version` with `latest` in
+    /// the asset path, for when versions don't come from tags.
+    pub fn url_for_release(&self, tag: &str, version: &str, latest: &str) -> String {
+        if let Some(refs) = self.source_archive {
+            // a source archive's URL names nothing but the tag
+            return format!(
+                "https://github.com/{}/{}/archive/{refs}{tag}{}{}",
+                self.org, self.repo, self.path, self.suffix
+            );
+        }
+        format!(
+            "https://github.com/{}/{}/releases/download/{tag}/{}{}",
+            self.org,
+            self.repo,
+            crate::substitute_version(self.path, version, latest),
+            self.suffix
+        )
+    }
+}
+
+/// Warns when the artifact at `url`, a GitHub-generated source archive, hashes
+/// differently under the same tag. GitHub doesn't promise to keep the bytes of
+/// these archives stable, so this is usually a regenerated archive rather than
+/// a moved tag.
+pub fn warn_if_source_archive(url: &str) {
+    if let Some(release) =
+        GitHubRelease::from(url).filter(|release| release.source_archive.is_some())
+    {
+        warn(format!(
+            "the sha256 of {url} changed under the same tag {}; GitHub may have regenerated the source archive of {}/{}",
+            release.version, release.org, release.repo
+        ));
+    }
+}
+
+/// A cached GitHub API response.
+pub struct ApiResponse {
+    status: StatusCode,
+    body: String,
+    /// The `rel="next"` URL from the `Link` header, for paginated endpoints.
+    next: Option<String>,
+}
+
+/// Fetches `url` from the GitHub API, caching the response by URL so each
+/// endpoint is only queried once.
+fn fetch<'c>(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    cache: &'c mut HashMap<String, ApiResponse>,
+    deadline: Option<Instant>,
+    url: &str,
+) -> Result<&'c ApiResponse, Error> {
+    if !cache.contains_key(url) {
+        let response = retry::send(api_request(client.get(url), settings), settings, deadline)?;
+        if settings.verbose {
+            if let Some(summary) = rate_limit_summary(response.headers()) {
+                eprintln!("{url}: {summary}");
+            }
+        }
+        let status = response.status();
+        let next = next_link(response.headers());
+        let body = response.text()?;
+        cache.insert(url.to_owned(), ApiResponse { status, body, next });
+    }
+    Ok(&cache[url])
+}
+
+/// Looks up the tag of the latest release of the release's repo. With a
+/// `tag_regex` it is the newest stable release whose tag matches.
+pub fn latest_tag(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    cache: &mut HashMap<String, ApiResponse>,
+    deadline: Option<Instant>,
+    release: &GitHubRelease,
+    tag_regex: Option<&Regex>,
+) -> Result<String, Error> {
+    let org = release.org;
+    let repo = release.repo;
+
+    if let Some(pattern) = tag_regex {
+        let tag = find_tag(client, settings, cache, deadline, release, |candidate| {
+            let flag = |name: &str| candidate[name].as_bool().unwrap_or(false);
+            let tag = candidate["tag_name"].as_str().unwrap_or_default();
+            !flag("draft") && !flag("prerelease") && pattern.is_match(tag)
+        })?;
+        return tag.ok_or_else(|| {
+            format!("{org}/{repo} has no releases matching tag_regex '{pattern}'").into()
+        });
+    }
+
+    let url = latest_release_url(settings, org, repo);
+    if fetch(client, settings, cache, deadline, &url)?.status == StatusCode::NOT_FOUND {
+        // GitHub answers 404 both for a missing repo and for one whose releases
+        // are all drafts or prereleases; the repo endpoint tells them apart.
+        let repo_url = format!(
+            "{}/repos/{org}/{repo}",
+            settings.github_api_url.trim_end_matches('/')
+        );
+        let message = if fetch(client, settings, cache, deadline, &repo_url)?.status
+            == StatusCode::NOT_FOUND
+        {
+            format!(
+                "{org}/{repo} does not exist or is not visible with the configured GitHub token"
+            )
+        } else {
+            format!("{org}/{repo} has no published stable release, and prereleases aren't tracked")
+        };
+        return Err(message.into());
+    }
+    let ApiResponse {
+        status, body: raw, ..
+    } = &cache[&url];
+    if !status.is_success() {
+        return Err(format!("GitHub API returned {status} for {url}").into());
+    }
+
+    let response: Value = serde_json::from_str(raw)?;
+    let latest_tag = response["tag_name"]
+        .as_str()
+        .unwrap_or_else(|| panic!("Failed to find tag_name in response:\n===\n{raw}\n===\n"));
+
+    Ok(latest_tag.to_owned())
+}
+
+/// A release asset, as listed by the GitHub API.
+pub struct Asset {
+    pub url: String,
+    pub size: Option<u64>,
+}
+
+fn release_by_tag_url(settings: &Settings, release: &GitHubRelease, tag: &str) -> String {
+    format!(
+        "{}/repos/{}/{}/releases/tags/{tag}",
+        settings.github_api_url.trim_end_matches('/'),
+        release.org,
+        release.repo
+    )
+}
+
+/// The assets of the release's repo at `tag`.
+pub fn release_assets(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    cache: &mut HashMap<String, ApiResponse>,
+    deadline: Option<Instant>,
+    release: &GitHubRelease,
+    tag: &str,
+) -> Result<Vec<Asset>, Error> {
+    let url = release_by_tag_url(settings, release, tag);
+    let response: Value =
+        serde_json::from_str(&fetch(client, settings, cache, deadline, &url)?.body)?;
+    let assets = response["assets"]
+        .as_array()
+        .ok_or_else(|| format!("Failed to find assets in {url}"))?;
+    Ok(assets
+        .iter()
+        .filter_map(|asset| {
+            Some(Asset {
+                url: asset["browser_download_url"].as_str()?.to_owned(),
+                size: asset["size"].as_u64(),
+            })
+        })
+        .collect())
+}
+
+/// The `name` of the release of `release`'s repo at `tag`, which must be set.
+pub fn release_name(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    cache: &mut HashMap<String, ApiResponse>,
+    deadline: Option<Instant>,
+    release: &GitHubRelease,
+    tag: &str,
+) -> Result<String, Error> {
+    let url = release_by_tag_url(settings, release, tag);
+    let response = fetch(client, settings, cache, deadline, &url)?;
+    if !response.status.is_success() {
+        return Err(format!("GitHub API returned {} for {url}", response.status).into());
+    }
+    let body: Value = serde_json::from_str(&response.body)?;
+    match body["name"].as_str().map(str::trim) {
+        Some(name) if !name.is_empty() => Ok(name.to_owned()),
+        _ => Err(format!(
+            "release {tag} of {}/{} has no name to take its version from (version_from: name)",
+            release.org, release.repo
+        )
+        .into()),
+    }
+}
+
+/// When the release of `release`'s repo at `tag` was published, in seconds
+/// since the epoch.
+pub fn release_published_at(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    cache: &mut HashMap<String, ApiResponse>,
+    deadline: Option<Instant>,
+    release: &GitHubRelease,
+    tag: &str,
+) -> Result<u64, Error> {
+    let url = release_by_tag_url(settings, release, tag);
+    let response = fetch(client, settings, cache, deadline, &url)?;
+    if !response.status.is_success() {
+        return Err(format!("GitHub API returned {} for {url}", response.status).into());
+    }
+    let body: Value = serde_json::from_str(&response.body)?;
+    body["published_at"]
+        .as_str()
+        .and_then(timestamp::parse)
+        .ok_or_else(|| {
+            format!(
+                "release {tag} of {}/{} has no published_at timestamp",
+                release.org, release.repo
+            )
+            .into()
+        })
+}
+
+/// Characters of a release's notes kept in reports; longer notes are cut off
+/// with an ellipsis.
+const RELEASE_NOTES_LEN: usize = 1000;
+
+/// The page and notes of the release of `release`'s repo at `tag`, for
+/// `update --include-release-notes`.
+pub fn release_notes(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    cache: &mut HashMap<String, ApiResponse>,
+    deadline: Option<Instant>,
+    release: &GitHubRelease,
+    tag: &str,
+) -> Result<ReleaseNotes, Error> {
+    let url = release_by_tag_url(settings, release, tag);
+    let response = fetch(client, settings, cache, deadline, &url)?;
+    if !response.status.is_success() {
+        return Err(format!("GitHub API returned {} for {url}", response.status).into());
+    }
+    let body: Value = serde_json::from_str(&response.body)?;
+    let html_url = body["html_url"].as_str().map_or_else(
+        || {
+            format!(
+                "https://github.com/{}/{}/releases/tag/{tag}",
+                release.org, release.repo
+            )
+        },
+        str::to_owned,
+    );
+    let notes = body["body"]
+        .as_str()
+        .map(str::trim)
+        .filter(|notes| !notes.is_empty())
+        .map(|notes| match notes.char_indices().nth(RELEASE_NOTES_LEN) {
+            Some((end, _)) => format!("{}…", notes[..end].trim_end()),
+            None => notes.to_owned(),
+        });
+    Ok(ReleaseNotes {
+        url: html_url,
+        notes,
+    })
+}
+
+fn latest_release_url(settings: &Settings, org: &str, repo: &str) -> String {
+    format!(
+        "{}/repos/{org}/{repo}/releases/latest",
+        settings.github_api_url.trim_end_matches('/')
+    )
+}
+
+/// Repos looked up per GraphQL request, well under GitHub's node limits.
+const GRAPHQL_BATCH_SIZE: usize = 100;
+
+/// The GraphQL endpoint alongside a REST API base URL; GitHub Enterprise Server
+/// serves REST under `/api/v3` and GraphQL under `/api/graphql`.
+fn graphql_url(api_url: &str) -> String {
+    let api_url = api_url.trim_end_matches('/');
+    match api_url.strip_suffix("/api/v3") {
+        Some(host) => format!("{host}/api/graphql"),
+        None => format!("{api_url}/graphql"),
+    }
+}
+
+/// Looks up the latest release tags of `repos` (org, repo) with batched GraphQL
+/// queries, seeding `cache` as if each `releases/latest` endpoint had been
+/// fetched over REST. Returns how many repos were resolved; repos without a
+/// latest release, or that GraphQL reports errors for, are left uncached so
+/// `latest_tag` falls back to REST for them.
+pub fn prefetch_latest_tags(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    cache: &mut HashMap<String, ApiResponse>,
+    repos: &[(&str, &str)],
+) -> Result<usize, Error> {
+    let url = graphql_url(&settings.github_api_url);
+    let mut found = 0;
+    for batch in repos.chunks(GRAPHQL_BATCH_SIZE) {
+        let fields: String = batch
+            .iter()
+            .enumerate()
+            .map(|(i, (org, repo))| {
+                format!(
+                    "r{i}: repository(owner: {}, name: {}) {{ latestRelease {{ tagName }} }} ",
+                    Value::from(*org),
+                    Value::from(*repo)
+                )
+            })
+            .collect();
+        let query = serde_json::json!({ "query": format!("query {{ {fields}}}") });
+
+        let request = client
+            .post(&url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(query.to_string());
+        let response = retry::send(api_request(request, settings), settings, None)?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(format!("HTTP {status} from {url}").into());
+        }
+        let response: Value = serde_json::from_str(&response.text()?)?;
+        let data = response["data"]
+            .as_object()
+            .ok_or_else(|| format!("GraphQL response has no data: {}", response["errors"]))?;
+
+        for (i, (org, repo)) in batch.iter().enumerate() {
+            let Some(tag) = data
+                .get(&format!("r{i}"))
+                .and_then(|repository| repository["latestRelease"]["tagName"].as_str())
+            else {
+                continue;
+            };
+            cache.insert(
+                latest_release_url(settings, org, repo),
+                ApiResponse {
+                    status: StatusCode::OK,
+                    body: serde_json::json!({ "tag_name": tag }).to_string(),
+                    next: None,
+                },
+            );
+            found += 1;
+        }
+    }
+    Ok(found)
+}
+
+/// Scans the release's repo's releases newest first and returns the tag of the
+/// first one `accept` takes, stopping as soon as one is found.
+///
+/// Pages of `--api-page-size` releases are followed via their `Link` headers,
+/// up to [`MAX_API_PAGES`]; running into the cap is an error rather than a
+/// silent miss.
+pub fn find_tag(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    cache: &mut HashMap<String, ApiResponse>,
+    deadline: Option<Instant>,
+    release: &GitHubRelease,
+    accept: impl Fn(&Value) -> bool,
+) -> Result<Option<String>, Error> {
+    let mut url = format!(
+        "{}/repos/{}/{}/releases?per_page={}",
+        settings.github_api_url.trim_end_matches('/'),
+        release.org,
+        release.repo,
+        settings.api_page_size
+    );
+    for _ in 0..MAX_API_PAGES {
+        let response = fetch(client, settings, cache, deadline, &url)?;
+        let releases: Vec<Value> = serde_json::from_str(&response.body)?;
+        if let Some(found) = releases.iter().find(|candidate| accept(candidate)) {
+            let tag = found["tag_name"]
+                .as_str()
+                .ok_or_else(|| format!("release without tag_name in {url}"))?;
+            return Ok(Some(tag.to_owned()));
+        }
+        match &response.next {
+            Some(next) => url = next.clone(),
+            None => return Ok(None),
+        }
+    }
+    Err(format!(
+        "no matching release in the first {MAX_API_PAGES} pages of {}/{} releases",
+        release.org, release.repo
+    )
+    .into())
+}
+
+/// The `rel="next"` target of a `Link` header, e.g.
+/// `<https://api.github.com/repositories/1/releases?page=2>; rel="next", <…>; rel="last"`.
+fn next_link(headers: &HeaderMap) -> Option<String> {
+    let link = headers.get(LINK)?.to_str().ok()?;
+    link.split(',').find_map(|entry| {
+        let (target, params) = entry.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim() == r#"rel="next""#)
+            .then(|| {
+                let target = target.trim();
+                target
+                    .strip_prefix('<')
+                    .and_then(|target| target.strip_suffix('>'))
+                    .unwrap_or(target)
+                    .to_owned()
+            })
+    })
+}
+
+/// Summarizes GitHub's `X-RateLimit-*` response headers, if present.
+fn rate_limit_summary(headers: &HeaderMap) -> Option<String> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let remaining = header("x-ratelimit-remaining")?;
+    let limit = header("x-ratelimit-limit").unwrap_or("?");
+    let reset = header("x-ratelimit-reset").unwrap_or("?");
+    Some(format!(
+        "rate limit {remaining}/{limit} remaining, resets at {reset} (unix time)"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{settings, Response, Server};
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn url_for_tag_windows_arm64() {
+        let url = "https://github.com/org/tool/releases/download/v1.2.3/tool-v1.2.3-aarch64-pc-windows-msvc.zip";
+        let release = GitHubRelease::from(url).unwrap();
+        assert_eq!(
+            release.url_for_tag("v1.3.0"),
+            "https://github.com/org/tool/releases/download/v1.3.0/tool-v1.3.0-aarch64-pc-windows-msvc.zip"
+        );
+    }
+
+    #[test]
+    fn from_separates_query_and_fragment() {
+        let url = "https://github.com/org/tool/releases/download/v1.2.3/tool-1.2.3.tar.gz";
+        let release = GitHubRelease::from(url).unwrap();
+        assert_eq!(release.path, "tool-1.2.3.tar.gz");
+        assert_eq!(release.suffix, "");
+        assert_eq!(
+            release.url_for_tag("v1.3.0"),
+            "https://github.com/org/tool/releases/download/v1.3.0/tool-1.3.0.tar.gz"
+        );
+
+        let url = "https://github.com/org/tool/releases/download/v1.2.3/tool-1.2.3.tar.gz?sig=1.2.3&x=y#frag";
+        let release = GitHubRelease::from(url).unwrap();
+        assert_eq!(release.version, "v1.2.3");
+        assert_eq!(release.path, "tool-1.2.3.tar.gz");
+        assert_eq!(release.suffix, "?sig=1.2.3&x=y#frag");
+        assert_eq!(
+            release.url_for_tag("v1.3.0"),
+            "https://github.com/org/tool/releases/download/v1.3.0/tool-1.3.0.tar.gz?sig=1.2.3&x=y#frag"
+        );
+
+        let url = "https://github.com/org/tool/releases/download/v1.2.3/tool#readme";
+        let release = GitHubRelease::from(url).unwrap();
+        assert_eq!((release.path, release.suffix), ("tool", "#readme"));
+    }
+
+    #[test]
+    fn source_archives_rewrite_only_the_tag() {
+        let url = "https://github.com/org/tool/archive/refs/tags/v1.2.3.tar.gz";
+        let release = GitHubRelease::from(url).unwrap();
+        assert_eq!((release.org, release.repo), ("org", "tool"));
+        assert_eq!(release.version, "v1.2.3");
+        assert_eq!(release.source_archive, Some("refs/tags/"));
+        assert_eq!(
+            release.url_for_tag("v1.3.0"),
+            "https://github.com/org/tool/archive/refs/tags/v1.3.0.tar.gz"
+        );
+        // release names don't appear in source archive URLs
+        assert_eq!(
+            release.url_for_release("v1.3.0", "Tool 1.2.3", "Tool 1.3.0"),
+            "https://github.com/org/tool/archive/refs/tags/v1.3.0.tar.gz"
+        );
+
+        let url = "https://github.com/org/tool/archive/refs/tags/1.2.3-rc.1.zip?raw=1";
+        let release = GitHubRelease::from(url).unwrap();
+        assert_eq!(release.version, "1.2.3-rc.1");
+        assert_eq!(
+            release.url_for_tag("1.3.0"),
+            "https://github.com/org/tool/archive/refs/tags/1.3.0.zip?raw=1"
+        );
+
+        assert!(GitHubRelease::from("https://github.com/org/tool/archive/main").is_none());
+        assert!(
+            GitHubRelease::from("https://github.com/org/tool/releases/download/v1.2.3/tool")
+                .unwrap()
+                .source_archive
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn commit_and_branch_archives_are_not_releases() {
+        for url in [
+            "https://github.com/org/tool/archive/0123456789abcdef0123456789abcdef01234567.tar.gz",
+            "https://github.com/org/tool/archive/main.tar.gz",
+            "https://github.com/org/tool/archive/v1.2.3.zip",
+            "https://github.com/org/tool/archive/refs/heads/main.tar.gz",
+        ] {
+            assert!(GitHubRelease::from(url).is_none(), "{url}");
+        }
+    }
+
+    #[test]
+    fn rate_limit_summary_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(rate_limit_summary(&headers), None);
+
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("57"));
+        headers.insert("x-ratelimit-limit", HeaderValue::from_static("60"));
+        headers.insert("x-ratelimit-reset", HeaderValue::from_static("1717171717"));
+        assert_eq!(
+            rate_limit_summary(&headers).unwrap(),
+            "rate limit 57/60 remaining, resets at 1717171717 (unix time)"
+        );
+    }
+
+    #[test]
+    fn next_link_from_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(next_link(&headers), None);
+
+        headers.insert(
+            LINK,
+            HeaderValue::from_static(
+                r#"<https://api.github.com/repositories/1/releases?page=1>; rel="prev", <https://api.github.com/repositories/1/releases?page=3>; rel="next""#,
+            ),
+        );
+        assert_eq!(
+            next_link(&headers).unwrap(),
+            "https://api.github.com/repositories/1/releases?page=3"
+        );
+
+        headers.insert(
+            LINK,
+            HeaderValue::from_static(r#"<https://x/?page=1>; rel="first""#),
+        );
+        assert_eq!(next_link(&headers), None);
+    }
+
+    #[test]
+    fn find_tag_follows_pagination() {
+        let server = Server::start(|request| {
+            match request.path.as_str() {
+            "/repos/org/tool/releases?per_page=2" => Response::ok(
+                r#"[{"tag_name": "v3.0.0", "draft": true}, {"tag_name": "v2.1.0-rc1", "prerelease": true}]"#,
+            )
+            .header(
+                "Link",
+                &format!(
+                    r#"<http://{}/repos/org/tool/releases?per_page=2&page=2>; rel="next""#,
+                    request.headers["host"]
+                ),
+            ),
+            "/repos/org/tool/releases?per_page=2&page=2" => {
+                Response::ok(r#"[{"tag_name": "v2.0.0"}, {"tag_name": "v1.0.0"}]"#)
+            }
+            _ => Response::status(404, ""),
+        }
+        });
+        let settings = Settings {
+            api_page_size: 2,
+            ..settings(&server.url)
+        };
+        let client = settings.client();
+        let release = GitHubRelease::from(
+            "https://github.com/org/tool/releases/download/v1.0.0/tool-linux-x86_64.tar.gz",
+        )
+        .unwrap();
+        let mut cache = HashMap::new();
+
+        let stable = |candidate: &Value| {
+            !candidate["draft"].as_bool().unwrap_or(false)
+                && !candidate["prerelease"].as_bool().unwrap_or(false)
+        };
+        let tag = find_tag(&client, &settings, &mut cache, None, &release, stable).unwrap();
+        assert_eq!(tag.as_deref(), Some("v2.0.0"));
+        assert_eq!(server.requests().len(), 2);
+
+        // stops on the first page when it already has a match, reusing the cache
+        let tag = find_tag(&client, &settings, &mut cache, None, &release, |_| true).unwrap();
+        assert_eq!(tag.as_deref(), Some("v3.0.0"));
+        assert_eq!(server.requests().len(), 2);
+
+        let tag = find_tag(&client, &settings, &mut cache, None, &release, |_| false).unwrap();
+        assert_eq!(tag, None);
+    }
+
+    #[test]
+    fn latest_tag_explains_missing_latest_release() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/prerelease-only" => Response::ok(r#"{"full_name": "org/prerelease-only"}"#),
+            _ => Response::status(404, r#"{"message": "Not Found"}"#),
+        });
+        let settings = settings(&server.url);
+        let client = settings.client();
+        let mut cache = HashMap::new();
+        let mut latest = |repo: &str| {
+            let url = format!("https://github.com/org/{repo}/releases/download/v1.0.0/{repo}");
+            latest_tag(
+                &client,
+                &settings,
+                &mut cache,
+                None,
+                &GitHubRelease::from(&url).unwrap(),
+                None,
+            )
+            .unwrap_err()
+            .to_string()
+        };
+
+        assert_eq!(
+            latest("prerelease-only"),
+            "org/prerelease-only has no published stable release, and prereleases aren't tracked"
+        );
+        assert_eq!(
+            latest("missing"),
+            "org/missing does not exist or is not visible with the configured GitHub token"
+        );
+    }
+
+    #[test]
+    fn api_requests_pin_media_type_and_version() {
+        let server = Server::start(|_| Response::ok(r#"{"tag_name": "v1.1.0"}"#));
+        let settings = Settings {
+            github_token: Some("secret".to_owned()),
+            ..settings(&server.url)
+        };
+        let release =
+            GitHubRelease::from("https://github.com/org/tool/releases/download/v1.0.0/tool-linux")
+                .unwrap();
+        let tag = latest_tag(
+            &settings.client(),
+            &settings,
+            &mut HashMap::new(),
+            None,
+            &release,
+            None,
+        );
+        assert_eq!(tag.unwrap(), "v1.1.0");
+
+        let request = &server.requests()[0];
+        assert_eq!(request.headers["accept"], "application/vnd.github+json");
+        assert_eq!(request.headers["x-github-api-version"], API_VERSION);
+        assert_eq!(request.headers["authorization"], "Bearer secret");
+
+        let settings = Settings {
+            host_tokens: crate::config::host_tokens(
+                [("127.0.0.1".to_owned(), "GHES_TOKEN".to_owned())],
+                |_| Some("ghes-secret".to_owned()),
+            ),
+            ..settings
+        };
+        let tag = latest_tag(
+            &settings.client(),
+            &settings,
+            &mut HashMap::new(),
+            None,
+            &release,
+            None,
+        );
+        assert_eq!(tag.unwrap(), "v1.1.0");
+        assert_eq!(
+            server.requests()[1].headers["authorization"],
+            "Bearer ghes-secret"
+        );
+    }
+
+    #[test]
+    fn graphql_url_for_api_url() {
+        assert_eq!(
+            graphql_url("https://api.github.com"),
+            "https://api.github.com/graphql"
+        );
+        assert_eq!(
+            graphql_url("https://ghe.example.com/api/v3/"),
+            "https://ghe.example.com/api/graphql"
+        );
+    }
+
+    #[test]
+    fn prefetch_latest_tags_seeds_rest_cache() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/graphql" => Response::ok(
+                r#"{"data": {"r0": {"latestRelease": {"tagName": "v2.0.0"}}, "r1": {"latestRelease": null}}}"#,
+            ),
+            "/repos/org/b/releases/latest" => Response::ok(r#"{"tag_name": "v0.9.0"}"#),
+            _ => Response::status(404, ""),
+        });
+        let settings = Settings {
+            github_token: Some("secret".to_owned()),
+            ..settings(&server.url)
+        };
+        let client = settings.client();
+        let mut cache = HashMap::new();
+
+        let found = prefetch_latest_tags(
+            &client,
+            &settings,
+            &mut cache,
+            &[("org", "a"), ("org", "b")],
+        );
+        assert_eq!(found.unwrap(), 1);
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].headers["authorization"], "Bearer secret");
+        assert!(requests[0]
+            .body
+            .contains(r#"r1: repository(owner: \"org\", name: \"b\")"#));
+
+        let release = |repo: &str| {
+            format!("https://github.com/org/{repo}/releases/download/v1.0.0/{repo}.tar.gz")
+        };
+        let a = release("a");
+        let tag = latest_tag(
+            &client,
+            &settings,
+            &mut cache,
+            None,
+            &GitHubRelease::from(&a).unwrap(),
+            None,
+        );
+        assert_eq!(tag.unwrap(), "v2.0.0");
+        assert_eq!(server.requests().len(), 1);
+
+        // repos GraphQL couldn't resolve still go to REST
+        let b = release("b");
+        let tag = latest_tag(
+            &client,
+            &settings,
+            &mut cache,
+            None,
+            &GitHubRelease::from(&b).unwrap(),
+            None,
+        );
+        assert_eq!(tag.unwrap(), "v0.9.0");
+        assert_eq!(server.requests().len(), 2);
+    }
+
+    #[test]
+    fn prefetch_latest_tags_reports_graphql_errors() {
+        let server =
+            Server::start(|_| Response::ok(r#"{"errors": [{"message": "Bad credentials"}]}"#));
+        let settings = settings(&server.url);
+        let mut cache = HashMap::new();
+        let error =
+            prefetch_latest_tags(&settings.client(), &settings, &mut cache, &[("org", "a")])
+                .unwrap_err();
+        assert!(error.to_string().contains("Bad credentials"), "{error}");
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn latest_tag_skips_tags_not_matching_tag_regex() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => Response::ok(r#"{"tag_name": "nightly"}"#),
+            "/repos/org/tool/releases?per_page=100" => Response::ok(
+                r#"[
+                  {"tag_name": "nightly"},
+                  {"tag_name": "edge"},
+                  {"tag_name": "v1.3.0-ci.5"},
+                  {"tag_name": "v1.3.0-rc1", "prerelease": true},
+                  {"tag_name": "v1.2.0"},
+                  {"tag_name": "v1.1.0"}
+                ]"#,
+            ),
+            _ => Response::status(404, ""),
+        });
+        let settings = settings(&server.url);
+        let client = settings.client();
+        let release = GitHubRelease::from(
+            "https://github.com/org/tool/releases/download/v1.1.0/tool-linux-x86_64.tar.gz",
+        )
+        .unwrap();
+        let mut cache = HashMap::new();
+
+        let latest = latest_tag(&client, &settings, &mut cache, None, &release, None);
+        assert_eq!(latest.unwrap(), "nightly");
+
+        let pattern = Regex::new(r"^v\d+\.\d+\.\d+(-rc\d+)?$").unwrap();
+        let latest = latest_tag(
+            &client,
+            &settings,
+            &mut cache,
+            None,
+            &release,
+            Some(&pattern),
+        );
+        assert_eq!(latest.unwrap(), "v1.2.0");
+
+        let pattern = Regex::new("^release-").unwrap();
+        let error = latest_tag(
+            &client,
+            &settings,
+            &mut cache,
+            None,
+            &release,
+            Some(&pattern),
+        );
+        assert_eq!(
+            error.unwrap_err().to_string(),
+            "org/tool has no releases matching tag_regex '^release-'"
+        );
+    }
+
+    #[test]
+    fn release_assets_lists_urls_and_sizes() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/tags/v2.0.0" => Response::ok(
+                r#"{"tag_name": "v2.0.0", "assets": [
+                  {"name": "a", "browser_download_url": "https://github.com/org/tool/releases/download/v2.0.0/a", "size": 5},
+                  {"name": "b", "browser_download_url": "https://github.com/org/tool/releases/download/v2.0.0/b"}
+                ]}"#,
+            ),
+            _ => Response::status(404, "{}"),
+        });
+        let settings = settings(&server.url);
+        let release =
+            GitHubRelease::from("https://github.com/org/tool/releases/download/v1.0.0/a").unwrap();
+        let mut cache = HashMap::new();
+
+        let assets = release_assets(
+            &settings.client(),
+            &settings,
+            &mut cache,
+            None,
+            &release,
+            "v2.0.0",
+        );
+        let assets: Vec<(String, Option<u64>)> = assets
+            .unwrap()
+            .into_iter()
+            .map(|asset| (asset.url, asset.size))
+            .collect();
+        assert_eq!(
+            assets,
+            [
+                (
+                    "https://github.com/org/tool/releases/download/v2.0.0/a".to_owned(),
+                    Some(5)
+                ),
+                (
+                    "https://github.com/org/tool/releases/download/v2.0.0/b".to_owned(),
+                    None
+                )
+            ]
+        );
+        let missing = release_assets(
+            &settings.client(),
+            &settings,
+            &mut cache,
+            None,
+            &release,
+            "v9.9.9",
+        );
+        assert!(missing.is_err());
+    }
+}
@@ -0,0 +1,102 @@
+//! SHA-256 hashing of artifacts. All digests go through here so the backend
+//! can be swapped in one place.
+//!
+//! The backend is `sha2`, which picks SHA-NI or the ARMv8 SHA extensions at
+//! runtime where the CPU has them and falls back to portable code elsewhere;
+//! `bench_backends` compares it with alternatives.
+
+use sha2::{Digest, Sha256};
+use std::{fs::File, io, path::Path};
+
+/// The lowercase hex sha256 of `bytes`.
+pub fn sha256_hex(bytes: impl AsRef<[u8]>) -> String {
+    hex(Sha256::digest(bytes))
+}
+
+/// The lowercase hex sha256 of the file at `path`, read in chunks rather than
+/// loaded whole.
+pub fn sha256_file_hex(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex(hasher.finalize()))
+}
+
+/// A sha256 of everything written to it, for hashing a download as it streams
+/// in rather than after holding it in memory.
+#[derive(Default)]
+pub struct Sha256Writer(Sha256);
+
+impl Sha256Writer {
+    /// The lowercase hex sha256 of what was written.
+    pub fn finish(self) -> String {
+        hex(self.0.finalize())
+    }
+}
+
+impl io::Write for Sha256Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn hex(digest: impl AsRef<[u8]>) -> String {
+    digest
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    type Backend = fn(&[u8]) -> String;
+
+    #[test]
+    fn matches_the_sha256_crate() {
+        for input in ["", "hello", &"multitool".repeat(1000)] {
+            assert_eq!(sha256_hex(input), sha256::digest(input));
+            let mut writer = Sha256Writer::default();
+            for chunk in input.as_bytes().chunks(7) {
+                io::Write::write_all(&mut writer, chunk).unwrap();
+            }
+            assert_eq!(writer.finish(), sha256::digest(input));
+        }
+
+        let path = std::env::temp_dir().join(format!("multitool-hash-{}", std::process::id()));
+        std::fs::write(&path, "hello").unwrap();
+        assert_eq!(
+            sha256_file_hex(&path).unwrap(),
+            sha256::try_digest(&path).unwrap()
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Compares backends on a large buffer; run with
+    /// `cargo test --release bench_backends -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_backends() {
+        let buffer: Vec<u8> = (0..64 * 1024 * 1024u32).map(|i| i as u8).collect();
+        let mib = buffer.len() as f64 / (1024.0 * 1024.0);
+        let backends: [(&str, Backend); 2] = [
+            ("sha2", |bytes| sha256_hex(bytes)),
+            ("sha256 crate", |bytes| sha256::digest(bytes)),
+        ];
+        let mut digests = Vec::new();
+        for (name, backend) in backends {
+            let start = Instant::now();
+            digests.push(backend(&buffer));
+            let elapsed = start.elapsed().as_secs_f64();
+            println!("{name}: {:.0} MiB/s", mib / elapsed);
+        }
+        assert!(digests.windows(2).all(|pair| pair[0] == pair[1]));
+    }
+}
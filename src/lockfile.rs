@@ -1,52 +1,120 @@
+use crate::{github::GitHubRelease, spelling::Spelled, Common};
+use clap::ValueEnum;
+use serde::ser::SerializeMap;
 use serde::{Deserialize, Serialize};
-use std::{
-    collections::{BTreeMap, HashMap},
-    fmt::Display,
-};
+use std::{cmp::Ordering, collections::BTreeMap, fmt::Display};
 
 pub const SCHEMA: &str =
     "https://raw.githubusercontent.com/theoremlp/rules_multitool/main/lockfile.schema.json";
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Whether `schema` is the rules_multitool lockfile schema this crate reads,
+/// either [`SCHEMA`] on `main` or the same file pinned to a tag or commit.
+pub fn is_known_schema(schema: &str) -> bool {
+    schema
+        .strip_prefix("https://raw.githubusercontent.com/theoremlp/rules_multitool/")
+        .and_then(|rest| rest.strip_suffix("/lockfile.schema.json"))
+        .is_some_and(|reference| !reference.is_empty() && !reference.contains('/'))
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
 #[serde(rename_all = "lowercase")]
 pub enum SupportedOs {
     Linux,
+    #[value(name = "macos")]
     MacOS,
     Windows,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+/// Legacy spellings are accepted on input, so lockfiles written with
+/// [`PlatformNames::Legacy`] read back the same.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
 #[serde(rename_all = "lowercase")]
 pub enum SupportedCpu {
+    #[serde(alias = "aarch64")]
+    #[value(alias = "aarch64")]
     Arm64,
+    #[serde(alias = "amd64")]
+    #[value(name = "x86_64", alias = "amd64")]
     X86_64,
 }
 
+/// The C library a Linux binary is linked against, for tools that publish
+/// both glibc and musl builds.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Libc {
+    Gnu,
+    Musl,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct FileBinary {
     pub url: String,
     pub sha256: String,
+    /// The artifact's size in bytes, recorded by `update` so a verification or
+    /// later update notices an artifact that was replaced or truncated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
     pub os: SupportedOs,
     pub cpu: SupportedCpu,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub headers: Option<HashMap<String, String>>,
+    pub libc: Option<Libc>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub auth_patterns: Option<HashMap<String, String>>,
+    pub headers: Option<BTreeMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_patterns: Option<BTreeMap<String, String>>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct ArchiveBinary {
     pub url: String,
+    /// For artifacts published in pieces (`.part0`, `.part1`, …): the URL of every
+    /// part in order, starting with `url`. The sha256 covers their concatenation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parts: Option<Vec<String>>,
     pub file: String,
     pub sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
     pub os: SupportedOs,
     pub cpu: SupportedCpu,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub headers: Option<HashMap<String, String>>,
+    pub libc: Option<Libc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<BTreeMap<String, String>>,
     #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
     pub type_: Option<String>, // TODO(mark): we should probably make this an enum
+    /// Set when `file` is itself an archive that holds the executable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inner: Option<InnerArchive>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub auth_patterns: Option<HashMap<String, String>>,
+    pub auth_patterns: Option<BTreeMap<String, String>>,
+    /// What `sha256` is computed over; the downloaded bytes when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256_of: Option<DigestOf>,
+}
+
+/// The bytes an archive's `sha256` covers. rules_multitool checks the download,
+/// so only `raw` digests can be verified by Bazel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestOf {
+    /// The asset as downloaded, compression included.
+    Raw,
+    /// The asset with its compression undone, such as the `.tar` inside a
+    /// `.tar.gz`.
+    Decompressed,
+}
+
+/// The second layer of an archive shipped inside another, such as a `.tar.gz`
+/// inside a `.zip`. The outer archive's `file` names this inner archive.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct InnerArchive {
+    /// Path of the executable within the inner archive.
+    pub file: String,
+    /// The inner archive's type, for an outer `file` without an archive extension.
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -54,12 +122,48 @@ pub struct PkgBinary {
     pub url: String,
     pub file: String,
     pub sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
     pub os: SupportedOs,
     pub cpu: SupportedCpu,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub headers: Option<HashMap<String, String>>,
+    pub headers: Option<BTreeMap<String, String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub auth_patterns: Option<HashMap<String, String>>,
+    pub auth_patterns: Option<BTreeMap<String, String>>,
+    /// The macOS package identifier, e.g. `com.example.tool`, for tooling that
+    /// installs or inspects the package. multitool only carries it along.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pkg_id: Option<String>,
+    /// Where the package installs to, e.g. `/usr/local`. Carried along as is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install_to: Option<String>,
+}
+
+/// A whole directory tree extracted from an archive, rather than a single file
+/// within it. The URL must still point at an archive (see `ARCHIVE_EXTENSIONS`)
+/// or declare its `type`, since the sha256 pins the downloaded archive.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DirBinary {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parts: Option<Vec<String>>,
+    pub sha256: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    pub os: SupportedOs,
+    pub cpu: SupportedCpu,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub libc: Option<Libc>,
+    /// Directory within the archive to use as the root of the tree (e.g. `tool-1.2.3`);
+    /// the archive's own root if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub headers: Option<BTreeMap<String, String>>,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_patterns: Option<BTreeMap<String, String>>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -68,11 +172,390 @@ pub enum Binary {
     File(FileBinary),
     Archive(ArchiveBinary),
     Pkg(PkgBinary),
+    Dir(DirBinary),
 }
 
-#[derive(Serialize, Deserialize)]
+/// Archive formats Bazel can extract, recognized by URL suffix.
+const ARCHIVE_EXTENSIONS: [&str; 15] = [
+    ".zip", ".jar", ".war", ".aar", ".tar", ".tar.gz", ".tgz", ".tar.xz", ".txz", ".tar.zst",
+    ".tzst", ".tar.bz2", ".tbz", ".ar", ".deb",
+];
+
+/// Whether `path` (a URL, ignoring any query or fragment) ends in one of the
+/// `ARCHIVE_EXTENSIONS`.
+fn has_archive_extension(path: &str) -> bool {
+    archive_extension(path).is_some()
+}
+
+/// The one of the `ARCHIVE_EXTENSIONS` that `path` (a URL, ignoring any query
+/// or fragment) ends in, without its leading dot. These are also the `"type"`
+/// names Bazel accepts.
+pub fn archive_extension(path: &str) -> Option<&'static str> {
+    let path = path.split(['?', '#']).next().unwrap_or(path);
+    ARCHIVE_EXTENSIONS
+        .iter()
+        .find(|extension| path.ends_with(*extension))
+        .map(|extension| &extension[1..])
+}
+
+/// The host of `url`, without any userinfo or port.
+pub fn url_host(url: &str) -> Option<&str> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    let host = host.split(':').next()?;
+    Some(host).filter(|host| !host.is_empty())
+}
+
+/// Whether `url` is fetched without TLS.
+pub fn is_plain_http(url: &str) -> bool {
+    url.get(..7)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("http://"))
+}
+
+/// Resolves a tool's latest version from an arbitrary HTTP endpoint and renders
+/// binary URLs from a template containing `{version}`, `{os}` and `{cpu}`.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TemplateSource {
+    pub version_url: String,
+    /// Dotted path (e.g. `$.releases[0].version`) selecting the version from a JSON response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_json_path: Option<String>,
+    /// Regex matched against the response body; the `version` group (or first group) is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_regex: Option<String>,
+    pub url_template: String,
+}
+
+/// Tunes how a tool's GitHub releases are resolved; binaries still take their
+/// repo and version from their own release URLs.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GitHubSource {
+    /// Only releases whose tag matches this regex are considered, skipping noise
+    /// tags like `nightly`; when set, the release list is scanned instead of
+    /// trusting GitHub's "latest" release.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag_regex: Option<String>,
+    /// Where a release's version comes from; `name` is for repos whose tags are
+    /// opaque but whose release names carry the version.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_from: Option<VersionFrom>,
+}
+
+/// The field of a GitHub release holding its version. With `name`, versions
+/// are compared and substituted into asset paths and archive files by release
+/// name, while the download URL's tag segment still takes the tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VersionFrom {
+    Tag,
+    Name,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ToolSource {
+    GitHub(GitHubSource),
+    Template(TemplateSource),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Platform {
+    pub os: SupportedOs,
+    pub cpu: SupportedCpu,
+}
+
+/// Declares that `os`/`cpu` runs the binary built for `target` (e.g. macos/arm64
+/// using the x86_64 binary under Rosetta), without duplicating its entry.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PlatformFallback {
+    pub os: SupportedOs,
+    pub cpu: SupportedCpu,
+    pub target: Platform,
+}
+
+/// Digest algorithms, as recognized from the length of a hex digest.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    pub fn detect(digest: &str) -> Result<DigestAlgorithm, String> {
+        if !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("'{digest}' is not a hex digest"));
+        }
+        match digest.len() {
+            64 => Ok(DigestAlgorithm::Sha256),
+            128 => Ok(DigestAlgorithm::Sha512),
+            len => Err(format!(
+                "a {len}-character digest is neither sha256 (64) nor sha512 (128)"
+            )),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
 pub struct ToolDefinition {
     pub binaries: Vec<Binary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<ToolSource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fallbacks: Option<Vec<PlatformFallback>>,
+    /// `false` for tools managed by hand (e.g. internal builds), which `update`,
+    /// `check` and `verify` leave alone; unset means `true`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update: Option<bool>,
+    /// How the tool's artifacts are signed, checked by `update --verify-signatures`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<ToolSignature>,
+}
+
+/// A public key and where the signature of each of a tool's artifacts is
+/// published.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolSignature {
+    pub scheme: SignatureScheme,
+    /// The signer's public key, in the scheme's own encoding: for minisign, the
+    /// base64 line of a `minisign.pub` file.
+    pub public_key: String,
+    /// The signature's URL, with `{url}` standing for the artifact's; unset
+    /// means `{url}.minisig`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+impl ToolSignature {
+    /// The URL of the signature of the artifact at `url`.
+    pub fn url_for(&self, url: &str) -> String {
+        self.url
+            .as_deref()
+            .unwrap_or("{url}.minisig")
+            .replace("{url}", url)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureScheme {
+    Minisign,
+}
+
+impl ToolDefinition {
+    /// Runs every structural check on the tool.
+    pub fn validate(&self) -> Result<(), String> {
+        self.validate_fallbacks()?;
+        self.validate_dirs()?;
+        self.validate_libc()?;
+        self.validate_parts()?;
+        self.validate_inner_archives()?;
+        self.validate_signature()
+    }
+
+    /// Checks that a `signature`'s public key is well formed, so a typo is
+    /// caught before any download rather than reported as a bad signature, and
+    /// that no binary of a signed tool is split into parts: a signature is
+    /// published beside a single artifact, and there's no whole one to sign.
+    pub fn validate_signature(&self) -> Result<(), String> {
+        let Some(signature) = &self.signature else {
+            return Ok(());
+        };
+        crate::signature::validate(signature)?;
+        match self.binaries.iter().find(|binary| binary.parts().is_some()) {
+            Some(binary) => Err(format!(
+                "binary for {}/{} is split into parts, which a signature can't cover",
+                binary.os(),
+                binary.cpu()
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Every `url` and part of the tool's binaries that is fetched over plain
+    /// HTTP, and a template source's `version_url` or `url_template` if it is.
+    pub fn insecure_urls(&self) -> Vec<&str> {
+        let template = match &self.source {
+            Some(ToolSource::Template(source)) => {
+                vec![source.version_url.as_str(), source.url_template.as_str()]
+            }
+            _ => Vec::new(),
+        };
+        self.binaries
+            .iter()
+            .flat_map(|binary| {
+                std::iter::once(binary.url())
+                    .chain(binary.parts().into_iter().flatten().map(String::as_str))
+            })
+            .chain(template)
+            .filter(|url| is_plain_http(url))
+            .collect()
+    }
+
+    /// Binaries whose URL host doesn't fit the tool's `source`, which usually
+    /// means a URL was pasted in without updating the source (or the reverse).
+    /// These are warnings rather than errors since such a lockfile still works;
+    /// the binaries just aren't updated as expected.
+    pub fn source_warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for binary in &self.binaries {
+            let platform = format!("{}/{}", binary.os(), binary.cpu());
+            let host = url_host(binary.url()).unwrap_or_default();
+            match &self.source {
+                Some(ToolSource::GitHub(_)) if GitHubRelease::from(binary.url()).is_none() => {
+                    warnings.push(format!(
+                        "binary for {platform} is hosted on '{host}', but the tool's source is a \
+                         GitHub release, so it won't be updated"
+                    ));
+                }
+                Some(ToolSource::Template(source)) => {
+                    // a host templated on the version or platform can't be compared
+                    let expected =
+                        url_host(&source.url_template).filter(|expected| !expected.contains('{'));
+                    if let Some(expected) = expected.filter(|expected| *expected != host) {
+                        warnings.push(format!(
+                            "binary for {platform} is hosted on '{host}', but the tool's \
+                             url_template points at '{expected}'"
+                        ));
+                    }
+                }
+                _ => {}
+            }
+        }
+        warnings
+    }
+
+    /// Checks that both layers of a nested archive have a known type, from an
+    /// archive extension or an explicit `type`, and that the inner one names a
+    /// file.
+    pub fn validate_inner_archives(&self) -> Result<(), String> {
+        for binary in &self.binaries {
+            let Binary::Archive(archive) = binary else {
+                continue;
+            };
+            let Some(inner) = &archive.inner else {
+                continue;
+            };
+            let platform = format!("{}/{}", archive.os, archive.cpu);
+            if archive.type_.is_none() && !has_archive_extension(&archive.url) {
+                return Err(format!(
+                    "nested archive for {platform} needs a \"type\" since '{}' has no archive extension",
+                    archive.url
+                ));
+            }
+            if inner.type_.is_none() && !has_archive_extension(&archive.file) {
+                return Err(format!(
+                    "inner archive '{}' for {platform} needs a \"type\" since it has no archive extension",
+                    archive.file
+                ));
+            }
+            if inner.file.is_empty() {
+                return Err(format!(
+                    "inner archive for {platform} has an empty \"file\""
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that a multi-part artifact lists at least one part, starting with
+    /// its `url`.
+    pub fn validate_parts(&self) -> Result<(), String> {
+        for binary in &self.binaries {
+            let Some(parts) = binary.parts() else {
+                continue;
+            };
+            let platform = format!("{}/{}", binary.os(), binary.cpu());
+            match parts.first() {
+                None => return Err(format!("binary for {platform} has no parts")),
+                Some(first) if first != binary.url() => {
+                    return Err(format!(
+                        "the first part of the binary for {platform} must be its url {}",
+                        binary.url()
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every `sha256` really is a sha256 digest, so a pasted sha512 is
+    /// reported as such rather than as a mismatch on the next download.
+    pub fn validate_digests(&self) -> Result<(), String> {
+        for binary in &self.binaries {
+            let platform = format!("{}/{}", binary.os(), binary.cpu());
+            match DigestAlgorithm::detect(binary.sha256()) {
+                Ok(DigestAlgorithm::Sha256) => {}
+                Ok(DigestAlgorithm::Sha512) => {
+                    return Err(format!(
+                        "the sha256 of {platform} is a 128-character sha512 digest; only sha256 digests are supported"
+                    ))
+                }
+                Err(e) => return Err(format!("the sha256 of {platform} is invalid: {e}")),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that only Linux binaries declare a `libc`.
+    pub fn validate_libc(&self) -> Result<(), String> {
+        for binary in &self.binaries {
+            match binary.libc() {
+                Some(libc) if binary.os() != &SupportedOs::Linux => {
+                    return Err(format!(
+                        "libc '{libc}' is only supported for linux binaries, not {}/{}",
+                        binary.os(),
+                        binary.cpu()
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every fallback points at a binary of this tool and doesn't
+    /// shadow a platform that already has its own binary.
+    pub fn validate_fallbacks(&self) -> Result<(), String> {
+        let has_binary = |os: &SupportedOs, cpu: &SupportedCpu| {
+            self.binaries
+                .iter()
+                .any(|binary| binary.os() == os && binary.cpu() == cpu)
+        };
+        for fallback in self.fallbacks.iter().flatten() {
+            if !has_binary(&fallback.target.os, &fallback.target.cpu) {
+                return Err(format!(
+                    "fallback for {}/{} targets {}/{}, which has no binary",
+                    fallback.os, fallback.cpu, fallback.target.os, fallback.target.cpu
+                ));
+            }
+            if has_binary(&fallback.os, &fallback.cpu) {
+                return Err(format!(
+                    "fallback for {}/{} conflicts with an existing binary for that platform",
+                    fallback.os, fallback.cpu
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that every `dir` binary is extracted from an archive: either its URL
+    /// has a recognized archive extension or it declares the archive `type`.
+    pub fn validate_dirs(&self) -> Result<(), String> {
+        for binary in &self.binaries {
+            if let Binary::Dir(dir) = binary {
+                if dir.type_.is_none() && !has_archive_extension(&dir.url) {
+                    return Err(format!(
+                        "dir binary for {}/{} must be an archive; '{}' has no archive \
+                         extension, so set its \"type\"",
+                        dir.os, dir.cpu, dir.url
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
 }
 
 impl Display for SupportedCpu {
@@ -84,6 +567,15 @@ impl Display for SupportedCpu {
     }
 }
 
+impl Display for Libc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            Libc::Gnu => write!(f, "gnu"),
+            Libc::Musl => write!(f, "musl"),
+        }
+    }
+}
+
 impl Display for SupportedOs {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match &self {
@@ -98,15 +590,145 @@ fn schema() -> String {
     SCHEMA.to_owned()
 }
 
+/// Audit information about the run that last changed the lockfile, in its
+/// top-level `metadata` object. Keys multitool doesn't write are kept as is.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_updated: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub multitool_version: Option<String>,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, serde_json::Value>,
+}
+
+impl Metadata {
+    /// `previous`, stamped with the current time and multitool version.
+    pub fn stamped(previous: Option<Metadata>) -> Metadata {
+        Metadata {
+            last_updated: Some(crate::timestamp::now()),
+            multitool_version: Some(env!("CARGO_PKG_VERSION").to_owned()),
+            ..previous.unwrap_or_default()
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Lockfile {
     #[serde(rename = "$schema", default = "schema")]
     pub schema: String,
 
+    /// A named field, so `metadata` is never taken for a tool by the flattened
+    /// `tools` below.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<Metadata>,
+
     #[serde(flatten)]
     pub tools: BTreeMap<String, ToolDefinition>,
 }
 
+/// How CPU names are spelled when a lockfile is written. Lockfiles are always
+/// read into the canonical names; only the rendering differs.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PlatformNames {
+    /// `x86_64` and `arm64`.
+    #[default]
+    Canonical,
+    /// `amd64` and `aarch64`, for older rules_multitool consumers.
+    Legacy,
+}
+
+/// How tools are ordered when a lockfile is written.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ToolOrder {
+    /// Byte-wise order of tool names (`tool-10` before `tool-2`).
+    #[default]
+    Lexicographic,
+    /// Natural order, comparing runs of digits numerically (`tool-2` before `tool-10`).
+    Semantic,
+}
+
+/// Compares names piecewise, treating each run of ASCII digits as a number.
+/// Names that compare equal that way (e.g. `a01` and `a1`) fall back to byte
+/// order so the result is still total.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    fn chunks(name: &str) -> impl Iterator<Item = &str> {
+        let mut rest = name;
+        std::iter::from_fn(move || {
+            let first = rest.chars().next()?;
+            let end = rest
+                .find(|c: char| c.is_ascii_digit() != first.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let (chunk, tail) = rest.split_at(end);
+            rest = tail;
+            Some(chunk)
+        })
+    }
+
+    let mut left = chunks(a);
+    let mut right = chunks(b);
+    loop {
+        let ordering = match (left.next(), right.next()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => Ordering::Less,
+            (Some(_), None) => Ordering::Greater,
+            (Some(x), Some(y))
+                if x.starts_with(|c: char| c.is_ascii_digit())
+                    && y.starts_with(|c: char| c.is_ascii_digit()) =>
+            {
+                let x = x.trim_start_matches('0');
+                let y = y.trim_start_matches('0');
+                x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+            }
+            (Some(x), Some(y)) => x.cmp(y),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Serializes a lockfile with its tools in a chosen order.
+struct Ordered<'a>(&'a Lockfile, ToolOrder);
+
+impl Serialize for Ordered<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Ordered(lockfile, order) = self;
+        let mut tools: Vec<_> = lockfile.tools.iter().collect();
+        if *order == ToolOrder::Semantic {
+            tools.sort_by(|(a, _), (b, _)| natural_cmp(a, b));
+        }
+
+        let entries = tools.len() + 1 + usize::from(lockfile.metadata.is_some());
+        let mut map = serializer.serialize_map(Some(entries))?;
+        map.serialize_entry("$schema", &lockfile.schema)?;
+        if let Some(metadata) = &lockfile.metadata {
+            map.serialize_entry("metadata", metadata)?;
+        }
+        for (name, definition) in tools {
+            map.serialize_entry(name, definition)?;
+        }
+        map.end()
+    }
+}
+
+impl Lockfile {
+    /// Renders the lockfile in its canonical on-disk form: two-space indented JSON
+    /// with a single trailing newline, tools in `order` and CPUs spelled as `names`
+    /// asks. The formatter is spelled out rather than relying on `to_string_pretty`
+    /// so output stays byte-stable across serde_json releases.
+    pub fn to_canonical_string(&self, order: ToolOrder, names: PlatformNames) -> String {
+        let mut out = Vec::new();
+        let formatter = serde_json::ser::PrettyFormatter::with_indent(b"  ");
+        let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+        Spelled(&Ordered(self, order), names)
+            .serialize(&mut serializer)
+            .unwrap();
+        out.push(b'\n');
+        String::from_utf8(out).unwrap()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -118,6 +740,57 @@ mod tests {
         assert_eq!(lockfile.tools.len(), 0);
     }
 
+    #[test]
+    fn metadata_round_trips_ahead_of_tools() {
+        let json = format!(
+            r#"{{
+  "$schema": "{SCHEMA}",
+  "metadata": {{
+    "last_updated": "2024-01-02T03:04:05Z",
+    "multitool_version": "0.9.0",
+    "ticket": "OPS-1"
+  }},
+  "tool": {{
+    "binaries": []
+  }}
+}}
+"#
+        );
+        let lockfile: Lockfile = serde_json::from_str(&json).unwrap();
+        assert_eq!(lockfile.tools.len(), 1);
+        let metadata = lockfile.metadata.as_ref().unwrap();
+        assert_eq!(metadata.other["ticket"], "OPS-1");
+        assert_eq!(
+            lockfile.to_canonical_string(ToolOrder::default(), PlatformNames::default()),
+            json
+        );
+
+        let stamped = Metadata::stamped(lockfile.metadata);
+        assert_eq!(
+            stamped.multitool_version.as_deref(),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        assert_eq!(stamped.other["ticket"], "OPS-1");
+    }
+
+    #[test]
+    fn known_schemas_include_pinned_refs() {
+        assert!(is_known_schema(SCHEMA));
+        assert!(is_known_schema(
+            "https://raw.githubusercontent.com/theoremlp/rules_multitool/v0.4.0/lockfile.schema.json"
+        ));
+        assert!(is_known_schema(
+            "https://raw.githubusercontent.com/theoremlp/rules_multitool/3f1c2e9/lockfile.schema.json"
+        ));
+        assert!(!is_known_schema("https://example.com/lockfile.schema.json"));
+        assert!(!is_known_schema(
+            "https://raw.githubusercontent.com/theoremlp/rules_multitool//lockfile.schema.json"
+        ));
+        assert!(!is_known_schema(
+            "https://raw.githubusercontent.com/theoremlp/rules_multitool/main/other.schema.json"
+        ));
+    }
+
     #[test]
     fn deserialize_lockfile_with_schema_and_no_tools() {
         let lockfile: Lockfile = serde_json::from_str(r#"{
@@ -156,4 +829,505 @@ mod tests {
         assert_eq!(lockfile.tools["tool-name"].binaries.len(), 1);
         // TOOD(mark): richer tests
     }
+
+    #[test]
+    fn canonical_string_matches_golden_file() {
+        let golden = include_str!("../testdata/golden.lock.json");
+        let lockfile: Lockfile = serde_json::from_str(golden).unwrap();
+        assert_eq!(
+            lockfile.to_canonical_string(ToolOrder::Lexicographic, PlatformNames::default()),
+            golden
+        );
+    }
+
+    fn tool_with_fallback(target_cpu: &str) -> ToolDefinition {
+        serde_json::from_str(&format!(
+            r#"{{
+              "binaries": [
+                {{
+                  "kind": "file",
+                  "url": "https://github.com/org/tool/releases/download/v1.0.0/tool-macos-x86_64",
+                  "sha256": "9523faf97e4e3fea5f98ba9d051e67c90799182580d8ae56cba2e45c7de0b4ce",
+                  "os": "macos",
+                  "cpu": "x86_64"
+                }}
+              ],
+              "fallbacks": [
+                {{ "os": "macos", "cpu": "arm64", "target": {{ "os": "macos", "cpu": "{target_cpu}" }} }}
+              ]
+            }}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn validate_fallback_to_existing_binary() {
+        let tool = tool_with_fallback("x86_64");
+        assert!(tool.validate_fallbacks().is_ok());
+        let fallbacks = tool.fallbacks.as_ref().unwrap();
+        assert_eq!(fallbacks[0].os, SupportedOs::MacOS);
+        assert_eq!(fallbacks[0].cpu, SupportedCpu::Arm64);
+    }
+
+    #[test]
+    fn validate_fallback_to_missing_binary() {
+        assert_eq!(
+            tool_with_fallback("arm64").validate_fallbacks(),
+            Err("fallback for macos/arm64 targets macos/arm64, which has no binary".to_owned())
+        );
+    }
+
+    #[test]
+    fn windows_arm64_binary_round_trips() {
+        let json = r#"{
+  "kind": "archive",
+  "url": "https://github.com/org/tool/releases/download/v1.2.3/tool-v1.2.3-aarch64-pc-windows-msvc.zip",
+  "file": "tool.exe",
+  "sha256": "9523faf97e4e3fea5f98ba9d051e67c90799182580d8ae56cba2e45c7de0b4ce",
+  "os": "windows",
+  "cpu": "arm64",
+  "type": "zip"
+}"#;
+        let binary: Binary = serde_json::from_str(json).unwrap();
+        assert_eq!(binary.os(), &SupportedOs::Windows);
+        assert_eq!(binary.cpu(), &SupportedCpu::Arm64);
+        assert_eq!(serde_json::to_string_pretty(&binary).unwrap(), json);
+    }
+
+    #[test]
+    fn nested_archive_round_trips_and_validates() {
+        let json = r#"{
+  "kind": "archive",
+  "url": "https://github.com/org/tool/releases/download/v1.2.3/tool.zip",
+  "file": "tool-linux.tar.gz",
+  "sha256": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+  "os": "linux",
+  "cpu": "x86_64",
+  "inner": {
+    "file": "tool-1.2.3/bin/tool"
+  }
+}"#;
+        let binary: Binary = serde_json::from_str(json).unwrap();
+        assert_eq!(serde_json::to_string_pretty(&binary).unwrap(), json);
+
+        let validate = |binary: serde_json::Value| {
+            let tool = serde_json::json!({ "binaries": [binary] });
+            serde_json::from_value::<ToolDefinition>(tool)
+                .unwrap()
+                .validate()
+        };
+        let base: serde_json::Value = serde_json::from_str(json).unwrap();
+        assert_eq!(validate(base.clone()), Ok(()));
+
+        let mut inner_without_extension = base.clone();
+        inner_without_extension["file"] = "tool-linux.bin".into();
+        assert_eq!(
+            validate(inner_without_extension.clone()),
+            Err("inner archive 'tool-linux.bin' for linux/x86_64 needs a \"type\" since it has no archive extension".to_owned())
+        );
+        inner_without_extension["inner"]["type"] = "tar.gz".into();
+        assert_eq!(validate(inner_without_extension), Ok(()));
+
+        let mut outer_without_extension = base;
+        outer_without_extension["url"] = "https://example.com/download?id=1".into();
+        assert!(validate(outer_without_extension)
+            .unwrap_err()
+            .starts_with("nested archive for linux/x86_64 needs a \"type\""));
+    }
+
+    #[test]
+    fn source_warnings_flag_mismatched_hosts() {
+        let tool = |source: &str, url: &str| -> ToolDefinition {
+            serde_json::from_str(&format!(
+                r#"{{"binaries": [{{"kind": "file", "url": "{url}", "sha256": "00", "os": "linux", "cpu": "x86_64"}}], "source": {source}}}"#
+            ))
+            .unwrap()
+        };
+        let github = r#"{"kind": "github"}"#;
+        let template = r#"{"kind": "template", "version_url": "https://example.com/latest", "url_template": "https://dl.example.com/tool/{version}/tool-{os}"}"#;
+
+        let release = "https://github.com/org/tool/releases/download/v1.0.0/tool-linux";
+        assert!(tool(github, release).source_warnings().is_empty());
+        assert_eq!(
+            tool(github, "https://gitlab.com/org/tool/-/releases/v1.0.0/tool-linux").source_warnings(),
+            ["binary for linux/x86_64 is hosted on 'gitlab.com', but the tool's source is a GitHub release, so it won't be updated"]
+        );
+
+        assert!(
+            tool(template, "https://dl.example.com/tool/1.0.0/tool-linux")
+                .source_warnings()
+                .is_empty()
+        );
+        assert_eq!(
+            tool(template, release).source_warnings(),
+            ["binary for linux/x86_64 is hosted on 'github.com', but the tool's url_template points at 'dl.example.com'"]
+        );
+    }
+
+    #[test]
+    fn url_host_strips_userinfo_and_port() {
+        assert_eq!(
+            url_host("https://user:pw@example.com:8443/a?b"),
+            Some("example.com")
+        );
+        assert_eq!(url_host("https://example.com"), Some("example.com"));
+        assert_eq!(url_host("not a url"), None);
+    }
+
+    #[test]
+    fn size_round_trips_and_is_omitted_when_unset() {
+        let json = r#"{
+  "kind": "file",
+  "url": "https://github.com/org/tool/releases/download/v1.2.3/tool-linux",
+  "sha256": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+  "size": 5,
+  "os": "linux",
+  "cpu": "x86_64"
+}"#;
+        let binary: Binary = serde_json::from_str(json).unwrap();
+        assert_eq!(binary.size(), Some(5));
+        assert_eq!(serde_json::to_string_pretty(&binary).unwrap(), json);
+
+        let unsized_json = json.replace("  \"size\": 5,\n", "");
+        let binary: Binary = serde_json::from_str(&unsized_json).unwrap();
+        assert_eq!(binary.size(), None);
+        assert_eq!(serde_json::to_string_pretty(&binary).unwrap(), unsized_json);
+    }
+
+    #[test]
+    fn deserialize_tool_with_template_source() {
+        let lockfile: Lockfile = serde_json::from_str(
+            r#"{
+           "tool-name": {
+             "binaries": [
+                {
+                  "kind": "file",
+                  "url": "https://dl.example.com/1.2.3/tool-linux-x86_64",
+                  "sha256": "9523faf97e4e3fea5f98ba9d051e67c90799182580d8ae56cba2e45c7de0b4ce",
+                  "os": "linux",
+                  "cpu": "x86_64"
+                }
+             ],
+             "source": {
+               "kind": "template",
+               "version_url": "https://dl.example.com/latest.json",
+               "version_json_path": "$.version",
+               "url_template": "https://dl.example.com/{version}/tool-{os}-{cpu}"
+             }
+           }
+        }"#,
+        )
+        .unwrap();
+        let Some(ToolSource::Template(source)) = &lockfile.tools["tool-name"].source else {
+            panic!("expected a template source");
+        };
+        assert_eq!(source.version_json_path.as_deref(), Some("$.version"));
+        assert!(source.version_regex.is_none());
+    }
+
+    fn dir_tool(url: &str, type_: Option<&str>) -> ToolDefinition {
+        let mut binary = serde_json::json!({
+            "kind": "dir",
+            "url": url,
+            "sha256": "9523faf97e4e3fea5f98ba9d051e67c90799182580d8ae56cba2e45c7de0b4ce",
+            "os": "linux",
+            "cpu": "x86_64",
+            "root": "tool-1.0.0"
+        });
+        if let Some(type_) = type_ {
+            binary["type"] = type_.into();
+        }
+        serde_json::from_value(serde_json::json!({ "binaries": [binary] })).unwrap()
+    }
+
+    #[test]
+    fn dir_binary_round_trip() {
+        let tool = dir_tool(
+            "https://github.com/org/tool/releases/download/v1.0.0/tool-1.0.0.tar.gz",
+            None,
+        );
+        let Binary::Dir(dir) = &tool.binaries[0] else {
+            panic!("expected a dir binary");
+        };
+        assert_eq!(dir.root.as_deref(), Some("tool-1.0.0"));
+        assert_eq!(tool.binaries[0].sort_key(), "linux_x86_64");
+
+        let serialized = serde_json::to_value(&tool).unwrap();
+        assert_eq!(serialized["binaries"][0]["kind"], "dir");
+        assert_eq!(serialized["binaries"][0]["root"], "tool-1.0.0");
+        assert!(serialized["binaries"][0].get("type").is_none());
+    }
+
+    #[test]
+    fn pkg_install_fields_round_trip() {
+        let pkg = |extra: &str| {
+            format!(
+                r#"{{"kind": "pkg", "url": "https://example.com/tool.pkg", "file": "tool.pkg/Payload/bin/tool", "sha256": "00", "os": "macos", "cpu": "arm64"{extra}}}"#
+            )
+        };
+        let round_trip = |json: &str| {
+            let binary: Binary = serde_json::from_str(json).unwrap();
+            serde_json::to_value(&binary).unwrap()
+        };
+
+        let described = round_trip(&pkg(
+            r#", "pkg_id": "com.example.tool", "install_to": "/usr/local""#,
+        ));
+        assert_eq!(described["pkg_id"], "com.example.tool");
+        assert_eq!(described["install_to"], "/usr/local");
+
+        let plain = round_trip(&pkg(""));
+        assert!(plain.get("pkg_id").is_none());
+        assert!(plain.get("install_to").is_none());
+    }
+
+    #[test]
+    fn dir_binary_requires_archive() {
+        let url = "https://github.com/org/tool/releases/download/v1.0.0/tool-1.0.0.tar.gz?x=1";
+        assert!(dir_tool(url, None).validate_dirs().is_ok());
+
+        let url = "https://example.com/download/tool/1.0.0";
+        assert!(dir_tool(url, Some("tar.gz")).validate_dirs().is_ok());
+        let error = dir_tool(url, None).validate_dirs().unwrap_err();
+        assert!(error.contains("must be an archive"), "{error}");
+    }
+
+    #[test]
+    fn zstd_archives_are_recognized() {
+        let url = "https://github.com/org/tool/releases/download/v1.0.0/tool-1.0.0.tar.zst";
+        assert!(has_archive_extension(url));
+        assert!(has_archive_extension("tool-1.0.0.tzst"));
+        assert!(dir_tool(url, None).validate_dirs().is_ok());
+
+        let json = r#"{
+  "kind": "archive",
+  "url": "https://github.com/org/tool/releases/download/v1.2.3/tool.zip",
+  "file": "tool-linux.tar.zst",
+  "sha256": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824",
+  "os": "linux",
+  "cpu": "x86_64",
+  "inner": {
+    "file": "tool-1.2.3/bin/tool"
+  }
+}"#;
+        let binary: serde_json::Value = serde_json::from_str(json).unwrap();
+        let parsed: Binary = serde_json::from_value(binary.clone()).unwrap();
+        assert_eq!(serde_json::to_string_pretty(&parsed).unwrap(), json);
+        let tool: ToolDefinition =
+            serde_json::from_value(serde_json::json!({ "binaries": [binary] })).unwrap();
+        assert_eq!(tool.validate_inner_archives(), Ok(()));
+
+        // an explicit type survives a round trip
+        let tool = dir_tool("https://example.com/download/tool/1.0.0", Some("tar.zst"));
+        assert!(tool.validate_dirs().is_ok());
+        let serialized = serde_json::to_value(&tool).unwrap();
+        assert_eq!(serialized["binaries"][0]["type"], "tar.zst");
+    }
+
+    #[test]
+    fn deserialize_github_source() {
+        let tool: ToolDefinition = serde_json::from_str(
+            r#"{"binaries": [], "source": {"kind": "github", "tag_regex": "^v\\d+\\.\\d+\\.\\d+$"}}"#,
+        )
+        .unwrap();
+        let Some(ToolSource::GitHub(source)) = &tool.source else {
+            panic!("expected a github source");
+        };
+        assert_eq!(source.tag_regex.as_deref(), Some(r"^v\d+\.\d+\.\d+$"));
+    }
+
+    #[test]
+    fn platform_names_round_trip_in_both_dialects() {
+        let binary = |cpu: &str| {
+            format!(
+                r#"{{"kind": "file", "url": "https://example.com/\"cpu\": \"x86_64\"", "sha256": "00", "os": "linux", "cpu": "{cpu}"}}"#
+            )
+        };
+        let lockfile = |x86_64: &str, arm64: &str| -> Lockfile {
+            serde_json::from_str(&format!(
+                r#"{{"tool": {{"binaries": [{}, {}]}}}}"#,
+                binary(x86_64),
+                binary(arm64)
+            ))
+            .unwrap()
+        };
+        let render = |lockfile: &Lockfile, names: PlatformNames| {
+            lockfile.to_canonical_string(ToolOrder::default(), names)
+        };
+
+        let canonical = render(&lockfile("x86_64", "arm64"), PlatformNames::Canonical);
+        let legacy = render(&lockfile("x86_64", "arm64"), PlatformNames::Legacy);
+        assert!(legacy.contains(r#""cpu": "amd64""#) && legacy.contains(r#""cpu": "aarch64""#));
+        assert!(!legacy.contains(r#""cpu": "x86_64""#) && !legacy.contains(r#""cpu": "arm64""#));
+        // only cpu members are respelled, not strings that merely look like them
+        assert!(legacy.contains(r#"\"cpu\": \"x86_64\""#));
+        // nor cpu members outside binaries
+        let with_metadata: Lockfile =
+            serde_json::from_str(r#"{"metadata": {"runner": {"cpu": "x86_64"}}}"#).unwrap();
+        assert!(render(&with_metadata, PlatformNames::Legacy).contains(r#""cpu": "x86_64""#));
+        // and serializing without a dialect stays canonical
+        assert_eq!(
+            serde_json::to_string(&SupportedCpu::X86_64).unwrap(),
+            r#""x86_64""#
+        );
+
+        // legacy names read back as the canonical ones
+        let reread: Lockfile = serde_json::from_str(&legacy).unwrap();
+        assert_eq!(render(&reread, PlatformNames::Canonical), canonical);
+        assert_eq!(render(&reread, PlatformNames::Legacy), legacy);
+        assert_eq!(
+            render(&lockfile("amd64", "aarch64"), PlatformNames::Canonical),
+            canonical
+        );
+    }
+
+    #[test]
+    fn natural_order_compares_numbers_by_value() {
+        let mut names = [
+            "tool-10", "tool-2", "tool", "tool-1b", "tool-01", "tool-1", "Tool-3",
+        ];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(
+            names,
+            ["Tool-3", "tool", "tool-01", "tool-1", "tool-1b", "tool-2", "tool-10"]
+        );
+    }
+
+    #[test]
+    fn canonical_string_in_semantic_order() {
+        let tool = r#"{"binaries": []}"#;
+        let lockfile: Lockfile = serde_json::from_str(&format!(
+            r#"{{"tool-10": {tool}, "tool-9": {tool}, "tool-2": {tool}}}"#
+        ))
+        .unwrap();
+        let order_of = |order| {
+            let rendered = lockfile.to_canonical_string(order, PlatformNames::default());
+            let mut names = ["tool-2", "tool-9", "tool-10"];
+            names.sort_by_key(|name| rendered.find(&format!("\"{name}\": ")).unwrap());
+            names
+        };
+        assert_eq!(
+            order_of(ToolOrder::Lexicographic),
+            ["tool-10", "tool-2", "tool-9"]
+        );
+        assert_eq!(
+            order_of(ToolOrder::Semantic),
+            ["tool-2", "tool-9", "tool-10"]
+        );
+    }
+
+    fn linux_binary(libc: Option<&str>) -> String {
+        let libc = libc.map_or(String::new(), |libc| format!(r#", "libc": "{libc}""#));
+        format!(
+            r#"{{"kind": "file", "url": "https://example.com/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"{libc}}}"#
+        )
+    }
+
+    #[test]
+    fn libc_variants_round_trip_and_sort() {
+        let tool: ToolDefinition = serde_json::from_str(&format!(
+            r#"{{"binaries": [{}, {}, {}]}}"#,
+            linux_binary(Some("musl")),
+            linux_binary(None),
+            linux_binary(Some("gnu")),
+        ))
+        .unwrap();
+        assert!(tool.validate().is_ok());
+
+        let mut keys: Vec<String> = tool.binaries.iter().map(|b| b.sort_key()).collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            ["linux_x86_64", "linux_x86_64_gnu", "linux_x86_64_musl"]
+        );
+
+        let serialized = serde_json::to_value(&tool).unwrap();
+        assert_eq!(serialized["binaries"][0]["libc"], "musl");
+        assert!(serialized["binaries"][1].get("libc").is_none());
+        assert_eq!(serialized["binaries"][2]["libc"], "gnu");
+    }
+
+    #[test]
+    fn libc_only_on_linux() {
+        let binary = linux_binary(Some("musl")).replace(r#""os": "linux""#, r#""os": "macos""#);
+        let tool: ToolDefinition =
+            serde_json::from_str(&format!(r#"{{"binaries": [{binary}]}}"#)).unwrap();
+        assert_eq!(
+            tool.validate().unwrap_err(),
+            "libc 'musl' is only supported for linux binaries, not macos/x86_64"
+        );
+    }
+
+    #[test]
+    fn detect_digest_algorithm() {
+        let sha256 = sha256::digest("hello");
+        assert_eq!(
+            DigestAlgorithm::detect(&sha256),
+            Ok(DigestAlgorithm::Sha256)
+        );
+        assert_eq!(
+            DigestAlgorithm::detect(&sha256.repeat(2)),
+            Ok(DigestAlgorithm::Sha512)
+        );
+        assert_eq!(
+            DigestAlgorithm::detect(&sha256[..40]),
+            Err("a 40-character digest is neither sha256 (64) nor sha512 (128)".to_owned())
+        );
+        assert_eq!(
+            DigestAlgorithm::detect("sha256:00"),
+            Err("'sha256:00' is not a hex digest".to_owned())
+        );
+    }
+
+    #[test]
+    fn validate_digests_rejects_sha512() {
+        let tool = |sha256: &str| -> ToolDefinition {
+            let binary = linux_binary(None).replace(r#""00""#, &format!(r#""{sha256}""#));
+            serde_json::from_str(&format!(r#"{{"binaries": [{binary}]}}"#)).unwrap()
+        };
+        let sha256 = sha256::digest("hello");
+        assert!(tool(&sha256).validate_digests().is_ok());
+        assert_eq!(
+            tool(&sha256.repeat(2)).validate_digests().unwrap_err(),
+            "the sha256 of linux/x86_64 is a 128-character sha512 digest; only sha256 digests are supported"
+        );
+        assert_eq!(
+            tool("00").validate_digests().unwrap_err(),
+            "the sha256 of linux/x86_64 is invalid: a 2-character digest is neither sha256 (64) nor sha512 (128)"
+        );
+    }
+
+    #[test]
+    fn validate_parts_requires_url_first() {
+        let tool = |parts: &str| -> ToolDefinition {
+            serde_json::from_str(&format!(
+                r#"{{"binaries": [{{"kind": "archive", "url": "https://example.com/tool.tar.gz.part0", "file": "tool", "parts": {parts}, "sha256": "00", "os": "linux", "cpu": "x86_64", "type": "tar.gz"}}]}}"#
+            ))
+            .unwrap()
+        };
+        let parts =
+            r#"["https://example.com/tool.tar.gz.part0", "https://example.com/tool.tar.gz.part1"]"#;
+        assert!(tool(parts).validate().is_ok());
+        assert_eq!(
+            tool("[]").validate().unwrap_err(),
+            "binary for linux/x86_64 has no parts"
+        );
+        assert_eq!(
+            tool(r#"["https://example.com/tool.tar.gz.part1"]"#)
+                .validate()
+                .unwrap_err(),
+            "the first part of the binary for linux/x86_64 must be its url https://example.com/tool.tar.gz.part0"
+        );
+
+        let mut signed = tool(parts);
+        signed.signature = Some(ToolSignature {
+            scheme: SignatureScheme::Minisign,
+            public_key: crate::testing::MINISIGN_PUBLIC_KEY.to_owned(),
+            url: None,
+        });
+        assert_eq!(
+            signed.validate().unwrap_err(),
+            "binary for linux/x86_64 is split into parts, which a signature can't cover"
+        );
+    }
 }
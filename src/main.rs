@@ -1,28 +1,81 @@
-use clap::{Parser, Subcommand};
-use lockfile::{ArchiveBinary, Binary, FileBinary, Lockfile, PkgBinary, ToolDefinition, SCHEMA};
-use regex::Regex;
-use serde_json::Value;
+use asset::AssetSelection;
+use cache::DigestCache;
+use checkpoint::Checkpoint;
+use clap::{parser::ValueSource, Args, CommandFactory, FromArgMatches, Parser, Subcommand};
+use color::ColorChoice;
+use config::{
+    Config, Settings, DEFAULT_API_PAGE_SIZE, DEFAULT_GITHUB_API_URL, DEFAULT_MAX_REDIRECTS,
+    DEFAULT_MAX_RETRY_WAIT,
+};
+use download::DigestRequest;
+use edit::{BinaryKind, PlatformEntry};
+use error::Error;
+use exit::ExitCode;
+use explain::{Decision, Trace};
+use filter::ToolFilter;
+use github::GitHubRelease;
+use github_app::GitHubApp;
+use lockfile::{
+    url_host, ArchiveBinary, Binary, DirBinary, FileBinary, InnerArchive, Libc, Lockfile, Metadata,
+    PkgBinary, Platform, PlatformNames, SupportedCpu, SupportedOs, ToolDefinition, ToolOrder,
+    ToolSource, SCHEMA,
+};
+use manifest::ManifestFormat;
+use outcome::{BinaryOutcome, BinaryResult, SkipReason, ToolUpdateResult};
+use report::{Change, ReportFormat};
+use resolve::{ReleaseSource, Resolver};
 use std::{
-    collections::{BTreeMap, HashMap},
-    error::Error,
+    collections::{BTreeMap, BTreeSet},
     fs,
-    sync::LazyLock,
+    io::IsTerminal,
+    mem,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
+use timing::Timing;
+use warn::warn;
 
+mod archive;
+mod asset;
+mod atomic;
+mod bazel;
+mod cache;
+mod check;
+mod checkpoint;
+mod color;
+mod config;
+mod diff;
+mod doctor;
+mod download;
+mod edit;
+mod error;
+mod exit;
+mod explain;
+mod expr;
+mod filter;
+mod fix;
+mod github;
+mod github_app;
+mod hash;
 mod lockfile;
-
-static GITHUB_RELEASE_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
-    Regex::new(
-        r"(?x)
-        https://github\.com/
-        (?P<org>[A-Za-z0-9_-]+)/
-        (?P<repo>[A-Za-z0-9_-]+)/
-        releases/download/
-        (?P<version>v?[^/]+)/
-        (?P<path>.+)",
-    )
-    .unwrap()
-});
+mod manifest;
+mod matrix;
+mod outcome;
+mod plan;
+mod preserve;
+mod report;
+mod resolve;
+mod retry;
+mod signature;
+mod spelling;
+mod template;
+#[cfg(test)]
+mod testing;
+mod timestamp;
+mod timing;
+mod url;
+mod verify;
+mod warn;
+mod watch;
 
 #[derive(Parser)]
 struct Cli {
@@ -30,6 +83,170 @@ struct Cli {
     /// Path to a multitool lockfile (defaults to './multitool.lock.json')
     lockfile: Option<std::path::PathBuf>,
 
+    #[clap(long)]
+    /// Path to a multitool.toml config file (defaults to the nearest one above the lockfile)
+    config: Option<std::path::PathBuf>,
+
+    #[clap(long, env = "GITHUB_API_URL")]
+    /// Base URL of the GitHub API (defaults to 'https://api.github.com')
+    github_api_url: Option<String>,
+
+    #[clap(long, env = "GITHUB_TOKEN", hide_env_values = true)]
+    /// Token used to authenticate GitHub API requests
+    github_token: Option<String>,
+
+    #[clap(long = "token", value_name = "HOST=ENV_VAR", value_parser = parse_host_token)]
+    /// Send the token in environment variable ENV_VAR to HOST, for API requests and downloads;
+    /// repeatable. For the GitHub API host it takes precedence over --github-token
+    tokens: Vec<(String, String)>,
+
+    #[clap(long, env = "GITHUB_APP_ID", requires = "github_app_key")]
+    /// Authenticate to GitHub as this GitHub App, with installation tokens minted from its
+    /// private key; they take precedence over --github-token
+    github_app_id: Option<String>,
+
+    #[clap(
+        long,
+        env = "GITHUB_APP_PRIVATE_KEY",
+        hide_env_values = true,
+        group = "github_app_key",
+        requires = "github_app_id"
+    )]
+    /// PEM private key of the --github-app-id App
+    github_app_private_key: Option<String>,
+
+    #[clap(
+        long,
+        env = "GITHUB_APP_PRIVATE_KEY_FILE",
+        value_name = "PATH",
+        group = "github_app_key",
+        requires = "github_app_id"
+    )]
+    /// File holding the PEM private key of the --github-app-id App
+    github_app_private_key_file: Option<std::path::PathBuf>,
+
+    #[clap(long, env = "GITHUB_APP_INSTALLATION_ID", requires = "github_app_id")]
+    /// Installation of the --github-app-id App to mint tokens for (defaults to the App's
+    /// only installation)
+    github_app_installation_id: Option<u64>,
+
+    #[clap(long, env = "MULTITOOL_TIMEOUT_SECS")]
+    /// Timeout in seconds for each HTTP request
+    timeout_secs: Option<u64>,
+
+    #[clap(long, value_name = "SECS")]
+    /// Give up on the remaining binaries of a tool once `update` has spent this many seconds
+    /// on it, report them as errors and move on to the next tool
+    timeout_per_tool: Option<u64>,
+
+    #[clap(long, env = "MULTITOOL_MAX_REDIRECTS")]
+    /// Maximum number of redirects to follow per request (defaults to 10)
+    max_redirects: Option<usize>,
+
+    #[clap(long, env = "MULTITOOL_MAX_RETRY_WAIT_SECS", value_name = "SECS")]
+    /// Longest wait before retrying a request answered with 429 or 503, however long its
+    /// Retry-After asks for (defaults to 60)
+    max_retry_wait_secs: Option<u64>,
+
+    #[clap(long, env = "MULTITOOL_CA_BUNDLE", value_name = "PATH")]
+    /// Also trust the root certificates in this PEM file, e.g. a TLS-intercepting proxy's
+    ca_bundle: Option<std::path::PathBuf>,
+
+    #[clap(long)]
+    /// Accept any TLS certificate, even invalid or for the wrong host. Insecure; only for
+    /// debugging connection failures
+    danger_accept_invalid_certs: bool,
+
+    #[clap(long)]
+    /// Directory of pre-fetched artifacts, matched to binaries by URL basename, to hash instead
+    /// of downloading
+    from_dir: Option<std::path::PathBuf>,
+
+    #[clap(long, value_name = "BYTES")]
+    /// Abort downloads larger than this many bytes (defaults to unlimited)
+    max_download_size: Option<u64>,
+
+    #[clap(long, conflicts_with = "allow_insecure_http")]
+    /// Reject lockfiles and new binaries with URLs over plain `http://`, instead of warning
+    require_https: bool,
+
+    #[clap(long)]
+    /// Accept URLs over plain `http://` without warning, for internal mirrors that lack TLS
+    allow_insecure_http: bool,
+
+    #[clap(long, env = "MULTITOOL_CACHE_DIR")]
+    /// Directory for caching artifact digests, revalidated by ETag on later runs (defaults to no
+    /// caching)
+    cache_dir: Option<std::path::PathBuf>,
+
+    #[clap(long)]
+    /// When an artifact's digest differs from the one cached for its URL, download it once
+    /// more to tell a corrupted download from an upstream change (requires --cache-dir)
+    retry_on_checksum_mismatch: bool,
+
+    #[clap(long, default_value_t = DEFAULT_API_PAGE_SIZE, value_parser = clap::value_parser!(u32).range(1..=100))]
+    /// Number of results per page when listing GitHub releases
+    api_page_size: u32,
+
+    #[clap(long)]
+    /// Look up the latest GitHub releases of all selected tools in batched GraphQL requests
+    /// (requires a token; falls back to one REST request per repo)
+    github_graphql: bool,
+
+    #[clap(long, value_enum, value_name = "HEURISTIC")]
+    /// When an updated asset URL isn't among the release's assets, pick the asset whose name
+    /// best matches the binary's platform instead of failing
+    select_asset_by: Option<AssetSelection>,
+
+    #[clap(long)]
+    /// Take each updated asset URL from the latest release's assets, picking the asset whose
+    /// name best matches the binary's current one, and only substitute the version into the
+    /// old URL when no asset confidently matches
+    prefer_api_assets: bool,
+
+    #[clap(long)]
+    /// Check that each updated asset URL is among the release's assets, and record the
+    /// asset's size so downloads of a different size are rejected
+    verify_assets: bool,
+
+    #[clap(long)]
+    /// Check each downloaded artifact of a tool with a `signature` against its published
+    /// signature, failing the binary's update if it doesn't verify
+    verify_signatures: bool,
+
+    #[clap(long)]
+    /// Only move a GitHub binary to a release published strictly after its pinned release,
+    /// warning and keeping the pinned version when the latest release is older
+    only_if_newer_published: bool,
+
+    #[clap(long)]
+    /// Have `update` and `check` print, per binary, the pinned and latest versions, how the
+    /// latest was selected and what was decided, to stderr
+    explain: bool,
+
+    #[clap(long, value_enum, default_value_t)]
+    /// How to order tools when writing the lockfile
+    sort_tools: ToolOrder,
+
+    #[clap(long, value_enum, default_value_t)]
+    /// How to spell CPU names when writing the lockfile; `legacy` writes `amd64` and `aarch64`
+    /// for older rules_multitool consumers. Both spellings are always read
+    platform_names: PlatformNames,
+
+    #[clap(long, value_enum, default_value_t)]
+    /// When to color output; `auto` colors stdout and stderr each only when it's a terminal, and
+    /// honors NO_COLOR
+    color: ColorChoice,
+
+    #[clap(long, short)]
+    /// Log additional diagnostics, such as GitHub API rate-limit status and binaries
+    /// that are already up to date, to stderr
+    verbose: bool,
+
+    #[clap(long)]
+    /// Exit with code 7 if any warning was printed, for a run that would otherwise succeed
+    fail_on_warn: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -38,14 +255,345 @@ struct Cli {
 enum Commands {
     /// Updates GitHub release artifacts in the specified lockfile
     Update {
-        /// The tool to update, if unset, all tools are updated.
+        #[clap(flatten)]
+        options: UpdateOptions,
+
+        /// Write a report of the changed binaries to this path (not with --dry-run).
+        #[clap(long)]
+        report: Option<std::path::PathBuf>,
+
+        /// The format of the report written by --report.
+        #[clap(long, value_enum, default_value_t, requires = "report")]
+        report_format: ReportFormat,
+
+        /// Also write a flat manifest of each tool's binary for --platform to this path
+        /// (not with --dry-run).
+        #[clap(long, requires = "platform")]
+        emit_lockfile_to: Option<std::path::PathBuf>,
+
+        /// The format of the manifest written by --emit-lockfile-to.
+        #[clap(long, value_enum, default_value_t, requires = "emit_lockfile_to")]
+        emit_format: ManifestFormat,
+
+        /// The platform of the manifest, as os/cpu or os/cpu/libc (e.g. linux/x86_64/musl).
+        #[clap(long, requires = "emit_lockfile_to")]
+        platform: Option<manifest::Target>,
+    },
+    /// Looks up the latest tag of every selected tool and caches it in --cache-dir for the
+    /// next `update`, without downloading anything or changing the lockfile
+    Resolve {
+        #[clap(flatten)]
+        filter: ToolFilter,
+    },
+    /// Lists binaries that are behind their latest release, exiting nonzero if any are
+    Check {
+        #[clap(flatten)]
+        filter: ToolFilter,
+
+        /// Print the outdated binaries as JSON.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Keeps running `check` on an interval, printing updates as they become available, until
+    /// interrupted
+    Watch {
+        #[clap(flatten)]
+        filter: ToolFilter,
+
+        /// Seconds between checks.
+        #[clap(long, value_name = "SECS", default_value_t = 3600, value_parser = clap::value_parser!(u64).range(1..))]
+        interval: u64,
+
+        /// Print each newly outdated binary as a line of JSON.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Re-downloads every binary and checks it against its pinned sha256, exiting nonzero on
+    /// any mismatch or download failure
+    Verify {
+        #[clap(flatten)]
+        filter: ToolFilter,
+
+        /// Stop at the first sha256 mismatch instead of reporting all of them.
         #[clap(long)]
-        tool: Option<String>,
+        fail_fast: bool,
+
+        /// Only check that each artifact still exists with its recorded size and cached ETag,
+        /// using HEAD requests; digests are not recomputed.
+        #[clap(long)]
+        head_only: bool,
+
+        /// Also check that each archive holds the `file` (or inner archive's `file`, or `root`
+        /// directory) its binary names, reporting missing paths apart from sha256 mismatches.
+        /// Downloads every artifact in full, without the digest cache.
+        #[clap(long, conflicts_with = "head_only")]
+        deep: bool,
+
+        /// Print a JSON record for every checked binary instead of the summary.
+        #[clap(long)]
+        json: bool,
+    },
+    /// Prints which os/cpu platforms each tool has a binary for, to spot platforms some tools
+    /// are missing
+    PlatformMatrix {
+        /// Print the matrix as JSON.
+        #[clap(long)]
+        json: bool,
+
+        /// Exit nonzero if any tool has neither a binary nor a fallback for this os/cpu
+        /// platform; may be repeated.
+        #[clap(long, value_name = "OS/CPU", value_parser = matrix::parse_platform)]
+        require: Vec<Platform>,
+    },
+    /// Adds a binary for one platform of a tool, downloading it to compute its sha256
+    Add {
+        /// The tool to add the binary to; created if it doesn't exist.
+        tool: String,
+
+        /// The URL of the artifact.
+        #[clap(long, required_unless_present = "platforms_file")]
+        url: Option<String>,
+
+        #[clap(long, value_enum, required_unless_present = "platforms_file")]
+        os: Option<SupportedOs>,
+
+        #[clap(long, value_enum, required_unless_present = "platforms_file")]
+        cpu: Option<SupportedCpu>,
+
+        /// The libc a Linux binary links against, for tools that publish both.
+        #[clap(long, value_enum)]
+        libc: Option<Libc>,
+
+        #[clap(long, value_enum, default_value_t)]
+        kind: BinaryKind,
+
+        /// Path of the executable within an archive or pkg artifact.
+        #[clap(long)]
+        file: Option<String>,
+
+        /// Add a binary for every `[[platform]]` table in this TOML file, instead of the one
+        /// described by --url, --os, --cpu, --libc, --kind and --file.
+        #[clap(long, value_name = "PATH", conflicts_with_all = ["url", "os", "cpu", "libc", "kind", "file"])]
+        platforms_file: Option<std::path::PathBuf>,
+
+        /// Print a diff of the change instead of writing the lockfile.
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Removes a tool, or only its binary for one platform
+    Remove {
+        tool: String,
+
+        /// Only remove the binary for this OS (requires --cpu).
+        #[clap(long, value_enum, requires = "cpu")]
+        os: Option<SupportedOs>,
+
+        /// Only remove the binary for this CPU (requires --os).
+        #[clap(long, value_enum, requires = "os")]
+        cpu: Option<SupportedCpu>,
+
+        /// Only remove the binary for this libc (requires --os and --cpu); by
+        /// default every libc variant of the platform is removed.
+        #[clap(long, value_enum, requires = "os")]
+        libc: Option<Libc>,
+
+        /// Print a diff of the change instead of writing the lockfile.
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Prints how the lockfile differs from its contents at a git revision
+    Diff {
+        /// The git revision to compare against, e.g. HEAD~1 or origin/main.
+        #[clap(long, value_name = "REF")]
+        compare: String,
+    },
+    /// Diagnoses the token, proxy, TLS and GitHub API setup without changing the lockfile
+    Doctor,
+    /// Prints the settings in effect after layering flags, environment variables and the
+    /// config file, with the GitHub token redacted
+    PrintConfig,
+    /// Rewrites the lockfile's `$schema`, and in the same pass anything else the new schema
+    /// changes, to move a lockfile to a new schema URL
+    Migrate {
+        /// The schema URL to write (defaults to the one this multitool supports).
+        #[clap(long, value_name = "URL")]
+        write_schema_url: Option<String>,
+
+        /// Print a diff of the change instead of writing the lockfile.
+        #[clap(long)]
+        dry_run: bool,
+    },
+    /// Checks the lockfile, listing the problems `--fix` can repair; problems it can't
+    /// repair fail as they do for every other command
+    Validate {
+        /// Repair what can be repaired and write the lockfile back.
+        #[clap(long)]
+        fix: bool,
+
+        /// With --fix, print a diff of the repairs instead of writing the lockfile.
+        #[clap(long, requires = "fix")]
+        dry_run: bool,
     },
+    /// Manages the --cache-dir cache
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+impl Commands {
+    /// The filter selecting which tools the command operates on, for commands that take one.
+    fn filter(&self) -> Option<&ToolFilter> {
+        match self {
+            Commands::Update { options, .. } => Some(&options.filter),
+            Commands::Resolve { filter }
+            | Commands::Check { filter, .. }
+            | Commands::Watch { filter, .. }
+            | Commands::Verify { filter, .. } => Some(filter),
+            _ => None,
+        }
+    }
+
+    fn filter_mut(&mut self) -> Option<&mut ToolFilter> {
+        match self {
+            Commands::Update { options, .. } => Some(&mut options.filter),
+            Commands::Resolve { filter }
+            | Commands::Check { filter, .. }
+            | Commands::Watch { filter, .. }
+            | Commands::Verify { filter, .. } => Some(filter),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum CacheCommand {
+    /// Removes everything multitool has cached, reporting the bytes reclaimed
+    Clean,
+}
+
+/// Options controlling which binaries `update` touches and how.
+#[derive(Args, Clone, Debug, Default)]
+struct UpdateOptions {
+    #[clap(flatten)]
+    filter: ToolFilter,
+
+    /// Skip all release lookups and downloads; only validate, sort and
+    /// rewrite the lockfile.
+    #[clap(long)]
+    no_network: bool,
+
+    /// Write tools that didn't change back exactly as they were, only re-rendering
+    /// the changed ones, instead of rewriting the whole lockfile in canonical form.
+    #[clap(long, conflicts_with = "no_network")]
+    preserve_unchanged: bool,
+
+    /// Rewrite every binary's URLs in canonical form: lowercase scheme and host, no default
+    /// port, normalized percent-encoding and no `.` or `..` segments.
+    #[clap(long, conflicts_with = "preserve_unchanged")]
+    normalize_urls: bool,
+
+    /// Keep every binary at its current URL and only recompute its sha256 and size from a
+    /// fresh download, to repair wrong digests. Makes no release lookups.
+    #[clap(long, visible_alias = "refresh", conflicts_with_all = ["no_network", "normalize_urls"])]
+    sha_only: bool,
+
+    /// Time each binary's release lookups and download, and print the slowest binaries
+    /// and the total per download host when done. JSON reports include the times.
+    #[clap(long, conflicts_with = "no_network")]
+    concurrency_report: bool,
+
+    /// Only rewrite the version in the inner `file` paths of archive and pkg binaries, as an
+    /// update to VERSION would, keeping every URL, sha256 and size. Makes no requests.
+    #[clap(long, value_name = "VERSION", conflicts_with_all = ["no_network", "sha_only"])]
+    update_file_path_only: Option<String>,
+
+    /// With `--update-file-path-only`, the version to replace in the inner paths, for paths
+    /// left behind at another version than their URL's. By default it's the URL's version,
+    /// or else the one version the paths name.
+    #[clap(
+        long = "from",
+        value_name = "VERSION",
+        requires = "update_file_path_only"
+    )]
+    file_path_from: Option<String>,
+
+    /// Link each binary updated to a GitHub release to the release's page in reports, and
+    /// include its notes, truncated.
+    #[clap(long, conflicts_with_all = ["no_network", "sha_only", "update_file_path_only"])]
+    include_release_notes: bool,
+
+    /// Only print the URL each selected binary would be downloaded from at its latest version,
+    /// after the same lookups and URL rewriting as an update, without downloading anything or
+    /// changing the lockfile.
+    #[clap(long, conflicts_with_all = ["no_network", "sha_only", "update_file_path_only", "checkpoint"])]
+    print_url: bool,
+
+    /// Pretend the latest release of every selected binary is TAG and print the URL and inner
+    /// paths an update would rewrite it to, making no release lookups and without changing
+    /// the lockfile.
+    #[clap(long, value_name = "TAG", conflicts_with_all = ["no_network", "sha_only", "update_file_path_only", "checkpoint", "print_url"])]
+    assume_tag: Option<String>,
+
+    /// With --assume-tag, also download each rewritten URL and print its sha256.
+    #[clap(long, requires = "assume_tag")]
+    download: bool,
+
+    /// Before updating, print the release lookups and downloads the update would make,
+    /// grouped by GitHub repo, with totals.
+    #[clap(long, conflicts_with_all = ["no_network", "sha_only", "update_file_path_only", "print_url", "assume_tag"])]
+    plan: bool,
+
+    /// Only print the --plan, without updating anything.
+    #[clap(long, conflicts_with_all = ["no_network", "sha_only", "update_file_path_only", "print_url", "assume_tag"])]
+    plan_only: bool,
+
+    /// Refuse to start if the filters select more than this many tools, as a guard against
+    /// an unexpectedly large lockfile making thousands of requests.
+    #[clap(long, value_name = "N")]
+    max_tools: Option<usize>,
+
+    /// Stop starting new tools once this many binaries have been updated, leaving the rest
+    /// of the lockfile untouched for a later run; the tool in progress is finished. Reports
+    /// how many binaries are still outdated.
+    #[clap(long, value_name = "N", conflicts_with_all = ["no_network", "sha_only", "update_file_path_only"])]
+    max_updates: Option<usize>,
+
+    /// Save each finished tool to `<lockfile>.checkpoint` as the update runs, and resume
+    /// from it, so an interrupted update doesn't start over.
+    #[clap(long, conflicts_with = "no_network")]
+    checkpoint: bool,
+
+    /// Check the lockfile as written against what rules_multitool accepts, listing anything
+    /// Bazel would reject and exiting with code 5.
+    #[clap(long)]
+    emit_bazel_lock: bool,
+
+    /// Print a diff of the updated lockfile instead of writing it.
+    #[clap(long, conflicts_with = "checkpoint")]
+    dry_run: bool,
+
+    /// With --dry-run, exit with code 9 if the lockfile would change, like
+    /// `git diff --exit-code`.
+    #[clap(long, requires = "dry_run")]
+    diff_exit_code: bool,
+
+    /// Stamp the lockfile's `metadata` with the time and multitool version of an update that
+    /// changes it. Off by default, since rules_multitool reads every top-level key but
+    /// `$schema` as a tool; an existing `metadata` block is otherwise kept as it is.
+    #[clap(long)]
+    stamp_metadata: bool,
 }
 
 trait Common {
     fn url(&self) -> &str;
+    fn sha256(&self) -> &str;
+    fn size(&self) -> Option<u64>;
+    fn parts(&self) -> Option<&[String]>;
+    fn headers(&self) -> Option<&BTreeMap<String, String>>;
+    fn os(&self) -> &SupportedOs;
+    fn cpu(&self) -> &SupportedCpu;
+    fn libc(&self) -> Option<&Libc>;
     fn sort_key(&self) -> String;
 }
 
@@ -55,209 +603,3590 @@ impl Common for Binary {
             Binary::File(file) => &file.url,
             Binary::Archive(archive) => &archive.url,
             Binary::Pkg(pkg) => &pkg.url,
+            Binary::Dir(dir) => &dir.url,
         }
     }
 
-    fn sort_key(&self) -> String {
+    fn sha256(&self) -> &str {
+        match &self {
+            Binary::File(bin) => &bin.sha256,
+            Binary::Archive(bin) => &bin.sha256,
+            Binary::Pkg(bin) => &bin.sha256,
+            Binary::Dir(bin) => &bin.sha256,
+        }
+    }
+
+    fn size(&self) -> Option<u64> {
+        match &self {
+            Binary::File(bin) => bin.size,
+            Binary::Archive(bin) => bin.size,
+            Binary::Pkg(bin) => bin.size,
+            Binary::Dir(bin) => bin.size,
+        }
+    }
+
+    fn parts(&self) -> Option<&[String]> {
+        match &self {
+            Binary::Archive(bin) => bin.parts.as_deref(),
+            Binary::Dir(bin) => bin.parts.as_deref(),
+            Binary::File(_) | Binary::Pkg(_) => None,
+        }
+    }
+
+    fn headers(&self) -> Option<&BTreeMap<String, String>> {
+        match &self {
+            Binary::File(bin) => bin.headers.as_ref(),
+            Binary::Archive(bin) => bin.headers.as_ref(),
+            Binary::Pkg(bin) => bin.headers.as_ref(),
+            Binary::Dir(bin) => bin.headers.as_ref(),
+        }
+    }
+
+    fn os(&self) -> &SupportedOs {
+        match &self {
+            Binary::File(bin) => &bin.os,
+            Binary::Archive(bin) => &bin.os,
+            Binary::Pkg(bin) => &bin.os,
+            Binary::Dir(bin) => &bin.os,
+        }
+    }
+
+    fn cpu(&self) -> &SupportedCpu {
+        match &self {
+            Binary::File(bin) => &bin.cpu,
+            Binary::Archive(bin) => &bin.cpu,
+            Binary::Pkg(bin) => &bin.cpu,
+            Binary::Dir(bin) => &bin.cpu,
+        }
+    }
+
+    fn libc(&self) -> Option<&Libc> {
         match &self {
-            Binary::File(bin) => format!("{}_{}", bin.os, bin.cpu),
-            Binary::Archive(bin) => format!("{}_{}", bin.os, bin.cpu),
-            Binary::Pkg(bin) => format!("{}_{}", bin.os, bin.cpu),
+            Binary::File(bin) => bin.libc.as_ref(),
+            Binary::Archive(bin) => bin.libc.as_ref(),
+            Binary::Pkg(_) => None,
+            Binary::Dir(bin) => bin.libc.as_ref(),
+        }
+    }
+
+    fn sort_key(&self) -> String {
+        match self.libc() {
+            Some(libc) => format!("{}_{}_{libc}", self.os(), self.cpu()),
+            None => format!("{}_{}", self.os(), self.cpu()),
         }
     }
 }
 
-struct GitHubRelease<'a> {
-    org: &'a str,
-    repo: &'a str,
-    version: &'a str,
-    path: &'a str,
+/// A move of a binary from the release it's pinned to, `version`, to `latest`,
+/// both tags. `names` are their release names when the tool takes versions
+/// from them.
+struct Bump {
+    version: String,
+    latest: String,
+    names: Option<(String, String)>,
 }
 
-impl GitHubRelease<'_> {
-    fn from(url: &str) -> Option<GitHubRelease> {
-        GITHUB_RELEASE_PATTERN.captures(url).map(|capture| {
-            let (_, [org, repo, version, path]) = capture.extract();
-            GitHubRelease {
-                org,
-                repo,
-                version,
-                path,
-            }
+impl Bump {
+    /// Looks up the latest release of `source`, and the release names if needed.
+    fn resolve(
+        resolver: &mut Resolver,
+        source: &ReleaseSource,
+        binary: &Binary,
+    ) -> Result<Bump, Error> {
+        let version = source.current_version(binary)?;
+        let latest = resolver.resolve_latest(source)?;
+        let names = resolver.release_names(source, &version, &latest)?;
+        Ok(Bump {
+            version,
+            latest,
+            names,
         })
     }
-}
 
-fn compute_sha256(client: &reqwest::blocking::Client, url: &str) -> Result<String, Box<dyn Error>> {
-    let response = client.get(url).send()?.error_for_status()?;
-    let bytes = response.bytes()?;
-    Ok(sha256::digest(bytes.to_vec()))
+    /// A move to `tag` taken as the latest release, with no lookups, so release
+    /// names aren't known and the tags stand in for them.
+    fn assumed(source: &ReleaseSource, binary: &Binary, tag: &str) -> Result<Bump, Error> {
+        Ok(Bump {
+            version: source.current_version(binary)?,
+            latest: tag.to_owned(),
+            names: None,
+        })
+    }
+
+    fn names(&self) -> Option<(&str, &str)> {
+        self.names
+            .as_ref()
+            .map(|(pinned, newest)| (pinned.as_str(), newest.as_str()))
+    }
+
+    /// The pinned and newest versions that are compared and written.
+    fn versions(&self) -> (&str, &str) {
+        self.names().unwrap_or((&self.version, &self.latest))
+    }
+
+    /// Where `binary` is at the latest release as its URL spells it, before
+    /// any asset selection, keeping its digest until it's downloaded there.
+    fn locate(&self, source: &ReleaseSource, binary: &Binary) -> Result<Rewrite, Error> {
+        Ok(Rewrite {
+            url: source.url_for(binary, &self.latest, self.names()),
+            parts: source.parts_for(binary, &self.latest, self.names())?,
+            ..Rewrite::unmoved(binary)
+        })
+    }
+
+    /// `binary` moved by `rewrite`, with its inner paths following the version.
+    fn rewrite(&self, binary: &Binary, rewrite: Rewrite) -> Binary {
+        let (pinned, newest) = self.versions();
+        rewrite_binary(binary, rewrite, pinned, newest)
+    }
 }
 
-fn update_github_release(
-    client: &reqwest::blocking::Client,
-    gh_latest_releases: &mut HashMap<String, String>,
+/// Moves `binary` to the latest version available from its release source, if
+/// it has one and isn't already there.
+fn update_binary(
+    resolver: &mut Resolver,
     tool: &str,
+    definition: &ToolDefinition,
     binary: &Binary,
-    release: &GitHubRelease,
-) -> Result<Binary, Box<dyn Error>> {
-    let org = release.org;
-    let repo = release.repo;
-
-    let key = format!("https://api.github.com/repos/{org}/{repo}/releases/latest");
-    let raw = gh_latest_releases.entry(key.clone()).or_insert_with(|| {
-        client
-            .get(&key)
-            .send()
-            .unwrap_or_else(|_| panic!("Error making request to GitHub"))
-            .text()
-            .unwrap()
-    });
+) -> Result<Option<(Binary, Change)>, Error> {
+    let Some(release_source) = ReleaseSource::of(&definition.source, binary) else {
+        Trace {
+            tool,
+            binary,
+            selection: None,
+            versions: None,
+            decision: Decision::SkippedNoSource,
+        }
+        .emit(resolver.settings);
+        return Ok(None);
+    };
 
-    let response: Value = serde_json::from_str(raw)?;
-    let latest_tag = response["tag_name"]
-        .as_str()
-        .unwrap_or_else(|| panic!("Failed to find tag_name in response:\n===\n{raw}\n===\n"));
+    resolver.check_deadline()?;
+    let bump = Bump::resolve(resolver, &release_source, binary)?;
+    let (version, latest) = (&bump.version, &bump.latest);
+    let (pinned, newest) = bump.versions();
+    let trace = |decision| Trace {
+        tool,
+        binary,
+        selection: Some(explain::selection(&release_source)),
+        versions: Some((pinned, newest)),
+        decision,
+    };
 
-    if release.version == latest_tag {
-        return Ok(binary.clone());
+    if pinned == newest {
+        trace(Decision::UpToDate).emit(resolver.settings);
+        if resolver.settings.verbose {
+            eprintln!(
+                "{tool} ({}/{}) is up to date at {}",
+                binary.os(),
+                binary.cpu(),
+                strip_v(pinned)
+            );
+        }
+        return Ok(None);
     }
 
-    let version = release.version.strip_prefix('v').unwrap_or(release.version);
-    let latest = latest_tag.strip_prefix('v').unwrap_or(latest_tag);
+    if let Some((false, pinned_at, newest_at)) =
+        resolver.published_order(&release_source, version, latest)?
+    {
+        trace(Decision::NotNewer).emit(resolver.settings);
+        warn(format!(
+            "not updating {tool} ({}/{}) from {} to {}: {latest} was published at {}, not after {version} at {}",
+            binary.os(),
+            binary.cpu(),
+            strip_v(pinned),
+            strip_v(newest),
+            timestamp::format(newest_at),
+            timestamp::format(pinned_at)
+        ));
+        return Ok(None);
+    }
 
-    let url = format!(
-        "https://github.com/{org}/{repo}/releases/download/{latest_tag}/{0}",
-        release.path.replace(version, latest)
-    );
-    // TODO(mark): check that the new url is in .assets[].browser_download_url
+    let mut located = bump.locate(&release_source, binary)?;
+    let mut size = None;
+    let settings = resolver.settings;
+    // split artifacts are their own first part, so checking assets would only
+    // cover part of them, and source archives aren't assets at all
+    if let (
+        ReleaseSource::GitHub(
+            release @ GitHubRelease {
+                source_archive: None,
+                ..
+            },
+            _,
+        ),
+        None,
+    ) = (&release_source, &located.parts)
+    {
+        let checked = settings.verify_assets || settings.select_asset_by.is_some();
+        if checked || settings.prefer_api_assets {
+            let assets = resolver.release_assets(release, latest)?;
+            if settings.prefer_api_assets {
+                let previous = binary.url().split(['?', '#']).next().unwrap_or_default();
+                let previous = previous.rsplit('/').next().unwrap_or(previous);
+                let expected = located.url.rsplit('/').next().unwrap_or(&located.url);
+                let urls: Vec<String> = assets.iter().map(|asset| asset.url.clone()).collect();
+                let closest = asset::closest(
+                    &urls,
+                    previous,
+                    expected,
+                    binary.os(),
+                    binary.cpu(),
+                    binary.libc(),
+                )?;
+                match closest {
+                    Some(chosen) if changes_format(binary, chosen) => warn(format!(
+                        "not switching {tool} ({}/{}) to {}: it isn't the same kind of archive as {previous}; substituting the version into its URL",
+                        binary.os(),
+                        binary.cpu(),
+                        chosen.rsplit('/').next().unwrap_or(chosen)
+                    )),
+                    Some(chosen) => {
+                        let name = chosen.rsplit('/').next().unwrap_or(chosen);
+                        if asset::renamed(previous, name) {
+                            println!(
+                                "Asset of {tool} ({}/{}) renamed from {previous} to {name}",
+                                binary.os(),
+                                binary.cpu()
+                            );
+                        }
+                        located.url = chosen.to_owned();
+                    }
+                    None => warn(format!(
+                        "no asset of {latest} confidently matches {previous} for {tool} ({}/{}); substituting the version into its URL",
+                        binary.os(),
+                        binary.cpu()
+                    )),
+                }
+            }
+            if checked && !assets.iter().any(|asset| asset.url == located.url) {
+                let url = &located.url;
+                let expected = url.rsplit('/').next().unwrap_or(url);
+                let Some(AssetSelection::ArchTriple) = settings.select_asset_by else {
+                    return Err(format!("{url} is not an asset of {latest}").into());
+                };
+                let urls: Vec<String> = assets.iter().map(|asset| asset.url.clone()).collect();
+                let selected =
+                    asset::select(&urls, expected, binary.os(), binary.cpu(), binary.libc())
+                        .map_err(|e| format!("{url} is not an asset of {latest}: {e}"))?
+                        .ok_or_else(|| {
+                            format!(
+                                "{url} is not an asset of {latest} and no asset names its platform"
+                            )
+                        })?;
+                if changes_format(binary, selected) {
+                    let name = selected.rsplit('/').next().unwrap_or(selected);
+                    return Err(format!(
+                        "{url} is not an asset of {latest}, and {name}, which names its platform, isn't the same kind of archive"
+                    )
+                    .into());
+                }
+                println!(
+                    "Selected asset {selected} for {tool} ({}/{}) in place of {expected}",
+                    binary.os(),
+                    binary.cpu()
+                );
+                located.url = selected.to_owned();
+            }
+            if settings.verify_assets {
+                size = assets
+                    .iter()
+                    .find(|asset| asset.url == located.url)
+                    .and_then(|asset| asset.size);
+            }
+        }
+    }
+
+    // an upstream can move to plain HTTP between releases
+    let insecure = located
+        .parts
+        .iter()
+        .flatten()
+        .chain(located.parts.is_none().then_some(&located.url))
+        .filter(|url| lockfile::is_plain_http(url) && !settings.allow_insecure_http);
+    for insecure in insecure {
+        if settings.require_https {
+            return Err(format!("{insecure} is fetched over plain HTTP (--require-https)").into());
+        }
+        warn(format!(
+            "{tool} ({}/{}): {insecure} is fetched over plain HTTP; pass --allow-insecure-http if that's intended",
+            binary.os(),
+            binary.cpu()
+        ));
+    }
 
-    let sha256 = compute_sha256(client, &url)?;
+    resolver.check_deadline()?;
+    let deadline = resolver.deadline();
+    let digest = resolver.download(|resolver| {
+        download::compute_binary_digest(
+            &resolver.client,
+            settings,
+            &mut resolver.digests,
+            DigestRequest {
+                binary,
+                signature: definition.signature.as_ref(),
+                url: &located.url,
+                parts: located.parts.as_deref(),
+                expected_size: size,
+                deadline,
+            },
+        )
+    })?;
+
+    let updated = bump.rewrite(
+        binary,
+        Rewrite {
+            sha256: digest.sha256,
+            size: digest.size.or(size),
+            ..located
+        },
+    );
+    let change = Change {
+        tool: tool.to_owned(),
+        os: binary.os().clone(),
+        cpu: binary.cpu().clone(),
+        old_version: strip_v(pinned).to_owned(),
+        new_version: strip_v(newest).to_owned(),
+        old_sha256: binary.sha256().to_owned(),
+        new_sha256: updated.sha256().to_owned(),
+        timing: None,
+        release: None,
+    };
+    trace(Decision::Updated).emit(settings);
+    let palette = resolver.settings.palette;
+    println!(
+        "Updating {} ({}/{}) from {} to {}",
+        palette.bold(tool),
+        binary.os(),
+        binary.cpu(),
+        change.old_version,
+        palette.green(&change.new_version)
+    );
+    Ok(Some((updated, change)))
+}
 
-    Ok(match binary {
-        Binary::File(bin) => {
-            println!(
-                "Updating {tool} ({}/{}) from {version} to {latest}",
-                bin.os, bin.cpu
+/// Recomputes the sha256 and size of `binary` at its current URL, for
+/// `update --sha-only`, returning the repaired binary if its digest was wrong.
+fn repair_binary(
+    resolver: &mut Resolver,
+    tool: &str,
+    definition: &ToolDefinition,
+    binary: &Binary,
+) -> Result<Option<(Binary, Change)>, Error> {
+    resolver.check_deadline()?;
+    let settings = resolver.settings;
+    let deadline = resolver.deadline();
+    let digest = resolver.download(|resolver| {
+        download::compute_binary_digest(
+            &resolver.client,
+            settings,
+            &mut resolver.digests,
+            DigestRequest {
+                signature: definition.signature.as_ref(),
+                deadline,
+                ..DigestRequest::of(binary)
+            },
+        )
+    })?;
+    download::warn_if_resized(tool, binary, &digest);
+    // a recorded size that's wrong fails every later download as surely as a
+    // wrong sha256 does
+    let size_matches = binary.size().is_none() || binary.size() == digest.size;
+    if digest.sha256 == binary.sha256() && size_matches {
+        if settings.verbose {
+            eprintln!(
+                "{tool} ({}/{}) already has the right sha256",
+                binary.os(),
+                binary.cpu()
             );
-            Binary::File(FileBinary {
-                url,
-                cpu: bin.cpu.clone(),
-                os: bin.os.clone(),
-                sha256,
-                headers: bin.headers.clone(),
-                auth_patterns: bin.auth_patterns.clone(),
-            })
         }
-        Binary::Archive(bin) => {
-            println!(
-                "Updating {tool} ({}/{}) from {version} to {latest}",
-                bin.os, bin.cpu
-            );
-            Binary::Archive(ArchiveBinary {
-                url,
-                file: bin.file.replace(version, latest),
-                cpu: bin.cpu.clone(),
-                os: bin.os.clone(),
-                sha256,
-                headers: bin.headers.clone(),
-                type_: bin.type_.clone(),
-                auth_patterns: bin.auth_patterns.clone(),
-            })
+        return Ok(None);
+    }
+    github::warn_if_source_archive(binary.url());
+
+    // the version is only for the report; it's never looked up
+    let version = ReleaseSource::of(&definition.source, binary)
+        .and_then(|source| source.current_version(binary).ok())
+        .unwrap_or_default();
+    // with the same version on both sides inner paths are left as they are
+    let repaired = rewrite_binary(
+        binary,
+        Rewrite {
+            sha256: digest.sha256,
+            size: digest.size,
+            ..Rewrite::unmoved(binary)
+        },
+        &version,
+        &version,
+    );
+    let change = Change {
+        tool: tool.to_owned(),
+        os: binary.os().clone(),
+        cpu: binary.cpu().clone(),
+        old_version: strip_v(&version).to_owned(),
+        new_version: strip_v(&version).to_owned(),
+        old_sha256: binary.sha256().to_owned(),
+        new_sha256: repaired.sha256().to_owned(),
+        timing: None,
+        release: None,
+    };
+    println!(
+        "Repairing sha256 of {} ({}/{}): {} -> {}",
+        settings.palette.bold(tool),
+        binary.os(),
+        binary.cpu(),
+        change.old_sha256,
+        settings.palette.green(&change.new_sha256)
+    );
+    Ok(Some((repaired, change)))
+}
+
+/// Downloads, hashes and adds a binary of `tool` for each entry. A failing entry
+/// is reported and skipped so the others are still added; returns how many
+/// failed.
+fn add_platforms(
+    lockfile: &mut Lockfile,
+    settings: &Settings,
+    tool: &str,
+    entries: Vec<PlatformEntry>,
+) -> usize {
+    let client = settings.client();
+    let mut digests = DigestCache::load(settings.cache_dir.as_deref());
+    let mut failures = 0;
+    for entry in entries {
+        let platform = entry.platform();
+        if lockfile::is_plain_http(&entry.url) && !settings.allow_insecure_http {
+            if settings.require_https {
+                eprintln!(
+                    "Unable to add {tool} ({platform}): {} is fetched over plain HTTP (--require-https)",
+                    entry.url
+                );
+                failures += 1;
+                continue;
+            }
+            warn(format!(
+                "{tool} ({platform}): {} is fetched over plain HTTP; pass --allow-insecure-http if that's intended",
+                entry.url
+            ));
         }
-        Binary::Pkg(bin) => {
-            println!(
-                "Updating {tool} ({}/{}) from {version} to {latest}",
-                bin.os, bin.cpu
-            );
-            Binary::Pkg(PkgBinary {
-                url,
-                file: bin.file.replace(version, latest),
-                cpu: bin.cpu.clone(),
-                os: bin.os.clone(),
-                sha256,
-                headers: bin.headers.clone(),
-                auth_patterns: bin.auth_patterns.clone(),
-            })
+        let added =
+            download::compute_sha256(&client, settings, &mut digests, &entry.url, None, None)
+                .and_then(|sha256| {
+                    edit::new_binary(entry.kind, entry.url, sha256, platform.clone(), entry.file)
+                })
+                .and_then(|binary| edit::add_binary(lockfile, tool, binary));
+        if let Err(e) = added {
+            eprintln!("Unable to add {tool} ({platform}): {e}");
+            failures += 1;
         }
-    })
+    }
+    if let Err(e) = digests.save() {
+        eprintln!("Unable to save digest cache: {e}");
+    }
+    failures
 }
 
-fn update_lockfile(path: &std::path::Path, tool_to_update: &Option<String>) {
-    let contents = fs::read_to_string(path).expect("Unable to load lockfile");
+fn strip_v(version: &str) -> &str {
+    version.strip_prefix('v').unwrap_or(version)
+}
 
-    let lockfile: Lockfile =
-        serde_json::from_str(&contents).expect("Unable to deserialize lockfile");
+/// Replaces the version token in `path`, keeping the path's own `v`-prefix style
+/// regardless of whether either tag carries a `v`. Only whole version numbers are
+/// replaced, so `1.2.3` is left alone inside `11.2.3` or `1.2.30`.
+fn substitute_version(path: &str, version: &str, latest: &str) -> String {
+    let version = strip_v(version);
+    let latest = strip_v(latest);
 
-    if lockfile.schema != SCHEMA {
-        panic!("Unsupported lockfile schema {}", lockfile.schema)
+    let prefixed = format!("v{version}");
+    if !version_positions(path, &prefixed).is_empty() {
+        replace_version(path, &prefixed, &format!("v{latest}"))
+    } else if !version_positions(path, version).is_empty() {
+        replace_version(path, version, latest)
+    } else {
+        match (tag_number(version), tag_number(latest)) {
+            (Some(version), Some(latest)) => replace_version(path, version, latest),
+            _ => path.to_owned(),
+        }
     }
+}
 
-    let client = reqwest::blocking::Client::builder()
-        .user_agent("multitool")
-        .build()
-        .unwrap();
+/// The version number of a tag with a prefix that paths leave out, such as
+/// `17.0.6` of `llvmorg-17.0.6`: everything from its first digit.
+fn tag_number(tag: &str) -> Option<&str> {
+    tag.find(|c: char| c.is_ascii_digit())
+        .map(|start| &tag[start..])
+        .filter(|number| number.len() < tag.len())
+}
 
-    // basic cache of latest release lookups
-    let mut gh_latest_releases: HashMap<String, String> = HashMap::new();
+/// Where `token` occurs in `path` not directly extended by more version
+/// components: neither side touches a digit, or a `.` that leads to one.
+fn version_positions(path: &str, token: &str) -> Vec<usize> {
+    if token.is_empty() {
+        return Vec::new();
+    }
+    let bytes = path.as_bytes();
+    let digit = |i: Option<usize>| i.and_then(|i| bytes.get(i)).is_some_and(u8::is_ascii_digit);
+    path.match_indices(token)
+        .map(|(start, _)| start)
+        .filter(|&start| {
+            let end = start + token.len();
+            let before = start.checked_sub(1);
+            let extends_before = digit(before)
+                || (before.is_some_and(|i| bytes[i] == b'.') && digit(start.checked_sub(2)));
+            let extends_after =
+                digit(Some(end)) || (bytes.get(end) == Some(&b'.') && digit(Some(end + 1)));
+            !extends_before && !extends_after
+        })
+        .collect()
+}
 
-    let tools: BTreeMap<String, ToolDefinition> = lockfile
-        .tools
-        .into_iter()
-        .map(|(tool, binary)| {
-            if let Some(t) = tool_to_update {
-                if !t.eq_ignore_ascii_case(&tool) {
-                    // Return the tool definition unchanged if this is not being updated.
-                    return (tool, binary);
-                }
-            }
+fn replace_version(path: &str, token: &str, replacement: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    let mut copied = 0;
+    for start in version_positions(path, token) {
+        out.push_str(&path[copied..start]);
+        out.push_str(replacement);
+        copied = start + token.len();
+    }
+    out.push_str(&path[copied..]);
+    out
+}
 
-            let mut binaries: Vec<Binary> = binary
-                .binaries
-                .into_iter()
-                .map(|binary| match GitHubRelease::from(binary.url()) {
-                    Some(release) => update_github_release(
-                        &client,
-                        &mut gh_latest_releases,
-                        &tool,
-                        &binary,
-                        &release,
-                    )
-                    .map_err(|e| {
-                        println!("Encountered error while attempting to update {tool}: {e}")
-                    })
-                    .unwrap_or(binary),
-                    None => binary,
-                })
-                .collect();
+/// Where a rewritten binary is fetched from, and what it hashes to there.
+struct Rewrite {
+    url: String,
+    parts: Option<Vec<String>>,
+    sha256: String,
+    size: Option<u64>,
+}
 
-            binaries.sort_by_key(|v| v.sort_key());
+impl Rewrite {
+    /// Keeps `binary` where it is, with its recorded sha256 and size.
+    fn unmoved(binary: &Binary) -> Self {
+        Rewrite {
+            url: binary.url().to_owned(),
+            parts: binary.parts().map(<[String]>::to_vec),
+            sha256: binary.sha256().to_owned(),
+            size: binary.size(),
+        }
+    }
+}
 
-            (tool, ToolDefinition { binaries })
-        })
-        .collect();
+/// Rebuilds `binary` with the `url`, `parts`, `sha256` and `size` of
+/// `rewrite`, substituting `version` with `latest` in any inner `file` or
+/// `root` path, including the `file` of a nested archive. Performs no I/O.
+fn rewrite_binary(binary: &Binary, rewrite: Rewrite, version: &str, latest: &str) -> Binary {
+    let Rewrite {
+        url,
+        parts,
+        sha256,
+        size,
+    } = rewrite;
+    let version = strip_v(version);
+    let latest = strip_v(latest);
+    match binary {
+        Binary::File(bin) => Binary::File(FileBinary {
+            url,
+            cpu: bin.cpu.clone(),
+            os: bin.os.clone(),
+            libc: bin.libc.clone(),
+            sha256,
+            size,
+            headers: bin.headers.clone(),
+            auth_patterns: bin.auth_patterns.clone(),
+        }),
+        Binary::Archive(bin) => Binary::Archive(ArchiveBinary {
+            url,
+            parts,
+            file: substitute_version(&bin.file, version, latest),
+            cpu: bin.cpu.clone(),
+            os: bin.os.clone(),
+            libc: bin.libc.clone(),
+            sha256,
+            size,
+            headers: bin.headers.clone(),
+            type_: bin.type_.clone(),
+            inner: bin.inner.as_ref().map(|inner| InnerArchive {
+                file: substitute_version(&inner.file, version, latest),
+                type_: inner.type_.clone(),
+            }),
+            auth_patterns: bin.auth_patterns.clone(),
+            sha256_of: bin.sha256_of,
+        }),
+        Binary::Pkg(bin) => Binary::Pkg(PkgBinary {
+            url,
+            file: substitute_version(&bin.file, version, latest),
+            cpu: bin.cpu.clone(),
+            os: bin.os.clone(),
+            sha256,
+            size,
+            headers: bin.headers.clone(),
+            auth_patterns: bin.auth_patterns.clone(),
+            pkg_id: bin.pkg_id.clone(),
+            install_to: bin.install_to.clone(),
+        }),
+        Binary::Dir(bin) => Binary::Dir(DirBinary {
+            url,
+            parts,
+            cpu: bin.cpu.clone(),
+            os: bin.os.clone(),
+            libc: bin.libc.clone(),
+            sha256,
+            size,
+            root: bin
+                .root
+                .as_ref()
+                .map(|root| substitute_version(root, version, latest)),
+            headers: bin.headers.clone(),
+            type_: bin.type_.clone(),
+            auth_patterns: bin.auth_patterns.clone(),
+        }),
+    }
+}
 
-    let lockfile = Lockfile {
-        schema: lockfile.schema,
-        tools,
-    };
+/// Parses a `--token` value, `HOST=ENV_VAR`, into the host and the name of the
+/// environment variable holding its token.
+fn parse_host_token(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((host, name)) if !host.is_empty() && !name.is_empty() => {
+            Ok((host.to_owned(), name.to_owned()))
+        }
+        _ => Err("expected HOST=ENV_VAR".to_owned()),
+    }
+}
+
+/// Parses and validates lockfile `contents`. The `$schema` isn't checked here:
+/// any lockfile that parses is usable, and [`load_lockfile`] only warns about
+/// an unknown schema.
+fn parse_lockfile(contents: &str) -> Result<Lockfile, String> {
+    let lockfile: Lockfile = serde_json::from_str(contents)
+        .map_err(|e| format!("Unable to deserialize lockfile: {e}"))?;
 
-    let contents = serde_json::to_string_pretty(&lockfile).unwrap();
-    fs::write(path, contents + "\n").expect("Error updating lockfile")
+    validate_tools(&lockfile)?;
+    Ok(lockfile)
+}
+
+fn validate_tools(lockfile: &Lockfile) -> Result<(), String> {
+    // a tool named `metadata` would be read as lockfile metadata and lost
+    let is_tool = lockfile.metadata.as_ref().is_some_and(|metadata| {
+        serde_json::to_value(metadata)
+            .and_then(serde_json::from_value::<ToolDefinition>)
+            .is_ok()
+    });
+    if is_tool {
+        return Err("'metadata' holds lockfile metadata and can't name a tool".to_owned());
+    }
+    for (tool, definition) in &lockfile.tools {
+        definition
+            .validate()
+            .and_then(|()| definition.validate_digests())
+            .map_err(|e| format!("Invalid tool '{tool}': {e}"))?;
+    }
+    Ok(())
+}
+
+/// Parses lockfile `contents` written against any schema, rewriting `$schema` to
+/// `schema`. The tools must already be valid under the current schema; there
+/// are no field-level migrations yet, and any the schema gains belong here.
+fn migrate_lockfile(contents: &str, schema: &str) -> Result<Lockfile, String> {
+    let mut lockfile: Lockfile = serde_json::from_str(contents)
+        .map_err(|e| format!("Unable to deserialize lockfile: {e}"))?;
+    lockfile.schema = schema.to_owned();
+    validate_tools(&lockfile)?;
+    Ok(lockfile)
+}
+
+/// Binaries of `lockfile` downloaded over plain HTTP: warnings to print, none
+/// with `--allow-insecure-http`, or an error with `--require-https`.
+fn insecure_http(lockfile: &Lockfile, settings: &Settings) -> Result<Vec<String>, String> {
+    if settings.allow_insecure_http {
+        return Ok(Vec::new());
+    }
+    let found: Vec<String> = lockfile
+        .tools
+        .iter()
+        .flat_map(|(tool, definition)| {
+            definition
+                .insecure_urls()
+                .into_iter()
+                .map(move |url| format!("tool '{tool}': {url} is fetched over plain HTTP"))
+        })
+        .collect();
+    if settings.require_https && !found.is_empty() {
+        return Err(format!("{} (--require-https)", found.join("\n")));
+    }
+    Ok(found
+        .into_iter()
+        .map(|warning| format!("{warning}; pass --allow-insecure-http if that's intended"))
+        .collect())
+}
+
+/// Loads the lockfile at `path`, exiting with [`ExitCode::ValidationFailure`] if
+/// it is unusable.
+fn load_lockfile(path: &std::path::Path, settings: &Settings) -> Lockfile {
+    try_load_lockfile(path, settings).unwrap_or_else(|code| code.exit())
+}
+
+/// Loads the lockfile at `path` as [`load_lockfile`] does, printing why it's
+/// unusable and returning the code to exit with instead of exiting.
+fn try_load_lockfile(path: &std::path::Path, settings: &Settings) -> Result<Lockfile, ExitCode> {
+    let contents = fs::read_to_string(path).expect("Unable to load lockfile");
+    let lockfile = parse_lockfile(&contents)
+        .and_then(|lockfile| {
+            insecure_http(&lockfile, settings)?
+                .into_iter()
+                .for_each(warn);
+            Ok(lockfile)
+        })
+        .map_err(|e| {
+            eprintln!("{e}");
+            ExitCode::ValidationFailure
+        })?;
+    for (tool, definition) in &lockfile.tools {
+        for warning in definition.source_warnings() {
+            warn(format!("tool '{tool}': {warning}"));
+        }
+    }
+    if !lockfile::is_known_schema(&lockfile.schema) {
+        warn(format!(
+            "unrecognized lockfile schema {}; reading it as {SCHEMA} (`multitool migrate` rewrites it)",
+            lockfile.schema
+        ));
+    }
+    Ok(lockfile)
+}
+
+/// Checks `rendered`, a lockfile as written, against what rules_multitool
+/// accepts, exiting with [`ExitCode::ValidationFailure`] after listing any
+/// violations.
+fn check_bazel_lock(rendered: &str, settings: &Settings) {
+    if report_bazel_violations(rendered, settings) {
+        ExitCode::ValidationFailure.exit();
+    }
+}
+
+/// Lists what rules_multitool would reject in `rendered`, returning whether
+/// there was anything.
+fn report_bazel_violations(rendered: &str, settings: &Settings) -> bool {
+    let violations = bazel::check(rendered, settings.platform_names);
+    for violation in &violations {
+        eprintln!("rules_multitool would reject {violation}");
+    }
+    !violations.is_empty()
+}
+
+/// Writes `lockfile` to `path`, or with `dry_run` prints a diff of what would be
+/// written instead. Returns whether that differs from the file on disk.
+fn write_lockfile(
+    path: &std::path::Path,
+    lockfile: &Lockfile,
+    settings: &Settings,
+    dry_run: bool,
+) -> bool {
+    let contents = lockfile.to_canonical_string(settings.sort_tools, settings.platform_names);
+    write_contents(path, &contents, dry_run)
+}
+
+/// Writes `contents` to the lockfile at `path`, or with `dry_run` prints a diff
+/// of what would be written instead. Returns whether they differ from the file
+/// on disk.
+fn write_contents(path: &std::path::Path, contents: &str, dry_run: bool) -> bool {
+    let original = fs::read_to_string(path).expect("Unable to load lockfile");
+    if dry_run {
+        print!(
+            "{}",
+            diff::unified(&path.display().to_string(), &original, contents)
+        );
+    } else {
+        atomic::write(path, contents).expect("Error updating lockfile");
+    }
+    original != contents
+}
+
+/// Whether moving an archive or dir `binary` to `url` would change its archive
+/// format, which its `type` or else its current name decides: the new file
+/// couldn't be read as the old format, and `type` would still claim it.
+fn changes_format(binary: &Binary, url: &str) -> bool {
+    let type_ = match binary {
+        Binary::Archive(archive) => archive.type_.as_deref(),
+        Binary::Dir(dir) => dir.type_.as_deref(),
+        Binary::File(_) | Binary::Pkg(_) => return false,
+    };
+    let format = archive::Format::of(None, url);
+    format.is_some() && format != archive::Format::of(type_, binary.url())
+}
+
+/// The inner paths of an archive or pkg binary that name its version.
+fn inner_paths(binary: &Binary) -> Vec<&str> {
+    match binary {
+        Binary::Archive(archive) => std::iter::once(archive.file.as_str())
+            .chain(archive.inner.as_ref().map(|inner| inner.file.as_str()))
+            .collect(),
+        Binary::Pkg(pkg) => vec![pkg.file.as_str()],
+        Binary::File(_) | Binary::Dir(_) => Vec::new(),
+    }
+}
+
+/// The distinct version numbers, such as `1.2.3`, that `paths` name.
+fn path_versions<'a>(paths: &[&'a str]) -> BTreeSet<&'a str> {
+    let mut versions = BTreeSet::new();
+    for path in paths {
+        let bytes = path.as_bytes();
+        let mut start = 0;
+        while start < bytes.len() {
+            let starts_number = bytes[start].is_ascii_digit()
+                && (start == 0 || !(bytes[start - 1].is_ascii_digit() || bytes[start - 1] == b'.'));
+            if !starts_number {
+                start += 1;
+                continue;
+            }
+            let mut end = start;
+            while end < bytes.len() && (bytes[end].is_ascii_digit() || bytes[end] == b'.') {
+                end += 1;
+            }
+            let number = path[start..end].trim_end_matches('.');
+            if number.contains('.') {
+                versions.insert(number);
+            }
+            start = end;
+        }
+    }
+    versions
+}
+
+/// Substitutes `target` for the stale version in the inner paths of an archive
+/// or pkg `binary`, for `update --update-file-path-only`, leaving its URL and
+/// digest alone. The stale version is `from`, or else the version of the URL
+/// when the paths name it, or else the one version the paths name; binaries
+/// without a release source or where it's ambiguous are skipped.
+fn update_file_path(
+    settings: &Settings,
+    tool: &str,
+    source: &Option<ToolSource>,
+    binary: &Binary,
+    from: Option<&str>,
+    target: &str,
+) -> Result<Option<(Binary, Change)>, Error> {
+    let paths = inner_paths(binary);
+    let Some(release_source) = ReleaseSource::of(source, binary) else {
+        return Ok(None);
+    };
+    if paths.is_empty() {
+        return Ok(None);
+    }
+    let version = match from {
+        Some(from) => from.to_owned(),
+        None => {
+            let version = release_source.current_version(binary)?;
+            let named = paths
+                .iter()
+                .any(|path| substitute_version(path, &version, target) != *path);
+            let versions = path_versions(&paths);
+            match versions.iter().next() {
+                _ if named => version,
+                Some(stale) if versions.len() == 1 => (*stale).to_owned(),
+                _ => {
+                    if versions.len() > 1 {
+                        warn(format!(
+                            "{tool} ({}/{}): {} names several versions; pass --from to pick one",
+                            binary.os(),
+                            binary.cpu(),
+                            paths[0]
+                        ));
+                    }
+                    return Ok(None);
+                }
+            }
+        }
+    };
+    let updated = rewrite_binary(binary, Rewrite::unmoved(binary), &version, target);
+    if inner_paths(&updated) == paths {
+        return Ok(None);
+    }
+    let change = Change {
+        tool: tool.to_owned(),
+        os: binary.os().clone(),
+        cpu: binary.cpu().clone(),
+        old_version: strip_v(&version).to_owned(),
+        new_version: strip_v(target).to_owned(),
+        old_sha256: binary.sha256().to_owned(),
+        new_sha256: binary.sha256().to_owned(),
+        timing: None,
+        release: None,
+    };
+    println!(
+        "Rewriting file path of {} ({}/{}) from {} to {}",
+        settings.palette.bold(tool),
+        binary.os(),
+        binary.cpu(),
+        paths[0],
+        settings.palette.green(inner_paths(&updated)[0])
+    );
+    Ok(Some((updated, change)))
+}
+
+/// The URLs `binary` would be downloaded from at its latest version, for
+/// `update --print-url`: every part of a split artifact, or else just its URL.
+/// `None` for a binary without a release source. Asset selection through the
+/// API (`--prefer-api-assets`, `--select-asset-by`) isn't applied.
+fn resolve_urls(
+    resolver: &mut Resolver,
+    source: &Option<ToolSource>,
+    binary: &Binary,
+) -> Result<Option<Vec<String>>, Error> {
+    let Some(release_source) = ReleaseSource::of(source, binary) else {
+        return Ok(None);
+    };
+    let bump = Bump::resolve(resolver, &release_source, binary)?;
+    let located = bump.locate(&release_source, binary)?;
+    Ok(Some(located.parts.unwrap_or_else(|| vec![located.url])))
+}
+
+/// `binary` as an update to `tag` would rewrite it, for `update --assume-tag`,
+/// keeping its digest. Makes no requests, so release names aren't looked up and
+/// the tag stands in for the version. `None` for a binary without a release
+/// source.
+fn assume_latest(
+    source: &Option<ToolSource>,
+    binary: &Binary,
+    tag: &str,
+) -> Result<Option<Binary>, Error> {
+    let Some(release_source) = ReleaseSource::of(source, binary) else {
+        return Ok(None);
+    };
+    let bump = Bump::assumed(&release_source, binary, tag)?;
+    let located = bump.locate(&release_source, binary)?;
+    Ok(Some(bump.rewrite(binary, located)))
+}
+
+/// Prints every selected binary rewritten to `tag` for `update --assume-tag`,
+/// with its sha256 at the new URL when `download` is set, returning how many
+/// couldn't be rewritten or downloaded.
+fn print_assumed(
+    path: &std::path::Path,
+    settings: &Settings,
+    options: &UpdateOptions,
+    tag: &str,
+) -> usize {
+    let lockfile = load_lockfile(path, settings);
+    let mut resolver = Resolver::new(settings);
+    let filter = &options.filter;
+    let mut failures = 0;
+    for (tool, definition) in &lockfile.tools {
+        if !filter.selects(tool, definition) {
+            continue;
+        }
+        for binary in &definition.binaries {
+            if !filter.selects_binary(tool, binary) {
+                continue;
+            }
+            let platform = format!("{tool} ({}/{})", binary.os(), binary.cpu());
+            let rewritten = match assume_latest(&definition.source, binary, tag) {
+                Ok(Some(rewritten)) => rewritten,
+                Ok(None) => {
+                    if settings.verbose {
+                        eprintln!("{platform}: no release source");
+                    }
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!(
+                        "{} while rewriting {platform}: {e}",
+                        settings.stderr_palette.red("Encountered error")
+                    );
+                    failures += 1;
+                    continue;
+                }
+            };
+            let indent = " ".repeat(platform.len());
+            println!("{platform}: {}", rewritten.url());
+            for part in rewritten.parts().unwrap_or_default().iter().skip(1) {
+                println!("{indent}  {part}");
+            }
+            for file in inner_paths(&rewritten) {
+                println!("{indent}  file: {file}");
+            }
+            if !options.download {
+                continue;
+            }
+            let digest = download::compute_binary_digest(
+                &resolver.client,
+                settings,
+                &mut resolver.digests,
+                DigestRequest {
+                    signature: definition.signature.as_ref(),
+                    ..DigestRequest::of(&rewritten)
+                },
+            );
+            match digest {
+                Ok(digest) => println!("{indent}  sha256: {}", digest.sha256),
+                Err(e) => {
+                    eprintln!(
+                        "{} while downloading {platform}: {e}",
+                        settings.stderr_palette.red("Encountered error")
+                    );
+                    failures += 1;
+                }
+            }
+        }
+    }
+    resolver.save_caches();
+    failures
+}
+
+/// Prints the requests an update would make for `update --plan`.
+fn print_plan(path: &std::path::Path, settings: &Settings, filter: &ToolFilter) {
+    let lockfile = load_lockfile(path, settings);
+    let tags = cache::TagCache::load(settings.cache_dir.as_deref());
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
+    print!(
+        "{}",
+        plan::render(&plan::plan(&lockfile, filter, settings, &tags, now))
+    );
+}
+
+/// Prints the URLs of every selected binary for `update --print-url`, returning
+/// how many couldn't be resolved.
+fn print_urls(path: &std::path::Path, settings: &Settings, filter: &ToolFilter) -> usize {
+    let lockfile = load_lockfile(path, settings);
+    let mut resolver = Resolver::new(settings);
+    resolver.prefetch(&lockfile, filter);
+    let mut failures = 0;
+    for (tool, definition) in &lockfile.tools {
+        if !filter.selects(tool, definition) {
+            continue;
+        }
+        for binary in &definition.binaries {
+            if !filter.selects_binary(tool, binary) {
+                continue;
+            }
+            let platform = format!("{tool} ({}/{})", binary.os(), binary.cpu());
+            match resolve_urls(&mut resolver, &definition.source, binary) {
+                Ok(Some(urls)) => {
+                    println!("{platform}: {}", urls[0]);
+                    for part in urls.iter().skip(1) {
+                        println!("{:width$}  {part}", "", width = platform.len());
+                    }
+                }
+                Ok(None) => {
+                    if settings.verbose {
+                        eprintln!("{platform}: no release source");
+                    }
+                }
+                Err(e) => {
+                    eprintln!(
+                        "{} while resolving {platform}: {e}",
+                        settings.stderr_palette.red("Encountered error")
+                    );
+                    failures += 1;
+                }
+            }
+        }
+    }
+    failures
+}
+
+/// Looks up the latest tag of every selected binary for `resolve`, returning the
+/// distinct tags of each tool and how many lookups failed. Failures are printed
+/// as they happen.
+fn resolve_tags(
+    resolver: &mut Resolver,
+    lockfile: &Lockfile,
+    filter: &ToolFilter,
+) -> (BTreeMap<String, BTreeSet<String>>, usize) {
+    // fresh lookups, kept for the update after
+    resolver.record_tags = true;
+    resolver.prefetch(lockfile, filter);
+    let mut tags: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut failures = 0;
+    for (tool, definition) in &lockfile.tools {
+        if !filter.selects(tool, definition) {
+            continue;
+        }
+        for binary in &definition.binaries {
+            if !filter.selects_binary(tool, binary) {
+                continue;
+            }
+            let Some(source) = ReleaseSource::of(&definition.source, binary) else {
+                continue;
+            };
+            match resolver.resolve_latest(&source) {
+                Ok(tag) => {
+                    tags.entry(tool.clone()).or_default().insert(tag);
+                }
+                Err(e) => {
+                    eprintln!(
+                        "{} while resolving {tool} ({}/{}): {e}",
+                        resolver.settings.stderr_palette.red("Encountered error"),
+                        binary.os(),
+                        binary.cpu()
+                    );
+                    failures += 1;
+                }
+            }
+        }
+    }
+    (tags, failures)
+}
+
+/// With `--max-tools`, fails if the filters select more tools than allowed.
+fn check_max_tools(lockfile: &Lockfile, options: &UpdateOptions) -> Result<(), String> {
+    let Some(max) = options.max_tools else {
+        return Ok(());
+    };
+    let selected = lockfile
+        .tools
+        .iter()
+        .filter(|(tool, definition)| options.filter.selects(tool, definition))
+        .count();
+    if selected > max {
+        return Err(format!(
+            "Refusing to update {selected} tools, more than --max-tools {max}"
+        ));
+    }
+    Ok(())
+}
+
+/// The result of `tool` when `update` leaves it alone for `reason`: each of
+/// its binaries the filters select, as skipped.
+fn skipped(
+    filter: &ToolFilter,
+    tool: &str,
+    definition: &ToolDefinition,
+    reason: SkipReason,
+) -> ToolUpdateResult {
+    let binaries = definition
+        .binaries
+        .iter()
+        .filter(|binary| filter.selects_binary(tool, binary))
+        .map(|binary| BinaryResult {
+            os: binary.os().clone(),
+            cpu: binary.cpu().clone(),
+            outcome: BinaryOutcome::Skipped { reason },
+        })
+        .collect();
+    ToolUpdateResult {
+        tool: tool.to_owned(),
+        binaries,
+    }
+}
+
+/// What an `update` did.
+struct UpdateRun {
+    results: Vec<ToolUpdateResult>,
+    /// The updated lockfile, and its contents as written (or with `--dry-run`, as
+    /// they would have been).
+    lockfile: Lockfile,
+    contents: String,
+    /// Whether the updated lockfile differs from the one on disk before the
+    /// update, or with `--dry-run` from the one still there.
+    changed: bool,
+}
+
+fn update_lockfile(
+    path: &std::path::Path,
+    settings: &Settings,
+    options: &UpdateOptions,
+) -> UpdateRun {
+    let mut lockfile = load_lockfile(path, settings);
+    check_max_tools(&lockfile, options).unwrap_or_else(|e| panic!("{e}"));
+    let mut checkpoint = options
+        .checkpoint
+        .then(|| Checkpoint::open(path, &fs::read(path).expect("Unable to load lockfile")));
+    // tools an interrupted run finished start from their updated definitions, so
+    // they're only downloaded again if a newer release has come out since
+    let mut resumed = BTreeSet::new();
+    if let Some(checkpoint) = &checkpoint {
+        for (tool, definition) in checkpoint.finished().unwrap_or_else(|e| panic!("{e}")) {
+            if let Some(original) = lockfile.tools.get_mut(&tool) {
+                *original = definition;
+                resumed.insert(tool);
+            }
+        }
+        if !resumed.is_empty() {
+            println!("Resuming after {} tools finished earlier", resumed.len());
+        }
+    }
+    if options.normalize_urls {
+        let normalized = url::normalize_lockfile(&mut lockfile);
+        if normalized > 0 {
+            println!("Normalized {normalized} URLs");
+        }
+    }
+
+    let mut resolver = Resolver::new(settings);
+    if !options.no_network && !options.sha_only && options.update_file_path_only.is_none() {
+        resolver.prefetch(&lockfile, &options.filter);
+    }
+
+    let mut results: Vec<ToolUpdateResult> = Vec::new();
+    let mut timings: Vec<timing::Entry> = Vec::new();
+    let mut deferred = false;
+
+    let tools: BTreeMap<String, ToolDefinition> = lockfile
+        .tools
+        .into_iter()
+        .map(|(tool, definition)| {
+            if !options.filter.selects(&tool, &definition) {
+                if options.filter.matches(&tool) && !options.no_network {
+                    explain::pinned(settings, &tool, &definition);
+                    results.push(skipped(
+                        &options.filter,
+                        &tool,
+                        &definition,
+                        SkipReason::Pinned,
+                    ));
+                }
+                // Return the tool definition unchanged if this is not being updated.
+                return (tool, definition);
+            }
+            if let Some(max_updates) = options.max_updates {
+                let applied: usize = results.iter().map(|result| result.changes().count()).sum();
+                if applied >= max_updates {
+                    deferred = true;
+                    results.push(skipped(
+                        &options.filter,
+                        &tool,
+                        &definition,
+                        SkipReason::Deferred,
+                    ));
+                    return (tool, definition);
+                }
+            }
+
+            resolver.start_tool();
+            let mut definition = definition;
+            let mut result = ToolUpdateResult {
+                tool: tool.clone(),
+                binaries: Vec::new(),
+            };
+            let mut binaries: Vec<Binary> = mem::take(&mut definition.binaries)
+                .into_iter()
+                .map(|binary| {
+                    if options.no_network || !options.filter.selects_binary(&tool, &binary) {
+                        return binary;
+                    }
+
+                    let started = Instant::now();
+                    resolver.downloading = Duration::ZERO;
+                    let updated = if let Some(target) = &options.update_file_path_only {
+                        update_file_path(
+                            settings,
+                            &tool,
+                            &definition.source,
+                            &binary,
+                            options.file_path_from.as_deref(),
+                            target,
+                        )
+                    } else if options.sha_only {
+                        repair_binary(&mut resolver, &tool, &definition, &binary)
+                    } else {
+                        update_binary(&mut resolver, &tool, &definition, &binary)
+                    };
+                    let timing = options.concurrency_report.then(|| {
+                        let timing = Timing {
+                            resolve: started.elapsed().saturating_sub(resolver.downloading),
+                            download: resolver.downloading,
+                        };
+                        // the host the new version was fetched from, which an update may move
+                        let fetched = match &updated {
+                            Ok(Some((updated, _))) => updated,
+                            _ => &binary,
+                        };
+                        timings.push(timing::Entry {
+                            tool: tool.clone(),
+                            os: binary.os().clone(),
+                            cpu: binary.cpu().clone(),
+                            host: url_host(fetched.url()).unwrap_or("unknown").to_owned(),
+                            timing,
+                        });
+                        timing
+                    });
+                    let mut record = |outcome| {
+                        result.binaries.push(BinaryResult {
+                            os: binary.os().clone(),
+                            cpu: binary.cpu().clone(),
+                            outcome,
+                        })
+                    };
+                    match updated {
+                        Ok(Some((updated, mut change))) => {
+                            change.timing = timing;
+                            if options.include_release_notes {
+                                change.release = resolver.release_notes(&updated);
+                            }
+                            record(BinaryOutcome::Updated(change));
+                            updated
+                        }
+                        // only an update looks for a release source
+                        Ok(None)
+                            if options.update_file_path_only.is_none()
+                                && !options.sha_only
+                                && ReleaseSource::of(&definition.source, &binary).is_none() =>
+                        {
+                            record(BinaryOutcome::Skipped {
+                                reason: SkipReason::NoSource,
+                            });
+                            binary
+                        }
+                        Ok(None) => {
+                            record(BinaryOutcome::Unchanged);
+                            binary
+                        }
+                        Err(e) => {
+                            record(BinaryOutcome::Failed {
+                                error: e.to_string(),
+                            });
+                            Trace {
+                                tool: &tool,
+                                binary: &binary,
+                                selection: None,
+                                versions: None,
+                                decision: Decision::Failed(e.to_string()),
+                            }
+                            .emit(settings);
+                            println!(
+                                "{} while attempting to update {tool} ({}/{}): {e}",
+                                settings.palette.red("Encountered error"),
+                                binary.os(),
+                                binary.cpu()
+                            );
+                            binary
+                        }
+                    }
+                })
+                .collect();
+            if !options.no_network {
+                results.push(result);
+            }
+
+            binaries.sort_by_key(|v| v.sort_key());
+            definition.binaries = binaries;
+            let finished = results.last().filter(|result| result.tool == tool);
+            if let (Some(checkpoint), Some(result)) = (&mut checkpoint, finished) {
+                if result.changes().next().is_some() {
+                    checkpoint.record(&tool, &definition);
+                }
+            }
+            (tool, definition)
+        })
+        .collect();
+
+    let updated_tool = |result: &ToolUpdateResult| {
+        result.changes().next().is_some() || resumed.contains(&result.tool)
+    };
+    // stamped only when something changed, so no-op runs leave the file alone
+    let changed = !resumed.is_empty() || results.iter().any(updated_tool);
+    let metadata = match lockfile.metadata {
+        previous if changed && options.stamp_metadata => Some(Metadata::stamped(previous)),
+        previous => previous,
+    };
+    let lockfile = Lockfile {
+        schema: lockfile.schema,
+        metadata,
+        tools,
+    };
+
+    let contents = if options.preserve_unchanged {
+        let tools: BTreeMap<&str, &ToolDefinition> = results
+            .iter()
+            .filter(|result| updated_tool(result))
+            .map(|result| (result.tool.as_str(), &lockfile.tools[&result.tool]))
+            .collect();
+        let original = fs::read_to_string(path).expect("Unable to load lockfile");
+        preserve::rewrite_tools(
+            &original,
+            &tools,
+            lockfile.metadata.as_ref(),
+            settings.platform_names,
+        )
+        .unwrap_or_else(|e| panic!("{e}"))
+    } else {
+        lockfile.to_canonical_string(settings.sort_tools, settings.platform_names)
+    };
+    let differs = write_contents(path, &contents, options.dry_run);
+    if let Some(checkpoint) = checkpoint {
+        checkpoint.finish();
+    }
+    if deferred {
+        let applied = outcome::changes(&results).len();
+        let remaining = check::check_with(&mut resolver, &lockfile, &options.filter)
+            .outdated
+            .len();
+        println!("Stopped after {applied} updates (--max-updates); {remaining} remain");
+    }
+    resolver.save_caches();
+    if options.concurrency_report {
+        print!("{}", timing::render(&timings));
+    }
+    if settings.verbose && !options.no_network {
+        eprint!("{}", outcome::render_summary(&results));
+    }
+
+    UpdateRun {
+        results,
+        lockfile,
+        contents,
+        changed: differs,
+    }
 }
 
 fn main() {
-    let cli = Cli::parse();
+    // panics are how most errors surface; report them with the generic error code
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        ExitCode::Error.exit()
+    }));
+
+    let matches = Cli::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| {
+        let _ = e.print();
+        ExitCode::Usage.exit()
+    });
+    if let Some(filter) = cli.command.filter_mut() {
+        filter
+            .apply_current_platform(std::env::consts::OS, std::env::consts::ARCH)
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                ExitCode::Usage.exit()
+            });
+    }
+    let fail_on_warn = cli.fail_on_warn;
     let lockfile = cli
         .lockfile
         .as_deref()
         .unwrap_or_else(|| std::path::Path::new("./multitool.lock.json"));
 
-    if !lockfile.exists() {
+    // doctor reports a missing lockfile itself rather than failing up front, and
+    // print-config and cache don't need one
+    if !matches!(
+        cli.command,
+        Commands::Doctor | Commands::PrintConfig | Commands::Cache { .. }
+    ) && !lockfile.exists()
+    {
         panic!("Cannot find lockfile '{:?}'", lockfile);
     }
 
+    let config_path = cli.config.clone().or_else(|| config::discover(lockfile));
+    let config = Config::load(cli.config.as_deref(), lockfile).unwrap_or_else(|e| panic!("{e}"));
+    let github_app = cli.github_app_id.clone().map(|app_id| {
+        let installation_id = cli.github_app_installation_id;
+        let app = match (
+            &cli.github_app_private_key,
+            &cli.github_app_private_key_file,
+        ) {
+            (Some(pem), _) => GitHubApp::new(app_id, pem, installation_id),
+            (None, Some(path)) => GitHubApp::load(app_id, path, installation_id),
+            (None, None) => unreachable!("--github-app-id requires a private key"),
+        };
+        app.unwrap_or_else(|e| {
+            eprintln!("{e}");
+            ExitCode::ValidationFailure.exit()
+        })
+    });
+    let settings = Settings {
+        github_api_url: cli
+            .github_api_url
+            .or(config.github_api_url)
+            .unwrap_or_else(|| DEFAULT_GITHUB_API_URL.to_owned()),
+        host_tokens: config::host_tokens(config.tokens.into_iter().chain(cli.tokens), |name| {
+            std::env::var(name).ok()
+        }),
+        github_token: cli.github_token,
+        github_token_source: match matches.value_source("github_token") {
+            Some(ValueSource::EnvVariable) => Some("GITHUB_TOKEN"),
+            Some(_) => Some("--github-token"),
+            None => None,
+        },
+        github_app,
+        timeout: cli
+            .timeout_secs
+            .or(config.timeout_secs)
+            .map(std::time::Duration::from_secs),
+        timeout_per_tool: cli.timeout_per_tool.map(std::time::Duration::from_secs),
+        max_redirects: cli
+            .max_redirects
+            .or(config.max_redirects)
+            .unwrap_or(DEFAULT_MAX_REDIRECTS),
+        max_retry_wait: cli
+            .max_retry_wait_secs
+            .or(config.max_retry_wait_secs)
+            .map_or(DEFAULT_MAX_RETRY_WAIT, std::time::Duration::from_secs),
+        ca_bundle: cli.ca_bundle,
+        danger_accept_invalid_certs: cli.danger_accept_invalid_certs,
+        from_dir: cli.from_dir,
+        max_download_size: cli.max_download_size,
+        cache_dir: cli.cache_dir,
+        require_https: cli.require_https,
+        allow_insecure_http: cli.allow_insecure_http,
+        retry_on_checksum_mismatch: cli.retry_on_checksum_mismatch,
+        api_page_size: cli.api_page_size,
+        github_graphql: cli.github_graphql,
+        select_asset_by: cli.select_asset_by,
+        prefer_api_assets: cli.prefer_api_assets,
+        verify_assets: cli.verify_assets,
+        verify_signatures: cli.verify_signatures,
+        only_if_newer_published: cli.only_if_newer_published,
+        explain: cli.explain,
+        sort_tools: cli.sort_tools,
+        platform_names: cli.platform_names,
+        palette: cli.color.palette(
+            std::env::var("NO_COLOR").ok().as_deref(),
+            std::io::stdout().is_terminal(),
+        ),
+        stderr_palette: cli.color.palette(
+            std::env::var("NO_COLOR").ok().as_deref(),
+            std::io::stderr().is_terminal(),
+        ),
+        verbose: cli.verbose,
+    };
+    if settings.ca_bundle.is_some() {
+        if let Err(e) = settings.try_client() {
+            eprintln!("{e}");
+            ExitCode::ValidationFailure.exit();
+        }
+    }
+    if settings.danger_accept_invalid_certs {
+        warn("TLS certificate verification is disabled by --danger-accept-invalid-certs");
+    }
+
+    if let Some(filter) = cli.command.filter() {
+        if filter.tool_from_file.is_some() {
+            filter
+                .check_tool_list(&load_lockfile(lockfile, &settings))
+                .unwrap_or_else(|e| {
+                    eprintln!("{e}");
+                    ExitCode::Usage.exit()
+                });
+        }
+    }
+
     match &cli.command {
-        Commands::Update { tool } => update_lockfile(lockfile, tool),
+        Commands::Update { options, .. } if options.print_url => {
+            if print_urls(lockfile, &settings, &options.filter) > 0 {
+                ExitCode::NetworkError.exit();
+            }
+        }
+        Commands::Update { options, .. } if options.assume_tag.is_some() => {
+            let tag = options.assume_tag.as_deref().unwrap_or_default();
+            if print_assumed(lockfile, &settings, options, tag) > 0 {
+                ExitCode::NetworkError.exit();
+            }
+        }
+        Commands::Update {
+            options,
+            report,
+            report_format,
+            emit_lockfile_to,
+            emit_format,
+            platform,
+        } => {
+            if options.plan || options.plan_only {
+                print_plan(lockfile, &settings, &options.filter);
+                if options.plan_only {
+                    return;
+                }
+            }
+            let run = update_lockfile(lockfile, &settings, options);
+            if options.emit_bazel_lock {
+                check_bazel_lock(&run.contents, &settings);
+            }
+            // a dry run writes nothing, the lockfile's companions included
+            if let Some(report) = report.as_ref().filter(|_| !options.dry_run) {
+                let changes = outcome::changes(&run.results);
+                fs::write(report, report::render(&changes, *report_format))
+                    .expect("Error writing report");
+            }
+            if let (Some(path), Some(platform), false) =
+                (emit_lockfile_to, platform, options.dry_run)
+            {
+                let entries = manifest::entries(&run.lockfile, platform);
+                let manifest = manifest::render(&entries, *emit_format).unwrap_or_else(|e| {
+                    eprintln!("{e}");
+                    ExitCode::ValidationFailure.exit()
+                });
+                fs::write(path, manifest).expect("Error writing manifest");
+            }
+            if options.diff_exit_code && run.changed {
+                ExitCode::WouldChange.exit();
+            }
+        }
+        Commands::Resolve { filter } => {
+            if settings.cache_dir.is_none() {
+                warn("without --cache-dir, resolved tags aren't kept for the next update");
+            }
+            let lockfile = load_lockfile(lockfile, &settings);
+            let mut resolver = Resolver::new(&settings);
+            let (tags, failures) = resolve_tags(&mut resolver, &lockfile, filter);
+            for (tool, tags) in &tags {
+                let tags: Vec<&str> = tags.iter().map(String::as_str).collect();
+                println!("{tool}: {}", tags.join(", "));
+            }
+            resolver.save_caches();
+            if failures > 0 {
+                ExitCode::NetworkError.exit();
+            }
+        }
+        Commands::Check { filter, json } => {
+            let lockfile = load_lockfile(lockfile, &settings);
+            let result = check::check(&lockfile, &settings, filter);
+            if *json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&result.outdated).unwrap()
+                );
+            } else {
+                print!("{}", check::render_text(&result.outdated, settings.palette));
+            }
+            if !result.is_ok() {
+                result.exit_code().exit();
+            }
+        }
+        Commands::Watch {
+            filter,
+            interval,
+            json,
+        } => watch::run(
+            &settings,
+            filter,
+            std::time::Duration::from_secs(*interval),
+            *json,
+            || load_lockfile(lockfile, &settings),
+        ),
+        Commands::Verify {
+            filter,
+            fail_fast,
+            head_only,
+            deep,
+            json,
+        } => {
+            let lockfile = load_lockfile(lockfile, &settings);
+            let options = verify::VerifyOptions {
+                fail_fast: *fail_fast,
+                head_only: *head_only,
+                deep: *deep,
+            };
+            let result = verify::verify(&lockfile, &settings, filter, options);
+            if *json {
+                println!("{}", verify::render_json(&result));
+            } else {
+                print!("{}", verify::render_text(&result, settings.palette));
+            }
+            if !result.is_ok() {
+                result.exit_code().exit();
+            }
+        }
+        Commands::PlatformMatrix { json, require } => {
+            let matrix = matrix::matrix(&load_lockfile(lockfile, &settings), require);
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&matrix).unwrap());
+            } else {
+                print!("{}", matrix::render_text(&matrix, settings.palette));
+            }
+            let gaps = matrix::gaps(&matrix, require);
+            for (tool, platforms) in &gaps {
+                eprintln!(
+                    "{tool} is missing required platforms: {}",
+                    platforms.join(", ")
+                );
+            }
+            if !gaps.is_empty() {
+                ExitCode::MissingPlatforms.exit();
+            }
+        }
+        Commands::Add {
+            tool,
+            url,
+            os,
+            cpu,
+            libc,
+            kind,
+            file,
+            platforms_file,
+            dry_run,
+        } => {
+            let mut contents = load_lockfile(lockfile, &settings);
+            let entries = match platforms_file {
+                Some(path) => fs::read_to_string(path)
+                    .map_err(|e| Error::Message(e.to_string()))
+                    .and_then(|contents| edit::parse_platforms(&contents))
+                    .unwrap_or_else(|e| {
+                        eprintln!("Invalid platforms file '{}': {e}", path.display());
+                        ExitCode::ValidationFailure.exit()
+                    }),
+                // clap requires these without --platforms-file
+                None => vec![PlatformEntry {
+                    os: os.clone().unwrap(),
+                    cpu: cpu.clone().unwrap(),
+                    libc: libc.clone(),
+                    kind: *kind,
+                    url: url.clone().unwrap(),
+                    file: file.clone(),
+                }],
+            };
+            let attempted = entries.len();
+            let failures = add_platforms(&mut contents, &settings, tool, entries);
+            if failures < attempted {
+                write_lockfile(lockfile, &contents, &settings, *dry_run);
+            }
+            if failures > 0 {
+                ExitCode::Error.exit();
+            }
+        }
+        Commands::Remove {
+            tool,
+            os,
+            cpu,
+            libc,
+            dry_run,
+        } => {
+            let mut contents = load_lockfile(lockfile, &settings);
+            let platform = os.clone().zip(cpu.clone()).map(|(os, cpu)| edit::Platform {
+                os,
+                cpu,
+                libc: libc.clone(),
+            });
+            edit::remove(&mut contents, tool, platform).unwrap_or_else(|e| panic!("{e}"));
+            write_lockfile(lockfile, &contents, &settings, *dry_run);
+        }
+        Commands::Diff { compare } => {
+            let baseline = diff::at_revision(lockfile, compare).unwrap_or_else(|e| panic!("{e}"));
+            let current = fs::read_to_string(lockfile).expect("Unable to load lockfile");
+            print!(
+                "{}",
+                diff::unified(&lockfile.display().to_string(), &baseline, &current)
+            );
+        }
+        Commands::Migrate {
+            write_schema_url,
+            dry_run,
+        } => {
+            let schema = write_schema_url.as_deref().unwrap_or(SCHEMA);
+            if !lockfile::is_known_schema(schema) {
+                warn(format!("{schema} is not a known lockfile schema URL"));
+            }
+            let contents = fs::read_to_string(lockfile).expect("Unable to load lockfile");
+            let migrated = migrate_lockfile(&contents, schema).unwrap_or_else(|e| {
+                eprintln!("{e}");
+                ExitCode::ValidationFailure.exit()
+            });
+            write_lockfile(lockfile, &migrated, &settings, *dry_run);
+        }
+        Commands::Validate { fix, dry_run } => {
+            let mut contents = load_lockfile(lockfile, &settings);
+            let raw: serde_json::Value = serde_json::from_str(
+                &fs::read_to_string(lockfile).expect("Unable to load lockfile"),
+            )
+            .expect("Unable to parse lockfile");
+            let fixes = fix::fix(&mut contents, &raw, settings.platform_names);
+            // fixes are written even when rules_multitool would still reject
+            // something a fix can't repair
+            if *fix {
+                for fix in &fixes {
+                    eprintln!("Fixed {fix}");
+                }
+                write_lockfile(lockfile, &contents, &settings, *dry_run);
+            } else {
+                for fix in &fixes {
+                    eprintln!("Fixable: {fix}");
+                }
+            }
+            let rejected = report_bazel_violations(
+                &contents.to_canonical_string(settings.sort_tools, settings.platform_names),
+                &settings,
+            );
+            let fixable = !*fix && !fixes.is_empty();
+            if fixable {
+                eprintln!("Run `multitool validate --fix` to repair these");
+            }
+            if rejected || fixable {
+                ExitCode::ValidationFailure.exit();
+            }
+            if !*fix {
+                eprintln!("{} is valid", lockfile.display());
+            }
+        }
+        Commands::PrintConfig => {
+            let proxy = doctor::proxy_summary(|name| std::env::var(name).ok());
+            print!("{}", settings.render(config_path.as_deref(), &proxy));
+        }
+        Commands::Cache {
+            command: CacheCommand::Clean,
+        } => {
+            let Some(dir) = &settings.cache_dir else {
+                eprintln!("No cache to clean: pass --cache-dir or set MULTITOOL_CACHE_DIR");
+                ExitCode::Usage.exit()
+            };
+            match cache::clean(dir) {
+                Ok(reclaimed) => {
+                    println!("Cleaned {}, reclaiming {reclaimed} bytes", dir.display())
+                }
+                Err(e) => panic!("{e}"),
+            }
+        }
+        Commands::Doctor => {
+            let findings = doctor::diagnose(&settings, lockfile, |name| std::env::var(name).ok());
+            print!("{}", doctor::render(&findings, settings.palette));
+            let code = doctor::exit_code(&findings);
+            if code != ExitCode::Success {
+                code.exit();
+            }
+        }
+    }
+
+    let code = warn::exit_code(fail_on_warn, warn::emitted());
+    if code != ExitCode::Success {
+        eprintln!(
+            "--fail-on-warn: failing after {} warning(s)",
+            warn::emitted()
+        );
+        code.exit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, settings, Response, Server};
+
+    #[test]
+    fn substitute_version_prefixed_tags_prefixed_path() {
+        assert_eq!(
+            substitute_version("tool-v1.2.3.tar.gz", "v1.2.3", "v1.3.0"),
+            "tool-v1.3.0.tar.gz"
+        );
+    }
+
+    #[test]
+    fn substitute_version_prefixed_tags_bare_path() {
+        assert_eq!(
+            substitute_version("tool-1.2.3.tar.gz", "v1.2.3", "1.3.0"),
+            "tool-1.3.0.tar.gz"
+        );
+    }
+
+    #[test]
+    fn substitute_version_bare_tags_prefixed_path() {
+        assert_eq!(
+            substitute_version("tool-v1.2.3.tar.gz", "1.2.3", "v1.3.0"),
+            "tool-v1.3.0.tar.gz"
+        );
+        assert_eq!(
+            substitute_version("tool-v1.2.3.tar.gz", "1.2.3", "1.3.0"),
+            "tool-v1.3.0.tar.gz"
+        );
+    }
+
+    #[test]
+    fn substitute_version_bare_tags_bare_path() {
+        assert_eq!(
+            substitute_version("1.2.3/tool-1.2.3-linux.tar.gz", "1.2.3", "1.3.0"),
+            "1.3.0/tool-1.3.0-linux.tar.gz"
+        );
+    }
+
+    #[test]
+    fn substitute_version_replaces_whole_versions_only() {
+        assert_eq!(
+            substitute_version("tool-1.2.3/lib-11.2.3/1.2.30/1.2.3.4/bin", "1.2.3", "1.3.0"),
+            "tool-1.3.0/lib-11.2.3/1.2.30/1.2.3.4/bin"
+        );
+        assert_eq!(
+            substitute_version("1.2.3.tar.gz", "1.2.3", "1.3.0"),
+            "1.3.0.tar.gz"
+        );
+        // a `v` only counts as a prefix where it precedes the whole version
+        assert_eq!(
+            substitute_version("tool-v1.2.30/tool-1.2.3", "v1.2.3", "v1.3.0"),
+            "tool-v1.2.30/tool-1.3.0"
+        );
+        assert_eq!(substitute_version("tool", "", "1.3.0"), "tool");
+    }
+
+    #[test]
+    fn substitute_version_prefixed_tag_names() {
+        assert_eq!(
+            substitute_version(
+                "clang+llvm-17.0.6-x86_64-linux-gnu-ubuntu-22.04.tar.xz",
+                "llvmorg-17.0.6",
+                "llvmorg-18.1.8"
+            ),
+            "clang+llvm-18.1.8-x86_64-linux-gnu-ubuntu-22.04.tar.xz"
+        );
+        // a path naming the whole tag keeps it
+        assert_eq!(
+            substitute_version("tool-cli-1.2.3", "cli-1.2.3", "cli-1.3.0"),
+            "tool-cli-1.3.0"
+        );
+        assert_eq!(substitute_version("tool", "nightly", "1.3.0"), "tool");
+    }
+
+    #[test]
+    fn sort_key_orders_windows_arm64_with_other_platforms() {
+        let binary = |os: &str, cpu: &str| -> Binary {
+            serde_json::from_str(&format!(
+                r#"{{"kind": "file", "url": "https://example.com/{os}-{cpu}", "sha256": "00", "os": "{os}", "cpu": "{cpu}"}}"#
+            ))
+            .unwrap()
+        };
+        let mut binaries = [
+            binary("windows", "x86_64"),
+            binary("linux", "arm64"),
+            binary("windows", "arm64"),
+            binary("macos", "x86_64"),
+        ];
+        binaries.sort_by_key(|v| v.sort_key());
+        let keys: Vec<String> = binaries.iter().map(|b| b.sort_key()).collect();
+        assert_eq!(
+            keys,
+            [
+                "linux_arm64",
+                "macos_x86_64",
+                "windows_arm64",
+                "windows_x86_64"
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrite_archive_binary_substitutes_file() {
+        let binary: Binary = serde_json::from_str(
+            r#"{"kind": "archive", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool-v1.2.3-linux.tar.gz", "file": "tool-v1.2.3-linux/bin/tool", "sha256": "00", "os": "linux", "cpu": "x86_64", "type": "tar.gz"}"#,
+        )
+        .unwrap();
+        let url = "https://github.com/org/tool/releases/download/v1.3.0/tool-v1.3.0-linux.tar.gz";
+        let Binary::Archive(archive) = rewrite_binary(
+            &binary,
+            Rewrite {
+                url: url.to_owned(),
+                parts: None,
+                sha256: "11".to_owned(),
+                size: None,
+            },
+            "1.2.3",
+            "v1.3.0",
+        ) else {
+            panic!("expected an archive binary");
+        };
+        assert_eq!(archive.url, url);
+        assert_eq!(archive.sha256, "11");
+        assert_eq!(archive.file, "tool-v1.3.0-linux/bin/tool");
+        assert_eq!(archive.type_.as_deref(), Some("tar.gz"));
+    }
+
+    #[test]
+    fn rewrite_nested_archive_substitutes_both_files() {
+        let binary: Binary = serde_json::from_str(
+            r#"{"kind": "archive", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool-1.2.3.zip", "file": "tool-1.2.3-linux.tar.gz", "sha256": "00", "os": "linux", "cpu": "x86_64", "inner": {"file": "tool-1.2.3/bin/tool"}}"#,
+        )
+        .unwrap();
+        let url = "https://github.com/org/tool/releases/download/v1.3.0/tool-1.3.0.zip";
+        let Binary::Archive(archive) = rewrite_binary(
+            &binary,
+            Rewrite {
+                url: url.to_owned(),
+                parts: None,
+                sha256: "11".to_owned(),
+                size: None,
+            },
+            "v1.2.3",
+            "v1.3.0",
+        ) else {
+            panic!("expected an archive binary");
+        };
+        assert_eq!(archive.file, "tool-1.3.0-linux.tar.gz");
+        let inner = archive.inner.unwrap();
+        assert_eq!(inner.file, "tool-1.3.0/bin/tool");
+        assert_eq!(inner.type_, None);
+    }
+
+    #[test]
+    fn rewrite_dir_binary_substitutes_root() {
+        let binary: Binary = serde_json::from_str(
+            r#"{"kind": "dir", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool-1.2.3.tar.gz", "sha256": "00", "os": "linux", "cpu": "x86_64", "root": "tool-1.2.3"}"#,
+        )
+        .unwrap();
+        let url = "https://github.com/org/tool/releases/download/v1.3.0/tool-1.3.0.tar.gz";
+        let updated = rewrite_binary(
+            &binary,
+            Rewrite {
+                url: url.to_owned(),
+                parts: None,
+                sha256: "11".to_owned(),
+                size: None,
+            },
+            "v1.2.3",
+            "v1.3.0",
+        );
+        let Binary::Dir(dir) = updated else {
+            panic!("expected a dir binary");
+        };
+        assert_eq!(dir.url, url);
+        assert_eq!(dir.sha256, "11");
+        assert_eq!(dir.root.as_deref(), Some("tool-1.3.0"));
+    }
+
+    #[test]
+    fn update_is_idempotent_when_everything_is_latest() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => Response::ok(r#"{"tag_name": "v1.2.3"}"#),
+            _ => Response::status(404, "{}"),
+        });
+        let settings = settings(&server.url);
+
+        let path =
+            std::env::temp_dir().join(format!("multitool-idempotent-{}.json", std::process::id()));
+        // unsorted binaries, unordered header maps and compact formatting
+        fs::write(
+            &path,
+            r#"{"tool": {"binaries": [
+              {"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool-macos",
+               "sha256": "0000000000000000000000000000000000000000000000000000000000000000", "os": "macos", "cpu": "arm64",
+               "headers": {"X-B": "b", "Accept": "application/octet-stream", "X-A": "a"}},
+              {"kind": "archive", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool-linux.tar.gz",
+               "file": "tool", "sha256": "0000000000000000000000000000000000000000000000000000000000000000", "os": "linux", "cpu": "x86_64", "type": "tar.gz",
+               "auth_patterns": {"z.example.com": "Bearer <password>", "a.example.com": "token <password>"}}
+            ]}}"#,
+        )
+        .unwrap();
+
+        assert!(outcome::changes(
+            &update_lockfile(&path, &settings, &UpdateOptions::default()).results
+        )
+        .is_empty());
+        let first = fs::read_to_string(&path).unwrap();
+        assert!(outcome::changes(
+            &update_lockfile(&path, &settings, &UpdateOptions::default()).results
+        )
+        .is_empty());
+        let second = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(first, second);
+        assert!(first.ends_with("}\n") && !first.ends_with("\n\n"));
+        assert!(first.find("\"linux\"").unwrap() < first.find("\"macos\"").unwrap());
+        assert!(first.find("\"Accept\"").unwrap() < first.find("\"X-A\"").unwrap());
+    }
+
+    #[test]
+    fn update_binary_checks_signatures() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => Response::ok(r#"{"tag_name": "v1.3.0"}"#),
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-signed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool-linux"), "hello").unwrap();
+        fs::write(dir.join("tool-macos"), "tampered").unwrap();
+        for name in ["tool-linux.minisig", "tool-macos.minisig"] {
+            fs::write(dir.join(name), testing::MINISIGN_SIGNED).unwrap();
+        }
+        let settings = Settings {
+            verify_signatures: true,
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let mut resolver = Resolver::new(&settings);
+        let binary = |name: &str, os: &str| -> Binary {
+            serde_json::from_str(&format!(
+                r#"{{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/{name}", "sha256": "00", "os": "{os}", "cpu": "x86_64"}}"#
+            ))
+            .unwrap()
+        };
+        let signed = &ToolDefinition {
+            signature: serde_json::from_str(&format!(
+                r#"{{"scheme": "minisign", "public_key": "{}"}}"#,
+                testing::MINISIGN_PUBLIC_KEY
+            ))
+            .unwrap(),
+            ..ToolDefinition::default()
+        };
+
+        let (updated, _) = update_binary(
+            &mut resolver,
+            "tool",
+            signed,
+            &binary("tool-linux", "linux"),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(updated.sha256(), sha256::digest("hello"));
+
+        let error = update_binary(
+            &mut resolver,
+            "tool",
+            signed,
+            &binary("tool-macos", "macos"),
+        )
+        .err()
+        .unwrap();
+        assert!(error
+            .to_string()
+            .starts_with("https://github.com/org/tool/releases/download/v1.3.0/tool-macos: bad minisign signature"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn update_binary_verifies_assets_and_records_size() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => Response::ok(r#"{"tag_name": "v1.3.0"}"#),
+            "/repos/org/tool/releases/tags/v1.3.0" => Response::ok(
+                r#"{"assets": [{"browser_download_url": "https://github.com/org/tool/releases/download/v1.3.0/tool-linux", "size": 5}]}"#,
+            ),
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-assets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool-linux"), "hello").unwrap();
+        let settings = Settings {
+            verify_assets: true,
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let mut resolver = Resolver::new(&settings);
+        let binary = |name: &str| -> Binary {
+            serde_json::from_str(&format!(
+                r#"{{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/{name}", "sha256": "00", "os": "linux", "cpu": "x86_64"}}"#
+            ))
+            .unwrap()
+        };
+
+        let (updated, _) = update_binary(
+            &mut resolver,
+            "tool",
+            &ToolDefinition::default(),
+            &binary("tool-linux"),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(updated.size(), Some(5));
+        assert_eq!(updated.sha256(), sha256::digest("hello"));
+
+        let error = update_binary(
+            &mut resolver,
+            "tool",
+            &ToolDefinition::default(),
+            &binary("tool-macos"),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "https://github.com/org/tool/releases/download/v1.3.0/tool-macos is not an asset of v1.3.0"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn update_binary_prefers_api_assets() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => Response::ok(r#"{"tag_name": "v2.0.0"}"#),
+            "/repos/org/tool/releases/tags/v2.0.0" => Response::ok(
+                r#"{"assets": [
+                  {"browser_download_url": "https://github.com/org/tool/releases/download/v2.0.0/tool-2.0.0-x86_64-unknown-linux-musl.tar.gz"},
+                  {"browser_download_url": "https://github.com/org/tool/releases/download/v2.0.0/tool-2.0.0-aarch64-unknown-linux-musl.tar.gz"}
+                ]}"#,
+            ),
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-api-assets-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("tool-2.0.0-x86_64-unknown-linux-musl.tar.gz"),
+            "hello",
+        )
+        .unwrap();
+        fs::write(dir.join("tool-2.0.0.zip"), "hello").unwrap();
+        let settings = Settings {
+            prefer_api_assets: true,
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let mut resolver = Resolver::new(&settings);
+        let binary = |name: &str| -> Binary {
+            serde_json::from_str(&format!(
+                r#"{{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.9.0/{name}", "sha256": "00", "os": "linux", "cpu": "x86_64"}}"#
+            ))
+            .unwrap()
+        };
+
+        let (updated, _) = update_binary(
+            &mut resolver,
+            "tool",
+            &ToolDefinition::default(),
+            &binary("tool-1.9.0-linux-amd64-musl.tar.gz"),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            updated.url(),
+            "https://github.com/org/tool/releases/download/v2.0.0/tool-2.0.0-x86_64-unknown-linux-musl.tar.gz"
+        );
+
+        // without a confident match the version is substituted as usual
+        let (updated, _) = update_binary(
+            &mut resolver,
+            "tool",
+            &ToolDefinition::default(),
+            &binary("tool-1.9.0.zip"),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            updated.url(),
+            "https://github.com/org/tool/releases/download/v2.0.0/tool-2.0.0.zip"
+        );
+
+        // an asset of another archive format isn't switched to
+        fs::write(dir.join("tool-2.0.0-linux-amd64-musl.zip"), "hello").unwrap();
+        let archive: Binary = serde_json::from_str(
+            r#"{"kind": "archive", "url": "https://github.com/org/tool/releases/download/v1.9.0/tool-1.9.0-linux-amd64-musl.zip", "file": "tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}"#,
+        )
+        .unwrap();
+        let (updated, _) =
+            update_binary(&mut resolver, "tool", &ToolDefinition::default(), &archive)
+                .unwrap()
+                .unwrap();
+        assert_eq!(
+            updated.url(),
+            "https://github.com/org/tool/releases/download/v2.0.0/tool-2.0.0-linux-amd64-musl.zip"
+        );
+
+        // nor selected by its platform in place of the substituted URL
+        let settings = Settings {
+            select_asset_by: Some(AssetSelection::ArchTriple),
+            ..settings
+        };
+        let mut resolver = Resolver::new(&settings);
+        let archive: Binary = serde_json::from_str(
+            r#"{"kind": "archive", "url": "https://github.com/org/tool/releases/download/v1.9.0/tool-1.9.0-x86_64-unknown-linux-musl.zip", "file": "tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}"#,
+        )
+        .unwrap();
+        let error = update_binary(&mut resolver, "tool", &ToolDefinition::default(), &archive)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "https://github.com/org/tool/releases/download/v2.0.0/tool-2.0.0-x86_64-unknown-linux-musl.zip is not an asset of v2.0.0, and tool-2.0.0-x86_64-unknown-linux-musl.tar.gz, which names its platform, isn't the same kind of archive"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn update_binary_takes_versions_from_release_names() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => {
+                Response::ok(r#"{"tag_name": "build-def456", "name": "1.3.0"}"#)
+            }
+            "/repos/org/tool/releases/tags/build-abc123" => Response::ok(r#"{"name": "1.2.3"}"#),
+            "/repos/org/tool/releases/tags/build-def456" => Response::ok(r#"{"name": "1.3.0"}"#),
+            "/repos/org/nameless/releases/latest" => {
+                Response::ok(r#"{"tag_name": "build-def456", "name": ""}"#)
+            }
+            "/repos/org/nameless/releases/tags/build-abc123" => Response::ok(r#"{"name": ""}"#),
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-names-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool-1.3.0-linux"), "hello").unwrap();
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let mut resolver = Resolver::new(&settings);
+        let definition = ToolDefinition {
+            source: serde_json::from_str(r#"{"kind": "github", "version_from": "name"}"#).unwrap(),
+            ..ToolDefinition::default()
+        };
+        let binary = |repo: &str| -> Binary {
+            serde_json::from_str(&format!(
+                r#"{{"kind": "file", "url": "https://github.com/org/{repo}/releases/download/build-abc123/tool-1.2.3-linux", "sha256": "00", "os": "linux", "cpu": "x86_64"}}"#
+            ))
+            .unwrap()
+        };
+
+        let (updated, change) = update_binary(&mut resolver, "tool", &definition, &binary("tool"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            updated.url(),
+            "https://github.com/org/tool/releases/download/build-def456/tool-1.3.0-linux"
+        );
+        assert_eq!(
+            (change.old_version.as_str(), change.new_version.as_str()),
+            ("1.2.3", "1.3.0")
+        );
+
+        let error = update_binary(&mut resolver, "tool", &definition, &binary("nameless"))
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "release build-abc123 of org/nameless has no name to take its version from (version_from: name)"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn update_binary_follows_source_archives() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => Response::ok(r#"{"tag_name": "v1.3.0"}"#),
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-archive-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("v1.3.0.tar.gz"), "hello").unwrap();
+        // source archives aren't release assets, so there are none to verify
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            verify_assets: true,
+            ..settings(&server.url)
+        };
+        let mut resolver = Resolver::new(&settings);
+        let binary: Binary = serde_json::from_str(
+            r#"{"kind": "archive", "url": "https://github.com/org/tool/archive/refs/tags/v1.2.3.tar.gz", "file": "tool-1.2.3/tool.sh", "sha256": "00", "os": "linux", "cpu": "x86_64"}"#,
+        )
+        .unwrap();
+
+        let (updated, change) =
+            update_binary(&mut resolver, "tool", &ToolDefinition::default(), &binary)
+                .unwrap()
+                .unwrap();
+        assert_eq!(
+            updated.url(),
+            "https://github.com/org/tool/archive/refs/tags/v1.3.0.tar.gz"
+        );
+        let Binary::Archive(archive) = &updated else {
+            panic!("expected an archive");
+        };
+        assert_eq!(archive.file, "tool-1.3.0/tool.sh");
+        assert_eq!(change.new_sha256, sha256::digest("hello"));
+        assert_eq!(server.requests().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn update_binary_follows_prefixed_tags() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/llvm/llvm-project/releases/latest" => {
+                Response::ok(r#"{"tag_name": "llvmorg-18.1.8"}"#)
+            }
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-prefixed-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let asset = "clang+llvm-18.1.8-x86_64-linux-gnu-ubuntu-22.04.tar.xz";
+        fs::write(dir.join(asset), "hello").unwrap();
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let mut resolver = Resolver::new(&settings);
+        // asset names and paths carry the number, not the tag's llvmorg- prefix
+        let binary: Binary = serde_json::from_str(
+            r#"{"kind": "archive", "url": "https://github.com/llvm/llvm-project/releases/download/llvmorg-17.0.6/clang+llvm-17.0.6-x86_64-linux-gnu-ubuntu-22.04.tar.xz", "file": "clang+llvm-17.0.6-x86_64-linux-gnu-ubuntu-22.04/bin/clang-format", "sha256": "00", "os": "linux", "cpu": "x86_64"}"#,
+        )
+        .unwrap();
+
+        let (updated, change) = update_binary(
+            &mut resolver,
+            "clang-format",
+            &ToolDefinition::default(),
+            &binary,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            updated.url(),
+            format!(
+                "https://github.com/llvm/llvm-project/releases/download/llvmorg-18.1.8/{asset}"
+            )
+        );
+        assert_eq!(
+            inner_paths(&updated),
+            ["clang+llvm-18.1.8-x86_64-linux-gnu-ubuntu-22.04/bin/clang-format"]
+        );
+        assert_eq!(
+            (change.old_version.as_str(), change.new_version.as_str()),
+            ("llvmorg-17.0.6", "llvmorg-18.1.8")
+        );
+        assert_eq!(change.new_sha256, sha256::digest("hello"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn update_binary_only_if_newer_published() {
+        // `backdated` has a latest release published before its pinned one
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/newer/releases/latest" | "/repos/org/backdated/releases/latest" => {
+                Response::ok(r#"{"tag_name": "v1.3.0"}"#)
+            }
+            "/repos/org/newer/releases/tags/v1.2.3"
+            | "/repos/org/backdated/releases/tags/v1.3.0" => {
+                Response::ok(r#"{"published_at": "2024-01-02T00:00:00Z"}"#)
+            }
+            "/repos/org/newer/releases/tags/v1.3.0"
+            | "/repos/org/backdated/releases/tags/v1.2.3" => {
+                Response::ok(r#"{"published_at": "2024-03-04T00:00:00Z"}"#)
+            }
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-published-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool"), "hello").unwrap();
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            only_if_newer_published: true,
+            ..settings(&server.url)
+        };
+        let mut resolver = Resolver::new(&settings);
+        let binary = |repo: &str| -> Binary {
+            serde_json::from_str(&format!(
+                r#"{{"kind": "file", "url": "https://github.com/org/{repo}/releases/download/v1.2.3/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}}"#
+            ))
+            .unwrap()
+        };
+
+        let (updated, _) = update_binary(
+            &mut resolver,
+            "newer",
+            &ToolDefinition::default(),
+            &binary("newer"),
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            updated.url(),
+            "https://github.com/org/newer/releases/download/v1.3.0/tool"
+        );
+        assert!(update_binary(
+            &mut resolver,
+            "backdated",
+            &ToolDefinition::default(),
+            &binary("backdated")
+        )
+        .unwrap()
+        .is_none());
+
+        // without the guard the backdated release is taken
+        let settings = Settings {
+            only_if_newer_published: false,
+            ..settings
+        };
+        let mut resolver = Resolver::new(&settings);
+        assert!(update_binary(
+            &mut resolver,
+            "backdated",
+            &ToolDefinition::default(),
+            &binary("backdated")
+        )
+        .unwrap()
+        .is_some());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn insecure_http_warns_or_rejects() {
+        let lockfile = |url: &str| -> Lockfile {
+            serde_json::from_str(&format!(
+                r#"{{"tool": {{"binaries": [{{"kind": "file", "url": "{url}", "sha256": "00", "os": "linux", "cpu": "x86_64"}}]}}}}"#
+            ))
+            .unwrap()
+        };
+        let secure = lockfile("https://example.com/tool");
+        let insecure = lockfile("HTTP://mirror.internal/tool");
+        let default = settings("http://localhost");
+        let required = Settings {
+            require_https: true,
+            ..settings("http://localhost")
+        };
+        let allowed = Settings {
+            allow_insecure_http: true,
+            ..settings("http://localhost")
+        };
+
+        assert_eq!(insecure_http(&secure, &default), Ok(vec![]));
+        assert_eq!(insecure_http(&secure, &required), Ok(vec![]));
+        assert_eq!(
+            insecure_http(&insecure, &default),
+            Ok(vec!["tool 'tool': HTTP://mirror.internal/tool is fetched over plain HTTP; pass --allow-insecure-http if that's intended".to_owned()])
+        );
+        assert_eq!(
+            insecure_http(&insecure, &required),
+            Err("tool 'tool': HTTP://mirror.internal/tool is fetched over plain HTTP (--require-https)".to_owned())
+        );
+        assert_eq!(insecure_http(&insecure, &allowed), Ok(vec![]));
+
+        // a template source's own URLs are checked too
+        let template: Lockfile = serde_json::from_str(
+            r#"{"tool": {"source": {"kind": "template", "version_url": "https://example.com/latest", "url_template": "http://mirror.internal/{version}/tool"}, "binaries": []}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            insecure_http(&template, &required),
+            Err("tool 'tool': http://mirror.internal/{version}/tool is fetched over plain HTTP (--require-https)".to_owned())
+        );
+    }
+
+    #[test]
+    fn update_binary_rejects_new_plain_http_urls() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/version" => Response::ok("2.0.0"),
+            _ => Response::status(404, ""),
+        });
+        let settings = Settings {
+            require_https: true,
+            ..settings(&server.url)
+        };
+        let mut resolver = Resolver::new(&settings);
+        let definition = ToolDefinition {
+            source: serde_json::from_str(&format!(
+                r#"{{"kind": "template", "version_url": "{}/version", "url_template": "http://dl.example.com/{{version}}/tool"}}"#,
+                server.url
+            ))
+            .unwrap(),
+            ..ToolDefinition::default()
+        };
+        let binary: Binary = serde_json::from_str(
+            r#"{"kind": "file", "url": "http://dl.example.com/1.0.0/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}"#,
+        )
+        .unwrap();
+
+        let error = update_binary(&mut resolver, "tool", &definition, &binary)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "http://dl.example.com/2.0.0/tool is fetched over plain HTTP (--require-https)"
+        );
+        // nothing was downloaded
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[test]
+    fn parse_lockfile_rejects_invalid_lockfiles() {
+        let sha256 = sha256::digest("hello");
+        let error = |contents: &str| parse_lockfile(contents).err().unwrap();
+        let lockfile = |schema: &str, sha256: &str| {
+            format!(
+                r#"{{"$schema": "{schema}", "tool": {{"binaries": [{{"kind": "file", "url": "https://example.com/tool", "sha256": "{sha256}", "os": "linux", "cpu": "x86_64"}}]}}}}"#
+            )
+        };
+        assert!(parse_lockfile(&lockfile(SCHEMA, &sha256)).is_ok());
+        // an unknown schema only warns, as long as the lockfile itself parses
+        assert!(parse_lockfile(&lockfile("other", &sha256)).is_ok());
+        assert!(error(&lockfile("other", "00")).starts_with("Invalid tool 'tool': "));
+        // a lockfile of some other layout is rejected whatever its schema says
+        let other_layout = r#"{"$schema": "https://example.com/v2/lockfile.schema.json", "tool": {"artifacts": []}}"#;
+        assert!(error(other_layout).starts_with("Unable to deserialize lockfile: "));
+        assert!(error(r#"{"$schema": 2}"#).starts_with("Unable to deserialize lockfile: "));
+        assert!(error(&lockfile(SCHEMA, "00")).starts_with("Invalid tool 'tool': "));
+        assert!(error("{").starts_with("Unable to deserialize lockfile: "));
+        assert_eq!(
+            error(r#"{"metadata": {"binaries": []}}"#),
+            "'metadata' holds lockfile metadata and can't name a tool"
+        );
+        assert_eq!(
+            error(&lockfile(SCHEMA, &sha256).replace(r#""tool""#, r#""metadata""#)),
+            "'metadata' holds lockfile metadata and can't name a tool"
+        );
+        // metadata keys that merely share a tool's names are metadata
+        assert!(parse_lockfile(r#"{"metadata": {"binaries": "none yet"}}"#).is_ok());
+    }
+
+    #[test]
+    fn unusable_lockfiles_fail_validation() {
+        let server = Server::start(|_| Response::status(500, "{}"));
+        let settings = settings(&server.url);
+        let dir = std::env::temp_dir().join(format!("multitool-unusable-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("multitool.lock.json");
+        let load = |contents: &str| {
+            fs::write(&path, contents).unwrap();
+            try_load_lockfile(&path, &settings).map(|_| ())
+        };
+
+        assert_eq!(load("{}"), Ok(()));
+        assert_eq!(load("{"), Err(ExitCode::ValidationFailure));
+        assert_eq!(
+            load(
+                r#"{"tool": {"binaries": [{"kind": "file", "url": "https://example.com/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}]}}"#
+            ),
+            Err(ExitCode::ValidationFailure)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn migrate_lockfile_rewrites_schema() {
+        let old = r#"{"$schema": "https://example.com/old.schema.json", "tool": {"binaries": [{"kind": "archive", "url": "https://example.com/tool.tar.gz", "file": "tool", "sha256": "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824", "os": "linux", "cpu": "x86_64", "type": "tar.gz"}]}}"#;
+        let migrated = migrate_lockfile(old, SCHEMA).unwrap();
+        assert_eq!(migrated.schema, SCHEMA);
+        let written = migrated.to_canonical_string(ToolOrder::default(), PlatformNames::default());
+        let reparsed = parse_lockfile(&written).ok().unwrap();
+        let Binary::Archive(archive) = &reparsed.tools["tool"].binaries[0] else {
+            panic!("expected an archive binary");
+        };
+        assert_eq!(archive.type_.as_deref(), Some("tar.gz"));
+
+        let custom = migrate_lockfile(old, "https://example.com/new.schema.json").unwrap();
+        assert_eq!(custom.schema, "https://example.com/new.schema.json");
+        assert!(migrate_lockfile(r#"{"tool": {"binaries": []}}"#, SCHEMA).is_ok());
+    }
+
+    #[test]
+    fn update_binary_stops_at_timeout_per_tool() {
+        let server = Server::start(|_| Response::ok(r#"{"tag_name": "v1.3.0"}"#));
+        let settings = Settings {
+            timeout_per_tool: Some(std::time::Duration::ZERO),
+            ..settings(&server.url)
+        };
+        let mut resolver = Resolver::new(&settings);
+        let binary: Binary = serde_json::from_str(
+            r#"{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}"#,
+        )
+        .unwrap();
+
+        resolver.start_tool();
+        let error = update_binary(&mut resolver, "tool", &ToolDefinition::default(), &binary)
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "gave up after exceeding --timeout-per-tool of 0s"
+        );
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn add_platforms_reports_failures_without_aborting() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/tool-linux" | "/tool-macos.tar.gz" => Response::ok("hello"),
+            _ => Response::status(404, ""),
+        });
+        let settings = settings(&server.url);
+        let mut lockfile: Lockfile = serde_json::from_str("{}").unwrap();
+        let entries = edit::parse_platforms(&format!(
+            r#"
+            [[platform]]
+            os = "linux"
+            cpu = "x86_64"
+            url = "{0}/tool-linux"
+
+            [[platform]]
+            os = "linux"
+            cpu = "arm64"
+            url = "{0}/missing"
+
+            [[platform]]
+            os = "macos"
+            cpu = "arm64"
+            kind = "archive"
+            url = "{0}/tool-macos.tar.gz"
+
+            [[platform]]
+            os = "windows"
+            cpu = "x86_64"
+            kind = "archive"
+            url = "{0}/tool-linux"
+            "#,
+            server.url
+        ))
+        .unwrap();
+
+        assert_eq!(add_platforms(&mut lockfile, &settings, "tool", entries), 3);
+        let binaries = &lockfile.tools["tool"].binaries;
+        assert_eq!(binaries.len(), 1);
+        assert_eq!(binaries[0].sha256(), sha256::digest("hello"));
+    }
+
+    #[test]
+    fn update_resolves_tools_of_one_repo_by_their_own_tag_regex() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases?per_page=100" => Response::ok(
+                r#"[
+                  {"tag_name": "v2.1.0"},
+                  {"tag_name": "v1.9.0"},
+                  {"tag_name": "v2.0.0"},
+                  {"tag_name": "v1.8.0"}
+                ]"#,
+            ),
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-tracks-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool"), "hello").unwrap();
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let tool = |major: u32, pinned: &str| {
+            format!(
+                r#"{{"source": {{"kind": "github", "tag_regex": "^v{major}\\."}}, "binaries": [{{"kind": "file", "url": "https://github.com/org/tool/releases/download/{pinned}/tool", "sha256": "{}", "os": "linux", "cpu": "x86_64"}}]}}"#,
+                sha256::digest("hello")
+            )
+        };
+        let path = dir.join("multitool.lock.json");
+        fs::write(
+            &path,
+            format!(
+                r#"{{"tool-1": {}, "tool-2": {}}}"#,
+                tool(1, "v1.8.0"),
+                tool(2, "v2.0.0")
+            ),
+        )
+        .unwrap();
+
+        let changes =
+            outcome::changes(&update_lockfile(&path, &settings, &UpdateOptions::default()).results);
+        let versions: Vec<(&str, &str)> = changes
+            .iter()
+            .map(|change| (change.tool.as_str(), change.new_version.as_str()))
+            .collect();
+        assert_eq!(versions, [("tool-1", "1.9.0"), ("tool-2", "2.1.0")]);
+        // both tools are resolved from one fetch of the release list
+        assert_eq!(server.requests().len(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn update_preserve_unchanged_keeps_bytes() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => Response::ok(r#"{"tag_name": "v1.2.3"}"#),
+            _ => Response::status(404, "{}"),
+        });
+        let settings = settings(&server.url);
+        let path =
+            std::env::temp_dir().join(format!("multitool-preserve-{}.json", std::process::id()));
+        let original = format!(
+            r#"{{"tool": {{"binaries": [{{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool", "sha256": "{}", "os": "linux", "cpu": "x86_64"}}]}}}}"#,
+            sha256::digest("hello")
+        );
+        fs::write(&path, &original).unwrap();
+
+        let options = UpdateOptions {
+            preserve_unchanged: true,
+            ..UpdateOptions::default()
+        };
+        assert!(outcome::changes(&update_lockfile(&path, &settings, &options).results).is_empty());
+        let written = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(written, original);
+    }
+
+    #[test]
+    fn no_network_conflicts_with_refresh() {
+        let parse = |args: &[&str]| Cli::command().try_get_matches_from(args).map(|_| ());
+        assert!(parse(&["multitool", "update", "--refresh"]).is_ok());
+        for refresh in ["--refresh", "--sha-only"] {
+            let error = parse(&["multitool", "update", "--no-network", refresh]).unwrap_err();
+            assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+        }
+    }
+
+    #[test]
+    fn plan_conflicts_with_other_dry_runs() {
+        for plan in ["--plan", "--plan-only"] {
+            for other in [&["--print-url"][..], &["--assume-tag", "v1.0.0"]] {
+                let args = [&["multitool", "update", plan][..], other].concat();
+                let error = Cli::command().try_get_matches_from(args).unwrap_err();
+                assert_eq!(error.kind(), clap::error::ErrorKind::ArgumentConflict);
+            }
+        }
+    }
+
+    #[test]
+    fn update_sha_only_repairs_digests_in_place() {
+        // any request reaching the API server is a release lookup
+        let server = Server::start(|_| Response::status(500, "{}"));
+        let dir = std::env::temp_dir().join(format!("multitool-sha-only-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool"), "hello").unwrap();
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let path = dir.join("multitool.lock.json");
+        let lockfile = |sha256: &str| {
+            format!(
+                r#"{{"tool": {{"binaries": [{{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool", "sha256": "{sha256}", "os": "linux", "cpu": "x86_64"}}]}}}}"#
+            )
+        };
+        let wrong = "0".repeat(64);
+        fs::write(&path, lockfile(&wrong)).unwrap();
+        let options = UpdateOptions {
+            sha_only: true,
+            ..UpdateOptions::default()
+        };
+
+        let changes = outcome::changes(&update_lockfile(&path, &settings, &options).results);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(
+            (
+                changes[0].old_version.as_str(),
+                changes[0].new_version.as_str()
+            ),
+            ("1.2.3", "1.2.3")
+        );
+        assert_eq!(changes[0].old_sha256, wrong);
+        let repaired = load_lockfile(&path, &settings);
+        let binary = &repaired.tools["tool"].binaries[0];
+        assert_eq!(
+            binary.url(),
+            "https://github.com/org/tool/releases/download/v1.2.3/tool"
+        );
+        assert_eq!(binary.sha256(), sha256::digest("hello"));
+        assert_eq!(binary.size(), Some(5));
+        assert!(server.requests().is_empty());
+
+        // a right digest is left alone
+        assert!(outcome::changes(&update_lockfile(&path, &settings, &options).results).is_empty());
+
+        // but a right sha256 with a wrong size isn't
+        let right = sha256::digest("hello");
+        fs::write(
+            &path,
+            lockfile(&right).replace(r#""os""#, r#""size": 9, "os""#),
+        )
+        .unwrap();
+        let changes = outcome::changes(&update_lockfile(&path, &settings, &options).results);
+        assert_eq!(changes.len(), 1);
+        let repaired = load_lockfile(&path, &settings);
+        assert_eq!(repaired.tools["tool"].binaries[0].size(), Some(5));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn update_file_path_only_rewrites_archive_and_pkg_files() {
+        let server = Server::start(|_| Response::status(500, "{}"));
+        let dir = std::env::temp_dir().join(format!("multitool-file-path-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("multitool.lock.json");
+        let sha256 = "0".repeat(64);
+        fs::write(
+            &path,
+            format!(
+                r#"{{
+                  "archive": {{"binaries": [{{"kind": "archive", "url": "https://github.com/org/archive/releases/download/v1.2.3/archive.zip", "file": "archive-1.2.3/lib-11.2.3/archive.tar.gz", "inner": {{"file": "archive-v1.2.3/bin/archive"}}, "sha256": "{sha256}", "os": "linux", "cpu": "x86_64"}}]}},
+                  "pkg": {{"binaries": [{{"kind": "pkg", "url": "https://github.com/org/pkg/releases/download/1.2.3/pkg.pkg", "file": "pkg-1.2.30.pkg/Payload/1.2.3/pkg", "sha256": "{sha256}", "os": "macos", "cpu": "arm64"}}]}},
+                  "plain": {{"binaries": [{{"kind": "file", "url": "https://github.com/org/plain/releases/download/v1.2.3/plain", "sha256": "{sha256}", "os": "linux", "cpu": "x86_64"}}]}}
+                }}"#
+            ),
+        )
+        .unwrap();
+        let options = UpdateOptions {
+            update_file_path_only: Some("v1.3.0".to_owned()),
+            ..UpdateOptions::default()
+        };
+
+        let changes =
+            outcome::changes(&update_lockfile(&path, &settings(&server.url), &options).results);
+        let changed: Vec<&str> = changes.iter().map(|change| change.tool.as_str()).collect();
+        assert_eq!(changed, ["archive", "pkg"]);
+        assert!(changes
+            .iter()
+            .all(|change| change.old_sha256 == change.new_sha256));
+        let lockfile = load_lockfile(&path, &settings(&server.url));
+        let Binary::Archive(archive) = &lockfile.tools["archive"].binaries[0] else {
+            panic!("expected an archive");
+        };
+        assert_eq!(archive.file, "archive-1.3.0/lib-11.2.3/archive.tar.gz");
+        assert_eq!(
+            archive.inner.as_ref().unwrap().file,
+            "archive-v1.3.0/bin/archive"
+        );
+        assert_eq!(
+            archive.url,
+            "https://github.com/org/archive/releases/download/v1.2.3/archive.zip"
+        );
+        let Binary::Pkg(pkg) = &lockfile.tools["pkg"].binaries[0] else {
+            panic!("expected a pkg");
+        };
+        assert_eq!(pkg.file, "pkg-1.2.30.pkg/Payload/1.3.0/pkg");
+        assert_eq!(pkg.sha256, sha256);
+        assert!(server.requests().is_empty());
+
+        // paths already at the target version are left alone
+        assert!(outcome::changes(
+            &update_lockfile(&path, &settings(&server.url), &options).results
+        )
+        .is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn update_file_path_only_finds_the_stale_version_in_the_path() {
+        let server = Server::start(|_| Response::status(500, "{}"));
+        let dir = std::env::temp_dir().join(format!("multitool-stale-path-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("multitool.lock.json");
+        let archive = |file: &str| {
+            format!(
+                r#"{{"kind": "archive", "url": "https://github.com/org/tool/releases/download/v1.3.0/tool.tar.gz", "file": "{file}", "sha256": "{}", "os": "linux", "cpu": "x86_64"}}"#,
+                "0".repeat(64)
+            )
+        };
+        let file = |lockfile: &Lockfile| match &lockfile.tools["tool"].binaries[0] {
+            Binary::Archive(archive) => archive.file.clone(),
+            _ => panic!("expected an archive"),
+        };
+        let settings = settings(&server.url);
+        let update = |from: Option<&str>| {
+            let options = UpdateOptions {
+                update_file_path_only: Some("v1.3.0".to_owned()),
+                file_path_from: from.map(str::to_owned),
+                ..UpdateOptions::default()
+            };
+            outcome::changes(&update_lockfile(&path, &settings, &options).results)
+        };
+
+        // the URL is already at 1.3.0, but the path was left at 1.2.3
+        fs::write(
+            &path,
+            format!(
+                r#"{{"tool": {{"binaries": [{}]}}}}"#,
+                archive("tool-1.2.3/bin/tool")
+            ),
+        )
+        .unwrap();
+        let changes = update(None);
+        assert_eq!(changes[0].old_version, "1.2.3");
+        assert_eq!(
+            file(&load_lockfile(&path, &settings)),
+            "tool-1.3.0/bin/tool"
+        );
+
+        // a path naming several versions needs --from
+        fs::write(
+            &path,
+            format!(
+                r#"{{"tool": {{"binaries": [{}]}}}}"#,
+                archive("tool-1.2.3/lib-11.2.3/tool")
+            ),
+        )
+        .unwrap();
+        let before = warn::emitted();
+        assert!(update(None).is_empty());
+        assert!(warn::emitted() > before);
+        assert_eq!(update(Some("1.2.3")).len(), 1);
+        assert_eq!(
+            file(&load_lockfile(&path, &settings)),
+            "tool-1.3.0/lib-11.2.3/tool"
+        );
+        assert!(server.requests().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn update_lockfile_returns_an_outcome_per_binary() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/current/releases/latest" => Response::ok(r#"{"tag_name": "v1.2.3"}"#),
+            _ => Response::status(500, "{}"),
+        });
+        let settings = settings(&server.url);
+        let dir = std::env::temp_dir().join(format!("multitool-outcomes-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("multitool.lock.json");
+        let binary = |url: &str| {
+            format!(
+                r#"{{"kind": "file", "url": "{url}", "sha256": "{}", "os": "linux", "cpu": "x86_64"}}"#,
+                "0".repeat(64)
+            )
+        };
+        fs::write(
+            &path,
+            format!(
+                r#"{{"broken": {{"binaries": [{}]}}, "current": {{"binaries": [{}]}}, "manual": {{"binaries": [{}]}}, "pinned": {{"update": false, "binaries": [{}]}}}}"#,
+                binary("https://github.com/org/broken/releases/download/v1.0.0/broken"),
+                binary("https://github.com/org/current/releases/download/v1.2.3/current"),
+                binary("https://example.com/manual"),
+                binary("https://github.com/org/pinned/releases/download/v1.0.0/pinned"),
+            ),
+        )
+        .unwrap();
+
+        let results = update_lockfile(&path, &settings, &UpdateOptions::default()).results;
+        let outcomes: Vec<(&str, String)> = results
+            .iter()
+            .flat_map(|result| {
+                result.binaries.iter().map(|binary| {
+                    let outcome = match &binary.outcome {
+                        BinaryOutcome::Updated(change) => {
+                            format!("updated to {}", change.new_version)
+                        }
+                        BinaryOutcome::Unchanged => "unchanged".to_owned(),
+                        BinaryOutcome::Skipped { reason } => format!("skipped: {reason}"),
+                        BinaryOutcome::Failed { .. } => "failed".to_owned(),
+                    };
+                    (result.tool.as_str(), outcome)
+                })
+            })
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("broken", "failed".to_owned()),
+                ("current", "unchanged".to_owned()),
+                ("manual", "skipped: without a release source".to_owned()),
+                ("pinned", "skipped: pinned".to_owned()),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn update_stamps_and_preserves_metadata() {
+        let server = Server::start(|_| Response::status(500, "{}"));
+        let dir = std::env::temp_dir().join(format!("multitool-metadata-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool"), "hello").unwrap();
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let path = dir.join("multitool.lock.json");
+        let lockfile = |metadata: &str| {
+            format!(
+                r#"{{{metadata}"tool": {{"binaries": [{{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool", "sha256": "{}", "os": "linux", "cpu": "x86_64"}}]}}}}"#,
+                "0".repeat(64)
+            )
+        };
+        let options = UpdateOptions {
+            sha_only: true,
+            stamp_metadata: true,
+            ..UpdateOptions::default()
+        };
+        let metadata = |path: &std::path::Path| load_lockfile(path, &settings).metadata;
+
+        // nothing is stamped unless asked for
+        fs::write(&path, lockfile("")).unwrap();
+        let unstamped = UpdateOptions {
+            stamp_metadata: false,
+            ..options.clone()
+        };
+        assert_eq!(
+            outcome::changes(&update_lockfile(&path, &settings, &unstamped).results).len(),
+            1
+        );
+        assert!(metadata(&path).is_none());
+
+        // a lockfile without metadata gains it on its first change
+        fs::write(&path, lockfile("")).unwrap();
+        update_lockfile(&path, &settings, &options);
+        let stamped = metadata(&path).unwrap();
+        assert!(timestamp::parse(stamped.last_updated.as_deref().unwrap()).is_some());
+        assert_eq!(
+            stamped.multitool_version.as_deref(),
+            Some(env!("CARGO_PKG_VERSION"))
+        );
+        // and a run that changes nothing leaves the file alone
+        let written = fs::read_to_string(&path).unwrap();
+        assert!(outcome::changes(&update_lockfile(&path, &settings, &options).results).is_empty());
+        assert_eq!(fs::read_to_string(&path).unwrap(), written);
+
+        // keys multitool doesn't write survive, including through --preserve-unchanged
+        let original =
+            r#""metadata": {"last_updated": "2020-01-01T00:00:00Z", "ticket": "OPS-1"}, "#;
+        for preserve_unchanged in [false, true] {
+            fs::write(&path, lockfile(original)).unwrap();
+            let options = UpdateOptions {
+                preserve_unchanged,
+                ..options.clone()
+            };
+            update_lockfile(&path, &settings, &options);
+            let updated = metadata(&path).unwrap();
+            assert_ne!(
+                updated.last_updated,
+                Some("2020-01-01T00:00:00Z".to_owned())
+            );
+            assert_eq!(updated.other["ticket"], "OPS-1");
+        }
+
+        // without --stamp-metadata the block is kept as it was
+        fs::write(&path, lockfile(original)).unwrap();
+        assert_eq!(
+            outcome::changes(&update_lockfile(&path, &settings, &unstamped).results).len(),
+            1
+        );
+        assert_eq!(
+            metadata(&path).unwrap().last_updated.as_deref(),
+            Some("2020-01-01T00:00:00Z")
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn concurrency_report_times_changed_binaries() {
+        let server = Server::start(|_| Response::status(500, "{}"));
+        let dir = std::env::temp_dir().join(format!("multitool-timing-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool"), "hello").unwrap();
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let path = dir.join("multitool.lock.json");
+        let lockfile = format!(
+            r#"{{"tool": {{"binaries": [{{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool", "sha256": "{}", "os": "linux", "cpu": "x86_64"}}]}}}}"#,
+            "0".repeat(64)
+        );
+        fs::write(&path, &lockfile).unwrap();
+        let options = UpdateOptions {
+            sha_only: true,
+            ..UpdateOptions::default()
+        };
+        assert!(
+            outcome::changes(&update_lockfile(&path, &settings, &options).results)[0]
+                .timing
+                .is_none()
+        );
+
+        fs::write(&path, &lockfile).unwrap();
+        let options = UpdateOptions {
+            concurrency_report: true,
+            ..options
+        };
+        let changes = outcome::changes(&update_lockfile(&path, &settings, &options).results);
+        let timing = changes[0].timing.unwrap();
+        assert!(report::render(&changes, ReportFormat::Json)
+            .contains(&format!("\"download_ms\": {}", timing.download.as_millis())));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_release_notes_links_updated_releases() {
+        let notes = "x".repeat(1200);
+        let server = Server::start(move |request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => Response::ok(r#"{"tag_name": "v1.3.0"}"#),
+            "/repos/org/tool/releases/tags/v1.3.0" => Response::ok(
+                serde_json::json!({
+                    "html_url": "https://github.com/org/tool/releases/tag/v1.3.0",
+                    "body": notes,
+                })
+                .to_string(),
+            ),
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-notes-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool"), "hello").unwrap();
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let path = dir.join("multitool.lock.json");
+        let lockfile = format!(
+            r#"{{"tool": {{"binaries": [{{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool", "sha256": "{}", "os": "linux", "cpu": "x86_64"}}]}}}}"#,
+            "0".repeat(64)
+        );
+        fs::write(&path, &lockfile).unwrap();
+        let changes =
+            outcome::changes(&update_lockfile(&path, &settings, &UpdateOptions::default()).results);
+        assert!(changes[0].release.is_none());
+        assert_eq!(server.requests().len(), 1);
+
+        fs::write(&path, &lockfile).unwrap();
+        let options = UpdateOptions {
+            include_release_notes: true,
+            ..UpdateOptions::default()
+        };
+        let changes = outcome::changes(&update_lockfile(&path, &settings, &options).results);
+        let release = changes[0].release.as_ref().unwrap();
+        assert_eq!(
+            release.url,
+            "https://github.com/org/tool/releases/tag/v1.3.0"
+        );
+        let notes = release.notes.as_deref().unwrap();
+        assert_eq!(notes.chars().count(), 1001);
+        assert!(notes.ends_with('…'));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn max_tools_counts_selected_tools() {
+        let lockfile: Lockfile = serde_json::from_str(
+            r#"{"a": {"binaries": []}, "b": {"binaries": []}, "c": {"update": false, "binaries": []}}"#,
+        )
+        .unwrap();
+        let options = |max_tools| UpdateOptions {
+            max_tools,
+            ..UpdateOptions::default()
+        };
+        assert!(check_max_tools(&lockfile, &options(None)).is_ok());
+        assert!(check_max_tools(&lockfile, &options(Some(2))).is_ok());
+        assert_eq!(
+            check_max_tools(&lockfile, &options(Some(1))),
+            Err("Refusing to update 2 tools, more than --max-tools 1".to_owned())
+        );
+    }
+
+    #[test]
+    fn dry_run_reports_whether_the_lockfile_would_change() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => Response::ok(r#"{"tag_name": "v1.2.3"}"#),
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-dry-run-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool"), "hello").unwrap();
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let path = dir.join("multitool.lock.json");
+        let original = format!(
+            r#"{{"tool": {{"binaries": [{{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool", "sha256": "{}", "os": "linux", "cpu": "x86_64"}}]}}}}"#,
+            "0".repeat(64)
+        );
+        fs::write(&path, &original).unwrap();
+        let options = |sha_only| UpdateOptions {
+            dry_run: true,
+            diff_exit_code: true,
+            sha_only,
+            ..UpdateOptions::default()
+        };
+
+        // same tag, new digest: `check` sees nothing, but the lockfile would change
+        let run = update_lockfile(&path, &settings, &options(true));
+        assert!(run.changed);
+        assert_eq!(fs::read_to_string(&path).unwrap(), original);
+        // what would have been written is still there to check and emit from
+        assert!(run.contents.contains(&sha256::digest("hello")));
+        assert_eq!(
+            run.lockfile.tools["tool"].binaries[0].sha256(),
+            sha256::digest("hello")
+        );
+
+        let applied = UpdateOptions {
+            dry_run: false,
+            ..options(true)
+        };
+        let run = update_lockfile(&path, &settings, &applied);
+        assert!(run.changed);
+        let updated = fs::read_to_string(&path).unwrap();
+        assert_eq!(run.contents, updated);
+        assert!(!update_lockfile(&path, &settings, &options(true)).changed);
+        assert!(!update_lockfile(&path, &settings, &options(false)).changed);
+        assert_eq!(fs::read_to_string(&path).unwrap(), updated);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn max_updates_leaves_later_tools_untouched() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/a/releases/latest"
+            | "/repos/org/b/releases/latest"
+            | "/repos/org/c/releases/latest" => Response::ok(r#"{"tag_name": "v1.3.0"}"#),
+            _ => Response::status(404, "{}"),
+        });
+        let dir =
+            std::env::temp_dir().join(format!("multitool-max-updates-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool"), "hello").unwrap();
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let binary = |repo: &str, os: &str| {
+            format!(
+                r#"{{"kind": "file", "url": "https://github.com/org/{repo}/releases/download/v1.2.3/tool", "sha256": "{}", "os": "{os}", "cpu": "x86_64"}}"#,
+                "0".repeat(64)
+            )
+        };
+        let path = dir.join("multitool.lock.json");
+        fs::write(
+            &path,
+            format!(
+                r#"{{"a": {{"binaries": [{}, {}]}}, "b": {{"binaries": [{}]}}, "c": {{"binaries": [{}]}}}}"#,
+                binary("a", "linux"),
+                binary("a", "macos"),
+                binary("b", "linux"),
+                binary("c", "linux"),
+            ),
+        )
+        .unwrap();
+
+        let options = UpdateOptions {
+            max_updates: Some(1),
+            ..UpdateOptions::default()
+        };
+        // `a` is finished even though it passes the cap, and nothing after it starts
+        let results = update_lockfile(&path, &settings, &options).results;
+        assert_eq!(outcome::changes(&results).len(), 2);
+        assert!(outcome::render_summary(&results)
+            .starts_with("2 updated, 0 unchanged, 2 skipped (2 deferred by --max-updates)"));
+        let version = |lockfile: &Lockfile, tool: &str| {
+            lockfile.tools[tool].binaries[0].url().contains("/v1.3.0/")
+        };
+        let lockfile = load_lockfile(&path, &settings);
+        assert!(version(&lockfile, "a"));
+        assert!(!version(&lockfile, "b"));
+        assert!(!version(&lockfile, "c"));
+
+        // the next run picks up where this one stopped
+        update_lockfile(&path, &settings, &options);
+        let lockfile = load_lockfile(&path, &settings);
+        assert!(version(&lockfile, "b"));
+        assert!(!version(&lockfile, "c"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_resumes_finished_tools() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/done/releases/latest" | "/repos/org/todo/releases/latest" => {
+                Response::ok(r#"{"tag_name": "v1.3.0"}"#)
+            }
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-resume-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("tool"), "hello").unwrap();
+        let settings = Settings {
+            from_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let tool = |repo: &str, version: &str, sha256: &str| {
+            format!(
+                r#"{{"binaries": [{{"kind": "file", "url": "https://github.com/org/{repo}/releases/download/{version}/tool", "sha256": "{sha256}", "os": "linux", "cpu": "x86_64"}}]}}"#
+            )
+        };
+        let path = dir.join("multitool.lock.json");
+        let stale = "0".repeat(64);
+        let original = format!(
+            r#"{{"done": {}, "todo": {}}}"#,
+            tool("done", "v1.2.3", &stale),
+            tool("todo", "v1.2.3", &stale)
+        );
+        fs::write(&path, &original).unwrap();
+        // as left by a run interrupted after updating `done`
+        let finished: ToolDefinition =
+            serde_json::from_str(&tool("done", "v1.3.0", &sha256::digest("hello"))).unwrap();
+        Checkpoint::open(&path, original.as_bytes()).record("done", &finished);
+
+        let options = UpdateOptions {
+            checkpoint: true,
+            stamp_metadata: true,
+            ..UpdateOptions::default()
+        };
+        let results = update_lockfile(&path, &settings, &options).results;
+        assert!(matches!(
+            results[0].binaries[0].outcome,
+            BinaryOutcome::Unchanged
+        ));
+        assert_eq!(results[1].tool, "todo");
+        assert_eq!(outcome::changes(&results).len(), 1);
+        let lockfile = load_lockfile(&path, &settings);
+        for tool in ["done", "todo"] {
+            assert!(lockfile.tools[tool].binaries[0].url().contains("/v1.3.0/"));
+        }
+        assert!(lockfile.metadata.is_some());
+        assert!(!checkpoint::path_for(&path).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn github_graphql_without_a_token_fails_on_warn() {
+        let server = Server::start(|_| Response::status(500, "{}"));
+        let settings = Settings {
+            github_graphql: true,
+            ..settings(&server.url)
+        };
+        let lockfile: Lockfile = serde_json::from_str(
+            r#"{"tool": {"binaries": [{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}]}}"#,
+        )
+        .unwrap();
+
+        let before = warn::emitted();
+        Resolver::new(&settings).prefetch(&lockfile, &ToolFilter::default());
+        assert!(warn::emitted() > before);
+        assert_eq!(warn::exit_code(true, warn::emitted()) as i32, 7);
+        // falling back to REST, nothing was asked of GraphQL
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn resolve_tags_warms_the_tag_cache() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => Response::ok(r#"{"tag_name": "v1.3.0"}"#),
+            "/repos/org/other/releases/latest" => Response::ok(r#"{"tag_name": "v1.1.0"}"#),
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-resolve-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let settings = Settings {
+            cache_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let binary = |os: &str| {
+            format!(
+                r#"{{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool-{os}", "sha256": "00", "os": "{os}", "cpu": "x86_64"}}"#
+            )
+        };
+        let lockfile: Lockfile = serde_json::from_str(&format!(
+            r#"{{"tool": {{"binaries": [{}, {}]}}, "manual": {{"binaries": [{{"kind": "file", "url": "https://example.com/manual", "sha256": "00", "os": "linux", "cpu": "x86_64"}}]}}}}"#,
+            binary("linux"),
+            binary("macos")
+        ))
+        .unwrap();
+
+        let mut resolver = Resolver::new(&settings);
+        let (tags, failures) = resolve_tags(&mut resolver, &lockfile, &ToolFilter::default());
+        resolver.save_caches();
+        assert_eq!(failures, 0);
+        assert_eq!(
+            tags,
+            BTreeMap::from([("tool".to_owned(), BTreeSet::from(["v1.3.0".to_owned()]))])
+        );
+        let paths: Vec<String> = server.requests().into_iter().map(|r| r.path).collect();
+        assert_eq!(paths, ["/repos/org/tool/releases/latest"]);
+        // only tags are cached; nothing was downloaded
+        assert!(dir.join(cache::TAGS_FILE).exists());
+        assert!(!dir.join(cache::DIGESTS_FILE).exists());
+
+        // the next run reuses the tag without asking GitHub again
+        let mut resolver = Resolver::new(&settings);
+        let source = ReleaseSource::of(&None, &lockfile.tools["tool"].binaries[0]).unwrap();
+        assert_eq!(resolver.resolve_latest(&source).unwrap(), "v1.3.0");
+        assert_eq!(server.requests().len(), 1);
+
+        // but only resolve records the tags it looks up
+        let other: Binary = serde_json::from_str(
+            r#"{"kind": "file", "url": "https://github.com/org/other/releases/download/v1.0.0/other", "sha256": "00", "os": "linux", "cpu": "x86_64"}"#,
+        )
+        .unwrap();
+        let source = ReleaseSource::of(&None, &other).unwrap();
+        assert_eq!(resolver.resolve_latest(&source).unwrap(), "v1.1.0");
+        resolver.save_caches();
+        let tags = cache::TagCache::load(Some(&dir));
+        assert!(tags.get(&resolve::tag_key(&source, &settings)).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn assume_latest_rewrites_llvm_releases() {
+        let binary: Binary = serde_json::from_str(
+            r#"{"kind": "archive", "url": "https://github.com/llvm/llvm-project/releases/download/llvmorg-17.0.6/clang+llvm-17.0.6-x86_64-linux-gnu-ubuntu-22.04.tar.xz", "file": "clang+llvm-17.0.6-x86_64-linux-gnu-ubuntu-22.04/bin/clang-format", "sha256": "00", "os": "linux", "cpu": "x86_64"}"#,
+        )
+        .unwrap();
+        let rewritten = assume_latest(&None, &binary, "llvmorg-18.1.8")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            rewritten.url(),
+            "https://github.com/llvm/llvm-project/releases/download/llvmorg-18.1.8/clang+llvm-18.1.8-x86_64-linux-gnu-ubuntu-22.04.tar.xz"
+        );
+        assert_eq!(
+            inner_paths(&rewritten),
+            ["clang+llvm-18.1.8-x86_64-linux-gnu-ubuntu-22.04/bin/clang-format"]
+        );
+        // the digest is kept until the new URL is downloaded
+        assert_eq!(rewritten.sha256(), "00");
+
+        let binary: Binary = serde_json::from_str(
+            r#"{"kind": "file", "url": "https://example.com/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}"#,
+        )
+        .unwrap();
+        assert!(assume_latest(&None, &binary, "v1.3.0").unwrap().is_none());
+    }
+
+    #[test]
+    fn resolve_urls_rewrites_without_downloading() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => Response::ok(r#"{"tag_name": "v1.10.0"}"#),
+            "/version" => Response::ok("2.0.0"),
+            _ => Response::status(404, "{}"),
+        });
+        let settings = settings(&server.url);
+        let mut resolver = Resolver::new(&settings);
+        let binary = |json: &str| -> Binary { serde_json::from_str(json).unwrap() };
+        let mut resolve = |source: &Option<ToolSource>, json: &str| {
+            resolve_urls(&mut resolver, source, &binary(json)).unwrap()
+        };
+
+        assert_eq!(
+            resolve(
+                &None,
+                r#"{"kind": "archive", "url": "https://github.com/org/tool/releases/download/v1.9.0/tool-1.9.0-linux.tar.gz", "file": "tool-1.9.0/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}"#
+            ),
+            Some(vec![
+                "https://github.com/org/tool/releases/download/v1.10.0/tool-1.10.0-linux.tar.gz"
+                    .to_owned()
+            ])
+        );
+        assert_eq!(
+            resolve(
+                &None,
+                r#"{"kind": "archive", "url": "https://github.com/org/tool/releases/download/v1.9.0/tool.tar.gz.part0", "parts": ["https://github.com/org/tool/releases/download/v1.9.0/tool.tar.gz.part0", "https://github.com/org/tool/releases/download/v1.9.0/tool.tar.gz.part1"], "file": "tool", "sha256": "00", "os": "linux", "cpu": "x86_64", "type": "tar.gz"}"#
+            ),
+            Some(vec![
+                "https://github.com/org/tool/releases/download/v1.10.0/tool.tar.gz.part0"
+                    .to_owned(),
+                "https://github.com/org/tool/releases/download/v1.10.0/tool.tar.gz.part1"
+                    .to_owned(),
+            ])
+        );
+        let template: Option<ToolSource> = serde_json::from_str(&format!(
+            r#"{{"kind": "template", "version_url": "{}/version", "url_template": "https://dl.example.com/{{version}}/tool-{{os}}-{{cpu}}"}}"#,
+            server.url
+        ))
+        .unwrap();
+        assert_eq!(
+            resolve(
+                &template,
+                r#"{"kind": "file", "url": "https://dl.example.com/1.0.0/tool-macos-arm64", "sha256": "00", "os": "macos", "cpu": "arm64"}"#
+            ),
+            Some(vec![
+                "https://dl.example.com/2.0.0/tool-macos-arm64".to_owned()
+            ])
+        );
+        assert_eq!(
+            resolve(
+                &None,
+                r#"{"kind": "file", "url": "https://example.com/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}"#
+            ),
+            None
+        );
+
+        // only the release and version lookups are requested
+        let paths: Vec<String> = server
+            .requests()
+            .into_iter()
+            .map(|request| request.path)
+            .collect();
+        assert_eq!(paths, ["/repos/org/tool/releases/latest", "/version"]);
+    }
+
+    #[test]
+    fn print_urls_counts_failed_lookups() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/repos/org/tool/releases/latest" => Response::ok(r#"{"tag_name": "v1.3.0"}"#),
+            _ => Response::status(404, "{}"),
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-print-url-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("multitool.lock.json");
+        let binary = |repo: &str| {
+            format!(
+                r#"{{"binaries": [{{"kind": "file", "url": "https://github.com/org/{repo}/releases/download/v1.2.3/{repo}", "sha256": "{}", "os": "linux", "cpu": "x86_64"}}]}}"#,
+                "0".repeat(64)
+            )
+        };
+        fs::write(
+            &path,
+            format!(
+                r#"{{"gone": {}, "tool": {}}}"#,
+                binary("gone"),
+                binary("tool")
+            ),
+        )
+        .unwrap();
+
+        let settings = settings(&server.url);
+        assert_eq!(print_urls(&path, &settings, &ToolFilter::default()), 1);
+        fs::remove_dir_all(&dir).unwrap();
     }
 }
@@ -0,0 +1,278 @@
+//! A flat, single-platform view of the lockfile for scripts that don't want to
+//! walk its nested structure.
+
+use crate::{
+    lockfile::{Binary, Libc, Lockfile, Platform, SupportedCpu, SupportedOs},
+    Common,
+};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt::Write, str::FromStr};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ManifestFormat {
+    /// A JSON object keyed by tool name
+    #[default]
+    Json,
+    /// Shell-sourceable `NAME='value'` lines
+    Env,
+}
+
+/// The platform a manifest is written for, as `os/cpu` or `os/cpu/libc`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Target {
+    pub platform: Platform,
+    pub libc: Option<Libc>,
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('/').collect();
+        let (os, cpu, libc) = match parts.as_slice() {
+            [os, cpu] => (os, cpu, None),
+            [os, cpu, libc] => (os, cpu, Some(Libc::from_str(libc, false)?)),
+            _ => return Err(format!("expected os/cpu or os/cpu/libc, got '{s}'")),
+        };
+        Ok(Target {
+            platform: Platform {
+                os: SupportedOs::from_str(os, false)?,
+                cpu: SupportedCpu::from_str(cpu, false)?,
+            },
+            libc,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    pub kind: &'static str,
+    pub url: String,
+    pub sha256: String,
+    /// The executable within an archive or package, or a directory's root.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+impl Entry {
+    fn of(binary: &Binary) -> Entry {
+        let (kind, path) = match binary {
+            Binary::File(_) => ("file", None),
+            Binary::Archive(bin) => ("archive", Some(bin.file.clone())),
+            Binary::Pkg(bin) => ("pkg", Some(bin.file.clone())),
+            Binary::Dir(bin) => ("dir", bin.root.clone()),
+        };
+        Entry {
+            kind,
+            url: binary.url().to_owned(),
+            sha256: binary.sha256().to_owned(),
+            path,
+        }
+    }
+}
+
+/// The binary each tool uses on `target`, following fallbacks. Without a libc
+/// in the target a binary without one is preferred, then glibc. Tools with no
+/// binary for the platform are left out.
+pub fn entries(lockfile: &Lockfile, target: &Target) -> BTreeMap<String, Entry> {
+    let mut entries = BTreeMap::new();
+    for (tool, definition) in &lockfile.tools {
+        let platform = definition
+            .fallbacks
+            .iter()
+            .flatten()
+            .find(|fallback| {
+                fallback.os == target.platform.os && fallback.cpu == target.platform.cpu
+            })
+            .map_or(&target.platform, |fallback| &fallback.target);
+        let mut candidates = definition
+            .binaries
+            .iter()
+            .filter(|binary| binary.os() == &platform.os && binary.cpu() == &platform.cpu);
+        let binary = match &target.libc {
+            Some(libc) => candidates.find(|binary| binary.libc() == Some(libc)),
+            None => candidates.min_by_key(|binary| match binary.libc() {
+                None => 0,
+                Some(Libc::Gnu) => 1,
+                Some(Libc::Musl) => 2,
+            }),
+        };
+        if let Some(binary) = binary {
+            entries.insert(tool.clone(), Entry::of(binary));
+        }
+    }
+    entries
+}
+
+/// `NAME` as an environment variable prefix: uppercased, with anything other
+/// than ASCII letters and digits replaced by `_`.
+fn env_name(tool: &str) -> String {
+    tool.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// The [`env_name`] of each tool in `entries`, failing on a name that isn't a
+/// valid shell variable or that two tools share, such as `foo-bar` and
+/// `foo_bar`, since either would silently lose a tool's values.
+fn env_names(entries: &BTreeMap<String, Entry>) -> Result<BTreeMap<&str, String>, String> {
+    let mut tools: BTreeMap<String, &str> = BTreeMap::new();
+    for tool in entries.keys() {
+        let name = env_name(tool);
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            return Err(format!(
+                "tool '{tool}' has no environment variable name: '{name}' doesn't start with a letter or '_'"
+            ));
+        }
+        if let Some(other) = tools.insert(name.clone(), tool) {
+            return Err(format!(
+                "tools '{other}' and '{tool}' both map to environment variables named {name}_*"
+            ));
+        }
+    }
+    Ok(tools.into_iter().map(|(name, tool)| (tool, name)).collect())
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// The manifest of `entries` in `format`. An env manifest fails when tools'
+/// names don't give distinct, valid variable names.
+pub fn render(entries: &BTreeMap<String, Entry>, format: ManifestFormat) -> Result<String, String> {
+    match format {
+        ManifestFormat::Json => Ok(serde_json::to_string_pretty(entries).unwrap() + "\n"),
+        ManifestFormat::Env => {
+            let names = env_names(entries)?;
+            let mut out = String::new();
+            for (tool, entry) in entries {
+                let name = &names[tool.as_str()];
+                let mut line = |key: &str, value: &str| {
+                    writeln!(out, "{name}_{key}={}", shell_quote(value)).unwrap()
+                };
+                line("KIND", entry.kind);
+                line("URL", &entry.url);
+                line("SHA256", &entry.sha256);
+                if let Some(path) = &entry.path {
+                    line("PATH", path);
+                }
+            }
+            Ok(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lockfile() -> Lockfile {
+        serde_json::from_str(
+            r#"{
+              "rg": {
+                "binaries": [
+                  {"kind": "archive", "url": "https://example.com/rg-linux-musl.tar.gz", "file": "rg", "sha256": "01", "os": "linux", "cpu": "x86_64", "libc": "musl"},
+                  {"kind": "archive", "url": "https://example.com/rg-linux-gnu.tar.gz", "file": "rg", "sha256": "02", "os": "linux", "cpu": "x86_64", "libc": "gnu"},
+                  {"kind": "file", "url": "https://example.com/rg-macos", "sha256": "03", "os": "macos", "cpu": "x86_64"}
+                ],
+                "fallbacks": [
+                  {"os": "macos", "cpu": "arm64", "target": {"os": "macos", "cpu": "x86_64"}}
+                ]
+              },
+              "my-tool": {
+                "binaries": [
+                  {"kind": "file", "url": "https://example.com/it's", "sha256": "04", "os": "linux", "cpu": "x86_64"}
+                ]
+              }
+            }"#,
+        )
+        .unwrap()
+    }
+
+    fn target(s: &str) -> Target {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_target() {
+        assert_eq!(
+            target("linux/arm64/musl"),
+            Target {
+                platform: Platform {
+                    os: SupportedOs::Linux,
+                    cpu: SupportedCpu::Arm64
+                },
+                libc: Some(Libc::Musl),
+            }
+        );
+        assert!("linux".parse::<Target>().is_err());
+        assert!("linux/sparc".parse::<Target>().is_err());
+    }
+
+    #[test]
+    fn entries_resolve_libc_and_fallbacks() {
+        let lockfile = lockfile();
+
+        let linux = entries(&lockfile, &target("linux/x86_64"));
+        assert_eq!(linux["rg"].url, "https://example.com/rg-linux-gnu.tar.gz");
+        assert_eq!(linux["my-tool"].sha256, "04");
+
+        let musl = entries(&lockfile, &target("linux/x86_64/musl"));
+        assert_eq!(musl["rg"].sha256, "01");
+        assert!(!musl.contains_key("my-tool"));
+
+        let macos = entries(&lockfile, &target("macos/arm64"));
+        assert_eq!(macos.len(), 1);
+        assert_eq!(macos["rg"].url, "https://example.com/rg-macos");
+    }
+
+    #[test]
+    fn render_formats() {
+        let entries = entries(&lockfile(), &target("linux/x86_64"));
+        assert_eq!(
+            render(&entries, ManifestFormat::Env).unwrap(),
+            "MY_TOOL_KIND='file'\nMY_TOOL_URL='https://example.com/it'\\''s'\nMY_TOOL_SHA256='04'\n\
+             RG_KIND='archive'\nRG_URL='https://example.com/rg-linux-gnu.tar.gz'\nRG_SHA256='02'\nRG_PATH='rg'\n"
+        );
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&entries, ManifestFormat::Json).unwrap()).unwrap();
+        assert_eq!(json["rg"]["path"], "rg");
+        assert!(json["my-tool"].get("path").is_none());
+    }
+
+    #[test]
+    fn render_env_rejects_clashing_and_invalid_names() {
+        let entry = || Entry {
+            kind: "file",
+            url: "https://example.com/tool".to_owned(),
+            sha256: "00".to_owned(),
+            path: None,
+        };
+        let env = |tools: &[&str]| {
+            let entries = tools
+                .iter()
+                .map(|tool| (tool.to_string(), entry()))
+                .collect();
+            render(&entries, ManifestFormat::Env)
+        };
+        assert_eq!(
+            env(&["foo-bar", "foo_bar"]).unwrap_err(),
+            "tools 'foo-bar' and 'foo_bar' both map to environment variables named FOO_BAR_*"
+        );
+        assert_eq!(
+            env(&["9tool"]).unwrap_err(),
+            "tool '9tool' has no environment variable name: '9TOOL' doesn't start with a letter or '_'"
+        );
+        assert!(env(&["_tool", "tool"]).is_ok());
+        // JSON keys are the tool names themselves
+        let entries = [("9tool".to_owned(), entry())].into_iter().collect();
+        assert!(render(&entries, ManifestFormat::Json).is_ok());
+    }
+}
@@ -0,0 +1,252 @@
+//! `platform-matrix`: which os/cpu platforms each tool has a binary for, so a
+//! platform left out of some tools stands out in a large lockfile.
+
+use crate::{
+    color::Palette,
+    lockfile::{Lockfile, Platform, SupportedCpu, SupportedOs, ToolDefinition},
+    Common,
+};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+/// How a tool covers one platform.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Coverage {
+    /// The tool has a binary for the platform.
+    Present,
+    /// A `fallbacks` entry points the platform at another platform's binary.
+    Fallback,
+    Missing,
+}
+
+impl Coverage {
+    pub fn is_covered(self) -> bool {
+        self != Coverage::Missing
+    }
+}
+
+/// `os/cpu`, as written in `--require`.
+pub fn parse_platform(text: &str) -> Result<Platform, String> {
+    let (os, cpu) = text
+        .split_once('/')
+        .ok_or_else(|| format!("expected os/cpu, got '{text}'"))?;
+    Ok(Platform {
+        os: SupportedOs::from_str(os, false)?,
+        cpu: SupportedCpu::from_str(cpu, false)?,
+    })
+}
+
+fn key(os: &SupportedOs, cpu: &SupportedCpu) -> String {
+    format!("{os}/{cpu}")
+}
+
+fn coverage(definition: &ToolDefinition, platform: &Platform) -> Coverage {
+    let has = |platform: &Platform| {
+        definition
+            .binaries
+            .iter()
+            .any(|binary| binary.os() == &platform.os && binary.cpu() == &platform.cpu)
+    };
+    if has(platform) {
+        return Coverage::Present;
+    }
+    let fallback = definition
+        .fallbacks
+        .iter()
+        .flatten()
+        .find(|fallback| fallback.os == platform.os && fallback.cpu == platform.cpu);
+    match fallback {
+        Some(fallback) if has(&fallback.target) => Coverage::Fallback,
+        _ => Coverage::Missing,
+    }
+}
+
+/// The coverage of every tool, keyed by tool and then `os/cpu`. The platforms
+/// are every one any tool has a binary or fallback for, plus `required`.
+pub fn matrix(
+    lockfile: &Lockfile,
+    required: &[Platform],
+) -> BTreeMap<String, BTreeMap<String, Coverage>> {
+    let mut platforms: BTreeMap<String, Platform> = required
+        .iter()
+        .map(|platform| (key(&platform.os, &platform.cpu), platform.clone()))
+        .collect();
+    for definition in lockfile.tools.values() {
+        let binaries = definition.binaries.iter().map(|binary| Platform {
+            os: binary.os().clone(),
+            cpu: binary.cpu().clone(),
+        });
+        let fallbacks = definition
+            .fallbacks
+            .iter()
+            .flatten()
+            .map(|fallback| Platform {
+                os: fallback.os.clone(),
+                cpu: fallback.cpu.clone(),
+            });
+        for platform in binaries.chain(fallbacks) {
+            platforms.insert(key(&platform.os, &platform.cpu), platform);
+        }
+    }
+
+    lockfile
+        .tools
+        .iter()
+        .map(|(tool, definition)| {
+            let row = platforms
+                .iter()
+                .map(|(key, platform)| (key.clone(), coverage(definition, platform)))
+                .collect();
+            (tool.clone(), row)
+        })
+        .collect()
+}
+
+/// The tools without a binary or fallback for a `required` platform, with the
+/// platforms each lacks.
+pub fn gaps(
+    matrix: &BTreeMap<String, BTreeMap<String, Coverage>>,
+    required: &[Platform],
+) -> BTreeMap<String, Vec<String>> {
+    let required: BTreeSet<String> = required
+        .iter()
+        .map(|platform| key(&platform.os, &platform.cpu))
+        .collect();
+    matrix
+        .iter()
+        .filter_map(|(tool, row)| {
+            let missing: Vec<String> = required
+                .iter()
+                .filter(|platform| !row[platform.as_str()].is_covered())
+                .cloned()
+                .collect();
+            (!missing.is_empty()).then(|| (tool.clone(), missing))
+        })
+        .collect()
+}
+
+/// The matrix as a table: `x` for a binary, `f` for a fallback and `-` for a
+/// gap, with a legend.
+pub fn render_text(
+    matrix: &BTreeMap<String, BTreeMap<String, Coverage>>,
+    palette: Palette,
+) -> String {
+    let Some(platforms) = matrix
+        .values()
+        .next()
+        .map(|row| row.keys().collect::<Vec<_>>())
+    else {
+        return String::new();
+    };
+    let width = matrix.keys().map(String::len).max().unwrap_or(0).max(4);
+    let mut out = format!("{:width$}", "tool");
+    for platform in &platforms {
+        write!(out, "  {platform}").unwrap();
+    }
+    out.push('\n');
+    for (tool, row) in matrix {
+        write!(out, "{tool:width$}").unwrap();
+        for (platform, coverage) in row {
+            // pad before coloring, so escape codes don't count toward the width
+            let cell = format!("{:<1$}", cell(*coverage), platform.len());
+            let cell = match coverage {
+                Coverage::Present => cell,
+                Coverage::Fallback => palette.yellow(&cell),
+                Coverage::Missing => palette.red(&cell),
+            };
+            write!(out, "  {cell}").unwrap();
+        }
+        out.truncate(out.trim_end().len());
+        out.push('\n');
+    }
+    out.push_str("x: binary, f: fallback, -: missing\n");
+    out
+}
+
+fn cell(coverage: Coverage) -> &'static str {
+    match coverage {
+        Coverage::Present => "x",
+        Coverage::Fallback => "f",
+        Coverage::Missing => "-",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lockfile(macos_arm64: &str) -> Lockfile {
+        let binary = |os: &str, cpu: &str| {
+            format!(
+                r#"{{"kind": "file", "url": "https://example.com/{os}-{cpu}", "sha256": "00", "os": "{os}", "cpu": "{cpu}"}}"#
+            )
+        };
+        serde_json::from_str(&format!(
+            r#"{{
+              "complete": {{"binaries": [{}, {}, {}]}},
+              "partial": {{"binaries": [{}, {}]{macos_arm64}}}
+            }}"#,
+            binary("linux", "x86_64"),
+            binary("macos", "arm64"),
+            binary("macos", "x86_64"),
+            binary("linux", "x86_64"),
+            binary("macos", "x86_64"),
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn matrix_marks_gaps() {
+        let lockfile = lockfile("");
+        let required = [parse_platform("macos/arm64").unwrap()];
+        let matrix = matrix(&lockfile, &required);
+        assert_eq!(matrix["partial"]["macos/arm64"], Coverage::Missing);
+        assert_eq!(matrix["complete"]["macos/arm64"], Coverage::Present);
+        assert_eq!(
+            gaps(&matrix, &required),
+            BTreeMap::from([("partial".to_owned(), vec!["macos/arm64".to_owned()])])
+        );
+        assert_eq!(
+            render_text(&matrix, Palette::default()),
+            "tool      linux/x86_64  macos/arm64  macos/x86_64\n\
+             complete  x             x            x\n\
+             partial   x             -            x\n\
+             x: binary, f: fallback, -: missing\n"
+        );
+        assert_eq!(
+            serde_json::to_value(&matrix).unwrap()["partial"],
+            serde_json::json!({
+                "linux/x86_64": "present",
+                "macos/arm64": "missing",
+                "macos/x86_64": "present"
+            })
+        );
+    }
+
+    #[test]
+    fn fallbacks_close_gaps() {
+        let lockfile = lockfile(
+            r#", "fallbacks": [{"os": "macos", "cpu": "arm64", "target": {"os": "macos", "cpu": "x86_64"}}]"#,
+        );
+        let required = [
+            parse_platform("macos/arm64").unwrap(),
+            parse_platform("linux/x86_64").unwrap(),
+        ];
+        let matrix = matrix(&lockfile, &required);
+        assert_eq!(matrix["partial"]["macos/arm64"], Coverage::Fallback);
+        assert!(gaps(&matrix, &required).is_empty());
+
+        // a platform no tool has is still a column when it's required
+        let required = [parse_platform("windows/x86_64").unwrap()];
+        assert_eq!(
+            gaps(&super::matrix(&lockfile, &required), &required).len(),
+            2
+        );
+        assert!(parse_platform("linux").is_err());
+    }
+}
@@ -0,0 +1,192 @@
+//! What `update` did with each binary of each tool it worked on, so callers can
+//! decide what to report without parsing its output.
+
+use crate::{
+    lockfile::{SupportedCpu, SupportedOs},
+    report::Change,
+};
+use std::{collections::BTreeMap, fmt, fmt::Write};
+
+/// Why `update` left a binary alone without trying it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SkipReason {
+    /// The tool is marked `"update": false`.
+    Pinned,
+    /// The URL isn't a GitHub release asset and the tool has no `source`.
+    NoSource,
+    /// `--max-updates` was reached before the tool was started.
+    Deferred,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::Pinned => f.write_str("pinned"),
+            SkipReason::NoSource => f.write_str("without a release source"),
+            SkipReason::Deferred => f.write_str("deferred by --max-updates"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum BinaryOutcome {
+    /// The binary was rewritten. The change records the old and new versions
+    /// and digests.
+    Updated(Change),
+    /// The binary was checked and is already current.
+    Unchanged,
+    Skipped {
+        reason: SkipReason,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct BinaryResult {
+    pub os: SupportedOs,
+    pub cpu: SupportedCpu,
+    pub outcome: BinaryOutcome,
+}
+
+/// The outcome of every binary of one tool. Binaries left out by `--os` or
+/// `--cpu` aren't listed.
+#[derive(Clone, Debug)]
+pub struct ToolUpdateResult {
+    pub tool: String,
+    pub binaries: Vec<BinaryResult>,
+}
+
+impl ToolUpdateResult {
+    pub fn changes(&self) -> impl Iterator<Item = &Change> {
+        self.binaries
+            .iter()
+            .filter_map(|binary| match &binary.outcome {
+                BinaryOutcome::Updated(change) => Some(change),
+                _ => None,
+            })
+    }
+}
+
+/// Every change across `results`, in tool order, for `--report`.
+pub fn changes(results: &[ToolUpdateResult]) -> Vec<Change> {
+    results
+        .iter()
+        .flat_map(ToolUpdateResult::changes)
+        .cloned()
+        .collect()
+}
+
+/// The totals of an `update` run, then each failure again, so one at the start
+/// of a long run isn't lost in the scrollback.
+pub fn render_summary(results: &[ToolUpdateResult]) -> String {
+    let (mut updated, mut unchanged, mut failed) = (0, 0, 0);
+    let mut skipped: BTreeMap<String, usize> = BTreeMap::new();
+    let mut failures = String::new();
+    for result in results {
+        for binary in &result.binaries {
+            match &binary.outcome {
+                BinaryOutcome::Updated(_) => updated += 1,
+                BinaryOutcome::Unchanged => unchanged += 1,
+                BinaryOutcome::Skipped { reason } => {
+                    *skipped.entry(reason.to_string()).or_default() += 1
+                }
+                BinaryOutcome::Failed { error } => {
+                    failed += 1;
+                    writeln!(
+                        failures,
+                        "  {} ({}/{}): {error}",
+                        result.tool, binary.os, binary.cpu
+                    )
+                    .unwrap()
+                }
+            }
+        }
+    }
+    let mut out = format!("{updated} updated, {unchanged} unchanged");
+    if !skipped.is_empty() {
+        let reasons: Vec<String> = skipped
+            .iter()
+            .map(|(reason, count)| format!("{count} {reason}"))
+            .collect();
+        let total: usize = skipped.values().sum();
+        write!(out, ", {total} skipped ({})", reasons.join(", ")).unwrap();
+    }
+    writeln!(out, ", {failed} failed").unwrap();
+    if failed > 0 {
+        writeln!(out, "Failed:").unwrap();
+        out.push_str(&failures);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary(os: SupportedOs, outcome: BinaryOutcome) -> BinaryResult {
+        BinaryResult {
+            os,
+            cpu: SupportedCpu::X86_64,
+            outcome,
+        }
+    }
+
+    #[test]
+    fn summary_counts_outcomes_and_repeats_failures() {
+        let change = Change {
+            tool: "a".to_owned(),
+            os: SupportedOs::Linux,
+            cpu: SupportedCpu::X86_64,
+            old_version: "1.2.3".to_owned(),
+            new_version: "1.3.0".to_owned(),
+            old_sha256: "00".to_owned(),
+            new_sha256: "11".to_owned(),
+            timing: None,
+            release: None,
+        };
+        let results = [
+            ToolUpdateResult {
+                tool: "a".to_owned(),
+                binaries: vec![
+                    binary(SupportedOs::Linux, BinaryOutcome::Updated(change.clone())),
+                    binary(SupportedOs::MacOS, BinaryOutcome::Unchanged),
+                    binary(
+                        SupportedOs::Windows,
+                        BinaryOutcome::Failed {
+                            error: "HTTP 404".to_owned(),
+                        },
+                    ),
+                    // errors spanning several lines still count once
+                    binary(
+                        SupportedOs::Linux,
+                        BinaryOutcome::Failed {
+                            error: "invalid release:\n  no tag".to_owned(),
+                        },
+                    ),
+                ],
+            },
+            ToolUpdateResult {
+                tool: "b".to_owned(),
+                binaries: vec![binary(
+                    SupportedOs::Linux,
+                    BinaryOutcome::Skipped {
+                        reason: SkipReason::Pinned,
+                    },
+                )],
+            },
+        ];
+        assert_eq!(
+            render_summary(&results),
+            "1 updated, 1 unchanged, 1 skipped (1 pinned), 2 failed\n\
+             Failed:\n\
+            \x20 a (windows/x86_64): HTTP 404\n\
+            \x20 a (linux/x86_64): invalid release:\n\
+            \x20 no tag\n"
+        );
+        let changes = changes(&results);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].new_version, "1.3.0");
+    }
+}
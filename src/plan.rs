@@ -0,0 +1,184 @@
+//! `update --plan`: the release lookups and downloads an update would make,
+//! grouped by the repo they go to, to estimate rate-limit and bandwidth use
+//! before starting.
+
+use crate::{
+    cache::TagCache,
+    config::Settings,
+    filter::ToolFilter,
+    lockfile::Lockfile,
+    resolve::{tag_key, ReleaseSource},
+    Common,
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+/// The requests an update would send to one repo, or to one version endpoint
+/// for tools with a template source.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Group {
+    pub tools: BTreeSet<String>,
+    /// Latest-release lookups, one per distinct way of selecting the latest
+    /// release; binaries that share one share the lookup.
+    pub lookups: usize,
+    /// Lookups answered by the tag cache instead.
+    pub cached: usize,
+    /// Artifacts to download if every binary is outdated, counting each part of
+    /// a split artifact.
+    pub downloads: usize,
+}
+
+/// The plan of an update of the binaries `filter` selects from `lockfile`,
+/// keyed by `org/repo` or version URL. Tags `tags` has fresh at `now` count as
+/// cached. Lookups the resolved release may need on top, such as its assets
+/// or names, aren't counted.
+pub fn plan(
+    lockfile: &Lockfile,
+    filter: &ToolFilter,
+    settings: &Settings,
+    tags: &TagCache,
+    now: u64,
+) -> BTreeMap<String, Group> {
+    let mut groups: BTreeMap<String, Group> = BTreeMap::new();
+    let mut seen = BTreeSet::new();
+    for (tool, definition) in &lockfile.tools {
+        if !filter.selects(tool, definition) {
+            continue;
+        }
+        for binary in &definition.binaries {
+            if !filter.selects_binary(tool, binary) {
+                continue;
+            }
+            let Some(source) = ReleaseSource::of(&definition.source, binary) else {
+                continue;
+            };
+            let group = groups.entry(source.name()).or_default();
+            group.tools.insert(tool.clone());
+            group.downloads += binary.parts().map_or(1, <[String]>::len);
+            let key = tag_key(&source, settings);
+            if seen.insert(key.clone()) {
+                match tags.fresh(&key, now) {
+                    Some(_) => group.cached += 1,
+                    None => group.lookups += 1,
+                }
+            }
+        }
+    }
+    groups
+}
+
+fn count(n: usize, noun: &str) -> String {
+    match n {
+        1 => format!("1 {noun}"),
+        n => format!("{n} {noun}s"),
+    }
+}
+
+/// One line per group, then the totals.
+pub fn render(groups: &BTreeMap<String, Group>) -> String {
+    let mut out = String::new();
+    let (mut lookups, mut cached, mut downloads) = (0, 0, 0);
+    for (name, group) in groups {
+        let tools: Vec<&str> = group.tools.iter().map(String::as_str).collect();
+        write!(out, "{name}: {}", count(group.lookups, "lookup")).unwrap();
+        if group.cached > 0 {
+            write!(out, " ({} cached)", group.cached).unwrap();
+        }
+        writeln!(
+            out,
+            ", up to {} ({})",
+            count(group.downloads, "download"),
+            tools.join(", ")
+        )
+        .unwrap();
+        lookups += group.lookups;
+        cached += group.cached;
+        downloads += group.downloads;
+    }
+    write!(out, "Total: {}", count(lookups, "lookup")).unwrap();
+    if cached > 0 {
+        write!(out, " ({cached} cached)").unwrap();
+    }
+    writeln!(
+        out,
+        ", up to {} from {}",
+        count(downloads, "download"),
+        count(groups.len(), "source")
+    )
+    .unwrap();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::CachedTag, testing::settings};
+
+    #[test]
+    fn groups_tools_sharing_a_repo() {
+        let binary = |url: &str, os: &str| {
+            format!(
+                r#"{{"kind": "file", "url": "{url}", "sha256": "00", "os": "{os}", "cpu": "x86_64"}}"#
+            )
+        };
+        let release = |repo: &str, asset: &str| {
+            format!("https://github.com/org/{repo}/releases/download/v1.0.0/{asset}")
+        };
+        let lockfile: Lockfile = serde_json::from_str(&format!(
+            r#"{{
+                "tool-a": {{"binaries": [{}, {}]}},
+                "tool-b": {{"binaries": [{}]}},
+                "other": {{"binaries": [{}]}},
+                "unmanaged": {{"binaries": [{}]}}
+            }}"#,
+            binary(&release("suite", "tool-a-linux"), "linux"),
+            binary(&release("suite", "tool-a-macos"), "macos"),
+            binary(&release("suite", "tool-b-linux"), "linux"),
+            binary(&release("other", "other-linux"), "linux"),
+            binary("https://example.com/unmanaged", "linux"),
+        ))
+        .unwrap();
+        let settings = settings("http://localhost");
+
+        let groups = plan(
+            &lockfile,
+            &ToolFilter::default(),
+            &settings,
+            &TagCache::default(),
+            0,
+        );
+        assert_eq!(
+            render(&groups),
+            "org/other: 1 lookup, up to 1 download (other)\n\
+             org/suite: 1 lookup, up to 3 downloads (tool-a, tool-b)\n\
+             Total: 2 lookups, up to 4 downloads from 2 sources\n"
+        );
+
+        // a fresh cached tag saves the lookup
+        let definition = &lockfile.tools["other"];
+        let source = ReleaseSource::of(&definition.source, &definition.binaries[0]).unwrap();
+        // never saved, so the directory is never created
+        let dir = std::env::temp_dir().join(format!("multitool-plan-{}", std::process::id()));
+        let mut tags = TagCache::load(Some(&dir));
+        tags.insert(
+            &tag_key(&source, &settings),
+            CachedTag {
+                tag: "v1.0.0".to_owned(),
+                resolved_at: 0,
+            },
+        );
+        let groups = plan(&lockfile, &ToolFilter::default(), &settings, &tags, 0);
+        assert_eq!(
+            groups["org/other"],
+            Group {
+                tools: BTreeSet::from(["other".to_owned()]),
+                lookups: 0,
+                cached: 1,
+                downloads: 1,
+            }
+        );
+        assert!(render(&groups).starts_with("org/other: 0 lookups (1 cached), "));
+    }
+}
@@ -0,0 +1,226 @@
+//! Rewrites individual tools in a lockfile while leaving every other byte of
+//! it untouched, for `update --preserve-unchanged`.
+
+use crate::{
+    error::Error,
+    lockfile::{Metadata, PlatformNames, ToolDefinition},
+    spelling::Spelled,
+};
+use serde::Serialize;
+use std::{collections::BTreeMap, ops::Range};
+
+/// The byte ranges of each top-level key (including its quotes) and value.
+struct Member {
+    key: Range<usize>,
+    value: Range<usize>,
+}
+
+struct Scanner<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Scanner<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Error> {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) != Some(&byte) {
+            return Err(format!("expected '{}' at byte {}", byte as char, self.pos).into());
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// Skips a string starting at the current `"`.
+    fn string(&mut self) -> Result<Range<usize>, Error> {
+        let start = self.pos;
+        self.expect(b'"')?;
+        loop {
+            match self.bytes.get(self.pos) {
+                Some(b'"') => {
+                    self.pos += 1;
+                    return Ok(start..self.pos);
+                }
+                Some(b'\\') => self.pos += 2,
+                Some(_) => self.pos += 1,
+                None => return Err("unterminated string".into()),
+            }
+        }
+    }
+
+    /// Skips any value, which must already be valid JSON.
+    fn value(&mut self) -> Result<Range<usize>, Error> {
+        self.skip_whitespace();
+        let start = self.pos;
+        let mut depth = 0usize;
+        while let Some(&byte) = self.bytes.get(self.pos) {
+            match byte {
+                b'"' => {
+                    self.string()?;
+                    if depth == 0 {
+                        return Ok(start..self.pos);
+                    }
+                    continue;
+                }
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' if depth == 0 => break,
+                b'}' | b']' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += 1;
+                        return Ok(start..self.pos);
+                    }
+                }
+                b',' if depth == 0 => break,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        let end = self.bytes[start..self.pos]
+            .iter()
+            .rposition(|byte| !byte.is_ascii_whitespace())
+            .map_or(start, |last| start + last + 1);
+        Ok(start..end)
+    }
+}
+
+fn members(contents: &str) -> Result<Vec<Member>, Error> {
+    let mut scanner = Scanner {
+        bytes: contents.as_bytes(),
+        pos: 0,
+    };
+    let mut members = Vec::new();
+    scanner.expect(b'{')?;
+    scanner.skip_whitespace();
+    if scanner.bytes.get(scanner.pos) == Some(&b'}') {
+        return Ok(members);
+    }
+    loop {
+        scanner.skip_whitespace();
+        let key = scanner.string()?;
+        scanner.expect(b':')?;
+        let value = scanner.value()?;
+        members.push(Member { key, value });
+        scanner.skip_whitespace();
+        match scanner.bytes.get(scanner.pos) {
+            Some(b',') => scanner.pos += 1,
+            Some(b'}') => return Ok(members),
+            _ => return Err(format!("expected ',' or '}}' at byte {}", scanner.pos).into()),
+        }
+    }
+}
+
+/// `definition` pretty-printed like the rest of a canonical lockfile, with every
+/// line after the first indented by `indent` and CPUs spelled as `names`.
+fn render(definition: &impl Serialize, indent: &str, names: PlatformNames) -> String {
+    let mut out = Vec::new();
+    let formatter = serde_json::ser::PrettyFormatter::with_indent(b"  ");
+    let mut serializer = serde_json::Serializer::with_formatter(&mut out, formatter);
+    Spelled(definition, names)
+        .serialize(&mut serializer)
+        .unwrap();
+    String::from_utf8(out)
+        .unwrap()
+        .replace('\n', &format!("\n{indent}"))
+}
+
+/// Replaces the definitions of the `changed` tools in the lockfile text
+/// `original`, and its `metadata` block with `metadata` if given, keeping
+/// everything else byte-for-byte. Each replaced value is indented to match the
+/// line its key is on. A `metadata` block is never added.
+pub fn rewrite_tools(
+    original: &str,
+    changed: &BTreeMap<&str, &ToolDefinition>,
+    metadata: Option<&Metadata>,
+    names: PlatformNames,
+) -> Result<String, Error> {
+    let mut out = String::with_capacity(original.len());
+    let mut last = 0;
+    for member in members(original)? {
+        let key: String = serde_json::from_str(&original[member.key.clone()])?;
+        let line_start = original[..member.key.start]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1);
+        let indent = &original[line_start..member.key.start];
+        let indent = &indent[..indent.len() - indent.trim_start().len()];
+        let rendered = match (key.as_str(), metadata) {
+            ("metadata", Some(metadata)) => render(metadata, indent, names),
+            (key, _) => match changed.get(key) {
+                Some(definition) => render(definition, indent, names),
+                None => continue,
+            },
+        };
+        out.push_str(&original[last..member.value.start]);
+        out.push_str(&rendered);
+        last = member.value.end;
+    }
+    out.push_str(&original[last..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str = r#"{
+    "$schema": "https://example.com/schema.json",
+    "a": {"binaries": [
+        {"kind": "file", "url": "https://example.com/a", "sha256": "00", "os": "linux", "cpu": "x86_64"}
+    ]},
+    "b": {
+        "binaries": [{"kind": "file", "url": "https://example.com/b\"}", "sha256": "00", "os": "linux", "cpu": "x86_64"}]
+    }
+}
+"#;
+
+    #[test]
+    fn members_skip_nested_values_and_strings() {
+        let found = members(ORIGINAL).unwrap();
+        let keys: Vec<&str> = found.iter().map(|m| &ORIGINAL[m.key.clone()]).collect();
+        assert_eq!(keys, [r#""$schema""#, r#""a""#, r#""b""#]);
+        assert!(ORIGINAL[found[2].value.clone()].ends_with("]\n    }"));
+        assert!(members("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn rewrite_tools_only_touches_changed_tools() {
+        let lockfile: crate::lockfile::Lockfile = serde_json::from_str(ORIGINAL).unwrap();
+        assert_eq!(
+            rewrite_tools(ORIGINAL, &BTreeMap::new(), None, PlatformNames::Canonical).unwrap(),
+            ORIGINAL
+        );
+
+        let changed = BTreeMap::from([("a", &lockfile.tools["a"])]);
+        let rewritten = rewrite_tools(ORIGINAL, &changed, None, PlatformNames::Canonical).unwrap();
+        assert_eq!(
+            rewritten,
+            r#"{
+    "$schema": "https://example.com/schema.json",
+    "a": {
+      "binaries": [
+        {
+          "kind": "file",
+          "url": "https://example.com/a",
+          "sha256": "00",
+          "os": "linux",
+          "cpu": "x86_64"
+        }
+      ]
+    },
+    "b": {
+        "binaries": [{"kind": "file", "url": "https://example.com/b\"}", "sha256": "00", "os": "linux", "cpu": "x86_64"}]
+    }
+}
+"#
+        );
+    }
+}
@@ -0,0 +1,271 @@
+use crate::{
+    lockfile::{SupportedCpu, SupportedOs},
+    timing::Timing,
+};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::{collections::BTreeSet, fmt::Write};
+
+/// A single binary that was moved to a new version by `update`.
+#[derive(Clone, Debug, Serialize)]
+pub struct Change {
+    pub tool: String,
+    pub os: SupportedOs,
+    pub cpu: SupportedCpu,
+    pub old_version: String,
+    pub new_version: String,
+    pub old_sha256: String,
+    pub new_sha256: String,
+    /// With `--concurrency-report`, where the binary's time went.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timing: Option<Timing>,
+    /// With `--include-release-notes`, the GitHub release moved to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub release: Option<ReleaseNotes>,
+}
+
+/// The release a binary was updated to, so reviewers can see what changed upstream.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ReleaseNotes {
+    /// The release's page, `https://github.com/<org>/<repo>/releases/tag/<tag>`.
+    pub url: String,
+    /// The release's notes, truncated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// One line per changed binary
+    #[default]
+    Text,
+    /// A JSON array of changes
+    Json,
+    /// A GitHub-flavored Markdown table, suitable for PR comments
+    #[value(alias = "github-markdown")]
+    Markdown,
+}
+
+/// Number of leading hex characters of a sha256 shown in Markdown reports.
+const SHORT_SHA_LEN: usize = 12;
+
+fn short_sha(sha256: &str) -> &str {
+    &sha256[..sha256.len().min(SHORT_SHA_LEN)]
+}
+
+pub fn render(changes: &[Change], format: ReportFormat) -> String {
+    match format {
+        ReportFormat::Text => render_text(changes),
+        ReportFormat::Json => serde_json::to_string_pretty(changes).unwrap() + "\n",
+        ReportFormat::Markdown => render_markdown(changes),
+    }
+}
+
+fn render_text(changes: &[Change]) -> String {
+    let mut out = String::new();
+    for change in changes {
+        write!(
+            out,
+            "{} ({}/{}): {} -> {}",
+            change.tool, change.os, change.cpu, change.old_version, change.new_version
+        )
+        .unwrap();
+        match &change.release {
+            Some(release) => writeln!(out, " ({})", release.url).unwrap(),
+            None => out.push('\n'),
+        }
+    }
+    out
+}
+
+/// `text` made safe for a Markdown table cell: a `|` would end the cell and a
+/// line break the row.
+fn cell(text: &str) -> String {
+    text.replace('|', r"\|")
+        .replace("\r\n", "<br>")
+        .replace(['\r', '\n'], "<br>")
+}
+
+/// Release `notes` made safe to put inside `<details>`: a `</details>` outside
+/// code would close the block early, so its `<` is escaped. A code fence left
+/// open, as a truncated note may be, is closed so it can't swallow the rest of
+/// the report.
+fn details(notes: &str) -> String {
+    let mut out = String::new();
+    let mut fence: Option<&str> = None;
+    for line in notes.lines() {
+        let marker = ["```", "~~~"]
+            .into_iter()
+            .find(|marker| line.trim_start().starts_with(marker));
+        match (fence, marker) {
+            (None, Some(marker)) => fence = Some(marker),
+            (Some(open), Some(marker)) if open == marker => fence = None,
+            _ => {}
+        }
+        if fence.is_none() && marker.is_none() {
+            let lower = line.to_ascii_lowercase();
+            let mut rest = 0;
+            for (at, _) in lower.match_indices("</details") {
+                out.push_str(&line[rest..at]);
+                out.push_str("&lt;");
+                rest = at + 1;
+            }
+            out.push_str(&line[rest..]);
+        } else {
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+    if let Some(marker) = fence {
+        out.push_str(marker);
+        out.push('\n');
+    }
+    out
+}
+
+fn render_markdown(changes: &[Change]) -> String {
+    if changes.is_empty() {
+        return "No changes.\n".to_owned();
+    }
+
+    let mut out =
+        String::from("| Tool | Platform | Version | sha256 |\n| --- | --- | --- | --- |\n");
+    for change in changes {
+        let new_version = match &change.release {
+            Some(release) => format!("[{}]({})", change.new_version, release.url),
+            None => change.new_version.clone(),
+        };
+        writeln!(
+            out,
+            "| {} | {}/{} | {} → {} | `{}` → `{}` |",
+            cell(&change.tool),
+            change.os,
+            change.cpu,
+            cell(&change.old_version),
+            cell(&new_version),
+            short_sha(&change.old_sha256),
+            short_sha(&change.new_sha256),
+        )
+        .unwrap();
+    }
+
+    // every platform of a tool moves to the same release, so its notes are shown once
+    let mut shown = BTreeSet::new();
+    for change in changes {
+        let Some(ReleaseNotes {
+            url,
+            notes: Some(notes),
+        }) = &change.release
+        else {
+            continue;
+        };
+        if shown.insert(url) {
+            write!(
+                out,
+                "\n<details><summary>{} {} release notes</summary>\n\n{}\n</details>\n",
+                change.tool,
+                change.new_version,
+                details(notes)
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn changes() -> Vec<Change> {
+        vec![Change {
+            tool: "tool-name".to_owned(),
+            os: SupportedOs::Linux,
+            cpu: SupportedCpu::X86_64,
+            old_version: "0.2.1".to_owned(),
+            new_version: "0.3.0".to_owned(),
+            old_sha256: "9523faf97e4e3fea5f98ba9d051e67c90799182580d8ae56cba2e45c7de0b4ce"
+                .to_owned(),
+            new_sha256: "0a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c6d7e8f9"
+                .to_owned(),
+            timing: None,
+            release: None,
+        }]
+    }
+
+    #[test]
+    fn render_text_report() {
+        assert_eq!(
+            render(&changes(), ReportFormat::Text),
+            "tool-name (linux/x86_64): 0.2.1 -> 0.3.0\n"
+        );
+    }
+
+    #[test]
+    fn render_json_report() {
+        let value: serde_json::Value =
+            serde_json::from_str(&render(&changes(), ReportFormat::Json)).unwrap();
+        assert_eq!(value[0]["tool"], "tool-name");
+        assert_eq!(value[0]["os"], "linux");
+        assert_eq!(value[0]["new_version"], "0.3.0");
+    }
+
+    #[test]
+    fn render_markdown_report() {
+        assert_eq!(
+            render(&changes(), ReportFormat::Markdown),
+            "| Tool | Platform | Version | sha256 |\n\
+             | --- | --- | --- | --- |\n\
+             | tool-name | linux/x86_64 | 0.2.1 → 0.3.0 | `9523faf97e4e` → `0a1b2c3d4e5f` |\n"
+        );
+        assert_eq!(render(&[], ReportFormat::Markdown), "No changes.\n");
+
+        // names and versions can't break out of their cells
+        let mut changes = changes();
+        changes[0].tool = "a|b".to_owned();
+        changes[0].new_version = "0.3.0\r\nbeta|2".to_owned();
+        assert!(render(&changes, ReportFormat::Markdown)
+            .ends_with("| a\\|b | linux/x86_64 | 0.2.1 → 0.3.0<br>beta\\|2 | `9523faf97e4e` → `0a1b2c3d4e5f` |\n"));
+    }
+
+    #[test]
+    fn reports_link_release_notes() {
+        let mut changes = changes();
+        changes[0].release = Some(ReleaseNotes {
+            url: "https://github.com/org/tool-name/releases/tag/v0.3.0".to_owned(),
+            notes: Some("Faster.".to_owned()),
+        });
+        // a second platform of the same release doesn't repeat the notes
+        let mut other = changes[0].clone();
+        other.os = SupportedOs::MacOS;
+        changes.push(other);
+        assert_eq!(
+            render(&changes[..1], ReportFormat::Text),
+            "tool-name (linux/x86_64): 0.2.1 -> 0.3.0 (https://github.com/org/tool-name/releases/tag/v0.3.0)\n"
+        );
+        let markdown = render(&changes, ReportFormat::Markdown);
+        assert!(markdown.contains(
+            "| tool-name | linux/x86_64 | 0.2.1 → [0.3.0](https://github.com/org/tool-name/releases/tag/v0.3.0) |"
+        ));
+        assert!(markdown.ends_with(
+            "|\n\n<details><summary>tool-name 0.3.0 release notes</summary>\n\nFaster.\n\n</details>\n"
+        ));
+        assert_eq!(markdown.matches("<details>").count(), 1);
+    }
+
+    #[test]
+    fn release_notes_stay_inside_details() {
+        assert_eq!(
+            details("Faster.\n</Details> here"),
+            "Faster.\n&lt;/Details> here\n"
+        );
+        // inside code the tag is shown as written
+        assert_eq!(
+            details("```html\n</details>\n```\nDone."),
+            "```html\n</details>\n```\nDone.\n"
+        );
+        // a truncated note's open fence is closed
+        assert_eq!(details("Run:\n```sh\nmake…"), "Run:\n```sh\nmake…\n```\n");
+        assert_eq!(details("~~~\n```\n"), "~~~\n```\n~~~\n");
+    }
+}
@@ -0,0 +1,414 @@
+use crate::error::Error;
+use crate::{
+    cache::{CachedTag, DigestCache, TagCache},
+    config::Settings,
+    filter::ToolFilter,
+    github::{self, GitHubRelease},
+    lockfile::{Binary, GitHubSource, Lockfile, TemplateSource, ToolSource, VersionFrom},
+    report::ReleaseNotes,
+    retry, template,
+    warn::warn,
+    Common,
+};
+use regex::Regex;
+use std::{
+    collections::{BTreeSet, HashMap},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Where a binary's latest version is looked up.
+pub enum ReleaseSource<'a> {
+    GitHub(GitHubRelease<'a>, Option<&'a GitHubSource>),
+    Template(&'a TemplateSource),
+}
+
+impl<'a> ReleaseSource<'a> {
+    /// The source for `binary`, or `None` if it can't be updated automatically.
+    pub fn of(source: &'a Option<ToolSource>, binary: &'a Binary) -> Option<ReleaseSource<'a>> {
+        match source {
+            Some(ToolSource::Template(source)) => Some(ReleaseSource::Template(source)),
+            Some(ToolSource::GitHub(source)) => GitHubRelease::from(binary.url())
+                .map(|release| ReleaseSource::GitHub(release, Some(source))),
+            None => GitHubRelease::from(binary.url())
+                .map(|release| ReleaseSource::GitHub(release, None)),
+        }
+    }
+
+    /// Where releases are looked up: the repo's `org/repo`, or the version URL.
+    pub fn name(&self) -> String {
+        match self {
+            ReleaseSource::GitHub(release, _) => format!("{}/{}", release.org, release.repo),
+            ReleaseSource::Template(source) => source.version_url.clone(),
+        }
+    }
+
+    /// The version `binary` is currently pinned to, in the source's own format
+    /// (a tag for GitHub releases).
+    pub fn current_version(&self, binary: &Binary) -> Result<String, Error> {
+        match self {
+            ReleaseSource::GitHub(release, _) => Ok(release.version.to_owned()),
+            ReleaseSource::Template(source) => template::current_version(
+                &source.url_template,
+                binary.url(),
+                binary.os(),
+                binary.cpu(),
+            )
+            .ok_or_else(|| format!("url '{}' does not match url_template", binary.url()).into()),
+        }
+    }
+
+    /// The download URL of `binary` at `latest`. `names` are the pinned and
+    /// latest release names when versions come from them.
+    pub fn url_for(&self, binary: &Binary, latest: &str, names: Option<(&str, &str)>) -> String {
+        match self {
+            ReleaseSource::GitHub(release, _) => match names {
+                Some((version, latest_name)) => {
+                    release.url_for_release(latest, version, latest_name)
+                }
+                None => release.url_for_tag(latest),
+            },
+            ReleaseSource::Template(source) => {
+                template::render_url(&source.url_template, latest, binary.os(), binary.cpu())
+            }
+        }
+    }
+
+    /// The URLs of `binary`'s parts at `latest`, or `None` for an artifact that
+    /// isn't split. Every part must itself be a GitHub release asset.
+    pub fn parts_for(
+        &self,
+        binary: &Binary,
+        latest: &str,
+        names: Option<(&str, &str)>,
+    ) -> Result<Option<Vec<String>>, Error> {
+        let Some(parts) = binary.parts() else {
+            return Ok(None);
+        };
+        if let ReleaseSource::Template(_) = self {
+            return Err("multi-part artifacts can't be updated from a template source".into());
+        }
+        parts
+            .iter()
+            .map(|part| {
+                GitHubRelease::from(part)
+                    .map(|release| match names {
+                        Some((version, latest_name)) => {
+                            release.url_for_release(latest, version, latest_name)
+                        }
+                        None => release.url_for_tag(latest),
+                    })
+                    .ok_or_else(|| format!("part {part} is not a GitHub release asset").into())
+            })
+            .collect::<Result<Vec<_>, Error>>()
+            .map(Some)
+    }
+}
+
+/// The key a tag from `source` is cached under: everything that decides which
+/// release is latest.
+pub fn tag_key(source: &ReleaseSource, settings: &Settings) -> String {
+    match source {
+        ReleaseSource::GitHub(release, source) => format!(
+            "github {} {}/{} {}",
+            settings.github_api_url,
+            release.org,
+            release.repo,
+            source.map_or_else(String::new, |source| serde_json::to_string(source).unwrap())
+        ),
+        ReleaseSource::Template(source) => {
+            format!("template {}", serde_json::to_string(source).unwrap())
+        }
+    }
+}
+
+/// Resolves latest versions, caching upstream responses across binaries and tools.
+pub struct Resolver<'a> {
+    pub client: reqwest::blocking::Client,
+    pub settings: &'a Settings,
+    pub digests: DigestCache,
+    /// Latest tags from a recent `resolve`, reused for [`TAG_TTL_SECS`](crate::cache::TAG_TTL_SECS).
+    pub tags: TagCache,
+    /// Whether looked-up tags are recorded in `tags`, which only `resolve` does:
+    /// a `--cache-dir` kept for digests mustn't leave other runs reusing tags.
+    pub record_tags: bool,
+    // basic cache of GitHub API responses
+    gh_latest_releases: HashMap<String, github::ApiResponse>,
+    // basic cache of version endpoint responses for template sources
+    version_endpoints: HashMap<String, String>,
+    // when the current tool's --timeout-per-tool runs out
+    deadline: Option<Instant>,
+    /// Time spent in [`Resolver::download`], for `--concurrency-report`.
+    pub downloading: Duration,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(settings: &'a Settings) -> Resolver<'a> {
+        Resolver {
+            client: settings.client(),
+            settings,
+            digests: DigestCache::load(settings.cache_dir.as_deref()),
+            tags: TagCache::load(settings.cache_dir.as_deref()),
+            record_tags: false,
+            gh_latest_releases: HashMap::new(),
+            version_endpoints: HashMap::new(),
+            deadline: None,
+            downloading: Duration::ZERO,
+        }
+    }
+
+    /// Runs `download`, adding the time it takes to `downloading`.
+    pub fn download<T>(&mut self, download: impl FnOnce(&mut Resolver<'a>) -> T) -> T {
+        let started = Instant::now();
+        let result = download(self);
+        self.downloading += started.elapsed();
+        result
+    }
+
+    /// Forgets every looked-up release so the next lookups see new ones, while the
+    /// client's connections and the digest cache are kept. Cached tags are
+    /// dropped and no longer recorded.
+    pub fn forget_releases(&mut self) {
+        self.gh_latest_releases.clear();
+        self.version_endpoints.clear();
+        self.tags = TagCache::default();
+    }
+
+    /// Writes the digest and tag caches back, reporting rather than failing on
+    /// errors since they can be rebuilt.
+    pub fn save_caches(&self) {
+        if let Err(e) = self.digests.save() {
+            eprintln!("Unable to save digest cache: {e}");
+        }
+        if let Err(e) = self.tags.save() {
+            eprintln!("Unable to save tag cache: {e}");
+        }
+    }
+
+    /// Starts the `--timeout-per-tool` clock for the next tool.
+    pub fn start_tool(&mut self) {
+        self.deadline = self
+            .settings
+            .timeout_per_tool
+            .map(|limit| Instant::now() + limit);
+    }
+
+    /// When the current tool runs out of `--timeout-per-tool`, which bounds how
+    /// long throttled requests are retried.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Fails once the current tool has used up its `--timeout-per-tool`. This is
+    /// checked between requests, so an in-flight request is only bounded by
+    /// `--timeout-secs`; retries of throttled requests stop at the deadline too.
+    pub fn check_deadline(&self) -> Result<(), Error> {
+        match (self.deadline, self.settings.timeout_per_tool) {
+            (Some(deadline), Some(limit)) if Instant::now() >= deadline => Err(format!(
+                "gave up after exceeding --timeout-per-tool of {}s",
+                limit.as_secs()
+            )
+            .into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// With `--github-graphql`, looks up the latest release of every GitHub repo
+    /// among the selected tools up front in batched GraphQL requests. Anything it
+    /// can't resolve is looked up over REST as usual.
+    pub fn prefetch(&mut self, lockfile: &Lockfile, filter: &ToolFilter) {
+        if !self.settings.github_graphql {
+            return;
+        }
+        if self.settings.github_token.is_none() {
+            warn("--github-graphql requires a GitHub token; using the REST API instead");
+            return;
+        }
+
+        let mut repos = BTreeSet::new();
+        for (tool, definition) in &lockfile.tools {
+            if !filter.selects(tool, definition) {
+                continue;
+            }
+            for binary in definition
+                .binaries
+                .iter()
+                .filter(|b| filter.selects_binary(tool, b))
+            {
+                // tag_regex resolves from the release list, which isn't prefetched
+                if let Some(ReleaseSource::GitHub(release, None)) =
+                    ReleaseSource::of(&definition.source, binary)
+                {
+                    repos.insert((release.org, release.repo));
+                }
+            }
+        }
+        let repos: Vec<(&str, &str)> = repos.into_iter().collect();
+
+        let prefetched = github::prefetch_latest_tags(
+            &self.client,
+            self.settings,
+            &mut self.gh_latest_releases,
+            &repos,
+        );
+        match prefetched {
+            Ok(found) if self.settings.verbose => {
+                eprintln!("GraphQL resolved {found} of {} repos", repos.len())
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("GraphQL lookup failed, falling back to the REST API: {e}"),
+        }
+    }
+
+    /// The download URLs of the assets of `release`'s repo at `tag`.
+    pub fn release_assets(
+        &mut self,
+        release: &GitHubRelease,
+        tag: &str,
+    ) -> Result<Vec<github::Asset>, Error> {
+        github::release_assets(
+            &self.client,
+            self.settings,
+            &mut self.gh_latest_releases,
+            self.deadline,
+            release,
+            tag,
+        )
+    }
+
+    /// The GitHub release `binary` downloads from, for `--include-release-notes`;
+    /// `None` for binaries hosted elsewhere, or with a warning if the lookup fails.
+    pub fn release_notes(&mut self, binary: &Binary) -> Option<ReleaseNotes> {
+        let release = GitHubRelease::from(binary.url())?;
+        github::release_notes(
+            &self.client,
+            self.settings,
+            &mut self.gh_latest_releases,
+            self.deadline,
+            &release,
+            release.version,
+        )
+        .map_err(|e| warn(format!("no release notes for {}: {e}", binary.url())))
+        .ok()
+    }
+
+    /// With `"version_from": "name"`, the release names at the pinned tag
+    /// `version` and at `latest`; otherwise `None`, as tags are the versions.
+    pub fn release_names(
+        &mut self,
+        source: &ReleaseSource,
+        version: &str,
+        latest: &str,
+    ) -> Result<Option<(String, String)>, Error> {
+        let ReleaseSource::GitHub(release, Some(source)) = source else {
+            return Ok(None);
+        };
+        if source.version_from != Some(VersionFrom::Name) {
+            return Ok(None);
+        }
+        let mut name = |tag| {
+            github::release_name(
+                &self.client,
+                self.settings,
+                &mut self.gh_latest_releases,
+                self.deadline,
+                release,
+                tag,
+            )
+        };
+        Ok(Some((name(version)?, name(latest)?)))
+    }
+
+    /// With `--only-if-newer-published`, whether the GitHub release at tag
+    /// `latest` was published strictly after the one at `version`, with both
+    /// timestamps; `None` when the guard is off or `source` has no timestamps.
+    pub fn published_order(
+        &mut self,
+        source: &ReleaseSource,
+        version: &str,
+        latest: &str,
+    ) -> Result<Option<(bool, u64, u64)>, Error> {
+        let ReleaseSource::GitHub(release, _) = source else {
+            return Ok(None);
+        };
+        if !self.settings.only_if_newer_published {
+            return Ok(None);
+        }
+        let mut published_at = |tag| {
+            github::release_published_at(
+                &self.client,
+                self.settings,
+                &mut self.gh_latest_releases,
+                self.deadline,
+                release,
+                tag,
+            )
+        };
+        let (pinned, newest) = (published_at(version)?, published_at(latest)?);
+        Ok(Some((newest > pinned, pinned, newest)))
+    }
+
+    /// The latest version available from `source`. URL and path rewriting happen
+    /// separately in `rewrite_binary`, without further requests. Outside
+    /// `resolve`, a tag it recorded recently enough in `--cache-dir` is reused
+    /// without a lookup.
+    pub fn resolve_latest(&mut self, source: &ReleaseSource) -> Result<String, Error> {
+        let key = tag_key(source, self.settings);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        if !self.record_tags {
+            if let Some(tag) = self.tags.fresh(&key, now) {
+                eprintln!(
+                    "Using {tag} for {}, as cached by an earlier resolve",
+                    source.name()
+                );
+                return Ok(tag.to_owned());
+            }
+            return self.look_up_latest(source);
+        }
+        let tag = self.look_up_latest(source)?;
+        let cached = CachedTag {
+            tag: tag.clone(),
+            resolved_at: now,
+        };
+        self.tags.insert(&key, cached);
+        Ok(tag)
+    }
+
+    fn look_up_latest(&mut self, source: &ReleaseSource) -> Result<String, Error> {
+        match source {
+            ReleaseSource::GitHub(release, source) => {
+                let tag_regex = source
+                    .and_then(|source| source.tag_regex.as_deref())
+                    .map(Regex::new)
+                    .transpose()?;
+                github::latest_tag(
+                    &self.client,
+                    self.settings,
+                    &mut self.gh_latest_releases,
+                    self.deadline,
+                    release,
+                    tag_regex.as_ref(),
+                )
+            }
+            ReleaseSource::Template(source) => {
+                let body = match self.version_endpoints.get(&source.version_url) {
+                    Some(body) => body,
+                    None => {
+                        let mut request = self.client.get(&source.version_url);
+                        if let Some(token) = self.settings.token_for(&source.version_url) {
+                            request = request.bearer_auth(token);
+                        }
+                        let body = retry::send(request, self.settings, self.deadline)?
+                            .error_for_status()?
+                            .text()?;
+                        self.version_endpoints
+                            .entry(source.version_url.clone())
+                            .or_insert(body)
+                    }
+                };
+                template::extract_version(source, body)
+            }
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Retrying requests a server turned away for the moment, with a 429 or 503,
+//! after waiting as long as its `Retry-After` asks, or else backing off
+//! exponentially. Waits never exceed `--max-retry-wait-secs`, and a retry that
+//! would outlast the current tool's `--timeout-per-tool` isn't made.
+
+use crate::{config::Settings, timestamp};
+use reqwest::{
+    blocking::{RequestBuilder, Response},
+    header::RETRY_AFTER,
+    StatusCode,
+};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// How many times a request is sent before a 429 or 503 is returned as is.
+const ATTEMPTS: u32 = 3;
+
+/// The wait before the first retry when the server doesn't say, doubling on each
+/// further one.
+const BACKOFF: Duration = Duration::from_secs(1);
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Seconds since the epoch of an HTTP-date in any of the three forms HTTP
+/// allows: `Sun, 06 Nov 1994 08:49:37 GMT`, the obsolete
+/// `Sunday, 06-Nov-94 08:49:37 GMT` and asctime's `Sun Nov  6 08:49:37 1994`.
+fn http_date(value: &str) -> Option<u64> {
+    let fields: Vec<&str> = value
+        .split([' ', ',', '-'])
+        .filter(|field| !field.is_empty())
+        .collect();
+    let (day, month, year, time) = match fields.as_slice() {
+        [_, day, month, year, time, "GMT"] => (day, month, year, time),
+        [_, month, day, time, year] => (day, month, year, time),
+        _ => return None,
+    };
+    let month = MONTHS.iter().position(|name| name == month)? + 1;
+    let day: u64 = day.parse().ok()?;
+    let year = match year.parse::<u64>().ok()? {
+        // two-digit years only appear in the obsolete form
+        year if year < 70 => 2000 + year,
+        year if year < 100 => 1900 + year,
+        year => year,
+    };
+    timestamp::parse(&format!("{year:04}-{month:02}-{day:02}T{time}Z"))
+}
+
+/// How long a `Retry-After` of `value` asks to wait, `now` seconds after the
+/// epoch. It's either a number of seconds or an HTTP-date, and a date already
+/// past asks for no wait.
+fn retry_after(value: &str, now: u64) -> Option<Duration> {
+    let value = value.trim();
+    match value.parse() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => http_date(value).map(|at| Duration::from_secs(at.saturating_sub(now))),
+    }
+}
+
+/// Sends `request`, retrying a 429 or 503 unless the wait would reach
+/// `deadline`, the end of the current tool's `--timeout-per-tool`. A request
+/// whose body can't be copied is only sent once.
+pub fn send(
+    mut request: RequestBuilder,
+    settings: &Settings,
+    deadline: Option<Instant>,
+) -> reqwest::Result<Response> {
+    let mut attempt = 1;
+    loop {
+        let next = (attempt < ATTEMPTS).then(|| request.try_clone()).flatten();
+        let response = request.send()?;
+        let status = response.status();
+        let retryable = matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+        );
+        let Some(next) = next.filter(|_| retryable) else {
+            return Ok(response);
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let wait = response
+            .headers()
+            .get(RETRY_AFTER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| retry_after(value, now))
+            .unwrap_or(BACKOFF * 2u32.pow(attempt - 1))
+            .min(settings.max_retry_wait);
+        // waiting past the deadline would only fail the tool later
+        let left = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if left.is_some_and(|left| wait >= left) {
+            if settings.verbose {
+                eprintln!(
+                    "{}: HTTP {status}, not retrying since waiting {}s would exceed --timeout-per-tool",
+                    response.url(),
+                    wait.as_secs()
+                );
+            }
+            return Ok(response);
+        }
+        if settings.verbose {
+            eprintln!(
+                "{}: HTTP {status}, retrying in {}s",
+                response.url(),
+                wait.as_secs()
+            );
+        }
+        std::thread::sleep(wait);
+        request = next;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{settings, Response, Server};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn retry_after_seconds() {
+        assert_eq!(retry_after("120", 0), Some(Duration::from_secs(120)));
+        assert_eq!(retry_after(" 0 ", 0), Some(Duration::ZERO));
+        assert_eq!(retry_after("soon", 0), None);
+    }
+
+    #[test]
+    fn retry_after_dates() {
+        // 1994-11-06T08:49:37Z
+        let at = 784_111_777;
+        for date in [
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(
+                retry_after(date, at - 30),
+                Some(Duration::from_secs(30)),
+                "{date}"
+            );
+        }
+        assert_eq!(
+            retry_after("Sun, 06 Nov 1994 08:49:37 GMT", at + 30),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after("Sun, 06 Nov 1994 08:49:37 PST", at), None);
+        assert_eq!(retry_after("Sat, 00 Mar 2025 08:49:37 GMT", at), None);
+    }
+
+    #[test]
+    fn send_retries_throttled_requests() {
+        let attempts = AtomicUsize::new(0);
+        let server = Server::start(move |_| match attempts.fetch_add(1, Ordering::SeqCst) {
+            0 => Response::status(429, "").header("Retry-After", "0"),
+            1 => Response::status(503, "").header("Retry-After", "Sun, 06 Nov 1994 08:49:37 GMT"),
+            _ => Response::ok("hello"),
+        });
+        let settings = settings(&server.url);
+        let client = settings.client();
+        let response = send(client.get(format!("{}/tool", server.url)), &settings, None).unwrap();
+        assert_eq!(response.text().unwrap(), "hello");
+        assert_eq!(server.requests().len(), 3);
+
+        // the last attempt's response is returned even if it's still throttled
+        let server = Server::start(|_| Response::status(503, ""));
+        let response = send(client.get(format!("{}/tool", server.url)), &settings, None).unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(server.requests().len(), ATTEMPTS as usize);
+    }
+
+    #[test]
+    fn send_gives_up_rather_than_wait_past_the_deadline() {
+        let server = Server::start(|_| Response::status(429, "").header("Retry-After", "60"));
+        let settings = Settings {
+            max_retry_wait: Duration::from_secs(60),
+            ..settings(&server.url)
+        };
+        let client = settings.client();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let started = Instant::now();
+        let request = client.get(format!("{}/tool", server.url));
+        let response = send(request, &settings, Some(deadline)).unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(server.requests().len(), 1);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,138 @@
+//! `update --verify-signatures`: checking each downloaded artifact of a tool
+//! with a `signature` against the signature its project publishes beside it,
+//! for projects that sign releases with minisign rather than sigstore.
+
+use crate::{
+    config::Settings,
+    download,
+    error::Error,
+    lockfile::{SignatureScheme, ToolSignature},
+};
+use std::{collections::BTreeMap, time::Instant};
+
+fn minisign_key(signature: &ToolSignature) -> Result<minisign_verify::PublicKey, String> {
+    minisign_verify::PublicKey::from_base64(signature.public_key.trim()).map_err(|e| {
+        format!(
+            "invalid minisign public key '{}': {e}",
+            signature.public_key
+        )
+    })
+}
+
+/// Checks that `signature`'s public key can be read.
+pub fn validate(signature: &ToolSignature) -> Result<(), String> {
+    match signature.scheme {
+        SignatureScheme::Minisign => minisign_key(signature).map(|_| ()),
+    }
+}
+
+/// Checks that `signed`, the text of a signature file, is `signature`'s key's
+/// signature of `bytes`. Signatures of older minisign versions, which sign the
+/// contents rather than their hash, are accepted too.
+pub fn check(signature: &ToolSignature, bytes: &[u8], signed: &str) -> Result<(), String> {
+    match signature.scheme {
+        SignatureScheme::Minisign => {
+            let key = minisign_key(signature)?;
+            let signed = minisign_verify::Signature::decode(signed)
+                .map_err(|e| format!("unreadable minisign signature: {e}"))?;
+            key.verify(bytes, &signed, true)
+                .map_err(|e| format!("bad minisign signature: {e}"))
+        }
+    }
+}
+
+/// Downloads the signature of the artifact at `url`, whose contents are
+/// `bytes`, and checks it. `headers` are sent with the signature's download
+/// as with the artifact's, and it's retried no later than `deadline`.
+pub fn verify(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    signature: &ToolSignature,
+    url: &str,
+    headers: Option<&BTreeMap<String, String>>,
+    bytes: &[u8],
+    deadline: Option<Instant>,
+) -> Result<(), Error> {
+    let signature_url = signature.url_for(url);
+    let signed = download::fetch_parts(
+        client,
+        settings,
+        std::slice::from_ref(&signature_url),
+        headers,
+        None,
+        deadline,
+    )?;
+    check(signature, bytes, &String::from_utf8_lossy(&signed))
+        .map_err(|e| format!("{url}: {e} ({signature_url})").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{settings, Response, Server, MINISIGN_PUBLIC_KEY, MINISIGN_SIGNED};
+
+    fn minisign(public_key: &str) -> ToolSignature {
+        ToolSignature {
+            scheme: SignatureScheme::Minisign,
+            public_key: public_key.to_owned(),
+            url: None,
+        }
+    }
+
+    #[test]
+    fn checks_minisign_signatures() {
+        let signature = minisign(MINISIGN_PUBLIC_KEY);
+        assert!(validate(&signature).is_ok());
+        assert_eq!(check(&signature, b"hello", MINISIGN_SIGNED), Ok(()));
+        assert!(check(&signature, b"hellO", MINISIGN_SIGNED)
+            .unwrap_err()
+            .starts_with("bad minisign signature"));
+        assert!(check(&signature, b"hello", "not a signature")
+            .unwrap_err()
+            .starts_with("unreadable minisign signature"));
+
+        // the same key id with another key: the signature doesn't verify
+        let other = "RWQBI0VniavN79AJoIky5wiaj3Prh9sXBnKU8GyrsYTleHHcIZaLNB4P";
+        assert!(check(&minisign(other), b"hello", MINISIGN_SIGNED).is_err());
+        assert!(validate(&minisign("RWQ")).is_err());
+    }
+
+    #[test]
+    fn verify_fetches_the_signature_beside_the_artifact() {
+        let server = Server::start(|request| match request.path.as_str() {
+            "/tool-linux.minisig" => Response::ok(MINISIGN_SIGNED),
+            "/tool-linux.sig" => Response::ok("untrusted comment: empty\n"),
+            _ => Response::status(404, ""),
+        });
+        let settings = settings(&server.url);
+        let url = format!("{}/tool-linux", server.url);
+        let mut signature = minisign(MINISIGN_PUBLIC_KEY);
+        assert!(verify(
+            &settings.client(),
+            &settings,
+            &signature,
+            &url,
+            None,
+            b"hello",
+            None
+        )
+        .is_ok());
+
+        signature.url = Some("{url}.sig".to_owned());
+        let error = verify(
+            &settings.client(),
+            &settings,
+            &signature,
+            &url,
+            None,
+            b"hello",
+            None,
+        )
+        .unwrap_err()
+        .to_string();
+        assert_eq!(
+            error,
+            format!("{url}: unreadable minisign signature: Invalid encoding in minisign data ({url}.sig)")
+        );
+    }
+}
@@ -0,0 +1,314 @@
+//! Serializing lockfile values with their CPUs spelled in a [`PlatformNames`]
+//! dialect. [`SupportedCpu`](crate::lockfile::SupportedCpu) serializes as a unit
+//! variant, so a serializer wrapped in [`Respeller`] can rename exactly those
+//! variants while passing everything else through untouched.
+
+use crate::lockfile::PlatformNames;
+use serde::ser::{
+    Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+    SerializeTupleStruct, SerializeTupleVariant, Serializer,
+};
+
+/// `value`, serialized with every CPU in it spelled as `names` asks.
+pub struct Spelled<'a, T: ?Sized>(pub &'a T, pub PlatformNames);
+
+impl<T: Serialize + ?Sized> Serialize for Spelled<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Spelled(value, names) = *self;
+        value.serialize(Respeller {
+            inner: serializer,
+            names,
+        })
+    }
+}
+
+/// A serializer, or one of its compound serializers, whose values are all
+/// serialized as [`Spelled`].
+struct Respeller<S> {
+    inner: S,
+    names: PlatformNames,
+}
+
+/// The legacy spelling of a `SupportedCpu` variant, if it has one.
+fn legacy_cpu(variant: &str) -> Option<&'static str> {
+    match variant {
+        "x86_64" => Some("amd64"),
+        "arm64" => Some("aarch64"),
+        _ => None,
+    }
+}
+
+macro_rules! forward {
+    ($($method:ident($type:ty)),* $(,)?) => {
+        $(
+            fn $method(self, value: $type) -> Result<S::Ok, S::Error> {
+                self.inner.$method(value)
+            }
+        )*
+    };
+}
+
+impl<S: Serializer> Serializer for Respeller<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Respeller<S::SerializeSeq>;
+    type SerializeTuple = Respeller<S::SerializeTuple>;
+    type SerializeTupleStruct = Respeller<S::SerializeTupleStruct>;
+    type SerializeTupleVariant = Respeller<S::SerializeTupleVariant>;
+    type SerializeMap = Respeller<S::SerializeMap>;
+    type SerializeStruct = Respeller<S::SerializeStruct>;
+    type SerializeStructVariant = Respeller<S::SerializeStructVariant>;
+
+    forward!(
+        serialize_bool(bool),
+        serialize_i8(i8),
+        serialize_i16(i16),
+        serialize_i32(i32),
+        serialize_i64(i64),
+        serialize_i128(i128),
+        serialize_u8(u8),
+        serialize_u16(u16),
+        serialize_u32(u32),
+        serialize_u64(u64),
+        serialize_u128(u128),
+        serialize_f32(f32),
+        serialize_f64(f64),
+        serialize_char(char),
+        serialize_str(&str),
+        serialize_bytes(&[u8]),
+        serialize_unit_struct(&'static str),
+    );
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_none()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_some(&Spelled(value, self.names))
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.inner.serialize_unit()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+    ) -> Result<S::Ok, S::Error> {
+        let variant = match (name, self.names) {
+            ("SupportedCpu", PlatformNames::Legacy) => legacy_cpu(variant).unwrap_or(variant),
+            _ => variant,
+        };
+        self.inner.serialize_unit_variant(name, index, variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_newtype_struct(name, &Spelled(value, self.names))
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<S::Ok, S::Error> {
+        self.inner
+            .serialize_newtype_variant(name, index, variant, &Spelled(value, self.names))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, S::Error> {
+        Ok(Respeller {
+            inner: self.inner.serialize_seq(len)?,
+            names: self.names,
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, S::Error> {
+        Ok(Respeller {
+            inner: self.inner.serialize_tuple(len)?,
+            names: self.names,
+        })
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleStruct, S::Error> {
+        Ok(Respeller {
+            inner: self.inner.serialize_tuple_struct(name, len)?,
+            names: self.names,
+        })
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeTupleVariant, S::Error> {
+        Ok(Respeller {
+            inner: self
+                .inner
+                .serialize_tuple_variant(name, index, variant, len)?,
+            names: self.names,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, S::Error> {
+        Ok(Respeller {
+            inner: self.inner.serialize_map(len)?,
+            names: self.names,
+        })
+    }
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStruct, S::Error> {
+        Ok(Respeller {
+            inner: self.inner.serialize_struct(name, len)?,
+            names: self.names,
+        })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Self::SerializeStructVariant, S::Error> {
+        Ok(Respeller {
+            inner: self
+                .inner
+                .serialize_struct_variant(name, index, variant, len)?,
+            names: self.names,
+        })
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.inner.is_human_readable()
+    }
+}
+
+impl<S: SerializeSeq> SerializeSeq for Respeller<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), S::Error> {
+        self.inner.serialize_element(&Spelled(value, self.names))
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeTuple> SerializeTuple for Respeller<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), S::Error> {
+        self.inner.serialize_element(&Spelled(value, self.names))
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeTupleStruct> SerializeTupleStruct for Respeller<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), S::Error> {
+        self.inner.serialize_field(&Spelled(value, self.names))
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeTupleVariant> SerializeTupleVariant for Respeller<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), S::Error> {
+        self.inner.serialize_field(&Spelled(value, self.names))
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeMap> SerializeMap for Respeller<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), S::Error> {
+        self.inner.serialize_key(&Spelled(key, self.names))
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), S::Error> {
+        self.inner.serialize_value(&Spelled(value, self.names))
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeStruct> SerializeStruct for Respeller<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), S::Error> {
+        self.inner.serialize_field(key, &Spelled(value, self.names))
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), S::Error> {
+        self.inner.skip_field(key)
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
+
+impl<S: SerializeStructVariant> SerializeStructVariant for Respeller<S> {
+    type Ok = S::Ok;
+    type Error = S::Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), S::Error> {
+        self.inner.serialize_field(key, &Spelled(value, self.names))
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<(), S::Error> {
+        self.inner.skip_field(key)
+    }
+
+    fn end(self) -> Result<S::Ok, S::Error> {
+        self.inner.end()
+    }
+}
@@ -0,0 +1,175 @@
+use crate::error::Error;
+use crate::lockfile::{SupportedCpu, SupportedOs, TemplateSource};
+use regex::Regex;
+use serde_json::Value;
+
+/// Extracts the version string from the body returned by `source.version_url`.
+///
+/// Exactly one of `version_json_path` and `version_regex` may be set; when neither
+/// is set the trimmed body is treated as the version (plain-text endpoints).
+pub fn extract_version(source: &TemplateSource, body: &str) -> Result<String, Error> {
+    match (&source.version_json_path, &source.version_regex) {
+        (Some(_), Some(_)) => {
+            Err("only one of version_json_path and version_regex may be set".into())
+        }
+        (Some(path), None) => {
+            let value: Value = serde_json::from_str(body)?;
+            match select_json(&value, path) {
+                Some(Value::String(version)) => Ok(version.clone()),
+                Some(Value::Number(version)) => Ok(version.to_string()),
+                Some(other) => Err(format!("'{path}' selected a non-string value: {other}").into()),
+                None => Err(format!(
+                    "'{path}' did not match the response from {}",
+                    source.version_url
+                )
+                .into()),
+            }
+        }
+        (None, Some(pattern)) => {
+            let regex = Regex::new(pattern)?;
+            let captures = regex.captures(body).ok_or_else(|| {
+                format!(
+                    "'{pattern}' did not match the response from {}",
+                    source.version_url
+                )
+            })?;
+            let version = captures
+                .name("version")
+                .or_else(|| captures.get(1))
+                .or_else(|| captures.get(0))
+                .unwrap();
+            Ok(version.as_str().to_owned())
+        }
+        (None, None) => {
+            let version = body.trim();
+            if version.is_empty() {
+                return Err(format!("empty response from {}", source.version_url).into());
+            }
+            Ok(version.to_owned())
+        }
+    }
+}
+
+/// Selects a value using a minimal JSON path: dot-separated keys with optional
+/// `[n]` (or `.n`) array indices and an optional leading `$`.
+fn select_json<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    let path = path.strip_prefix('$').unwrap_or(path);
+    path.split(['.', '[', ']'])
+        .filter(|segment| !segment.is_empty())
+        .try_fold(value, |value, segment| match value {
+            Value::Array(items) => segment.parse::<usize>().ok().and_then(|i| items.get(i)),
+            _ => value.get(segment),
+        })
+}
+
+/// Renders `url_template` for a specific version and platform.
+pub fn render_url(
+    url_template: &str,
+    version: &str,
+    os: &SupportedOs,
+    cpu: &SupportedCpu,
+) -> String {
+    url_template
+        .replace("{version}", version)
+        .replace("{os}", &os.to_string())
+        .replace("{cpu}", &cpu.to_string())
+}
+
+/// Recovers the version currently encoded in `url` by matching it against `url_template`.
+pub fn current_version(
+    url_template: &str,
+    url: &str,
+    os: &SupportedOs,
+    cpu: &SupportedCpu,
+) -> Option<String> {
+    let pattern = regex::escape(url_template)
+        .replace(r"\{os\}", &regex::escape(&os.to_string()))
+        .replace(r"\{cpu\}", &regex::escape(&cpu.to_string()))
+        .replacen(r"\{version\}", "(?P<version>.+?)", 1)
+        .replace(r"\{version\}", ".+?");
+    let regex = Regex::new(&format!("^{pattern}$")).ok()?;
+    regex
+        .captures(url)
+        .and_then(|captures| captures.name("version"))
+        .map(|version| version.as_str().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(version_json_path: Option<&str>, version_regex: Option<&str>) -> TemplateSource {
+        TemplateSource {
+            version_url: "https://dl.example.com/latest".to_owned(),
+            version_json_path: version_json_path.map(str::to_owned),
+            version_regex: version_regex.map(str::to_owned),
+            url_template: "https://dl.example.com/{version}/tool-{os}-{cpu}".to_owned(),
+        }
+    }
+
+    #[test]
+    fn extract_version_with_json_path() {
+        let body = r#"{"channels": {"stable": [{"version": "1.4.0"}, {"version": "1.3.9"}]}}"#;
+        let version = extract_version(&source(Some("$.channels.stable[0].version"), None), body);
+        assert_eq!(version.unwrap(), "1.4.0");
+
+        let version = extract_version(&source(Some("channels.stable.1.version"), None), body);
+        assert_eq!(version.unwrap(), "1.3.9");
+    }
+
+    #[test]
+    fn extract_version_with_json_path_missing_key() {
+        let body = r#"{"version": "1.4.0"}"#;
+        assert!(extract_version(&source(Some("$.tag"), None), body).is_err());
+    }
+
+    #[test]
+    fn extract_version_with_regex() {
+        let body = "<a href=\"tool-2.0.1.tar.gz\">tool-2.0.1.tar.gz</a>";
+        let version = extract_version(&source(None, Some(r"tool-(\d+\.\d+\.\d+)\.tar")), body);
+        assert_eq!(version.unwrap(), "2.0.1");
+
+        let version = extract_version(
+            &source(None, Some(r"(tool)-(?P<version>[0-9.]+)\.tar")),
+            body,
+        );
+        assert_eq!(version.unwrap(), "2.0.1");
+    }
+
+    #[test]
+    fn extract_version_with_regex_no_match() {
+        assert!(extract_version(&source(None, Some(r"v(\d+)")), "nothing here").is_err());
+    }
+
+    #[test]
+    fn extract_version_from_plain_text() {
+        assert_eq!(
+            extract_version(&source(None, None), "v1.31.0\n").unwrap(),
+            "v1.31.0"
+        );
+        assert!(extract_version(&source(None, None), " \n").is_err());
+    }
+
+    #[test]
+    fn extract_version_rejects_both_extractors() {
+        assert!(extract_version(&source(Some("$.version"), Some("(.*)")), "{}").is_err());
+    }
+
+    #[test]
+    fn render_and_recover_version() {
+        let template = "https://dl.example.com/{version}/tool-{version}-{os}-{cpu}.tar.gz";
+        let url = render_url(template, "1.2.3", &SupportedOs::Linux, &SupportedCpu::Arm64);
+        assert_eq!(
+            url,
+            "https://dl.example.com/1.2.3/tool-1.2.3-linux-arm64.tar.gz"
+        );
+        assert_eq!(
+            current_version(template, &url, &SupportedOs::Linux, &SupportedCpu::Arm64),
+            Some("1.2.3".to_owned())
+        );
+        assert_eq!(
+            current_version(template, &url, &SupportedOs::MacOS, &SupportedCpu::Arm64),
+            None
+        );
+    }
+}
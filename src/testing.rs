@@ -0,0 +1,211 @@
+//! A minimal HTTP/1.1 server for exercising network code paths in tests, and
+//! builders of the artifacts it serves.
+
+use crate::color::Palette;
+use crate::config::{Settings, DEFAULT_API_PAGE_SIZE, DEFAULT_MAX_REDIRECTS};
+use crate::lockfile::{PlatformNames, ToolOrder};
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex},
+    thread,
+};
+
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Header names are lowercased.
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn ok(body: impl Into<Vec<u8>>) -> Response {
+        Response {
+            status: 200,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn status(status: u16, body: impl Into<Vec<u8>>) -> Response {
+        Response {
+            status,
+            headers: vec![],
+            body: body.into(),
+        }
+    }
+
+    pub fn redirect(location: &str) -> Response {
+        Response::status(302, "").header("Location", location)
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+}
+
+/// Settings pointing the GitHub API at `github_api_url`, with a short timeout.
+pub fn settings(github_api_url: &str) -> Settings {
+    Settings {
+        github_api_url: github_api_url.to_owned(),
+        github_token: None,
+        github_token_source: None,
+        github_app: None,
+        host_tokens: Default::default(),
+        timeout: Some(std::time::Duration::from_secs(5)),
+        timeout_per_tool: None,
+        max_redirects: DEFAULT_MAX_REDIRECTS,
+        // throttled requests are retried without waiting
+        max_retry_wait: std::time::Duration::ZERO,
+        ca_bundle: None,
+        danger_accept_invalid_certs: false,
+        from_dir: None,
+        max_download_size: None,
+        cache_dir: None,
+        require_https: false,
+        allow_insecure_http: false,
+        retry_on_checksum_mismatch: false,
+        api_page_size: DEFAULT_API_PAGE_SIZE,
+        github_graphql: false,
+        select_asset_by: None,
+        prefer_api_assets: false,
+        verify_assets: false,
+        verify_signatures: false,
+        only_if_newer_published: false,
+        explain: false,
+        sort_tools: ToolOrder::default(),
+        platform_names: PlatformNames::default(),
+        palette: Palette::default(),
+        stderr_palette: Palette::default(),
+        verbose: false,
+    }
+}
+
+/// A tar archive holding `files`, as (path, contents).
+pub fn tar(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (path, contents) in files {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(contents.len() as u64);
+        header.set_mode(0o755);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, path, contents.as_bytes())
+            .unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+/// [`tar`], gzipped.
+pub fn tar_gz(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+    encoder.write_all(&tar(files)).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// A minisign public key made for these tests.
+pub const MINISIGN_PUBLIC_KEY: &str = "RWQBI0VniavN79AJoIkx5wiaj3Prh9sXBnKU8GyrsYTleHHcIZaLNB4P";
+
+/// The minisign signature of `hello` by the secret key of [`MINISIGN_PUBLIC_KEY`].
+pub const MINISIGN_SIGNED: &str = "untrusted comment: signature from minisign secret key
+RUQBI0VniavN72yx7V7rLLBeCKvlKdadAlFYa1qsd5vG9984j2WVo+/OUPsFdJ/oftoRmf5nNV9Vlarhdp7JttRDfpjyglf4eQ4=
+trusted comment: timestamp:1700000000\tfile:tool-linux
+/JB4zigmeu9gzme2Zq4lLsziG3wBkH+fhGbzts59n9owx8fgkh3GCKfHnaleYOvl5hpnbOZ600Zc3BhFjd+zAg==
+";
+
+type Handler = dyn Fn(&Request) -> Response + Send + Sync;
+
+/// Serves requests on an ephemeral local port until the process exits.
+pub struct Server {
+    pub url: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl Server {
+    pub fn start(handler: impl Fn(&Request) -> Response + Send + Sync + 'static) -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        let recorded = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let handler = handler.clone();
+                let recorded = recorded.clone();
+                thread::spawn(move || serve(stream, handler.as_ref(), &recorded));
+            }
+        });
+
+        Server { url, requests }
+    }
+
+    /// All requests received so far, in arrival order.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn serve(stream: TcpStream, handler: &Handler, recorded: &Mutex<Vec<Request>>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+    let mut line = String::new();
+    if reader.read_line(&mut line).unwrap_or(0) == 0 {
+        return;
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_owned();
+    let path = parts.next().unwrap_or_default().to_owned();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_owned());
+        }
+    }
+    let mut body = Vec::new();
+    if let Some(length) = headers.get("content-length").and_then(|l| l.parse().ok()) {
+        body.resize(length, 0);
+        let _ = reader.read_exact(&mut body);
+    }
+
+    let request = Request {
+        method,
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    };
+    recorded.lock().unwrap().push(request.clone());
+    let response = handler(&request);
+
+    let mut out = stream;
+    let mut head = format!(
+        "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+    let _ = out.write_all(head.as_bytes());
+    if request.method != "HEAD" {
+        let _ = out.write_all(&response.body);
+    }
+    let _ = out.flush();
+}
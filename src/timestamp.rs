@@ -0,0 +1,114 @@
+//! UTC timestamps in the `2024-01-02T03:04:05Z` form GitHub returns, without
+//! pulling in a date library.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the epoch of a UTC timestamp like `2024-01-02T03:04:05Z`.
+pub fn parse(timestamp: &str) -> Option<u64> {
+    let (date, time) = timestamp.strip_suffix('Z')?.split_once('T')?;
+    let numbers = |text: &str, separator| -> Option<Vec<u64>> {
+        text.split(separator)
+            .map(|part| part.parse().ok())
+            .collect()
+    };
+    let (date, time) = (numbers(date, '-')?, numbers(time, ':')?);
+    let (&[year, month, day], &[hour, minute, second]) = (date.as_slice(), time.as_slice()) else {
+        return None;
+    };
+    let in_range = (1..=12).contains(&month)
+        && (1..=31).contains(&day)
+        && year > 0
+        && hour < 24
+        && minute < 60
+        && second <= 60;
+    if !in_range {
+        return None;
+    }
+    // days from civil, shifting the year to start in March
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = (year % 400) * 365 + (year % 400) / 4 - (year % 400) / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+/// The UTC timestamp of `seconds` since the epoch, in the form [`parse`] reads.
+pub fn format(seconds: u64) -> String {
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+    // civil from days, with years starting in March
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let (year, month) = if month < 10 {
+        (era * 400 + year_of_era, month + 3)
+    } else {
+        (era * 400 + year_of_era + 1, month - 9)
+    };
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// The current time as a timestamp.
+pub fn now() -> String {
+    format(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_timestamps() {
+        assert_eq!(parse("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse("2016-07-11T22:14:10Z"), Some(1_468_275_250));
+        assert_eq!(parse("2024-02-29T12:00:00Z"), Some(1_709_208_000));
+        assert_eq!(parse("2024-02-29 12:00:00"), None);
+    }
+
+    #[test]
+    fn parse_rejects_out_of_range_fields() {
+        for timestamp in [
+            "2025-03-00T00:00:00Z",
+            "2025-00-01T00:00:00Z",
+            "2025-13-01T00:00:00Z",
+            "2025-01-32T00:00:00Z",
+            "0000-01-01T00:00:00Z",
+            "2025-01-01T24:00:00Z",
+            "2025-01-01T00:60:00Z",
+        ] {
+            assert_eq!(parse(timestamp), None, "{timestamp}");
+        }
+    }
+
+    #[test]
+    fn format_inverts_parse() {
+        for timestamp in [
+            "1970-01-01T00:00:00Z",
+            "2000-02-29T23:59:59Z",
+            "2016-07-11T22:14:10Z",
+            "2024-02-29T12:00:00Z",
+            "2100-03-01T00:00:01Z",
+        ] {
+            assert_eq!(format(parse(timestamp).unwrap()), timestamp);
+        }
+        assert!(parse(&now()).is_some());
+    }
+}
@@ -0,0 +1,137 @@
+//! `update --concurrency-report`: how long each binary spent resolving its
+//! latest version and downloading, to find the tools and hosts that slow a run.
+
+use crate::lockfile::{SupportedCpu, SupportedOs};
+use serde::{Serialize, Serializer};
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+/// How many of the slowest binaries the summary lists.
+const SLOWEST: usize = 10;
+
+fn millis<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+/// Where one binary's time went. Resolution is everything but the download:
+/// release lookups, asset listings and any other API requests.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct Timing {
+    #[serde(rename = "resolve_ms", serialize_with = "millis")]
+    pub resolve: Duration,
+    #[serde(rename = "download_ms", serialize_with = "millis")]
+    pub download: Duration,
+}
+
+impl Timing {
+    pub fn total(&self) -> Duration {
+        self.resolve + self.download
+    }
+}
+
+/// The timing of one binary, with its download host.
+pub struct Entry {
+    pub tool: String,
+    pub os: SupportedOs,
+    pub cpu: SupportedCpu,
+    pub host: String,
+    pub timing: Timing,
+}
+
+fn secs(duration: Duration) -> String {
+    format!("{:.2}s", duration.as_secs_f64())
+}
+
+/// The slowest binaries, slowest first, then the total time per download host.
+/// Equal times keep the order the binaries were updated in.
+pub fn render(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    if entries.is_empty() {
+        return out;
+    }
+    let mut slowest: Vec<&Entry> = entries.iter().collect();
+    slowest.sort_by_key(|entry| std::cmp::Reverse(entry.timing.total()));
+    writeln!(out, "Slowest binaries:").unwrap();
+    for entry in slowest.iter().take(SLOWEST) {
+        writeln!(
+            out,
+            "  {:>7}  {} ({}/{}) from {}: resolve {}, download {}",
+            secs(entry.timing.total()),
+            entry.tool,
+            entry.os,
+            entry.cpu,
+            entry.host,
+            secs(entry.timing.resolve),
+            secs(entry.timing.download)
+        )
+        .unwrap();
+    }
+
+    let mut hosts: BTreeMap<&str, (Timing, usize)> = BTreeMap::new();
+    for entry in entries {
+        let (timing, count) = hosts.entry(&entry.host).or_default();
+        timing.resolve += entry.timing.resolve;
+        timing.download += entry.timing.download;
+        *count += 1;
+    }
+    let mut hosts: Vec<(&str, (Timing, usize))> = hosts.into_iter().collect();
+    hosts.sort_by_key(|(_, (timing, _))| std::cmp::Reverse(timing.total()));
+    writeln!(out, "Time per host:").unwrap();
+    for (host, (timing, count)) in hosts {
+        writeln!(
+            out,
+            "  {:>7}  {host} ({count} binaries): resolve {}, download {}",
+            secs(timing.total()),
+            secs(timing.resolve),
+            secs(timing.download)
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(tool: &str, host: &str, resolve: u64, download: u64) -> Entry {
+        Entry {
+            tool: tool.to_owned(),
+            os: SupportedOs::Linux,
+            cpu: SupportedCpu::X86_64,
+            host: host.to_owned(),
+            timing: Timing {
+                resolve: Duration::from_millis(resolve),
+                download: Duration::from_millis(download),
+            },
+        }
+    }
+
+    #[test]
+    fn render_sorts_slowest_first_and_totals_hosts() {
+        let entries = [
+            entry("fast", "github.com", 100, 200),
+            entry("slow", "example.com", 250, 2000),
+            entry("medium", "github.com", 300, 900),
+        ];
+        assert_eq!(
+            render(&entries),
+            "Slowest binaries:\n\
+            \x20   2.25s  slow (linux/x86_64) from example.com: resolve 0.25s, download 2.00s\n\
+            \x20   1.20s  medium (linux/x86_64) from github.com: resolve 0.30s, download 0.90s\n\
+            \x20   0.30s  fast (linux/x86_64) from github.com: resolve 0.10s, download 0.20s\n\
+             Time per host:\n\
+            \x20   2.25s  example.com (1 binaries): resolve 0.25s, download 2.00s\n\
+            \x20   1.50s  github.com (2 binaries): resolve 0.40s, download 1.10s\n"
+        );
+        assert_eq!(render(&[]), "");
+    }
+
+    #[test]
+    fn timing_serializes_milliseconds() {
+        let timing = entry("tool", "github.com", 1500, 20).timing;
+        assert_eq!(
+            serde_json::to_string(&timing).unwrap(),
+            r#"{"resolve_ms":1500,"download_ms":20}"#
+        );
+    }
+}
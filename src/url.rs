@@ -0,0 +1,185 @@
+//! Canonical forms of artifact URLs, for `update --normalize-urls`, so that
+//! equivalent URLs are written identically.
+
+use crate::lockfile::{Binary, Lockfile};
+
+/// Characters RFC 3986 never requires to be percent-encoded.
+fn is_unreserved(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~')
+}
+
+/// Decodes escapes of unreserved characters and uppercases the hex digits of
+/// every other escape.
+fn normalize_escapes(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes
+            .get(i + 1..i + 3)
+            .filter(|hex| bytes[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit));
+        match escape {
+            Some(hex) => {
+                let byte = u8::from_str_radix(std::str::from_utf8(hex).unwrap(), 16).unwrap();
+                if is_unreserved(byte) {
+                    out.push(byte);
+                } else {
+                    out.push(b'%');
+                    out.extend(hex.to_ascii_uppercase());
+                }
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    // only ASCII sequences were replaced, by ASCII
+    String::from_utf8(out).unwrap()
+}
+
+/// Resolves `.` and `..` segments; `..` never climbs above the root.
+fn remove_dot_segments(path: &str) -> String {
+    let segments: Vec<&str> = path.split('/').collect();
+    let mut out: Vec<&str> = Vec::with_capacity(segments.len());
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        match *segment {
+            "." => {}
+            ".." => {
+                if out.len() > 1 {
+                    out.pop();
+                }
+            }
+            segment => {
+                out.push(segment);
+                continue;
+            }
+        }
+        // a trailing dot segment still names a directory
+        if last {
+            out.push("");
+        }
+    }
+    out.join("/")
+}
+
+/// The canonical form of `url`: lowercase scheme and host, no default port,
+/// normalized percent-encoding and no dot segments. Anything else, including
+/// the case of the path and so its version token, is kept. Text that isn't an
+/// absolute URL is returned unchanged.
+pub fn normalize(url: &str) -> String {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return url.to_owned();
+    };
+    let scheme = scheme.to_ascii_lowercase();
+    let (authority, rest) = rest.split_at(rest.find(['/', '?', '#']).unwrap_or(rest.len()));
+    let (userinfo, host) = match authority.rsplit_once('@') {
+        Some((userinfo, host)) => (Some(userinfo), host),
+        None => (None, authority),
+    };
+    let (host, port) = match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|byte| byte.is_ascii_digit()) => (name, port),
+        _ => (host, ""),
+    };
+    let default_port = match scheme.as_str() {
+        "http" => "80",
+        "https" => "443",
+        _ => "",
+    };
+    let (path, tail) = rest.split_at(rest.find(['?', '#']).unwrap_or(rest.len()));
+
+    let mut out = format!("{scheme}://");
+    if let Some(userinfo) = userinfo {
+        out.push_str(&normalize_escapes(userinfo));
+        out.push('@');
+    }
+    out.push_str(&host.to_ascii_lowercase());
+    if !port.is_empty() && port != default_port {
+        out.push(':');
+        out.push_str(port);
+    }
+    out.push_str(&remove_dot_segments(&normalize_escapes(path)));
+    out.push_str(&normalize_escapes(tail));
+    out
+}
+
+/// Normalizes the URL and parts of every binary, returning how many changed.
+pub fn normalize_lockfile(lockfile: &mut Lockfile) -> usize {
+    let mut changed = 0;
+    let mut normalize_in_place = |url: &mut String| {
+        let normalized = normalize(url);
+        if normalized != *url {
+            *url = normalized;
+            changed += 1;
+        }
+    };
+    for definition in lockfile.tools.values_mut() {
+        for binary in &mut definition.binaries {
+            let (url, parts) = match binary {
+                Binary::File(binary) => (&mut binary.url, None),
+                Binary::Archive(binary) => (&mut binary.url, binary.parts.as_mut()),
+                Binary::Pkg(binary) => (&mut binary.url, None),
+                Binary::Dir(binary) => (&mut binary.url, binary.parts.as_mut()),
+            };
+            normalize_in_place(url);
+            parts
+                .into_iter()
+                .flatten()
+                .for_each(&mut normalize_in_place);
+        }
+    }
+    changed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equivalent_urls_normalize_identically() {
+        let pairs = [
+            (
+                "HTTPS://GitHub.COM:443/org/tool/releases/download/v1.2.3/tool-linux",
+                "https://github.com/org/tool/releases/download/v1.2.3/tool-linux",
+            ),
+            (
+                "https://example.com/dl/./v1.2.3/../v1.2.3/tool%2dlinux%7e",
+                "https://example.com/dl/v1.2.3/tool-linux~",
+            ),
+            (
+                "https://example.com/dl/v1.2.3+build/tool%2flinux?sig=%3d%3D#top",
+                "https://example.com/dl/v1.2.3+build/tool%2Flinux?sig=%3D%3D#top",
+            ),
+            (
+                "http://User@Example.com:80/../V1.2.3/",
+                "http://User@example.com/V1.2.3/",
+            ),
+        ];
+        for (messy, clean) in pairs {
+            assert_eq!(normalize(messy), normalize(clean), "{messy}");
+            assert_eq!(normalize(clean), clean);
+        }
+    }
+
+    #[test]
+    fn normalize_keeps_meaningful_parts() {
+        for url in [
+            "https://example.com:8443/a//b/Tool-1.2.3.tar.gz",
+            "https://example.com/a/%25/%E2%9C%93",
+            "https://example.com",
+            "file:relative/path",
+        ] {
+            assert_eq!(normalize(url), url);
+        }
+        assert_eq!(
+            normalize("https://example.com/a/b/."),
+            "https://example.com/a/b/"
+        );
+        assert_eq!(
+            normalize("https://example.com/a/%"),
+            "https://example.com/a/%"
+        );
+    }
+}
@@ -0,0 +1,889 @@
+use crate::{
+    archive::{self, Format},
+    cache::DigestCache,
+    color::Palette,
+    config::Settings,
+    download,
+    error::Error,
+    exit::ExitCode,
+    filter::ToolFilter,
+    github,
+    lockfile::{Binary, Lockfile, SupportedCpu, SupportedOs},
+    Common,
+};
+use serde::Serialize;
+use std::{
+    fmt::Write,
+    fs::{self, File},
+    io::Cursor,
+    path::{Path, PathBuf},
+    sync::atomic::{self, AtomicUsize},
+};
+
+/// A binary whose artifact doesn't match the lockfile.
+pub enum Problem {
+    /// The artifact was downloaded but hashes differently; the lockfile or the
+    /// upstream artifact has changed.
+    Mismatch { expected: String, actual: String },
+    /// With `--head-only`, the artifact's size differs from the recorded `size`.
+    SizeChanged { expected: u64, actual: u64 },
+    /// With `--head-only`, the artifact's ETag differs from the one its cached
+    /// digest was computed under.
+    EtagChanged { cached: String, actual: String },
+    /// With `--deep`, a path the binary names within its archive isn't there.
+    /// `within` is the inner archive holding it, for nested archives.
+    MissingPath {
+        path: String,
+        within: Option<String>,
+    },
+    /// With `--deep`, the artifact matched but couldn't be read as its archive type.
+    BadArchive(String),
+    /// The artifact couldn't be fetched, so nothing is known about its digest.
+    DownloadFailed(String),
+}
+
+impl Problem {
+    /// Whether the artifact was reached and found to differ, as opposed to being
+    /// unreachable.
+    fn is_mismatch(&self) -> bool {
+        !matches!(self, Problem::DownloadFailed(_))
+    }
+
+    /// Whether the artifact matched but its archive's contents didn't.
+    fn is_missing_path(&self) -> bool {
+        matches!(self, Problem::MissingPath { .. } | Problem::BadArchive(_))
+    }
+}
+
+/// The result of checking one binary.
+pub struct Outcome {
+    pub tool: String,
+    pub os: SupportedOs,
+    pub cpu: SupportedCpu,
+    pub url: String,
+    pub expected: String,
+    /// The artifact's digest, when it was computed.
+    pub actual: Option<String>,
+    /// `None` when the binary verified.
+    pub problem: Option<Problem>,
+    /// With `--deep`, the binary verified but its archive is of a type that
+    /// can't be looked inside, so the paths within it weren't checked.
+    pub paths_unchecked: bool,
+}
+
+#[derive(Default)]
+pub struct VerifyResult {
+    pub outcomes: Vec<Outcome>,
+    /// Whether artifacts were only checked by their headers, without hashing.
+    pub head_only: bool,
+    /// Whether archives were also checked for the paths within them.
+    pub deep: bool,
+}
+
+impl VerifyResult {
+    pub fn verified(&self) -> usize {
+        self.outcomes.len() - self.failures().count()
+    }
+
+    fn failures(&self) -> impl Iterator<Item = (&Outcome, &Problem)> {
+        self.outcomes
+            .iter()
+            .filter_map(|outcome| Some((outcome, outcome.problem.as_ref()?)))
+    }
+
+    pub fn mismatches(&self) -> usize {
+        self.failures()
+            .filter(|(_, problem)| problem.is_mismatch() && !problem.is_missing_path())
+            .count()
+    }
+
+    pub fn missing_paths(&self) -> usize {
+        self.failures()
+            .filter(|(_, problem)| problem.is_missing_path())
+            .count()
+    }
+
+    pub fn paths_unchecked(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|outcome| outcome.paths_unchecked)
+            .count()
+    }
+
+    pub fn download_failures(&self) -> usize {
+        self.failures().count() - self.mismatches() - self.missing_paths()
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.failures().next().is_none()
+    }
+
+    /// A mismatch, including a missing path, takes precedence over download failures.
+    pub fn exit_code(&self) -> ExitCode {
+        if self.mismatches() > 0 || self.missing_paths() > 0 {
+            ExitCode::VerificationMismatch
+        } else if self.download_failures() > 0 {
+            ExitCode::NetworkError
+        } else {
+            ExitCode::Success
+        }
+    }
+}
+
+/// How [`verify`] checks each binary.
+#[derive(Clone, Copy, Default)]
+pub struct VerifyOptions {
+    /// Stop at the first mismatch; download failures never stop verification.
+    pub fail_fast: bool,
+    /// Download and hash nothing. Each artifact is only checked to still exist
+    /// with its recorded `size` and, when its digest is cached, the same ETag;
+    /// see [`check_headers`].
+    pub head_only: bool,
+    /// Download every artifact in full, bypassing the digest cache, and also
+    /// check a matching archive for the paths its binary names within it; see
+    /// [`check_deep`].
+    pub deep: bool,
+}
+
+/// Downloads (or reads from `--from-dir`) every selected binary and compares its
+/// sha256 with the lockfile, as `options` say.
+pub fn verify(
+    lockfile: &Lockfile,
+    settings: &Settings,
+    filter: &ToolFilter,
+    options: VerifyOptions,
+) -> VerifyResult {
+    let VerifyOptions {
+        fail_fast,
+        head_only,
+        deep,
+    } = options;
+    let client = settings.client();
+    let mut digests = DigestCache::load(settings.cache_dir.as_deref());
+    let mut result = VerifyResult {
+        head_only,
+        deep,
+        ..VerifyResult::default()
+    };
+    let spool = spool_path();
+
+    'tools: for (tool, definition) in &lockfile.tools {
+        if !filter.selects(tool, definition) {
+            continue;
+        }
+
+        for binary in &definition.binaries {
+            if !filter.selects_binary(tool, binary) {
+                continue;
+            }
+            let outcome = |actual, problem: Option<Problem>| Outcome {
+                tool: tool.clone(),
+                os: binary.os().clone(),
+                cpu: binary.cpu().clone(),
+                url: binary.url().to_owned(),
+                expected: binary.sha256().to_owned(),
+                actual,
+                paths_unchecked: deep && problem.is_none() && !looks_inside(binary),
+                problem,
+            };
+            if head_only {
+                let problem = check_headers(&client, settings, &digests, binary);
+                let stop = fail_fast && problem.as_ref().is_some_and(Problem::is_mismatch);
+                result.outcomes.push(outcome(None, problem));
+                if stop {
+                    break 'tools;
+                }
+                continue;
+            }
+            if deep {
+                let (actual, problem) = check_deep(&client, settings, tool, binary, &spool);
+                let stop = fail_fast && problem.as_ref().is_some_and(Problem::is_mismatch);
+                result.outcomes.push(outcome(actual, problem));
+                if stop {
+                    break 'tools;
+                }
+                continue;
+            }
+            // a download of another size is only warned about; its sha256 decides
+            let request = download::DigestRequest::of(binary);
+            let digest = download::compute_binary_digest(&client, settings, &mut digests, request);
+            if let Ok(digest) = &digest {
+                download::warn_if_resized(tool, binary, digest);
+            }
+            let (actual, problem) = match digest.map(|digest| digest.sha256) {
+                Ok(actual) if actual == binary.sha256() => (Some(actual), None),
+                Ok(actual) => {
+                    github::warn_if_source_archive(binary.url());
+                    (
+                        Some(actual.clone()),
+                        Some(Problem::Mismatch {
+                            expected: binary.sha256().to_owned(),
+                            actual,
+                        }),
+                    )
+                }
+                Err(e) => (None, Some(Problem::DownloadFailed(e.to_string()))),
+            };
+            let stop = fail_fast && problem.as_ref().is_some_and(Problem::is_mismatch);
+            result.outcomes.push(outcome(actual, problem));
+            if stop {
+                break 'tools;
+            }
+        }
+    }
+
+    if let Err(e) = digests.save() {
+        eprintln!("Unable to save digest cache: {e}");
+    }
+    result
+}
+
+/// Downloads `binary` to the file at `spool`, rather than into memory, and
+/// compares its digest; a matching archive is then read back from the file to
+/// check the paths within it. Returns the digest, when it was computed, and the
+/// problem found. The file is removed afterwards.
+fn check_deep(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    tool: &str,
+    binary: &Binary,
+    spool: &Path,
+) -> (Option<String>, Option<Problem>) {
+    let checked = {
+        let fetched = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(spool)
+            .map_err(|e| Error::from(format!("Unable to create {}: {e}", spool.display())))
+            .and_then(|mut file| {
+                let digest = download::fetch_binary_to(client, settings, binary, None, &mut file)?;
+                download::warn_if_resized(tool, binary, &digest);
+                Ok((digest.sha256, file))
+            });
+        match fetched {
+            Ok((actual, mut file)) if actual == binary.sha256() => {
+                (Some(actual), check_paths(binary, &mut file))
+            }
+            Ok((actual, _)) => (
+                Some(actual.clone()),
+                Some(Problem::Mismatch {
+                    expected: binary.sha256().to_owned(),
+                    actual,
+                }),
+            ),
+            Err(e) => (None, Some(Problem::DownloadFailed(e.to_string()))),
+        }
+    };
+    let _ = fs::remove_file(spool);
+    checked
+}
+
+/// A path in the temporary directory for [`check_deep`] to download to, unique
+/// to this call of [`verify`].
+fn spool_path() -> PathBuf {
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    let call = CALLS.fetch_add(1, atomic::Ordering::Relaxed);
+    std::env::temp_dir().join(format!("multitool-verify-{}-{call}", std::process::id()))
+}
+
+/// Whether `--deep` can check the paths `binary` names within its archive: not
+/// for pkgs, nor for archive types that can't be looked inside, such as `.deb`.
+fn looks_inside(binary: &Binary) -> bool {
+    match binary {
+        Binary::Archive(archive) => {
+            Format::of(archive.type_.as_deref(), &archive.url).is_some()
+                && archive.inner.as_ref().map_or(true, |inner| {
+                    Format::of(inner.type_.as_deref(), &archive.file).is_some()
+                })
+        }
+        Binary::Dir(dir) => {
+            dir.root.is_none() || Format::of(dir.type_.as_deref(), &dir.url).is_some()
+        }
+        Binary::Pkg(_) => false,
+        Binary::File(_) => true,
+    }
+}
+
+/// Checks that the archive `binary` downloaded as `file` holds the paths the
+/// binary names within it: an archive's `file` (and, when nested, the inner
+/// archive's `file`) or a directory's `root`. Files, pkgs and archive types that
+/// can't be looked inside pass here; [`looks_inside`] tells them apart.
+fn check_paths(binary: &Binary, file: &mut File) -> Option<Problem> {
+    let missing = |path: &str, within: Option<&str>| Problem::MissingPath {
+        path: path.to_owned(),
+        within: within.map(str::to_owned),
+    };
+    match binary {
+        Binary::Archive(archive) => {
+            let format = Format::of(archive.type_.as_deref(), &archive.url)?;
+            let contents = match archive::read_file(&mut *file, format, &archive.file) {
+                Ok(Some(contents)) => contents,
+                Ok(None) => return Some(missing(&archive.file, None)),
+                Err(e) => return Some(Problem::BadArchive(e)),
+            };
+            let inner = archive.inner.as_ref()?;
+            let format = Format::of(inner.type_.as_deref(), &archive.file)?;
+            match archive::read_file(Cursor::new(contents), format, &inner.file) {
+                Ok(Some(_)) => None,
+                Ok(None) => Some(missing(&inner.file, Some(&archive.file))),
+                Err(e) => Some(Problem::BadArchive(format!("{}: {e}", archive.file))),
+            }
+        }
+        Binary::Dir(dir) => {
+            let root = dir.root.as_deref()?;
+            let format = Format::of(dir.type_.as_deref(), &dir.url)?;
+            match archive::has_dir(file, format, root) {
+                Ok(true) => None,
+                Ok(false) => Some(missing(root, None)),
+                Err(e) => Some(Problem::BadArchive(e)),
+            }
+        }
+        Binary::File(_) | Binary::Pkg(_) => None,
+    }
+}
+
+/// Checks `binary` by its artifact's response headers alone, returning the
+/// problem found, if any. The size of a split artifact is the sum of its parts'.
+fn check_headers(
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    digests: &DigestCache,
+    binary: &Binary,
+) -> Option<Problem> {
+    let urls = binary
+        .parts()
+        .map_or_else(|| vec![binary.url().to_owned()], <[String]>::to_vec);
+    let mut size = Some(0);
+    for url in &urls {
+        let metadata = match download::fetch_metadata(client, settings, url, binary.headers()) {
+            Ok(metadata) => metadata,
+            Err(e) => return Some(Problem::DownloadFailed(e.to_string())),
+        };
+        size = size.zip(metadata.size).map(|(sum, size)| sum + size);
+        let cached = digests.get(url).map(|cached| &cached.etag);
+        if let (Some(cached), Some(actual)) = (cached, metadata.etag) {
+            if *cached != actual {
+                return Some(Problem::EtagChanged {
+                    cached: cached.clone(),
+                    actual,
+                });
+            }
+        }
+    }
+    match (binary.size(), size) {
+        (Some(expected), Some(actual)) if expected != actual => {
+            Some(Problem::SizeChanged { expected, actual })
+        }
+        _ => None,
+    }
+}
+
+/// Renders every failure followed by a one-line summary.
+pub fn render_text(result: &VerifyResult, palette: Palette) -> String {
+    let mut out = String::new();
+    for (failure, problem) in result.failures() {
+        let platform = format!("{} ({}/{})", failure.tool, failure.os, failure.cpu);
+        match problem {
+            Problem::Mismatch { expected, actual } => writeln!(
+                out,
+                "{} {platform}: expected {expected}, got {actual} from {}",
+                palette.red("sha256 mismatch:"),
+                failure.url
+            ),
+            Problem::SizeChanged { expected, actual } => writeln!(
+                out,
+                "{} {platform}: recorded {expected} bytes, got {actual} from {}",
+                palette.red("size mismatch:"),
+                failure.url
+            ),
+            Problem::EtagChanged { cached, actual } => writeln!(
+                out,
+                "{} {platform}: hashed under ETag {cached}, now {actual} at {}",
+                palette.red("ETag changed:"),
+                failure.url
+            ),
+            Problem::MissingPath { path, within } => writeln!(
+                out,
+                "{} {platform}: no {path} in {}",
+                palette.red("missing path:"),
+                within.as_deref().unwrap_or(&failure.url)
+            ),
+            Problem::BadArchive(e) => writeln!(
+                out,
+                "{} {platform}: {e} from {}",
+                palette.red("unreadable archive:"),
+                failure.url
+            ),
+            Problem::DownloadFailed(e) => {
+                writeln!(
+                    out,
+                    "{} {platform}: {e}",
+                    palette.yellow("download failed:")
+                )
+            }
+        }
+        .unwrap();
+    }
+    for outcome in result
+        .outcomes
+        .iter()
+        .filter(|outcome| outcome.paths_unchecked)
+    {
+        writeln!(
+            out,
+            "{} {} ({}/{}): can't look inside {}",
+            palette.yellow("not checked:"),
+            outcome.tool,
+            outcome.os,
+            outcome.cpu,
+            outcome.url
+        )
+        .unwrap();
+    }
+    write!(
+        out,
+        "{} verified{}, {} mismatched, ",
+        result.verified(),
+        if result.head_only {
+            " by headers only (digests not recomputed)"
+        } else {
+            ""
+        },
+        result.mismatches(),
+    )
+    .unwrap();
+    if result.deep {
+        write!(
+            out,
+            "{} missing inner paths, {} not looked inside, ",
+            result.missing_paths(),
+            result.paths_unchecked()
+        )
+        .unwrap();
+    }
+    writeln!(out, "{} failed to download", result.download_failures()).unwrap();
+    out
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+    Ok,
+    Mismatch,
+    /// With `--deep`, the artifact matched but a path within it is missing or
+    /// its archive is unreadable.
+    MissingPath,
+    DownloadError,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    tool: &'a str,
+    os: &'a SupportedOs,
+    cpu: &'a SupportedCpu,
+    url: &'a str,
+    expected_sha: &'a str,
+    /// `null` unless the artifact was hashed.
+    actual_sha: Option<&'a str>,
+    status: Status,
+    /// What differed, why the download failed, or with `--deep` why the paths
+    /// within the archive weren't checked.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+/// Renders one record per checked binary as a JSON array, for `verify --json`.
+pub fn render_json(result: &VerifyResult) -> String {
+    let records: Vec<Record> = result
+        .outcomes
+        .iter()
+        .map(|outcome| {
+            let (status, detail) = match &outcome.problem {
+                None if outcome.paths_unchecked => (
+                    Status::Ok,
+                    Some("not checked: can't look inside this archive type".to_owned()),
+                ),
+                None => (Status::Ok, None),
+                Some(Problem::Mismatch { .. }) => (Status::Mismatch, None),
+                Some(Problem::SizeChanged { expected, actual }) => (
+                    Status::Mismatch,
+                    Some(format!("recorded {expected} bytes, got {actual}")),
+                ),
+                Some(Problem::EtagChanged { cached, actual }) => (
+                    Status::Mismatch,
+                    Some(format!("hashed under ETag {cached}, now {actual}")),
+                ),
+                Some(Problem::MissingPath { path, within }) => (
+                    Status::MissingPath,
+                    Some(match within {
+                        Some(within) => format!("no {path} in {within}"),
+                        None => format!("no {path} in the archive"),
+                    }),
+                ),
+                Some(Problem::BadArchive(e)) => (Status::MissingPath, Some(e.clone())),
+                Some(Problem::DownloadFailed(e)) => (Status::DownloadError, Some(e.clone())),
+            };
+            Record {
+                tool: &outcome.tool,
+                os: &outcome.os,
+                cpu: &outcome.cpu,
+                url: &outcome.url,
+                expected_sha: &outcome.expected,
+                actual_sha: outcome.actual.as_deref(),
+                status,
+                detail,
+            }
+        })
+        .collect();
+    serde_json::to_string_pretty(&records).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, settings, Response, Server};
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    fn lockfile(server: &Server) -> Lockfile {
+        let binary = |name: &str, os: &str| {
+            format!(
+                r#"{{"kind": "file", "url": "{}/{name}", "sha256": "{HELLO_SHA256}", "os": "{os}", "cpu": "x86_64"}}"#,
+                server.url
+            )
+        };
+        serde_json::from_str(&format!(
+            r#"{{
+              "a": {{ "binaries": [{}, {}] }},
+              "b": {{ "binaries": [{}, {}] }},
+              "c": {{ "binaries": [{}, {}] }}
+            }}"#,
+            binary("a-linux", "linux"),
+            binary("a-macos", "macos"),
+            binary("tampered", "linux"),
+            binary("b-macos", "macos"),
+            binary("c-linux", "linux"),
+            binary("missing", "macos"),
+        ))
+        .unwrap()
+    }
+
+    fn artifacts() -> Server {
+        Server::start(|request| match request.path.as_str() {
+            "/tampered" => Response::ok("goodbye"),
+            "/missing" => Response::status(404, ""),
+            _ => Response::ok("hello"),
+        })
+    }
+
+    #[test]
+    fn verify_reports_every_failure() {
+        let server = artifacts();
+        let settings = settings(&server.url);
+        let result = verify(
+            &lockfile(&server),
+            &settings,
+            &ToolFilter::default(),
+            VerifyOptions::default(),
+        );
+
+        assert!(!result.is_ok());
+        assert_eq!(result.exit_code(), ExitCode::VerificationMismatch);
+        assert_eq!(result.verified(), 4);
+        assert_eq!(result.mismatches(), 1);
+        assert_eq!(result.download_failures(), 1);
+
+        let report = render_text(&result, Palette::default());
+        assert!(report.contains(&format!(
+            "sha256 mismatch: b (linux/x86_64): expected {HELLO_SHA256}, got {}",
+            sha256::digest("goodbye")
+        )));
+        assert!(report.contains("download failed: c (macos/x86_64): HTTP 404"));
+        assert!(report.ends_with("4 verified, 1 mismatched, 1 failed to download\n"));
+        assert_eq!(server.requests().len(), 6);
+    }
+
+    #[test]
+    fn verify_json_has_a_record_per_binary() {
+        let server = artifacts();
+        let settings = settings(&server.url);
+        let result = verify(
+            &lockfile(&server),
+            &settings,
+            &ToolFilter::default(),
+            VerifyOptions::default(),
+        );
+
+        let records: Vec<serde_json::Value> = serde_json::from_str(&render_json(&result)).unwrap();
+        let statuses: Vec<&str> = records
+            .iter()
+            .map(|record| record["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            statuses,
+            ["ok", "ok", "mismatch", "ok", "ok", "download_error"]
+        );
+        assert_eq!(
+            records[2],
+            serde_json::json!({
+                "tool": "b",
+                "os": "linux",
+                "cpu": "x86_64",
+                "url": format!("{}/tampered", server.url),
+                "expected_sha": HELLO_SHA256,
+                "actual_sha": sha256::digest("goodbye"),
+                "status": "mismatch",
+            })
+        );
+        assert_eq!(records[0]["actual_sha"], HELLO_SHA256);
+        assert!(records[5]["actual_sha"].is_null());
+        assert!(records[5]["detail"].as_str().unwrap().contains("HTTP 404"));
+    }
+
+    #[test]
+    fn verify_fail_fast_stops_at_first_mismatch() {
+        let server = artifacts();
+        let settings = settings(&server.url);
+        let result = verify(
+            &lockfile(&server),
+            &settings,
+            &ToolFilter::default(),
+            VerifyOptions {
+                fail_fast: true,
+                ..VerifyOptions::default()
+            },
+        );
+
+        assert_eq!(result.verified(), 2);
+        assert_eq!(result.mismatches(), 1);
+        assert_eq!(result.download_failures(), 0);
+        assert_eq!(server.requests().len(), 3);
+    }
+
+    #[test]
+    fn verify_warns_of_size_changes() {
+        let server = artifacts();
+        let settings = settings(&server.url);
+        let lockfile = |name: &str, size: u64| -> Lockfile {
+            serde_json::from_str(&format!(
+                r#"{{"a": {{"binaries": [{{"kind": "file", "url": "{}/{name}", "sha256": "{HELLO_SHA256}", "size": {size}, "os": "linux", "cpu": "x86_64"}}]}}}}"#,
+                server.url
+            ))
+            .unwrap()
+        };
+        for deep in [false, true] {
+            let verify = |lockfile: &Lockfile| {
+                let before = crate::warn::emitted();
+                let result = verify(
+                    lockfile,
+                    &settings,
+                    &ToolFilter::default(),
+                    VerifyOptions {
+                        deep,
+                        ..VerifyOptions::default()
+                    },
+                );
+                assert!(crate::warn::emitted() > before);
+                result
+            };
+
+            // the size only warns; the sha256 decides
+            let result = verify(&lockfile("tampered", 5));
+            assert_eq!(result.exit_code(), ExitCode::VerificationMismatch);
+            assert_eq!(result.mismatches(), 1);
+            let result = verify(&lockfile("hello", 9));
+            assert_eq!(result.exit_code(), ExitCode::Success);
+            assert_eq!(result.verified(), 1);
+        }
+    }
+
+    #[test]
+    fn verify_exit_codes() {
+        let server = artifacts();
+        let settings = settings(&server.url);
+        let verify = |tool: &str| {
+            let filter = ToolFilter {
+                tool: Some(tool.to_owned()),
+                ..ToolFilter::default()
+            };
+            verify(
+                &lockfile(&server),
+                &settings,
+                &filter,
+                VerifyOptions::default(),
+            )
+            .exit_code()
+        };
+        assert_eq!(verify("a"), ExitCode::Success);
+        assert_eq!(verify("b"), ExitCode::VerificationMismatch);
+        assert_eq!(verify("c"), ExitCode::NetworkError);
+    }
+
+    #[test]
+    fn verify_head_only_compares_size_and_etag() {
+        let server = Server::start(|request| {
+            assert_eq!(request.method, "HEAD");
+            match request.path.as_str() {
+                "/missing" => Response::status(404, ""),
+                "/replaced" => Response::ok("hello").header("ETag", "\"v2\""),
+                _ => Response::ok("hello").header("ETag", "\"v1\""),
+            }
+        });
+        let dir = std::env::temp_dir().join(format!("multitool-head-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut digests = DigestCache::load(Some(&dir));
+        for name in ["same", "replaced"] {
+            let digest = crate::cache::CachedDigest {
+                etag: "\"v1\"".to_owned(),
+                sha256: HELLO_SHA256.to_owned(),
+                size: Some(5),
+            };
+            digests.insert(&format!("{}/{name}", server.url), digest);
+        }
+        digests.save().unwrap();
+
+        let binary = |name: &str, size: u64| {
+            format!(
+                r#"{{"kind": "file", "url": "{}/{name}", "sha256": "{HELLO_SHA256}", "size": {size}, "os": "linux", "cpu": "x86_64"}}"#,
+                server.url
+            )
+        };
+        let lockfile: Lockfile = serde_json::from_str(&format!(
+            r#"{{
+              "same": {{ "binaries": [{}] }},
+              "resized": {{ "binaries": [{}] }},
+              "replaced": {{ "binaries": [{}] }},
+              "missing": {{ "binaries": [{}] }}
+            }}"#,
+            binary("same", 5),
+            binary("resized", 6),
+            binary("replaced", 5),
+            binary("missing", 5),
+        ))
+        .unwrap();
+        let settings = Settings {
+            cache_dir: Some(dir.clone()),
+            ..settings(&server.url)
+        };
+        let result = verify(
+            &lockfile,
+            &settings,
+            &ToolFilter::default(),
+            VerifyOptions {
+                head_only: true,
+                ..VerifyOptions::default()
+            },
+        );
+
+        assert_eq!(result.verified(), 1);
+        assert_eq!(result.mismatches(), 2);
+        assert_eq!(result.download_failures(), 1);
+        let report = render_text(&result, Palette::default());
+        assert!(report.contains("size mismatch: resized (linux/x86_64): recorded 6 bytes, got 5"));
+        assert!(report.contains(
+            r#"ETag changed: replaced (linux/x86_64): hashed under ETag "v1", now "v2""#
+        ));
+        assert!(report.ends_with(
+            "1 verified by headers only (digests not recomputed), 2 mismatched, 1 failed to download\n"
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn verify_deep_reports_missing_paths() {
+        let archive = testing::tar_gz(&[("tool-1.2.3/bin/tool", "hello")]);
+        let nested = testing::tar_gz(&[("tool-linux.tar.gz", "not an archive")]);
+        let (sha, nested_sha) = (sha256::digest(&archive), sha256::digest(&nested));
+        let server = Server::start(move |request| match request.path.as_str() {
+            "/nested.tar.gz" => Response::ok(nested.clone()),
+            _ => Response::ok(archive.clone()),
+        });
+        let binary = |kind: &str, name: &str, sha: &str, extra: &str| {
+            format!(
+                r#"{{"kind": "{kind}", "url": "{}/{name}", "sha256": "{sha}", "os": "linux", "cpu": "x86_64"{extra}}}"#,
+                server.url
+            )
+        };
+        let lockfile: Lockfile = serde_json::from_str(&format!(
+            r#"{{
+              "good": {{ "binaries": [{}] }},
+              "wrong": {{ "binaries": [{}] }},
+              "dir": {{ "binaries": [{}] }},
+              "nested": {{ "binaries": [{}] }},
+              "deb": {{ "binaries": [{}] }}
+            }}"#,
+            binary(
+                "archive",
+                "tool.tar.gz",
+                &sha,
+                r#", "file": "./tool-1.2.3/bin/tool""#
+            ),
+            binary(
+                "archive",
+                "tool.tar.gz",
+                &sha,
+                r#", "file": "tool-1.2.4/bin/tool""#
+            ),
+            binary("dir", "tool.tar.gz", &sha, r#", "root": "tool-1.2.3""#),
+            binary(
+                "archive",
+                "nested.tar.gz",
+                &nested_sha,
+                r#", "file": "tool-linux.tar.gz", "inner": {"file": "tool"}"#
+            ),
+            binary("archive", "tool.deb", &sha, r#", "file": "tool""#),
+        ))
+        .unwrap();
+        let settings = settings(&server.url);
+        let result = verify(
+            &lockfile,
+            &settings,
+            &ToolFilter::default(),
+            VerifyOptions {
+                deep: true,
+                ..VerifyOptions::default()
+            },
+        );
+
+        assert_eq!(result.verified(), 3);
+        assert_eq!(result.mismatches(), 0);
+        assert_eq!(result.missing_paths(), 2);
+        assert_eq!(result.paths_unchecked(), 1);
+        assert_eq!(result.exit_code(), ExitCode::VerificationMismatch);
+        let report = render_text(&result, Palette::default());
+        assert!(report.contains(&format!(
+            "missing path: wrong (linux/x86_64): no tool-1.2.4/bin/tool in {}/tool.tar.gz",
+            server.url
+        )));
+        assert!(report.contains("unreadable archive: nested (linux/x86_64): tool-linux.tar.gz: "));
+        assert!(report.contains(&format!(
+            "not checked: deb (linux/x86_64): can't look inside {}/tool.deb",
+            server.url
+        )));
+        assert!(report.ends_with(
+            "3 verified, 0 mismatched, 2 missing inner paths, 1 not looked inside, 0 failed to download\n"
+        ));
+        let records: Vec<serde_json::Value> = serde_json::from_str(&render_json(&result)).unwrap();
+        assert_eq!(records[0]["status"], "ok");
+        assert_eq!(
+            records[0]["detail"],
+            "not checked: can't look inside this archive type"
+        );
+        assert_eq!(records[4]["status"], "missing_path");
+        assert_eq!(
+            records[4]["detail"],
+            "no tool-1.2.4/bin/tool in the archive"
+        );
+
+        // without --deep only the digests are compared
+        let result = verify(
+            &lockfile,
+            &settings,
+            &ToolFilter::default(),
+            VerifyOptions::default(),
+        );
+        assert!(result.is_ok());
+    }
+}
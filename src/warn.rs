@@ -0,0 +1,47 @@
+//! Warnings, printed to stderr and counted so `--fail-on-warn` can fail a run
+//! that emitted any.
+
+use crate::exit::ExitCode;
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static EMITTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Prints `message` as a warning and counts it.
+pub fn warn(message: impl Display) {
+    eprintln!("Warning: {message}");
+    EMITTED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// How many warnings this process has emitted.
+pub fn emitted() -> usize {
+    EMITTED.load(Ordering::Relaxed)
+}
+
+/// The exit code for a run that otherwise succeeded after emitting `emitted`
+/// warnings.
+pub fn exit_code(fail_on_warn: bool, emitted: usize) -> ExitCode {
+    if fail_on_warn && emitted > 0 {
+        ExitCode::Warnings
+    } else {
+        ExitCode::Success
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warnings_fail_only_with_fail_on_warn() {
+        let before = emitted();
+        warn("something looks off");
+        assert!(emitted() > before);
+
+        assert_eq!(exit_code(false, emitted()), ExitCode::Success);
+        assert_eq!(exit_code(true, emitted()), ExitCode::Warnings);
+        assert_eq!(exit_code(true, 0), ExitCode::Success);
+    }
+}
@@ -0,0 +1,104 @@
+//! `watch`: runs `check` on an interval in one long-lived process, reporting
+//! updates as they become available.
+
+use crate::{
+    check::{self, Outdated},
+    config::Settings,
+    filter::ToolFilter,
+    lockfile::Lockfile,
+    resolve::Resolver,
+};
+use std::{collections::BTreeSet, sync::mpsc, time::Duration};
+
+/// Repeated checks sharing one resolver, so the HTTP client's connections and
+/// the digest cache outlive each iteration.
+pub struct Watcher<'a> {
+    resolver: Resolver<'a>,
+    /// (tool, os, cpu, latest) of every update already reported.
+    reported: BTreeSet<(String, String, String, String)>,
+}
+
+impl<'a> Watcher<'a> {
+    pub fn new(settings: &'a Settings) -> Watcher<'a> {
+        Watcher {
+            resolver: Resolver::new(settings),
+            reported: BTreeSet::new(),
+        }
+    }
+
+    /// Checks `lockfile` against freshly looked-up releases and returns the
+    /// updates not reported by an earlier poll.
+    pub fn poll(&mut self, lockfile: &Lockfile, filter: &ToolFilter) -> Vec<Outdated> {
+        self.resolver.forget_releases();
+        let result = check::check_with(&mut self.resolver, lockfile, filter);
+        result
+            .outdated
+            .into_iter()
+            .filter(|entry| {
+                self.reported.insert((
+                    entry.tool.clone(),
+                    entry.os.to_string(),
+                    entry.cpu.to_string(),
+                    entry.latest.clone(),
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Polls every `interval` until interrupted by SIGINT, reloading the lockfile
+/// with `load` each time so edits between polls are picked up. An interrupt
+/// during a poll takes effect once it finishes.
+pub fn run(
+    settings: &Settings,
+    filter: &ToolFilter,
+    interval: Duration,
+    json: bool,
+    load: impl Fn() -> Lockfile,
+) {
+    let (interrupt, interrupted) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = interrupt.send(());
+    })
+    .expect("Unable to install SIGINT handler");
+
+    let mut watcher = Watcher::new(settings);
+    loop {
+        let new = watcher.poll(&load(), filter);
+        if json {
+            for entry in &new {
+                println!("{}", serde_json::to_string(entry).unwrap());
+            }
+        } else {
+            print!("{}", check::render_text(&new, settings.palette));
+        }
+        watcher.resolver.save_caches();
+        if interrupted.recv_timeout(interval).is_ok() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{settings, Response, Server};
+
+    #[test]
+    fn poll_reports_each_update_once() {
+        let server = Server::start(|_| Response::ok(r#"{"tag_name": "v1.3.0"}"#));
+        let settings = settings(&server.url);
+        let lockfile: Lockfile = serde_json::from_str(
+            r#"{"tool": {"binaries": [{"kind": "file", "url": "https://github.com/org/tool/releases/download/v1.2.3/tool", "sha256": "00", "os": "linux", "cpu": "x86_64"}]}}"#,
+        )
+        .unwrap();
+        let mut watcher = Watcher::new(&settings);
+
+        let new = watcher.poll(&lockfile, &ToolFilter::default());
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].latest, "1.3.0");
+        assert!(watcher.poll(&lockfile, &ToolFilter::default()).is_empty());
+        // every poll looks the release up again
+        assert_eq!(server.requests().len(), 2);
+    }
+}